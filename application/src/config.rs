@@ -32,6 +32,15 @@ fn api_file_search_threads() -> usize {
 fn api_upload_limit() -> usize {
     100
 }
+fn api_webdav_auth_cache_ttl() -> u64 {
+    60
+}
+fn api_remote_download_block_private_networks() -> bool {
+    true
+}
+fn api_remote_download_max_redirects() -> usize {
+    10
+}
 
 fn system_root_directory() -> String {
     "/var/lib/pterodactyl".to_string()
@@ -73,6 +82,9 @@ fn system_passwd_directory() -> String {
 fn system_disk_check_interval() -> u64 {
     150
 }
+fn system_disk_watcher_reconcile_interval() -> u64 {
+    6 * 60 * 60
+}
 fn system_activity_send_interval() -> u64 {
     60
 }
@@ -89,6 +101,19 @@ fn system_websocket_log_count() -> usize {
     150
 }
 
+fn system_console_history_enabled() -> bool {
+    true
+}
+fn system_console_history_max_file_size() -> u64 {
+    10
+}
+fn system_console_history_max_size() -> u64 {
+    100
+}
+fn system_console_history_max_age() -> u64 {
+    30
+}
+
 fn system_sftp_bind_address() -> String {
     "0.0.0.0".to_string()
 }
@@ -98,6 +123,24 @@ fn system_sftp_bind_port() -> u16 {
 fn system_sftp_key_algorithm() -> String {
     "ssh-ed25519".to_string()
 }
+fn system_sftp_limits_max_connections() -> usize {
+    512
+}
+fn system_sftp_limits_max_connections_per_ip() -> usize {
+    16
+}
+fn system_sftp_limits_max_failures() -> u32 {
+    5
+}
+fn system_sftp_limits_failure_window() -> u64 {
+    300
+}
+fn system_sftp_limits_lockout() -> u64 {
+    60
+}
+fn system_sftp_limits_max_lockout() -> u64 {
+    86400
+}
 fn system_sftp_directory_entry_limit() -> u64 {
     20000
 }
@@ -105,6 +148,16 @@ fn system_sftp_directory_entry_send_amount() -> usize {
     500
 }
 
+fn system_trash_enabled() -> bool {
+    true
+}
+fn system_trash_retention_days() -> u64 {
+    7
+}
+fn system_trash_disk_share() -> u64 {
+    20
+}
+
 fn system_crash_detection_enabled() -> bool {
     true
 }
@@ -257,6 +310,30 @@ nestify::nest! {
 
             #[serde(default)]
             pub disable_remote_download: bool,
+            #[serde(default)]
+            /// refuse webdav access to server files at `/webdav/{server}`
+            pub disable_webdav: bool,
+            #[serde(default = "api_webdav_auth_cache_ttl")]
+            /// seconds webdav credentials are reused without asking the panel, independent of the
+            /// sftp auth cache since file managers send a request for every file they look at.
+            /// 0 disables it
+            pub webdav_auth_cache_ttl: u64,
+            #[serde(default)]
+            pub remote_download: #[derive(Clone, Deserialize, Serialize, DefaultFromSerde)] #[serde(default)] pub struct ApiRemoteDownload {
+                #[serde(default = "api_remote_download_block_private_networks")]
+                /// refuse loopback, private, link-local and other non-public addresses
+                pub block_private_networks: bool,
+                #[serde(default)]
+                /// when not empty, only these hosts (or `*.domain` patterns) may be pulled from
+                pub allowed_hosts: Vec<String>,
+                #[serde(default)]
+                pub denied_hosts: Vec<String>,
+                #[serde(default)]
+                /// MB, 0 disables the limit
+                pub max_size: u64,
+                #[serde(default = "api_remote_download_max_redirects")]
+                pub max_redirects: usize,
+            },
             #[serde(default = "api_directory_entry_limit")]
             pub directory_entry_limit: usize,
             #[serde(default)]
@@ -325,6 +402,14 @@ nestify::nest! {
                 ZfsDataset,
                 XfsQuota,
             },
+            #[serde(default)]
+            pub disk_watcher: #[derive(Deserialize, Serialize, DefaultFromSerde)] #[serde(default)] pub struct SystemDiskWatcher {
+                #[serde(default)]
+                pub enabled: bool,
+                #[serde(default = "system_disk_watcher_reconcile_interval")]
+                /// seconds between full walks while the watcher is active
+                pub reconcile_interval: u64,
+            },
             #[serde(default = "system_activity_send_interval")]
             pub activity_send_interval: u64,
             #[serde(default = "system_activity_send_count")]
@@ -336,6 +421,22 @@ nestify::nest! {
             #[serde(default = "system_websocket_log_count")]
             pub websocket_log_count: usize,
 
+            #[serde(default)]
+            pub console_history: #[derive(Deserialize, Serialize, DefaultFromSerde)] #[serde(default)] pub struct SystemConsoleHistory {
+                #[serde(default = "system_console_history_enabled")]
+                /// keep console output in `console/<server>` of the log directory
+                pub enabled: bool,
+                #[serde(default = "system_console_history_max_file_size")]
+                /// megabytes written to a file before it is compressed and a new one is started
+                pub max_file_size: u64,
+                #[serde(default = "system_console_history_max_size")]
+                /// megabytes of compressed history kept per server, 0 disables the limit
+                pub max_size: u64,
+                #[serde(default = "system_console_history_max_age")]
+                /// days compressed history is kept, 0 disables the limit
+                pub max_age: u64,
+            },
+
             #[serde(default)]
             pub sftp: #[derive(Deserialize, Serialize, DefaultFromSerde)] #[serde(default)] pub struct SystemSftp {
                 #[serde(default = "system_sftp_bind_address")]
@@ -349,10 +450,71 @@ nestify::nest! {
                 pub key_algorithm: String,
                 #[serde(default)]
                 pub disable_password_auth: bool,
+                #[serde(default)]
+                /// openssh public keys of certificate authorities whose user certificates
+                /// are accepted without asking the panel
+                pub trusted_user_ca_keys: Vec<String>,
+                #[serde(default)]
+                /// refuse `shell` and `exec` requests for console access
+                pub disable_shell: bool,
+                #[serde(default)]
+                /// also append sftp activity to `sftp_audit.log` in the log directory
+                pub audit_log: bool,
                 #[serde(default = "system_sftp_directory_entry_limit")]
                 pub directory_entry_limit: u64,
                 #[serde(default = "system_sftp_directory_entry_send_amount")]
                 pub directory_entry_send_amount: usize,
+
+                #[serde(default)]
+                pub auth_cache: #[derive(Deserialize, Serialize, DefaultFromSerde)] #[serde(default)] pub struct SystemSftpAuthCache {
+                    #[serde(default)]
+                    /// seconds a successful login is reused without asking the panel, 0 disables it.
+                    /// revoked passwords, keys and permissions keep working for up to this long
+                    pub ttl: u64,
+                    #[serde(default)]
+                    /// seconds a cached login stays usable while the panel is unreachable, 0 disables it.
+                    /// revocations made during a panel outage only apply once it is reachable again
+                    pub stale_ttl: u64,
+                },
+                #[serde(default)]
+                pub limits: #[derive(Deserialize, Serialize, DefaultFromSerde)] #[serde(default)] pub struct SystemSftpLimits {
+                    #[serde(default = "system_sftp_limits_max_connections")]
+                    /// 0 disables the limit
+                    pub max_connections: usize,
+                    #[serde(default = "system_sftp_limits_max_connections_per_ip")]
+                    /// 0 disables the limit
+                    pub max_connections_per_ip: usize,
+
+                    #[serde(default = "system_sftp_limits_max_failures")]
+                    /// failed logins before an address, or a username from that address, is locked out, 0 disables lockouts.
+                    /// rejected public keys count once per connection
+                    pub max_failures: u32,
+                    #[serde(default = "system_sftp_limits_failure_window")]
+                    /// seconds
+                    pub failure_window: u64,
+                    #[serde(default = "system_sftp_limits_lockout")]
+                    /// seconds, doubled for every further failure
+                    pub lockout: u64,
+                    #[serde(default = "system_sftp_limits_max_lockout")]
+                    /// seconds
+                    pub max_lockout: u64,
+
+                    #[serde(default)]
+                    /// addresses that are never limited or banned
+                    pub trusted_ips: Vec<std::net::IpAddr>,
+                },
+            },
+
+            #[serde(default)]
+            pub trash: #[derive(Deserialize, Serialize, DefaultFromSerde)] #[serde(default)] pub struct SystemTrash {
+                #[serde(default = "system_trash_enabled")]
+                pub enabled: bool,
+                #[serde(default = "system_trash_retention_days")]
+                /// days, 0 = never expire
+                pub retention_days: u64,
+                #[serde(default = "system_trash_disk_share")]
+                /// % of the server disk limit
+                pub disk_share: u64,
             },
 
             #[serde(default)]
@@ -536,6 +698,13 @@ nestify::nest! {
             pub lines: u64,
             #[serde(default = "throttles_line_reset_interval")]
             pub line_reset_interval: u64,
+
+            #[serde(default)]
+            /// MB/s per direction for all file transfers on the node, 0 disables the limit
+            pub transfer_limit: u64,
+            #[serde(default)]
+            /// MB/s per direction for a single sftp session or http transfer, 0 disables the limit
+            pub session_transfer_limit: u64,
         },
 
         pub remote: String,
@@ -7,7 +7,7 @@ use axum::{
 };
 use clap::{Arg, Command};
 use colored::Colorize;
-use russh::{keys::ssh_key::rand_core::OsRng, server::Server};
+use russh::keys::ssh_key::rand_core::OsRng;
 use std::{net::SocketAddr, path::Path, sync::Arc, time::Instant};
 use tower_http::catch_panic::CatchPanicLayer;
 use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
@@ -201,7 +201,8 @@ async fn handle_cors(
         );
     }
 
-    if method == Method::OPTIONS {
+    // webdav clients need the dav headers of the actual options response
+    if method == Method::OPTIONS && !req.uri().path().starts_with("/webdav/") {
         let mut response = Response::new(Body::empty());
         response.headers_mut().extend(headers);
         *response.status_mut() = StatusCode::NO_CONTENT;
@@ -303,6 +304,11 @@ async fn main() {
         docker: Arc::clone(&docker),
         server_manager: Arc::clone(&server_manager),
         extension_manager: Arc::clone(&extension_manager),
+        sftp_auth_cache: Default::default(),
+        sftp_limiter: wings_rs::sftp::limiter::Limiter::new(Arc::clone(&config)),
+        bandwidth: Default::default(),
+        webdav_locks: Default::default(),
+        webdav_auth_cache: Default::default(),
     });
 
     let mut extension_router = OpenApiRouter::new();
@@ -448,7 +454,7 @@ async fn main() {
             );
 
             server
-                .run_on_address(Arc::new(config), address)
+                .run(Arc::new(config), address)
                 .await
                 .context("failed to bind to SFTP address")
                 .unwrap();
@@ -41,3 +41,77 @@ pub struct Download {
     pub progress: u64,
     pub total: u64,
 }
+
+#[derive(ToSchema, Serialize)]
+pub struct Copy {
+    pub identifier: uuid::Uuid,
+
+    pub progress: u64,
+    pub total: u64,
+    pub destination: String,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct Operation {
+    pub identifier: uuid::Uuid,
+    pub kind: crate::server::filesystem::operations::OperationKind,
+
+    pub path: String,
+    pub progress: u64,
+    pub total: u64,
+    pub started: chrono::DateTime<chrono::Utc>,
+    pub eta: Option<u64>,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct TrashEntry {
+    pub identifier: uuid::Uuid,
+
+    pub path: String,
+    pub size: u64,
+    pub directory: bool,
+    pub deleted: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct ConsoleLine {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub line: String,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct Throughput {
+    pub downloaded: u64,
+    /// bytes per second
+    pub downloading_rate: u64,
+    pub uploaded: u64,
+    /// bytes per second
+    pub uploading_rate: u64,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct SftpBan {
+    pub ip: String,
+    pub failures: u32,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub expires: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub directory: bool,
+    pub size: u64,
+    pub mode_bits: String,
+    pub modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct RegionChunk {
+    pub x: i32,
+    pub z: i32,
+
+    pub offset: u64,
+    pub size: u64,
+    pub modified: Option<chrono::DateTime<chrono::Utc>>,
+}
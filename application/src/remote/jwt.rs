@@ -4,7 +4,7 @@ use std::{
 };
 
 use hmac::digest::KeyInit;
-use jwt::VerifyWithKey;
+use jwt::{SignWithKey, VerifyWithKey};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
 #[derive(Deserialize, Serialize)]
@@ -82,6 +82,10 @@ impl JwtClient {
         token.verify_with_key(&self.key)
     }
 
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, jwt::Error> {
+        claims.sign_with_key(&self.key)
+    }
+
     pub fn one_time_id(&self, id: &str) -> bool {
         let seen = self.seen_jtoken_ids.read().unwrap();
         if seen.contains(&id.to_string()) {
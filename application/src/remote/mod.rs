@@ -41,6 +41,7 @@ pub async fn get_sftp_auth(
         }))
         .send()
         .await?
+        .error_for_status()?
         .json()
         .await?;
 
@@ -10,6 +10,7 @@ use utoipa_axum::router::OpenApiRouter;
 
 mod extensions;
 pub mod servers;
+mod sftp;
 mod stats;
 mod system;
 mod transfers;
@@ -62,6 +63,11 @@ pub fn router(state: &State) -> OpenApiRouter<State> {
             update::router(state)
                 .route_layer(axum::middleware::from_fn_with_state(state.clone(), auth)),
         )
+        .nest(
+            "/sftp",
+            sftp::router(state)
+                .route_layer(axum::middleware::from_fn_with_state(state.clone(), auth)),
+        )
         .nest("/transfers", transfers::router(state))
         .nest(
             "/servers",
@@ -0,0 +1,120 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod get {
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::permissions::Permission,
+    };
+    use axum::{extract::Query, http::StatusCode};
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Params {
+        file: String,
+
+        limit: Option<usize>,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        entries: Vec<crate::models::ArchiveEntry>,
+    }
+
+    #[utoipa::path(get, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), params(
+        (
+            "file" = String, Query,
+            description = "The archive to list the entries of",
+        ),
+        (
+            "limit" = Option<usize>, Query,
+            description = "The maximum amount of entries to return",
+        ),
+    ))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        Query(data): Query<Params>,
+    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+        let path = match server.filesystem.canonicalize(&data.file).await {
+            Ok(path) => path,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(ApiError::new("file not found").to_json()),
+                );
+            }
+        };
+
+        let metadata = server.filesystem.metadata(&path).await;
+        if !metadata.as_ref().is_ok_and(|m| m.is_file())
+            || server.filesystem.is_ignored(&path, false).await
+            || server
+                .filesystem
+                .is_outside_grants(&path, permissions.as_ref(), Permission::FileReadContent)
+                .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("file not found").to_json()),
+            );
+        }
+
+        let archive =
+            match crate::server::filesystem::archive::Archive::open(server.0.clone(), path.clone())
+                .await
+            {
+                Some(archive) => archive,
+                None => {
+                    return (
+                        StatusCode::EXPECTATION_FAILED,
+                        axum::Json(ApiError::new("failed to open archive").to_json()),
+                    );
+                }
+            };
+
+        let entries = match archive.entries(data.limit.unwrap_or(10_000)).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                tracing::error!(
+                    server = %server.uuid,
+                    path = %path.display(),
+                    error = %err,
+                    "failed to list archive entries",
+                );
+
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    axum::Json(ApiError::new("failed to read archive").to_json()),
+                );
+            }
+        };
+
+        (
+            StatusCode::OK,
+            axum::Json(
+                serde_json::to_value(&Response {
+                    entries: entries
+                        .iter()
+                        .map(|entry| entry.to_api_response())
+                        .collect(),
+                })
+                .unwrap(),
+            ),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(get::route))
+        .with_state(state.clone())
+}
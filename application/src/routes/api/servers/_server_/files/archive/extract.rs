@@ -0,0 +1,148 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod post {
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::permissions::Permission,
+    };
+    use axum::http::StatusCode;
+    use serde::{Deserialize, Serialize};
+    use std::{path::Path, sync::Arc};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Payload {
+        pub file: String,
+        pub entries: Vec<String>,
+
+        #[serde(default)]
+        pub destination: String,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {}
+
+    #[utoipa::path(post, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = BAD_REQUEST, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), request_body = inline(Payload))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        axum::Json(data): axum::Json<Payload>,
+    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+        let selection = match data
+            .entries
+            .iter()
+            .map(|entry| crate::server::filesystem::archive::normalize_entry_path(Path::new(entry)))
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(selection) if !selection.is_empty() => selection,
+            _ => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    axum::Json(ApiError::new("invalid entries").to_json()),
+                );
+            }
+        };
+
+        let destination = match server.filesystem.canonicalize(data.destination).await {
+            Ok(path) => path,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(ApiError::new("destination not found").to_json()),
+                );
+            }
+        };
+
+        let metadata = server.filesystem.metadata(&destination).await;
+        if !metadata.map(|m| m.is_dir()).unwrap_or(true) {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("destination is not a directory").to_json()),
+            );
+        }
+
+        let source = match server.filesystem.canonicalize(&data.file).await {
+            Ok(path) => path,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(ApiError::new("file not found").to_json()),
+                );
+            }
+        };
+
+        if server.filesystem.is_ignored(&source, false).await
+            || server
+                .filesystem
+                .is_outside_grants(&source, permissions.as_ref(), Permission::FileReadContent)
+                .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("file not found").to_json()),
+            );
+        }
+
+        let mut archive = match crate::server::filesystem::archive::Archive::open(
+            server.0.clone(),
+            source.clone(),
+        )
+        .await
+        {
+            Some(archive) => archive,
+            None => {
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    axum::Json(ApiError::new("failed to open archive").to_json()),
+                );
+            }
+        };
+
+        archive.permissions = permissions.0;
+
+        let reader = archive.reader().await;
+        if let Err(err) = archive
+            .extract_entries(
+                Arc::clone(&server.filesystem.base_dir().await.unwrap()),
+                destination.clone(),
+                reader,
+                selection,
+            )
+            .await
+        {
+            tracing::error!(
+                server = %server.uuid,
+                path = %source.display(),
+                error = %err,
+                "failed to extract archive entries",
+            );
+
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("failed to extract archive entries").to_json()),
+            );
+        }
+
+        server.filesystem.chown_path(&destination).await;
+
+        (
+            StatusCode::OK,
+            axum::Json(serde_json::to_value(&Response {}).unwrap()),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(post::route))
+        .with_state(state.clone())
+}
@@ -0,0 +1,12 @@
+use super::State;
+use utoipa_axum::router::OpenApiRouter;
+
+mod entries;
+mod extract;
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .nest("/entries", entries::router(state))
+        .nest("/extract", extract::router(state))
+        .with_state(state.clone())
+}
@@ -2,7 +2,13 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod post {
-    use crate::routes::{ApiError, api::servers::_server_::GetServer};
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::permissions::Permission,
+    };
     use axum::http::StatusCode;
     use cap_std::fs::{Permissions, PermissionsExt};
     use serde::{Deserialize, Serialize};
@@ -35,6 +41,7 @@ mod post {
     ), request_body = inline(Payload))]
     pub async fn route(
         server: GetServer,
+        permissions: GetPermissions,
         axum::Json(data): axum::Json<Payload>,
     ) -> (StatusCode, axum::Json<serde_json::Value>) {
         let root = match server.filesystem.canonicalize(data.root).await {
@@ -56,8 +63,8 @@ mod post {
         }
 
         let mut updated_count = 0;
-        for file in data.files {
-            let source = match server.filesystem.canonicalize(root.join(file.file)).await {
+        for file in &data.files {
+            let source = match server.filesystem.canonicalize(root.join(&file.file)).await {
                 Ok(path) => path,
                 Err(_) => continue,
             };
@@ -73,6 +80,10 @@ mod post {
                 .filesystem
                 .is_ignored(&source, metadata.is_dir())
                 .await
+                || server
+                    .filesystem
+                    .is_outside_grants(&source, permissions.as_ref(), Permission::FileUpdate)
+                    .await
             {
                 continue;
             }
@@ -2,10 +2,20 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod post {
-    use crate::routes::{ApiError, GetState, api::servers::_server_::GetServer};
+    use crate::{
+        routes::{
+            ApiError, GetState,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::{
+            filesystem::operations::{Operation, OperationKind},
+            permissions::{Permission, Permissions},
+        },
+    };
     use axum::http::StatusCode;
     use cap_std::fs::PermissionsExt;
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
+    use std::sync::{Arc, atomic::Ordering};
     use utoipa::ToSchema;
 
     #[derive(ToSchema, Deserialize)]
@@ -14,16 +24,51 @@ mod post {
         pub root: String,
 
         pub files: Vec<String>,
+
+        #[schema(default = "true")]
+        #[serde(default = "default_foreground")]
+        pub foreground: bool,
+    }
+
+    #[inline]
+    fn default_foreground() -> bool {
+        true
+    }
+
+    /// Directories only have to be visible, files need their content to be readable
+    #[inline]
+    fn is_permitted(
+        server: &crate::server::Server,
+        permissions: Option<&Permissions>,
+        path: &std::path::Path,
+        is_dir: bool,
+    ) -> bool {
+        if is_dir {
+            !server.filesystem.is_outside_view(path, permissions)
+        } else {
+            !server.filesystem.is_outside_grants_sync(
+                path,
+                permissions,
+                Permission::FileReadContent,
+            )
+        }
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        identifier: uuid::Uuid,
     }
 
     #[utoipa::path(post, path = "/", responses(
         (status = OK, body = crate::models::DirectoryEntry),
+        (status = ACCEPTED, body = inline(Response)),
         (status = NOT_FOUND, body = inline(ApiError)),
         (status = EXPECTATION_FAILED, body = inline(ApiError)),
     ), request_body = inline(Payload))]
     pub async fn route(
         state: GetState,
         server: GetServer,
+        permissions: GetPermissions,
         axum::Json(data): axum::Json<Payload>,
     ) -> (StatusCode, axum::Json<serde_json::Value>) {
         let root = match server.filesystem.canonicalize(data.root).await {
@@ -50,26 +95,65 @@ mod post {
         );
         let file_name = root.join(file_name);
 
-        if server.filesystem.is_ignored(&file_name, false).await {
+        if server.filesystem.is_ignored(&file_name, false).await
+            || server
+                .filesystem
+                .is_outside_grants(&file_name, permissions.as_ref(), Permission::FileCreate)
+                .await
+        {
             return (
                 StatusCode::EXPECTATION_FAILED,
                 axum::Json(ApiError::new("file not found").to_json()),
             );
         }
 
-        tokio::task::spawn_blocking({
+        let mut operation = Operation::new(OperationKind::Compress, &file_name);
+        operation.abortable = false;
+
+        let progress = Arc::clone(&operation.progress);
+        let total = Arc::clone(&operation.total);
+        let cancelled = Arc::clone(&operation.cancelled);
+
+        let compress = tokio::task::spawn_blocking({
             let filesystem = server.filesystem.base_dir().await.unwrap();
             let file_name = file_name.clone();
             let server = server.0.clone();
+            let cancelled = Arc::clone(&cancelled);
+
+            move || -> Result<(), anyhow::Error> {
+                for file in &data.files {
+                    let source = match filesystem
+                        .canonicalize(server.filesystem.relative_path(&root.join(file)))
+                    {
+                        Ok(path) => path,
+                        Err(_) => continue,
+                    };
+
+                    if let Ok((mut walker, _)) = server.filesystem.walk_dir(&source) {
+                        for entry in walker
+                            .git_ignore(false)
+                            .ignore(false)
+                            .git_exclude(false)
+                            .follow_links(false)
+                            .hidden(false)
+                            .build()
+                            .flatten()
+                        {
+                            if let Ok(metadata) = entry.metadata()
+                                && metadata.is_file()
+                            {
+                                total.fetch_add(metadata.len(), Ordering::Relaxed);
+                            }
+                        }
+                    }
+                }
 
-            move || {
                 let writer = crate::server::filesystem::writer::FileSystemWriter::new(
                     server.clone(),
                     file_name,
                     None,
                     None,
-                )
-                .unwrap();
+                )?;
 
                 let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
                     writer,
@@ -77,6 +161,10 @@ mod post {
                 ));
 
                 for file in data.files {
+                    if cancelled.load(Ordering::Relaxed) {
+                        break;
+                    }
+
                     let source = match filesystem
                         .canonicalize(server.filesystem.relative_path(&root.join(file)))
                     {
@@ -96,6 +184,12 @@ mod post {
                     if server
                         .filesystem
                         .is_ignored_sync(&source, source_metadata.is_dir())
+                        || !is_permitted(
+                            &server,
+                            permissions.as_ref(),
+                            &source,
+                            source_metadata.is_dir(),
+                        )
                     {
                         continue;
                     }
@@ -120,6 +214,10 @@ mod post {
                                 continue;
                             }
 
+                            if cancelled.load(Ordering::Relaxed) {
+                                break;
+                            }
+
                             let display_path = relative.join(path);
                             let path = server.filesystem.relative_path(&source.join(path));
 
@@ -128,7 +226,14 @@ mod post {
                                 Err(_) => continue,
                             };
 
-                            if server.filesystem.is_ignored_sync(&path, metadata.is_dir()) {
+                            if server.filesystem.is_ignored_sync(&path, metadata.is_dir())
+                                || !is_permitted(
+                                    &server,
+                                    permissions.as_ref(),
+                                    &path,
+                                    metadata.is_dir(),
+                                )
+                            {
                                 continue;
                             }
 
@@ -156,6 +261,7 @@ mod post {
                                 );
 
                                 archive.append_data(&mut header, display_path, file).ok();
+                                progress.fetch_add(metadata.len(), Ordering::Relaxed);
                             } else if let Ok(link_target) = filesystem.read_link(&source) {
                                 let mut header = tar::Header::new_gnu();
                                 header.set_size(0);
@@ -197,6 +303,7 @@ mod post {
                         archive
                             .append_data(&mut header, relative, filesystem.open(&source).unwrap())
                             .unwrap();
+                        progress.fetch_add(source_metadata.len(), Ordering::Relaxed);
                     } else if let Ok(link_target) = filesystem.read_link(&source) {
                         let mut header = tar::Header::new_gnu();
                         header.set_size(0);
@@ -220,17 +327,57 @@ mod post {
                     }
                 }
 
-                archive.finish().unwrap();
+                archive.finish()?;
+
+                Ok(())
+            }
+        });
+
+        operation.start(server.0.clone(), {
+            let server = server.clone();
+            let file_name = file_name.clone();
+
+            async move {
+                let result = compress.await?;
+
+                if cancelled.load(Ordering::Relaxed) {
+                    server.filesystem.truncate_path(&file_name).await.ok();
+                }
+
+                result
+            }
+        });
+
+        let operation = server.filesystem.add_operation(operation).await;
+
+        if !data.foreground {
+            return (
+                StatusCode::ACCEPTED,
+                axum::Json(
+                    serde_json::to_value(&Response {
+                        identifier: operation.identifier,
+                    })
+                    .unwrap(),
+                ),
+            );
+        }
+
+        if !operation.wait().await {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("failed to compress files").to_json()),
+            );
+        }
+
+        let metadata = match server.filesystem.symlink_metadata(&file_name).await {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    axum::Json(ApiError::new("failed to compress files").to_json()),
+                );
             }
-        })
-        .await
-        .unwrap();
-
-        let metadata = server
-            .filesystem
-            .symlink_metadata(&file_name)
-            .await
-            .unwrap();
+        };
 
         (
             StatusCode::OK,
@@ -2,14 +2,24 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod get {
-    use crate::routes::{ApiError, api::servers::_server_::GetServer};
+    use crate::{
+        routes::{
+            ApiError, GetState,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::{
+            filesystem::encoding::{SAMPLE_SIZE, TextEncoding},
+            permissions::Permission,
+        },
+    };
     use axum::{
         body::Body,
         extract::Query,
         http::{HeaderMap, StatusCode},
     };
     use serde::Deserialize;
-    use std::path::PathBuf;
+    use std::{io::SeekFrom, path::PathBuf};
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
     use utoipa::ToSchema;
 
     #[derive(ToSchema, Deserialize)]
@@ -19,11 +29,26 @@ mod get {
         #[schema(default = "false")]
         #[serde(default)]
         download: bool,
+
+        #[schema(default = "false")]
+        #[serde(default)]
+        transcode: bool,
+    }
+
+    fn insert_encoding_headers(headers: &mut HeaderMap, encoding: &TextEncoding) {
+        headers.insert("X-File-Encoding", encoding.encoding.name().parse().unwrap());
+        headers.insert("X-File-Bom", encoding.bom.to_string().parse().unwrap());
+        if let Some(line_ending) = encoding.line_ending {
+            headers.insert("X-File-Line-Ending", line_ending.as_str().parse().unwrap());
+        }
     }
 
     #[utoipa::path(get, path = "/", responses(
         (status = OK, body = String),
+        (status = PARTIAL_CONTENT, body = String),
+        (status = NOT_MODIFIED, body = String),
         (status = NOT_FOUND, body = inline(ApiError)),
+        (status = RANGE_NOT_SATISFIABLE, body = String),
         (status = EXPECTATION_FAILED, body = inline(ApiError)),
     ), params(
         (
@@ -34,9 +59,16 @@ mod get {
             "download" = bool, Query,
             description = "Whether to add 'download headers' to the file",
         ),
+        (
+            "transcode" = bool, Query,
+            description = "Whether to convert the file to UTF-8 with its detected encoding",
+        ),
     ))]
     pub async fn route(
+        state: GetState,
         server: GetServer,
+        permissions: GetPermissions,
+        request_headers: HeaderMap,
         Query(data): Query<Params>,
     ) -> (StatusCode, HeaderMap, Body) {
         let path = match server.filesystem.canonicalize(&data.file).await {
@@ -44,6 +76,21 @@ mod get {
             Err(_) => PathBuf::from(data.file),
         };
 
+        if server
+            .filesystem
+            .is_outside_grants(&path, permissions.as_ref(), Permission::FileReadContent)
+            .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                HeaderMap::from_iter([(
+                    "Content-Type".parse().unwrap(),
+                    "application/json".parse().unwrap(),
+                )]),
+                Body::from(serde_json::to_string(&ApiError::new("file not found")).unwrap()),
+            );
+        }
+
         if let Some((backup, path)) = server.filesystem.backup_fs(&server, &path).await {
             match crate::server::filesystem::backup::reader(backup, &server, &path).await {
                 Ok((mut reader, size)) => {
@@ -106,7 +153,7 @@ mod get {
         }
 
         let metadata = server.filesystem.metadata(&path).await;
-        if let Ok(metadata) = metadata {
+        if let Ok(metadata) = &metadata {
             if !metadata.is_file() || server.filesystem.is_ignored(&path, metadata.is_dir()).await {
                 return (
                     StatusCode::NOT_FOUND,
@@ -138,6 +185,105 @@ mod get {
                 }
             };
 
+        let mut headers = HeaderMap::new();
+        if data.download {
+            headers.insert(
+                "Content-Disposition",
+                format!(
+                    "attachment; filename={}",
+                    serde_json::Value::String(
+                        path.file_name().unwrap().to_str().unwrap().to_string(),
+                    )
+                )
+                .parse()
+                .unwrap(),
+            );
+            headers.insert("Content-Type", "application/octet-stream".parse().unwrap());
+        }
+
+        if let Ok(metadata) = &metadata {
+            if matches!(
+                file.compression,
+                crate::server::filesystem::archive::CompressionType::None
+            ) {
+                let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+                if (&mut file.file)
+                    .take(SAMPLE_SIZE as u64)
+                    .read_to_end(&mut sample)
+                    .await
+                    .is_ok()
+                {
+                    let encoding = TextEncoding::detect(&sample);
+                    insert_encoding_headers(&mut headers, &encoding);
+
+                    if data.transcode && !data.download {
+                        if metadata.len() > state.config.api.upload_limit as u64 * 1000 * 1000 {
+                            return (
+                                StatusCode::EXPECTATION_FAILED,
+                                HeaderMap::from_iter([(
+                                    "Content-Type".parse().unwrap(),
+                                    "application/json".parse().unwrap(),
+                                )]),
+                                Body::from(
+                                    serde_json::to_string(&ApiError::new(
+                                        "file is too large to transcode",
+                                    ))
+                                    .unwrap(),
+                                ),
+                            );
+                        }
+
+                        // the validators stay those of the file on disk so the editor
+                        // can send them back as `If-Match` when writing
+                        crate::routes::conditional::insert_headers(&mut headers, metadata, false);
+                        if crate::routes::conditional::is_not_modified(&request_headers, metadata) {
+                            return (StatusCode::NOT_MODIFIED, headers, Body::empty());
+                        }
+
+                        let mut content = sample;
+                        if file.file.read_to_end(&mut content).await.is_err() {
+                            return (
+                                StatusCode::EXPECTATION_FAILED,
+                                HeaderMap::from_iter([(
+                                    "Content-Type".parse().unwrap(),
+                                    "application/json".parse().unwrap(),
+                                )]),
+                                Body::from(
+                                    serde_json::to_string(&ApiError::new(
+                                        "unable to open file for reading",
+                                    ))
+                                    .unwrap(),
+                                ),
+                            );
+                        }
+
+                        let content = encoding.decode(&content);
+                        headers
+                            .insert("Content-Type", "text/plain; charset=utf-8".parse().unwrap());
+                        headers.insert("Content-Length", content.len().into());
+
+                        return (StatusCode::OK, headers, Body::from(content));
+                    }
+                }
+
+                if file.file.seek(SeekFrom::Start(0)).await.is_ok() {
+                    return crate::routes::conditional::file_response(
+                        &request_headers,
+                        headers,
+                        metadata,
+                        file.file,
+                    )
+                    .await;
+                }
+            } else {
+                crate::routes::conditional::insert_headers(&mut headers, metadata, false);
+
+                if crate::routes::conditional::is_not_modified(&request_headers, metadata) {
+                    return (StatusCode::NOT_MODIFIED, headers, Body::empty());
+                }
+            }
+        }
+
         let size = match file.estimated_size().await {
             Some(size) => size,
             None => {
@@ -174,23 +320,7 @@ mod get {
             }
         };
 
-        let mut headers = HeaderMap::new();
-
         headers.insert("Content-Length", size.into());
-        if data.download {
-            headers.insert(
-                "Content-Disposition",
-                format!(
-                    "attachment; filename={}",
-                    serde_json::Value::String(
-                        path.file_name().unwrap().to_str().unwrap().to_string(),
-                    )
-                )
-                .parse()
-                .unwrap(),
-            );
-            headers.insert("Content-Type", "application/octet-stream".parse().unwrap());
-        }
 
         (
             StatusCode::OK,
@@ -2,7 +2,13 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod post {
-    use crate::routes::{ApiError, api::servers::_server_::GetServer};
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::permissions::Permission,
+    };
     use axum::http::StatusCode;
     use serde::Deserialize;
     use utoipa::ToSchema;
@@ -20,6 +26,7 @@ mod post {
     ), request_body = inline(Payload))]
     pub async fn route(
         server: GetServer,
+        permissions: GetPermissions,
         axum::Json(data): axum::Json<Payload>,
     ) -> (StatusCode, axum::Json<serde_json::Value>) {
         let location = match server.filesystem.canonicalize(data.location).await {
@@ -39,6 +46,14 @@ mod post {
                         .filesystem
                         .is_ignored(&location, metadata.is_dir())
                         .await
+                    || server
+                        .filesystem
+                        .is_outside_grants(
+                            &location,
+                            permissions.as_ref(),
+                            Permission::FileReadContent,
+                        )
+                        .await
                 {
                     return (
                         StatusCode::NOT_FOUND,
@@ -101,6 +116,17 @@ mod post {
         });
         let file_name = location.parent().unwrap().join(&new_name);
 
+        if server
+            .filesystem
+            .is_outside_grants(&file_name, permissions.as_ref(), Permission::FileCreate)
+            .await
+        {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("destination is not permitted").to_json()),
+            );
+        }
+
         if !server
             .filesystem
             .allocate_in_path(location.parent().unwrap(), metadata.len() as i64)
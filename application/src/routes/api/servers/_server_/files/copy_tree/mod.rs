@@ -0,0 +1,263 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod _copy_;
+
+mod get {
+    use crate::{
+        routes::api::servers::_server_::GetServer, server::filesystem::operations::OperationKind,
+    };
+    use serde::Serialize;
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        copies: Vec<crate::models::Copy>,
+    }
+
+    #[utoipa::path(get, path = "/", responses(
+        (status = OK, body = inline(Response)),
+    ))]
+    pub async fn route(server: GetServer) -> axum::Json<serde_json::Value> {
+        let copies = server
+            .filesystem
+            .operations()
+            .await
+            .values()
+            .filter(|operation| operation.kind == OperationKind::Copy)
+            .map(|operation| {
+                let operation = operation.to_api_response();
+
+                crate::models::Copy {
+                    identifier: operation.identifier,
+                    progress: operation.progress,
+                    total: operation.total,
+                    destination: operation.path,
+                }
+            })
+            .collect();
+
+        axum::Json(serde_json::to_value(&Response { copies }).unwrap())
+    }
+}
+
+mod post {
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::{
+            filesystem::{copy::ConflictPolicy, operations::OperationKind},
+            permissions::Permission,
+        },
+    };
+    use axum::http::StatusCode;
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Payload {
+        #[serde(default)]
+        root: String,
+        files: Vec<String>,
+
+        destination: String,
+        #[serde(default)]
+        conflict: ConflictPolicy,
+
+        #[serde(default)]
+        foreground: bool,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        identifier: uuid::Uuid,
+    }
+
+    #[utoipa::path(post, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), request_body = inline(Payload))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        axum::Json(data): axum::Json<Payload>,
+    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+        let root = match server.filesystem.canonicalize(data.root).await {
+            Ok(path) => path,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(ApiError::new("root not found").to_json()),
+                );
+            }
+        };
+
+        let mut sources = Vec::new();
+        for file in &data.files {
+            let source = match server.filesystem.canonicalize(root.join(file)).await {
+                Ok(path) => path,
+                Err(_) => {
+                    return (
+                        StatusCode::NOT_FOUND,
+                        axum::Json(ApiError::new("file not found").to_json()),
+                    );
+                }
+            };
+
+            let metadata = match server.filesystem.symlink_metadata(&source).await {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    return (
+                        StatusCode::NOT_FOUND,
+                        axum::Json(ApiError::new("file not found").to_json()),
+                    );
+                }
+            };
+
+            if source.components().next().is_none()
+                || server
+                    .filesystem
+                    .is_ignored(&source, metadata.is_dir())
+                    .await
+                || server
+                    .filesystem
+                    .is_outside_grants(&source, permissions.as_ref(), Permission::FileReadContent)
+                    .await
+            {
+                return (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(ApiError::new("file not found").to_json()),
+                );
+            }
+
+            sources.push(source);
+        }
+
+        if sources.is_empty() {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("no files to copy").to_json()),
+            );
+        }
+
+        if server
+            .filesystem
+            .is_outside_grants(
+                std::path::Path::new(&data.destination),
+                permissions.as_ref(),
+                Permission::FileCreate,
+            )
+            .await
+        {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("destination is not permitted").to_json()),
+            );
+        }
+
+        if server
+            .filesystem
+            .create_dir_all(&data.destination)
+            .await
+            .is_err()
+        {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("failed to create destination").to_json()),
+            );
+        }
+
+        let destination = match server.filesystem.canonicalize(data.destination).await {
+            Ok(path) => path,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(ApiError::new("destination not found").to_json()),
+                );
+            }
+        };
+
+        let metadata = server.filesystem.metadata(&destination).await;
+        if !metadata.map(|m| m.is_dir()).unwrap_or(true)
+            || server.filesystem.is_ignored(&destination, true).await
+        {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("destination is not a directory").to_json()),
+            );
+        }
+
+        if server
+            .filesystem
+            .is_outside_grants(&destination, permissions.as_ref(), Permission::FileCreate)
+            .await
+        {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("destination is not permitted").to_json()),
+            );
+        }
+
+        if server
+            .filesystem
+            .count_operations(OperationKind::Copy)
+            .await
+            >= 3
+        {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("too many concurrent copies").to_json()),
+            );
+        }
+
+        let copy = match crate::server::filesystem::copy::Copy::new(
+            server.0.clone(),
+            sources,
+            destination,
+            data.conflict,
+        )
+        .await
+        {
+            Ok(copy) => copy,
+            Err(err) => {
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    axum::Json(ApiError::new(&err.to_string()).to_json()),
+                );
+            }
+        };
+
+        let disk_limit = server.filesystem.disk_limit();
+        if disk_limit != 0
+            && server.filesystem.limiter_usage().await + copy.total > disk_limit as u64
+        {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("not enough disk space").to_json()),
+            );
+        }
+
+        let operation = server.filesystem.add_operation(copy.start()).await;
+        let identifier = operation.identifier;
+
+        if data.foreground {
+            operation.wait().await;
+        }
+
+        (
+            StatusCode::OK,
+            axum::Json(serde_json::to_value(&Response { identifier }).unwrap()),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .nest("/{copy}", _copy_::router(state))
+        .routes(routes!(get::route))
+        .routes(routes!(post::route))
+        .with_state(state.clone())
+}
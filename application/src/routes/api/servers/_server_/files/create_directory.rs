@@ -2,7 +2,13 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod post {
-    use crate::routes::{ApiError, api::servers::_server_::GetServer};
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::permissions::Permission,
+    };
     use axum::http::StatusCode;
     use serde::{Deserialize, Serialize};
     use std::path::Path;
@@ -24,6 +30,7 @@ mod post {
     ), request_body = inline(Payload))]
     pub async fn route(
         server: GetServer,
+        permissions: GetPermissions,
         axum::Json(data): axum::Json<Payload>,
     ) -> (StatusCode, axum::Json<serde_json::Value>) {
         let path = Path::new(&data.path);
@@ -45,7 +52,12 @@ mod post {
 
         let destination = path.join(&data.name);
 
-        if server.filesystem.is_ignored(&destination, true).await {
+        if server.filesystem.is_ignored(&destination, true).await
+            || server
+                .filesystem
+                .is_outside_grants(&destination, permissions.as_ref(), Permission::FileCreate)
+                .await
+        {
             return (
                 StatusCode::EXPECTATION_FAILED,
                 axum::Json(ApiError::new("destination not found").to_json()),
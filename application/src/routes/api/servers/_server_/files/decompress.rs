@@ -2,10 +2,22 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod post {
-    use crate::routes::{ApiError, api::servers::_server_::GetServer};
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::{
+            filesystem::{
+                archive::ArchiveType,
+                operations::{Operation, OperationKind},
+            },
+            permissions::Permission,
+        },
+    };
     use axum::http::StatusCode;
     use serde::{Deserialize, Serialize};
-    use std::sync::Arc;
+    use std::sync::{Arc, atomic::Ordering};
     use utoipa::ToSchema;
 
     #[derive(ToSchema, Deserialize)]
@@ -14,10 +26,21 @@ mod post {
         pub root: String,
 
         pub file: String,
+
+        #[schema(default = "true")]
+        #[serde(default = "default_foreground")]
+        pub foreground: bool,
+    }
+
+    #[inline]
+    fn default_foreground() -> bool {
+        true
     }
 
     #[derive(ToSchema, Serialize)]
-    struct Response {}
+    struct Response {
+        identifier: uuid::Uuid,
+    }
 
     #[utoipa::path(post, path = "/", responses(
         (status = OK, body = inline(Response)),
@@ -26,6 +49,7 @@ mod post {
     ), request_body = inline(Payload))]
     pub async fn route(
         server: GetServer,
+        permissions: GetPermissions,
         axum::Json(data): axum::Json<Payload>,
     ) -> (StatusCode, axum::Json<serde_json::Value>) {
         let root = match server.filesystem.canonicalize(data.root).await {
@@ -59,6 +83,10 @@ mod post {
                     .is_ok_and(|m| m.is_dir()),
             )
             .await
+            || server
+                .filesystem
+                .is_outside_grants(&source, permissions.as_ref(), Permission::FileReadContent)
+                .await
         {
             return (
                 StatusCode::NOT_FOUND,
@@ -78,20 +106,47 @@ mod post {
                 }
             };
 
-        let reader = archive.reader().await;
-        archive
-            .extract(
-                Arc::clone(&server.filesystem.base_dir().await.unwrap()),
-                root.clone(),
-                reader,
-            )
-            .await
-            .unwrap();
-        server.filesystem.chown_path(&root).await;
+        archive.permissions = permissions.0;
+
+        let mut operation = Operation::new(OperationKind::Decompress, &archive.path);
+        // only plain compressed files are extracted on the async runtime
+        operation.abortable = matches!(archive.archive, ArchiveType::None);
+
+        archive.progress = Arc::clone(&operation.progress);
+        archive.total = Arc::clone(&operation.total);
+        archive.cancelled = Arc::clone(&operation.cancelled);
+
+        if let Some(total) = archive.estimated_size().await {
+            archive.total.store(total, Ordering::Relaxed);
+        }
+
+        operation.start(server.0.clone(), {
+            let server = server.clone();
+
+            async move {
+                let reader = archive.reader().await;
+                let result = archive
+                    .extract(server.filesystem.base_dir().await?, root.clone(), reader)
+                    .await;
+                server.filesystem.chown_path(&root).await;
+
+                result
+            }
+        });
+
+        let operation = server.filesystem.add_operation(operation).await;
+        let identifier = operation.identifier;
+
+        if data.foreground && !operation.wait().await {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("failed to decompress archive").to_json()),
+            );
+        }
 
         (
             StatusCode::OK,
-            axum::Json(serde_json::to_value(&Response {}).unwrap()),
+            axum::Json(serde_json::to_value(&Response { identifier }).unwrap()),
         )
     }
 }
@@ -2,9 +2,22 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod post {
-    use crate::routes::{ApiError, api::servers::_server_::GetServer};
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::{
+            filesystem::operations::{Operation, OperationKind},
+            permissions::Permission,
+        },
+    };
     use axum::http::StatusCode;
     use serde::{Deserialize, Serialize};
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
     use utoipa::ToSchema;
 
     #[derive(ToSchema, Deserialize)]
@@ -13,6 +26,15 @@ mod post {
         pub root: String,
 
         pub files: Vec<String>,
+
+        #[schema(default = "true")]
+        #[serde(default = "default_foreground")]
+        pub foreground: bool,
+    }
+
+    #[inline]
+    fn default_foreground() -> bool {
+        true
     }
 
     #[derive(ToSchema, Serialize)]
@@ -20,13 +42,20 @@ mod post {
         deleted: usize,
     }
 
+    #[derive(ToSchema, Serialize)]
+    struct BackgroundResponse {
+        identifier: uuid::Uuid,
+    }
+
     #[utoipa::path(post, path = "/", responses(
         (status = OK, body = inline(Response)),
+        (status = ACCEPTED, body = inline(BackgroundResponse)),
         (status = NOT_FOUND, body = inline(ApiError)),
         (status = EXPECTATION_FAILED, body = inline(ApiError)),
     ), request_body = inline(Payload))]
     pub async fn route(
         server: GetServer,
+        permissions: GetPermissions,
         axum::Json(data): axum::Json<Payload>,
     ) -> (StatusCode, axum::Json<serde_json::Value>) {
         let root = match server.filesystem.canonicalize(data.root).await {
@@ -47,38 +76,86 @@ mod post {
             );
         }
 
-        let mut deleted_count = 0;
-        for file in data.files {
-            let destination = root.join(file);
-            if destination == root {
-                continue;
-            }
+        let mut operation = Operation::new(OperationKind::Delete, &root);
+        operation.abortable = false;
+        operation
+            .total
+            .store(data.files.len() as u64, Ordering::Relaxed);
+
+        let deleted_count = Arc::new(AtomicUsize::new(0));
+        let progress = Arc::clone(&operation.progress);
+        let cancelled = Arc::clone(&operation.cancelled);
+
+        operation.start(server.0.clone(), {
+            let server = server.clone();
+            let deleted_count = Arc::clone(&deleted_count);
+
+            async move {
+                for file in &data.files {
+                    if cancelled.load(Ordering::Relaxed) {
+                        break;
+                    }
 
-            if server
-                .filesystem
-                .is_ignored(
-                    &destination,
-                    server
+                    progress.fetch_add(1, Ordering::Relaxed);
+
+                    let destination = root.join(file);
+                    if destination == root {
+                        continue;
+                    }
+
+                    if server
                         .filesystem
-                        .metadata(&destination)
+                        .is_ignored(
+                            &destination,
+                            server
+                                .filesystem
+                                .metadata(&destination)
+                                .await
+                                .is_ok_and(|m| m.is_dir()),
+                        )
                         .await
-                        .is_ok_and(|m| m.is_dir()),
-                )
-                .await
-            {
-                continue;
-            }
+                        || server
+                            .filesystem
+                            .is_outside_grants(
+                                &destination,
+                                permissions.as_ref(),
+                                Permission::FileDelete,
+                            )
+                            .await
+                    {
+                        continue;
+                    }
 
-            if server.filesystem.truncate_path(&destination).await.is_ok() {
-                deleted_count += 1;
+                    if server.filesystem.trash_path(&destination).await.is_ok() {
+                        deleted_count.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+
+                Ok(())
             }
+        });
+
+        let operation = server.filesystem.add_operation(operation).await;
+
+        if !data.foreground {
+            return (
+                StatusCode::ACCEPTED,
+                axum::Json(
+                    serde_json::to_value(&BackgroundResponse {
+                        identifier: operation.identifier,
+                    })
+                    .unwrap(),
+                ),
+            );
         }
 
+        operation.wait().await;
+
         (
             StatusCode::OK,
             axum::Json(
                 serde_json::to_value(&Response {
-                    deleted: deleted_count,
+                    deleted: deleted_count.load(Ordering::Relaxed),
                 })
                 .unwrap(),
             ),
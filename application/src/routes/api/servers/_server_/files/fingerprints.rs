@@ -2,7 +2,10 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod get {
-    use crate::routes::api::servers::_server_::GetServer;
+    use crate::{
+        routes::api::servers::_server_::{GetPermissions, GetServer},
+        server::permissions::Permission,
+    };
     use axum_extra::extract::Query;
     use serde::{Deserialize, Serialize};
     use sha1::Digest;
@@ -49,6 +52,7 @@ mod get {
     ))]
     pub async fn route(
         server: GetServer,
+        permissions: GetPermissions,
         Query(data): Query<Params>,
     ) -> axum::Json<serde_json::Value> {
         let mut fingerprint_handles = Vec::new();
@@ -62,7 +66,13 @@ mod get {
                 Err(_) => continue,
             };
 
-            if !metadata.is_file() || server.filesystem.is_ignored(&path, metadata.is_dir()).await {
+            if !metadata.is_file()
+                || server.filesystem.is_ignored(&path, metadata.is_dir()).await
+                || server
+                    .filesystem
+                    .is_outside_grants(&path, permissions.as_ref(), Permission::FileReadContent)
+                    .await
+            {
                 continue;
             }
 
@@ -2,7 +2,10 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod get {
-    use crate::routes::{ApiError, GetState, api::servers::_server_::GetServer};
+    use crate::routes::{
+        ApiError, GetState,
+        api::servers::_server_::{GetPermissions, GetServer},
+    };
     use axum::{extract::Query, http::StatusCode};
     use serde::Deserialize;
     use std::path::PathBuf;
@@ -27,6 +30,7 @@ mod get {
     pub async fn route(
         state: GetState,
         server: GetServer,
+        permissions: GetPermissions,
         Query(data): Query<Params>,
     ) -> (StatusCode, axum::Json<serde_json::Value>) {
         let mut entries = Vec::new();
@@ -36,15 +40,29 @@ mod get {
             Err(_) => PathBuf::from(data.directory),
         };
 
-        if let Some((backup, path)) = server.filesystem.backup_fs(&server, &path).await {
-            match crate::server::filesystem::backup::list(backup, &server, &path).await {
+        if server
+            .filesystem
+            .is_outside_view(&path, permissions.as_ref())
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("path not found").to_json()),
+            );
+        }
+
+        if let Some((backup, backup_path)) = server.filesystem.backup_fs(&server, &path).await {
+            match crate::server::filesystem::backup::list(backup, &server, &backup_path).await {
                 Ok(entries_list) => {
-                    entries.extend(entries_list);
+                    entries.extend(entries_list.into_iter().filter(|entry| {
+                        !server
+                            .filesystem
+                            .is_outside_view(&path.join(&entry.name), permissions.as_ref())
+                    }));
                 }
                 Err(err) => {
                     tracing::error!(
                         server = %server.uuid,
-                        path = %path.display(),
+                        path = %backup_path.display(),
                         error = %err,
                         "failed to list backup directory",
                     );
@@ -95,7 +113,11 @@ mod get {
                 Err(_) => continue,
             };
 
-            if server.filesystem.is_ignored(&path, metadata.is_dir()).await {
+            if server.filesystem.is_ignored(&path, metadata.is_dir()).await
+                || server
+                    .filesystem
+                    .is_outside_view(&path, permissions.as_ref())
+            {
                 continue;
             }
 
@@ -1,18 +1,25 @@
 use super::State;
 use utoipa_axum::router::OpenApiRouter;
 
+mod archive;
 mod chmod;
 mod compress;
 mod contents;
 mod copy;
+mod copy_tree;
 mod create_directory;
 mod decompress;
 mod delete;
 mod fingerprints;
 mod list_directory;
+mod nbt;
+mod operations;
 mod pull;
+mod region;
 mod rename;
 mod search;
+mod trash;
+mod usage;
 mod write;
 
 pub fn router(state: &State) -> OpenApiRouter<State> {
@@ -21,6 +28,7 @@ pub fn router(state: &State) -> OpenApiRouter<State> {
         .nest("/list-directory", list_directory::router(state))
         .nest("/rename", rename::router(state))
         .nest("/copy", copy::router(state))
+        .nest("/copy-tree", copy_tree::router(state))
         .nest("/write", write::router(state))
         .nest("/create-directory", create_directory::router(state))
         .nest("/delete", delete::router(state))
@@ -28,7 +36,13 @@ pub fn router(state: &State) -> OpenApiRouter<State> {
         .nest("/search", search::router(state))
         .nest("/fingerprints", fingerprints::router(state))
         .nest("/pull", pull::router(state))
+        .nest("/operations", operations::router(state))
         .nest("/compress", compress::router(state))
         .nest("/decompress", decompress::router(state))
+        .nest("/archive", archive::router(state))
+        .nest("/nbt", nbt::router(state))
+        .nest("/region", region::router(state))
+        .nest("/trash", trash::router(state))
+        .nest("/usage", usage::router(state))
         .with_state(state.clone())
 }
@@ -0,0 +1,311 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod get {
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::{
+            filesystem::nbt::{Nbt, NbtCompression, Tag},
+            permissions::Permission,
+        },
+    };
+    use axum::{
+        extract::Query,
+        http::{HeaderMap, StatusCode},
+    };
+    use serde::{Deserialize, Serialize};
+    use tokio::io::AsyncReadExt;
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Params {
+        file: String,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        name: String,
+        compression: NbtCompression,
+        #[schema(value_type = Object)]
+        tag: Tag,
+    }
+
+    #[utoipa::path(get, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), params(
+        (
+            "file" = String, Query,
+            description = "The NBT file to decode",
+        ),
+    ))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        Query(data): Query<Params>,
+    ) -> (StatusCode, HeaderMap, axum::Json<serde_json::Value>) {
+        let path = match server.filesystem.canonicalize(&data.file).await {
+            Ok(path) => path,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    HeaderMap::new(),
+                    axum::Json(ApiError::new("file not found").to_json()),
+                );
+            }
+        };
+
+        let metadata = match server.filesystem.metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    HeaderMap::new(),
+                    axum::Json(ApiError::new("file not found").to_json()),
+                );
+            }
+        };
+
+        if server.filesystem.is_ignored(&path, false).await
+            || server
+                .filesystem
+                .is_outside_grants(&path, permissions.as_ref(), Permission::FileReadContent)
+                .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                HeaderMap::new(),
+                axum::Json(ApiError::new("file not found").to_json()),
+            );
+        }
+
+        let mut content = Vec::new();
+        match server.filesystem.open(&path).await {
+            Ok(mut file) => {
+                if file.read_to_end(&mut content).await.is_err() {
+                    return (
+                        StatusCode::EXPECTATION_FAILED,
+                        HeaderMap::new(),
+                        axum::Json(ApiError::new("unable to open file for reading").to_json()),
+                    );
+                }
+            }
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    HeaderMap::new(),
+                    axum::Json(ApiError::new("file not found").to_json()),
+                );
+            }
+        }
+
+        let compression = NbtCompression::detect(&content);
+        let nbt = match compression
+            .decompress(&content)
+            .and_then(|content| Nbt::parse(&content))
+        {
+            Ok(nbt) => nbt,
+            Err(err) => {
+                tracing::debug!(
+                    server = %server.uuid,
+                    path = %path.display(),
+                    "failed to decode nbt file: {:#?}",
+                    err
+                );
+
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    HeaderMap::new(),
+                    axum::Json(ApiError::new("file is not a valid nbt file").to_json()),
+                );
+            }
+        };
+
+        let mut headers = HeaderMap::new();
+        crate::routes::conditional::insert_headers(&mut headers, &metadata, false);
+
+        (
+            StatusCode::OK,
+            headers,
+            axum::Json(
+                serde_json::to_value(&Response {
+                    name: nbt.name,
+                    compression,
+                    tag: nbt.tag,
+                })
+                .unwrap(),
+            ),
+        )
+    }
+}
+
+mod post {
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::{
+            filesystem::nbt::{Nbt, NbtCompression, Tag},
+            permissions::Permission,
+        },
+    };
+    use axum::http::{HeaderMap, StatusCode};
+    use serde::{Deserialize, Serialize};
+    use std::path::PathBuf;
+    use tokio::io::AsyncReadExt;
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Payload {
+        file: String,
+
+        #[serde(default)]
+        name: String,
+        /// defaults to the compression of the existing file, or gzip for new files
+        compression: Option<NbtCompression>,
+        #[schema(value_type = Object)]
+        tag: Tag,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {}
+
+    #[utoipa::path(post, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = BAD_REQUEST, body = inline(ApiError)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = PRECONDITION_FAILED, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), request_body = inline(Payload))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        headers: HeaderMap,
+        axum::Json(data): axum::Json<Payload>,
+    ) -> (StatusCode, HeaderMap, axum::Json<serde_json::Value>) {
+        let path = match server.filesystem.canonicalize(&data.file).await {
+            Ok(path) => path,
+            Err(_) => PathBuf::from(data.file),
+        };
+
+        let metadata = server.filesystem.metadata(&path).await;
+
+        if server.filesystem.is_ignored(&path, false).await
+            || metadata.as_ref().is_ok_and(|m| !m.is_file())
+            || server
+                .filesystem
+                .is_outside_grants(
+                    &path,
+                    permissions.as_ref(),
+                    if metadata.is_ok() {
+                        Permission::FileUpdate
+                    } else {
+                        Permission::FileCreate
+                    },
+                )
+                .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                HeaderMap::new(),
+                axum::Json(ApiError::new("file not found").to_json()),
+            );
+        }
+
+        if !crate::routes::conditional::matches_precondition(&headers, metadata.as_ref().ok()) {
+            return (
+                StatusCode::PRECONDITION_FAILED,
+                HeaderMap::new(),
+                axum::Json(ApiError::new("file has been modified").to_json()),
+            );
+        }
+
+        let compression = match data.compression {
+            Some(compression) => compression,
+            None => {
+                let mut header = [0; 2];
+                match server.filesystem.open(&path).await {
+                    Ok(mut file) => match file.read_exact(&mut header).await {
+                        Ok(_) => NbtCompression::detect(&header),
+                        Err(_) => NbtCompression::Gzip,
+                    },
+                    Err(_) => NbtCompression::Gzip,
+                }
+            }
+        };
+
+        let content = match (Nbt {
+            name: data.name,
+            tag: data.tag,
+        })
+        .to_bytes()
+        .and_then(|content| compression.compress(&content))
+        {
+            Ok(content) => content,
+            Err(err) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    HeaderMap::new(),
+                    axum::Json(ApiError::new(&err.to_string()).to_json()),
+                );
+            }
+        };
+
+        let old_content_size = metadata.as_ref().map(|m| m.len() as i64).unwrap_or(0);
+
+        let parent = path.parent().unwrap();
+        server.filesystem.create_dir_all(parent).await.unwrap();
+
+        if !server
+            .filesystem
+            .allocate_in_path(parent, content.len() as i64 - old_content_size)
+            .await
+        {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                HeaderMap::new(),
+                axum::Json(ApiError::new("failed to allocate space").to_json()),
+            );
+        }
+
+        if let Err(err) = server.filesystem.write(&path, content).await {
+            tracing::error!(
+                server = %server.uuid,
+                path = %path.display(),
+                "failed to write nbt file: {:#?}",
+                err
+            );
+
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                HeaderMap::new(),
+                axum::Json(ApiError::new("failed to write file").to_json()),
+            );
+        }
+
+        server.filesystem.chown_path(&path).await;
+
+        let mut response_headers = HeaderMap::new();
+        if let Ok(metadata) = server.filesystem.metadata(&path).await {
+            crate::routes::conditional::insert_headers(&mut response_headers, &metadata, false);
+        }
+
+        (
+            StatusCode::OK,
+            response_headers,
+            axum::Json(serde_json::to_value(&Response {}).unwrap()),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(get::route))
+        .routes(routes!(post::route))
+        .with_state(state.clone())
+}
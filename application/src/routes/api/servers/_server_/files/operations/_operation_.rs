@@ -0,0 +1,54 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod delete {
+    use crate::routes::{
+        ApiError,
+        api::servers::_server_::{GetPermissions, GetServer},
+    };
+    use axum::{extract::Path, http::StatusCode};
+    use serde::Serialize;
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {}
+
+    #[utoipa::path(delete, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+    ))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        Path((_server, operation_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+        let is_hidden = server
+            .filesystem
+            .operations()
+            .await
+            .get(&operation_id)
+            .is_some_and(|operation| {
+                server
+                    .filesystem
+                    .is_outside_view(&operation.path, permissions.as_ref())
+            });
+
+        if is_hidden || !server.filesystem.cancel_operation(operation_id, None).await {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("operation not found").to_json()),
+            );
+        }
+
+        (
+            StatusCode::OK,
+            axum::Json(serde_json::to_value(&Response {}).unwrap()),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(delete::route))
+        .with_state(state.clone())
+}
@@ -0,0 +1,46 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod _operation_;
+
+mod get {
+    use crate::routes::api::servers::_server_::{GetPermissions, GetServer};
+    use serde::Serialize;
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        operations: Vec<crate::models::Operation>,
+    }
+
+    #[utoipa::path(get, path = "/", responses(
+        (status = OK, body = inline(Response)),
+    ))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+    ) -> axum::Json<serde_json::Value> {
+        let mut operations = server
+            .filesystem
+            .operations()
+            .await
+            .values()
+            .filter(|operation| {
+                !server
+                    .filesystem
+                    .is_outside_view(&operation.path, permissions.as_ref())
+            })
+            .map(|operation| operation.to_api_response())
+            .collect::<Vec<_>>();
+        operations.sort_unstable_by_key(|operation| operation.started);
+
+        axum::Json(serde_json::to_value(&Response { operations }).unwrap())
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .nest("/{operation}", _operation_::router(state))
+        .routes(routes!(get::route))
+        .with_state(state.clone())
+}
@@ -2,7 +2,13 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod delete {
-    use crate::routes::{ApiError, api::servers::_server_::GetServer};
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::filesystem::operations::OperationKind,
+    };
     use axum::{extract::Path, http::StatusCode};
     use serde::Serialize;
     use utoipa::ToSchema;
@@ -16,25 +22,32 @@ mod delete {
     ))]
     pub async fn route(
         server: GetServer,
+        permissions: GetPermissions,
         Path((_server, pull_id)): Path<(uuid::Uuid, uuid::Uuid)>,
     ) -> (StatusCode, axum::Json<serde_json::Value>) {
-        let pull = server.filesystem.pulls().await;
-        let pull = match pull.iter().find(|p| *p.0 == pull_id) {
-            Some(pull) => pull.1,
-            None => {
-                return (
-                    StatusCode::NOT_FOUND,
-                    axum::Json(ApiError::new("pull not found").to_json()),
-                );
-            }
-        };
+        let is_hidden = server
+            .filesystem
+            .operations()
+            .await
+            .get(&pull_id)
+            .is_some_and(|operation| {
+                server
+                    .filesystem
+                    .is_outside_view(&operation.path, permissions.as_ref())
+            });
 
-        if let Some(download) = pull.write().await.task.take() {
-            download.abort();
+        if is_hidden
+            || !server
+                .filesystem
+                .cancel_operation(pull_id, Some(OperationKind::Pull))
+                .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("pull not found").to_json()),
+            );
         }
 
-        server.filesystem.pulls.write().await.remove(&pull_id);
-
         (
             StatusCode::OK,
             axum::Json(serde_json::to_value(&Response {}).unwrap()),
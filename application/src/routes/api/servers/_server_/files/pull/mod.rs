@@ -4,7 +4,9 @@ use utoipa_axum::{router::OpenApiRouter, routes};
 mod _pull_;
 
 mod get {
-    use crate::routes::api::servers::_server_::GetServer;
+    use crate::{
+        routes::api::servers::_server_::GetServer, server::filesystem::operations::OperationKind,
+    };
     use serde::Serialize;
     use utoipa::ToSchema;
 
@@ -17,22 +19,40 @@ mod get {
         (status = OK, body = inline(Response)),
     ))]
     pub async fn route(server: GetServer) -> axum::Json<serde_json::Value> {
-        let mut downloads = Vec::new();
-
-        for download in server.filesystem.pulls().await.values() {
-            downloads.push(download.read().await.to_api_response());
-        }
+        let downloads = server
+            .filesystem
+            .operations()
+            .await
+            .values()
+            .filter(|operation| operation.kind == OperationKind::Pull)
+            .map(|operation| {
+                let operation = operation.to_api_response();
+
+                crate::models::Download {
+                    identifier: operation.identifier,
+                    progress: operation.progress,
+                    total: operation.total,
+                }
+            })
+            .collect();
 
         axum::Json(serde_json::to_value(&Response { downloads }).unwrap())
     }
 }
 
 mod post {
-    use crate::routes::{ApiError, GetState, api::servers::_server_::GetServer};
+    use crate::{
+        routes::{
+            ApiError, GetState,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::{
+            filesystem::{operations::OperationKind, pull::HashAlgorithm},
+            permissions::Permission,
+        },
+    };
     use axum::http::StatusCode;
     use serde::{Deserialize, Serialize};
-    use std::sync::Arc;
-    use tokio::sync::RwLock;
     use utoipa::ToSchema;
 
     #[derive(ToSchema, Deserialize)]
@@ -43,6 +63,10 @@ mod post {
         url: String,
         file_name: Option<String>,
 
+        hash: Option<String>,
+        #[serde(default)]
+        hash_algorithm: HashAlgorithm,
+
         #[serde(default)]
         use_header: bool,
         #[serde(default)]
@@ -61,6 +85,7 @@ mod post {
     pub async fn route(
         state: GetState,
         server: GetServer,
+        permissions: GetPermissions,
         axum::Json(data): axum::Json<Payload>,
     ) -> (StatusCode, axum::Json<serde_json::Value>) {
         let path = match server.filesystem.canonicalize(data.root).await {
@@ -105,7 +130,12 @@ mod post {
             );
         }
 
-        if server.filesystem.pulls().await.len() >= 3 {
+        if server
+            .filesystem
+            .count_operations(OperationKind::Pull)
+            .await
+            >= 3
+        {
             return (
                 StatusCode::EXPECTATION_FAILED,
                 axum::Json(ApiError::new("too many concurrent downloads").to_json()),
@@ -113,39 +143,46 @@ mod post {
         }
 
         tokio::fs::create_dir_all(&path).await.unwrap();
-        let download = Arc::new(RwLock::new(
-            crate::server::filesystem::pull::Download::new(
-                server.0.clone(),
-                &path,
-                data.file_name,
-                data.url,
-                data.use_header,
-            )
-            .await
-            .unwrap(),
-        ));
-
-        let identifier = download.read().await.identifier;
+        let download = match crate::server::filesystem::pull::Download::new(
+            server.0.clone(),
+            &state.config.api.remote_download,
+            &path,
+            data.file_name,
+            data.url,
+            data.use_header,
+            data.hash.map(|hash| (data.hash_algorithm, hash)),
+        )
+        .await
+        {
+            Ok(download) => download,
+            Err(err) => {
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    axum::Json(ApiError::new(&err.to_string()).to_json()),
+                );
+            }
+        };
 
-        server
+        if server
             .filesystem
-            .pulls
-            .write()
+            .is_outside_grants(
+                &download.destination,
+                permissions.as_ref(),
+                Permission::FileCreate,
+            )
             .await
-            .insert(identifier, Arc::clone(&download));
+        {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("destination is not permitted").to_json()),
+            );
+        }
 
-        download.write().await.start();
+        let operation = server.filesystem.add_operation(download.start()).await;
+        let identifier = operation.identifier;
 
         if data.foreground {
-            while download
-                .read()
-                .await
-                .task
-                .as_ref()
-                .is_some_and(|t| !t.is_finished())
-            {
-                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-            }
+            operation.wait().await;
         }
 
         (
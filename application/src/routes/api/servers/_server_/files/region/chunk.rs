@@ -0,0 +1,310 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod get {
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::filesystem::{
+            nbt::{NbtCompression, Tag},
+            region::Region,
+        },
+    };
+    use axum::{extract::Query, http::StatusCode};
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Params {
+        file: String,
+
+        x: i32,
+        z: i32,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        name: String,
+        compression: NbtCompression,
+        #[schema(value_type = Object)]
+        tag: Tag,
+    }
+
+    #[utoipa::path(get, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), params(
+        (
+            "file" = String, Query,
+            description = "The region file to read the chunk from",
+        ),
+        (
+            "x" = i32, Query,
+            description = "The chunk x coordinate",
+        ),
+        (
+            "z" = i32, Query,
+            description = "The chunk z coordinate",
+        ),
+    ))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        Query(data): Query<Params>,
+    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+        let path = match server.filesystem.canonicalize(&data.file).await {
+            Ok(path) => path,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(ApiError::new("file not found").to_json()),
+                );
+            }
+        };
+
+        let metadata = server.filesystem.metadata(&path).await;
+        if !metadata.as_ref().is_ok_and(|m| m.is_file())
+            || server.filesystem.is_ignored(&path, false).await
+            || server
+                .filesystem
+                .is_outside_grants(
+                    &path,
+                    permissions.as_ref(),
+                    crate::server::permissions::Permission::FileReadContent,
+                )
+                .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("file not found").to_json()),
+            );
+        }
+
+        let region = match server.filesystem.open(&path).await {
+            Ok(file) => Region::open(file, &path).await,
+            Err(err) => Err(err),
+        };
+        let mut region = match region {
+            Ok(region) => region,
+            Err(err) => {
+                tracing::debug!(
+                    server = %server.uuid,
+                    path = %path.display(),
+                    "failed to open region file: {:#?}",
+                    err
+                );
+
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    axum::Json(ApiError::new("file is not a valid region file").to_json()),
+                );
+            }
+        };
+
+        if !region.contains(data.x, data.z) {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("chunk not found").to_json()),
+            );
+        }
+
+        match region.read_chunk(data.x, data.z).await {
+            Ok(Some((compression, nbt))) => (
+                StatusCode::OK,
+                axum::Json(
+                    serde_json::to_value(&Response {
+                        name: nbt.name,
+                        compression,
+                        tag: nbt.tag,
+                    })
+                    .unwrap(),
+                ),
+            ),
+            Ok(None) => (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("chunk not found").to_json()),
+            ),
+            Err(err) => {
+                tracing::debug!(
+                    server = %server.uuid,
+                    path = %path.display(),
+                    "failed to read region chunk: {:#?}",
+                    err
+                );
+
+                (
+                    StatusCode::EXPECTATION_FAILED,
+                    axum::Json(ApiError::new("failed to read chunk").to_json()),
+                )
+            }
+        }
+    }
+}
+
+mod post {
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::filesystem::{
+            nbt::{Nbt, NbtCompression, Tag},
+            region::Region,
+        },
+    };
+    use axum::http::StatusCode;
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Payload {
+        file: String,
+
+        x: i32,
+        z: i32,
+
+        #[serde(default)]
+        name: String,
+        /// defaults to the compression of the existing chunk, or zlib for new chunks
+        compression: Option<NbtCompression>,
+        #[schema(value_type = Object)]
+        tag: Tag,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {}
+
+    #[utoipa::path(post, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = BAD_REQUEST, body = inline(ApiError)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), request_body = inline(Payload))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        axum::Json(data): axum::Json<Payload>,
+    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+        let path = match server.filesystem.canonicalize(&data.file).await {
+            Ok(path) => path,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(ApiError::new("file not found").to_json()),
+                );
+            }
+        };
+
+        let metadata = server.filesystem.metadata(&path).await;
+        if !metadata.as_ref().is_ok_and(|m| m.is_file())
+            || server.filesystem.is_ignored(&path, false).await
+            || server
+                .filesystem
+                .is_outside_grants(
+                    &path,
+                    permissions.as_ref(),
+                    crate::server::permissions::Permission::FileUpdate,
+                )
+                .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("file not found").to_json()),
+            );
+        }
+
+        let region = match server.filesystem.open_read_write(&path).await {
+            Ok(file) => Region::open(file, &path).await,
+            Err(err) => Err(err),
+        };
+        let mut region = match region {
+            Ok(region) => region,
+            Err(err) => {
+                tracing::debug!(
+                    server = %server.uuid,
+                    path = %path.display(),
+                    "failed to open region file: {:#?}",
+                    err
+                );
+
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    axum::Json(ApiError::new("file is not a valid region file").to_json()),
+                );
+            }
+        };
+
+        if !region.contains(data.x, data.z) {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("chunk not found").to_json()),
+            );
+        }
+
+        let compression = match data.compression {
+            Some(compression) => compression,
+            None => match region.read_chunk(data.x, data.z).await {
+                Ok(Some((compression, _))) => compression,
+                _ => NbtCompression::Zlib,
+            },
+        };
+
+        let content = match Region::encode_chunk(
+            compression,
+            &Nbt {
+                name: data.name,
+                tag: data.tag,
+            },
+        ) {
+            Ok(content) => content,
+            Err(err) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    axum::Json(ApiError::new(&err.to_string()).to_json()),
+                );
+            }
+        };
+
+        let (_, growth) = region.placement(data.x, data.z, content.len());
+        if !server
+            .filesystem
+            .allocate_in_path(path.parent().unwrap(), growth as i64)
+            .await
+        {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("failed to allocate space").to_json()),
+            );
+        }
+
+        if let Err(err) = region.write_chunk(data.x, data.z, &content).await {
+            tracing::error!(
+                server = %server.uuid,
+                path = %path.display(),
+                "failed to write region chunk: {:#?}",
+                err
+            );
+
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("failed to write chunk").to_json()),
+            );
+        }
+        region.sync().await.ok();
+
+        (
+            StatusCode::OK,
+            axum::Json(serde_json::to_value(&Response {}).unwrap()),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(get::route))
+        .routes(routes!(post::route))
+        .with_state(state.clone())
+}
@@ -0,0 +1,232 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod get {
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::filesystem::region::Region,
+    };
+    use axum::{extract::Query, http::StatusCode};
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Params {
+        file: String,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        chunks: Vec<crate::models::RegionChunk>,
+    }
+
+    #[utoipa::path(get, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), params(
+        (
+            "file" = String, Query,
+            description = "The region file to list the chunks of",
+        ),
+    ))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        Query(data): Query<Params>,
+    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+        let path = match server.filesystem.canonicalize(&data.file).await {
+            Ok(path) => path,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(ApiError::new("file not found").to_json()),
+                );
+            }
+        };
+
+        let metadata = server.filesystem.metadata(&path).await;
+        if !metadata.as_ref().is_ok_and(|m| m.is_file())
+            || server.filesystem.is_ignored(&path, false).await
+            || server
+                .filesystem
+                .is_outside_grants(
+                    &path,
+                    permissions.as_ref(),
+                    crate::server::permissions::Permission::FileReadContent,
+                )
+                .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("file not found").to_json()),
+            );
+        }
+
+        let region = match server.filesystem.open(&path).await {
+            Ok(file) => Region::open(file, &path).await,
+            Err(err) => Err(err),
+        };
+        let region = match region {
+            Ok(region) => region,
+            Err(err) => {
+                tracing::debug!(
+                    server = %server.uuid,
+                    path = %path.display(),
+                    "failed to open region file: {:#?}",
+                    err
+                );
+
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    axum::Json(ApiError::new("file is not a valid region file").to_json()),
+                );
+            }
+        };
+
+        (
+            StatusCode::OK,
+            axum::Json(
+                serde_json::to_value(&Response {
+                    chunks: region
+                        .chunks()
+                        .iter()
+                        .map(|chunk| chunk.to_api_response())
+                        .collect(),
+                })
+                .unwrap(),
+            ),
+        )
+    }
+}
+
+mod delete {
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::filesystem::region::Region,
+    };
+    use axum::http::StatusCode;
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Chunk {
+        x: i32,
+        z: i32,
+    }
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Payload {
+        file: String,
+
+        chunks: Vec<Chunk>,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        deleted: usize,
+    }
+
+    #[utoipa::path(delete, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), request_body = inline(Payload))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        axum::Json(data): axum::Json<Payload>,
+    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+        let path = match server.filesystem.canonicalize(&data.file).await {
+            Ok(path) => path,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(ApiError::new("file not found").to_json()),
+                );
+            }
+        };
+
+        let metadata = server.filesystem.metadata(&path).await;
+        if !metadata.as_ref().is_ok_and(|m| m.is_file())
+            || server.filesystem.is_ignored(&path, false).await
+            || server
+                .filesystem
+                .is_outside_grants(
+                    &path,
+                    permissions.as_ref(),
+                    crate::server::permissions::Permission::FileUpdate,
+                )
+                .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("file not found").to_json()),
+            );
+        }
+
+        let region = match server.filesystem.open_read_write(&path).await {
+            Ok(file) => Region::open(file, &path).await,
+            Err(err) => Err(err),
+        };
+        let mut region = match region {
+            Ok(region) => region,
+            Err(err) => {
+                tracing::debug!(
+                    server = %server.uuid,
+                    path = %path.display(),
+                    "failed to open region file: {:#?}",
+                    err
+                );
+
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    axum::Json(ApiError::new("file is not a valid region file").to_json()),
+                );
+            }
+        };
+
+        let chunks = data
+            .chunks
+            .iter()
+            .filter(|chunk| region.contains(chunk.x, chunk.z))
+            .map(|chunk| (chunk.x, chunk.z))
+            .collect::<Vec<_>>();
+
+        let deleted = match region.delete_chunks(&chunks).await {
+            Ok(deleted) => deleted,
+            Err(err) => {
+                tracing::error!(
+                    server = %server.uuid,
+                    path = %path.display(),
+                    "failed to delete region chunks: {:#?}",
+                    err
+                );
+
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    axum::Json(ApiError::new("failed to delete chunks").to_json()),
+                );
+            }
+        };
+        region.sync().await.ok();
+
+        (
+            StatusCode::OK,
+            axum::Json(serde_json::to_value(&Response { deleted }).unwrap()),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(get::route))
+        .routes(routes!(delete::route))
+        .with_state(state.clone())
+}
@@ -0,0 +1,12 @@
+use super::State;
+use utoipa_axum::router::OpenApiRouter;
+
+mod chunk;
+mod chunks;
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .nest("/chunks", chunks::router(state))
+        .nest("/chunk", chunk::router(state))
+        .with_state(state.clone())
+}
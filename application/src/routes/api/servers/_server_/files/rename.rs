@@ -2,7 +2,13 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod put {
-    use crate::routes::{ApiError, api::servers::_server_::GetServer};
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::permissions::Permission,
+    };
     use axum::http::StatusCode;
     use serde::{Deserialize, Serialize};
     use std::path::Path;
@@ -34,6 +40,7 @@ mod put {
     ), request_body = inline(Payload))]
     pub async fn route(
         server: GetServer,
+        permissions: GetPermissions,
         axum::Json(data): axum::Json<Payload>,
     ) -> (StatusCode, axum::Json<serde_json::Value>) {
         let root = Path::new(&data.root);
@@ -47,13 +54,13 @@ mod put {
         }
 
         let mut renamed_count = 0;
-        for file in data.files {
-            let from = root.join(file.from);
+        for file in &data.files {
+            let from = root.join(&file.from);
             if from == root {
                 continue;
             }
 
-            let to = root.join(file.to);
+            let to = root.join(&file.to);
             if to == root {
                 continue;
             }
@@ -76,6 +83,14 @@ mod put {
                     .filesystem
                     .is_ignored(&to, from_metadata.is_dir())
                     .await
+                || server
+                    .filesystem
+                    .is_outside_grants(&from, permissions.as_ref(), Permission::FileUpdate)
+                    .await
+                || server
+                    .filesystem
+                    .is_outside_grants(&to, permissions.as_ref(), Permission::FileUpdate)
+                    .await
             {
                 continue;
             }
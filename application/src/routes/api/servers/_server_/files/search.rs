@@ -2,18 +2,37 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod post {
-    use crate::routes::{ApiError, GetState, api::servers::_server_::GetServer};
-    use axum::http::StatusCode;
-    use ignore::{WalkBuilder, WalkState};
+    use crate::{
+        routes::{
+            ApiError, GetState,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::permissions::Permission,
+    };
+    use axum::{
+        body::Body,
+        http::{HeaderMap, StatusCode},
+    };
+    use futures::StreamExt;
+    use ignore::{WalkBuilder, WalkState, overrides::OverrideBuilder};
     use serde::{Deserialize, Serialize};
     use std::{
+        collections::VecDeque,
         fs::File,
-        io::Read,
-        path::PathBuf,
-        sync::{Arc, Mutex},
+        io::{BufRead, BufReader, Read},
+        path::{Path, PathBuf},
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
     };
     use utoipa::ToSchema;
 
+    const MAX_CONTENT_MATCHES: usize = 50;
+    const MAX_CONTEXT_LINES: usize = 10;
+    const MAX_LINE_LENGTH: usize = 512;
+    const MAX_CONTENT_SIZE: u64 = 16 * 1024 * 1024;
+
     #[derive(ToSchema, Deserialize)]
     pub struct Payload {
         #[serde(default)]
@@ -22,37 +41,185 @@ mod post {
         #[serde(default)]
         pub include_content: bool,
 
+        #[serde(default)]
+        pub regex: bool,
+        #[serde(default)]
+        pub case_insensitive: bool,
+
+        #[serde(default)]
+        pub include: Vec<String>,
+        #[serde(default)]
+        pub exclude: Vec<String>,
+
+        pub modified_after: Option<chrono::DateTime<chrono::Utc>>,
+        pub modified_before: Option<chrono::DateTime<chrono::Utc>>,
+        pub min_file_size: Option<u64>,
+        pub max_file_size: Option<u64>,
+
+        #[serde(default)]
+        pub context_lines: usize,
+        #[serde(default)]
+        pub stream: bool,
+
         pub limit: Option<usize>,
         pub max_size: Option<u64>,
     }
 
     #[derive(ToSchema, Serialize)]
-    struct Response<'a> {
-        results: &'a [crate::models::DirectoryEntry],
+    struct ContentMatch {
+        line: usize,
+        content: String,
+
+        before: Vec<String>,
+        after: Vec<String>,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct SearchResult {
+        #[serde(flatten)]
+        entry: crate::models::DirectoryEntry,
+
+        matches: Vec<ContentMatch>,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        results: Vec<SearchResult>,
+    }
+
+    #[inline]
+    fn truncate_line(line: &str) -> String {
+        match line.char_indices().nth(MAX_LINE_LENGTH) {
+            Some((index, _)) => line[..index].to_string(),
+            None => line.to_string(),
+        }
+    }
+
+    /// Scans a file line by line, only the last `context_lines` lines are kept
+    /// around for the context before a match
+    fn content_matches(
+        path: &Path,
+        matcher: &regex::Regex,
+        max_size: u64,
+        context_lines: usize,
+    ) -> Option<(Vec<ContentMatch>, Vec<u8>)> {
+        let mut reader = BufReader::new(File::open(path).ok()?.take(max_size));
+
+        let buffer = reader.fill_buf().ok()?;
+        let buffer = buffer[..buffer.len().min(128)].to_vec();
+        if std::str::from_utf8(&buffer).is_err() {
+            return None;
+        }
+
+        let mut matches: Vec<ContentMatch> = Vec::new();
+        let mut before = VecDeque::with_capacity(context_lines);
+        let mut line = Vec::new();
+        let mut number = 0;
+
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            number += 1;
+
+            let text = String::from_utf8_lossy(&line);
+            let text = text.strip_suffix('\n').unwrap_or(&text);
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            let truncated = truncate_line(text);
+
+            for previous in matches.iter_mut().rev() {
+                if number - previous.line > context_lines {
+                    break;
+                }
+
+                previous.after.push(truncated.clone());
+            }
+
+            if matches.len() < MAX_CONTENT_MATCHES && matcher.is_match(text) {
+                matches.push(ContentMatch {
+                    line: number,
+                    content: truncated.clone(),
+                    before: before.iter().cloned().collect(),
+                    after: Vec::new(),
+                });
+            }
+
+            if matches.len() >= MAX_CONTENT_MATCHES
+                && matches
+                    .last()
+                    .is_some_and(|last| number - last.line >= context_lines)
+            {
+                break;
+            }
+
+            if context_lines > 0 {
+                if before.len() >= context_lines {
+                    before.pop_front();
+                }
+                before.push_back(truncated);
+            }
+        }
+
+        Some((matches, buffer))
     }
 
     #[utoipa::path(post, path = "/", responses(
         (status = OK, body = inline(Response)),
         (status = NOT_FOUND, body = inline(ApiError)),
+        (status = BAD_REQUEST, body = inline(ApiError)),
     ), request_body = inline(Payload))]
     pub async fn route(
         state: GetState,
         server: GetServer,
+        permissions: GetPermissions,
         axum::Json(data): axum::Json<Payload>,
-    ) -> (StatusCode, axum::Json<serde_json::Value>) {
-        let limit = data.limit.unwrap_or(100).min(500);
-        let max_size = data.max_size.unwrap_or(512 * 1024);
+    ) -> (StatusCode, HeaderMap, Body) {
+        let limit = match data.limit {
+            Some(limit) if data.stream => limit,
+            Some(limit) => limit.min(500),
+            None if data.stream => usize::MAX,
+            None => 100,
+        };
+        let max_size = data.max_size.unwrap_or(512 * 1024).min(MAX_CONTENT_SIZE);
+        let context_lines = data.context_lines.min(MAX_CONTEXT_LINES);
+
+        let json_headers = HeaderMap::from_iter([(
+            "Content-Type".parse().unwrap(),
+            "application/json".parse().unwrap(),
+        )]);
+
+        let matcher = match regex::RegexBuilder::new(&if data.regex {
+            data.query.clone()
+        } else {
+            regex::escape(&data.query)
+        })
+        .case_insensitive(data.case_insensitive)
+        .size_limit(1024 * 1024)
+        .build()
+        {
+            Ok(matcher) => matcher,
+            Err(_) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    json_headers,
+                    Body::from(serde_json::to_string(&ApiError::new("invalid query")).unwrap()),
+                );
+            }
+        };
 
         let root = match server
             .filesystem
-            .canonicalize(PathBuf::from(data.root))
+            .canonicalize(PathBuf::from(&data.root))
             .await
         {
             Ok(path) => path,
             Err(_) => {
                 return (
                     StatusCode::NOT_FOUND,
-                    axum::Json(ApiError::new("root not found").to_json()),
+                    json_headers,
+                    Body::from(serde_json::to_string(&ApiError::new("root not found")).unwrap()),
                 );
             }
         };
@@ -61,16 +228,58 @@ mod post {
         if !metadata.map(|m| m.is_dir()).unwrap_or(true) {
             return (
                 StatusCode::EXPECTATION_FAILED,
-                axum::Json(ApiError::new("root is not a directory").to_json()),
+                json_headers,
+                Body::from(
+                    serde_json::to_string(&ApiError::new("root is not a directory")).unwrap(),
+                ),
             );
         }
 
-        let results = Arc::new(Mutex::new(Vec::new()));
         let root = server.filesystem.base_path.join(&root);
 
+        let mut overrides = OverrideBuilder::new(&root);
+        for glob in &data.include {
+            if overrides.add(glob).is_err() {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    json_headers,
+                    Body::from(
+                        serde_json::to_string(&ApiError::new("invalid include glob")).unwrap(),
+                    ),
+                );
+            }
+        }
+        for glob in &data.exclude {
+            if overrides.add(&format!("!{glob}")).is_err() {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    json_headers,
+                    Body::from(
+                        serde_json::to_string(&ApiError::new("invalid exclude glob")).unwrap(),
+                    ),
+                );
+            }
+        }
+        let overrides = match overrides.build() {
+            Ok(overrides) => overrides,
+            Err(_) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    json_headers,
+                    Body::from(serde_json::to_string(&ApiError::new("invalid globs")).unwrap()),
+                );
+            }
+        };
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<SearchResult>(64);
+        let stream = data.stream;
+
         tokio::task::spawn_blocking({
-            let results = Arc::clone(&results);
             let runtime = tokio::runtime::Handle::current();
+            let count = Arc::new(AtomicUsize::new(0));
+            let matcher = Arc::new(matcher);
+            let data = Arc::new(data);
+            let permissions = Arc::new(permissions.0);
 
             move || {
                 WalkBuilder::new(&root)
@@ -79,12 +288,16 @@ mod post {
                     .ignore(false)
                     .git_exclude(false)
                     .follow_links(false)
+                    .overrides(overrides)
                     .threads(state.config.api.file_search_threads)
                     .build_parallel()
                     .run(move || {
                         let server = Arc::clone(&server);
-                        let results = Arc::clone(&results);
-                        let query = data.query.clone();
+                        let sender = sender.clone();
+                        let count = Arc::clone(&count);
+                        let matcher = Arc::clone(&matcher);
+                        let data = Arc::clone(&data);
+                        let permissions = Arc::clone(&permissions);
                         let root = root.clone();
                         let runtime = runtime.clone();
 
@@ -110,115 +323,121 @@ mod post {
                                 return WalkState::Continue;
                             }
 
-                            if path.to_str().is_some_and(|s| s.contains(&query)) {
-                                let mut buffer = [0; 128];
-                                let mut file = match File::open(path) {
-                                    Ok(file) => file,
-                                    Err(_) => return WalkState::Continue,
-                                };
-                                let bytes_read = match file.read(&mut buffer) {
-                                    Ok(bytes_read) => bytes_read,
-                                    Err(_) => return WalkState::Continue,
-                                };
-
-                                let mut results = results.lock().unwrap();
-                                if results.len() >= limit {
-                                    return WalkState::Quit;
-                                }
+                            let permission = if data.include_content {
+                                Permission::FileReadContent
+                            } else {
+                                Permission::FileRead
+                            };
 
-                                let mut entry =
-                                    runtime.block_on(server.filesystem.to_api_entry_buffer(
-                                        path.to_path_buf(),
-                                        &cap_std::fs::Metadata::from_just_metadata(metadata),
-                                        Some(&buffer[..bytes_read]),
-                                        None,
-                                        None,
-                                    ));
-                                entry.name = match path.strip_prefix(&root) {
-                                    Ok(path) => path.to_string_lossy().to_string(),
-                                    Err(_) => return WalkState::Continue,
-                                };
+                            if runtime.block_on(server.filesystem.is_outside_grants(
+                                path,
+                                permissions.as_ref().as_ref(),
+                                permission,
+                            )) {
+                                return WalkState::Continue;
+                            }
 
-                                results.push(entry);
+                            if data.min_file_size.is_some_and(|size| metadata.len() < size)
+                                || data.max_file_size.is_some_and(|size| metadata.len() > size)
+                            {
                                 return WalkState::Continue;
                             }
 
-                            if data.include_content && metadata.len() <= max_size {
-                                let mut buffer = [0; 8192];
-                                let mut file = match File::open(path) {
-                                    Ok(file) => file,
-                                    Err(_) => return WalkState::Continue,
-                                };
-                                let mut bytes_read = match file.read(&mut buffer) {
-                                    Ok(bytes_read) => bytes_read,
-                                    Err(_) => return WalkState::Continue,
-                                };
+                            if data.modified_after.is_some() || data.modified_before.is_some() {
+                                let modified: chrono::DateTime<chrono::Utc> =
+                                    match metadata.modified() {
+                                        Ok(modified) => modified.into(),
+                                        Err(_) => return WalkState::Continue,
+                                    };
 
-                                if std::str::from_utf8(&buffer[..bytes_read.min(128)]).is_err() {
+                                if data.modified_after.is_some_and(|after| modified < after)
+                                    || data.modified_before.is_some_and(|before| modified > before)
+                                {
                                     return WalkState::Continue;
                                 }
+                            }
 
-                                let mut last_content = String::with_capacity(8192 * 2);
-                                loop {
-                                    let content = String::from_utf8_lossy(&buffer[..bytes_read]);
-                                    last_content.push_str(&content);
-
-                                    if last_content.contains(&query) {
-                                        let mut results = results.lock().unwrap();
-                                        if results.len() >= limit {
-                                            return WalkState::Quit;
-                                        }
-
-                                        let mut entry = runtime.block_on(
-                                            server.filesystem.to_api_entry_buffer(
-                                                path.to_path_buf(),
-                                                &cap_std::fs::Metadata::from_just_metadata(
-                                                    metadata,
-                                                ),
-                                                Some(&buffer[..bytes_read]),
-                                                None,
-                                                None,
-                                            ),
-                                        );
-                                        entry.name = match path.strip_prefix(&root) {
-                                            Ok(path) => path.to_string_lossy().to_string(),
-                                            Err(_) => return WalkState::Continue,
-                                        };
-
-                                        results.push(entry);
-                                        break;
-                                    }
-
-                                    last_content.clear();
-                                    last_content.push_str(&content);
+                            let name = match path.strip_prefix(&root) {
+                                Ok(path) => path.to_string_lossy().to_string(),
+                                Err(_) => return WalkState::Continue,
+                            };
 
-                                    bytes_read = match file.read(&mut buffer) {
-                                        Ok(bytes_read) => bytes_read,
-                                        Err(_) => break,
-                                    };
+                            let (matches, buffer) = if matcher.is_match(&name) {
+                                let mut buffer = vec![0; 128];
+                                let bytes_read = match File::open(path)
+                                    .and_then(|mut file| file.read(&mut buffer))
+                                {
+                                    Ok(bytes_read) => bytes_read,
+                                    Err(_) => return WalkState::Continue,
+                                };
+                                buffer.truncate(bytes_read);
 
-                                    if bytes_read == 0 {
-                                        break;
+                                (Vec::new(), buffer)
+                            } else if data.include_content && metadata.len() <= max_size {
+                                match content_matches(path, &matcher, max_size, context_lines) {
+                                    Some((matches, buffer)) if !matches.is_empty() => {
+                                        (matches, buffer)
                                     }
+                                    _ => return WalkState::Continue,
                                 }
+                            } else {
+                                return WalkState::Continue;
+                            };
+
+                            if count.fetch_add(1, Ordering::Relaxed) >= limit {
+                                return WalkState::Quit;
+                            }
+
+                            let mut entry =
+                                runtime.block_on(server.filesystem.to_api_entry_buffer(
+                                    path.to_path_buf(),
+                                    &cap_std::fs::Metadata::from_just_metadata(metadata),
+                                    Some(&buffer),
+                                    None,
+                                    None,
+                                ));
+                            entry.name = name;
+
+                            if sender
+                                .blocking_send(SearchResult { entry, matches })
+                                .is_err()
+                            {
+                                return WalkState::Quit;
                             }
 
                             WalkState::Continue
                         })
                     });
             }
-        })
-        .await
-        .unwrap();
+        });
+
+        if stream {
+            let stream = futures::stream::poll_fn(move |cx| receiver.poll_recv(cx)).map(|result| {
+                let mut line = serde_json::to_vec(&result)?;
+                line.push(b'\n');
+
+                Ok::<_, serde_json::Error>(line)
+            });
+
+            return (
+                StatusCode::OK,
+                HeaderMap::from_iter([(
+                    "Content-Type".parse().unwrap(),
+                    "application/x-ndjson".parse().unwrap(),
+                )]),
+                Body::from_stream(stream),
+            );
+        }
+
+        let mut results = Vec::new();
+        while let Some(result) = receiver.recv().await {
+            results.push(result);
+        }
 
         (
             StatusCode::OK,
-            axum::Json(
-                serde_json::to_value(&Response {
-                    results: &results.lock().unwrap(),
-                })
-                .unwrap(),
-            ),
+            json_headers,
+            Body::from(serde_json::to_string(&Response { results }).unwrap()),
         )
     }
 }
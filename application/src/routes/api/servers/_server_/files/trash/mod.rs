@@ -0,0 +1,45 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod purge;
+mod restore;
+
+mod get {
+    use crate::routes::api::servers::_server_::{GetPermissions, GetServer};
+    use serde::Serialize;
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        entries: Vec<crate::models::TrashEntry>,
+    }
+
+    #[utoipa::path(get, path = "/", responses(
+        (status = OK, body = inline(Response)),
+    ))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+    ) -> axum::Json<serde_json::Value> {
+        let entries = crate::server::filesystem::trash::list(&server.filesystem)
+            .await
+            .iter()
+            .filter(|entry| {
+                !server
+                    .filesystem
+                    .is_outside_view(&entry.path, permissions.as_ref())
+            })
+            .map(|entry| entry.to_api_response())
+            .collect();
+
+        axum::Json(serde_json::to_value(&Response { entries }).unwrap())
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(get::route))
+        .nest("/restore", restore::router(state))
+        .nest("/purge", purge::router(state))
+        .with_state(state.clone())
+}
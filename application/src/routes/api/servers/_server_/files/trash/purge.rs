@@ -0,0 +1,68 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod post {
+    use crate::{
+        routes::api::servers::_server_::{GetPermissions, GetServer},
+        server::permissions::Permission,
+    };
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Payload {
+        #[serde(default)]
+        entries: Vec<uuid::Uuid>,
+        #[serde(default)]
+        all: bool,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        purged: usize,
+    }
+
+    #[utoipa::path(post, path = "/", responses(
+        (status = OK, body = inline(Response)),
+    ), request_body = inline(Payload))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        axum::Json(data): axum::Json<Payload>,
+    ) -> axum::Json<serde_json::Value> {
+        let mut entries = Vec::new();
+        for entry in crate::server::filesystem::trash::list(&server.filesystem).await {
+            if (data.all || data.entries.contains(&entry.identifier))
+                && !server
+                    .filesystem
+                    .is_outside_grants(&entry.path, permissions.as_ref(), Permission::FileDelete)
+                    .await
+            {
+                entries.push(entry);
+            }
+        }
+
+        let mut purged_count = 0;
+        for entry in entries {
+            if crate::server::filesystem::trash::purge_entry(&server.filesystem, entry)
+                .await
+                .is_ok()
+            {
+                purged_count += 1;
+            }
+        }
+
+        axum::Json(
+            serde_json::to_value(&Response {
+                purged: purged_count,
+            })
+            .unwrap(),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(post::route))
+        .with_state(state.clone())
+}
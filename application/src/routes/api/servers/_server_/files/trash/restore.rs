@@ -0,0 +1,110 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod post {
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::permissions::Permission,
+    };
+    use axum::http::StatusCode;
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Payload {
+        entries: Vec<uuid::Uuid>,
+        destination: Option<String>,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        restored: usize,
+    }
+
+    #[utoipa::path(post, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), request_body = inline(Payload))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        axum::Json(data): axum::Json<Payload>,
+    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+        if data.destination.is_some() && data.entries.len() != 1 {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(
+                    ApiError::new("destination can only be used with a single entry").to_json(),
+                ),
+            );
+        }
+
+        let mut restored_count = 0;
+        for entry in data.entries {
+            if let Some(permissions) = permissions.as_ref() {
+                let Some(trash_entry) =
+                    crate::server::filesystem::trash::find(&server.filesystem, entry).await
+                else {
+                    continue;
+                };
+                let destination = data
+                    .destination
+                    .as_ref()
+                    .map_or(trash_entry.path.clone(), |d| d.into());
+
+                if server
+                    .filesystem
+                    .is_outside_grants(
+                        &trash_entry.path,
+                        Some(permissions),
+                        Permission::FileReadContent,
+                    )
+                    .await
+                    || server
+                        .filesystem
+                        .is_outside_grants(&destination, Some(permissions), Permission::FileCreate)
+                        .await
+                {
+                    continue;
+                }
+            }
+
+            match crate::server::filesystem::trash::restore(
+                &server.filesystem,
+                entry,
+                data.destination.as_ref().map(|d| d.into()),
+            )
+            .await
+            {
+                Ok(_) => restored_count += 1,
+                Err(err) => {
+                    tracing::debug!(
+                        server = %server.uuid,
+                        entry = %entry,
+                        "failed to restore trash entry: {:#?}",
+                        err
+                    );
+                }
+            }
+        }
+
+        (
+            StatusCode::OK,
+            axum::Json(
+                serde_json::to_value(&Response {
+                    restored: restored_count,
+                })
+                .unwrap(),
+            ),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(post::route))
+        .with_state(state.clone())
+}
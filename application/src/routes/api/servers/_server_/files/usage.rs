@@ -0,0 +1,337 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod get {
+    use crate::{
+        routes::{
+            ApiError,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::filesystem::usage::DiskUsage,
+    };
+    use axum::{extract::Query, http::StatusCode};
+    use serde::{Deserialize, Serialize};
+    use std::{cmp::Reverse, collections::BinaryHeap, path::Path};
+    use utoipa::ToSchema;
+
+    const MAX_DEPTH: usize = 5;
+    const MAX_CHILDREN: usize = 100;
+    const MAX_REPORT_ENTRIES: usize = 100;
+    const MAX_TREE_AGE: i64 = 300;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Params {
+        #[serde(default)]
+        path: String,
+        #[serde(default = "default_depth")]
+        depth: usize,
+        #[serde(default = "default_children")]
+        children: usize,
+
+        largest_files: Option<usize>,
+        recently_grown: Option<usize>,
+    }
+
+    #[inline]
+    fn default_depth() -> usize {
+        1
+    }
+
+    #[inline]
+    fn default_children() -> usize {
+        20
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct UsageNode {
+        name: String,
+        size: u64,
+        files: u64,
+
+        #[schema(no_recursion)]
+        children: Vec<UsageNode>,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct UsageFile {
+        path: String,
+        size: u64,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct UsageGrowth {
+        path: String,
+        size: u64,
+        grown: u64,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        checked: Option<chrono::DateTime<chrono::Utc>>,
+        stale: bool,
+
+        tree: UsageNode,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        largest_files: Option<Vec<UsageFile>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        recently_grown: Option<Vec<UsageGrowth>>,
+    }
+
+    /// Builds the tree below `path`, leaving out entries `is_visible` rejects
+    fn build_node(
+        name: String,
+        path: &Path,
+        usage: &DiskUsage,
+        depth: usize,
+        children: usize,
+        is_visible: &impl Fn(&Path) -> bool,
+    ) -> UsageNode {
+        let mut entries = if depth > 0 {
+            usage
+                .entries
+                .iter()
+                .filter(|(name, _)| is_visible(&path.join(name)))
+                .collect::<Vec<_>>()
+        } else {
+            Vec::new()
+        };
+        entries.sort_unstable_by_key(|(_, entry)| Reverse(entry.size));
+        entries.truncate(children);
+
+        UsageNode {
+            name,
+            size: usage.size,
+            files: usage.files,
+            children: entries
+                .into_iter()
+                .map(|(name, entry)| {
+                    build_node(
+                        name.clone(),
+                        &path.join(name),
+                        entry,
+                        depth - 1,
+                        children,
+                        is_visible,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn collect_growth(
+        path: &Path,
+        usage: &DiskUsage,
+        growth: &mut Vec<UsageGrowth>,
+        is_visible: &impl Fn(&Path) -> bool,
+    ) {
+        for (name, entry) in &usage.entries {
+            let path = path.join(name);
+            if !is_visible(&path) {
+                continue;
+            }
+
+            if entry.size > entry.previous_size {
+                growth.push(UsageGrowth {
+                    path: path.to_string_lossy().to_string(),
+                    size: entry.size,
+                    grown: entry.size - entry.previous_size,
+                });
+            }
+
+            collect_growth(&path, entry, growth, is_visible);
+        }
+    }
+
+    #[utoipa::path(get, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), params(
+        (
+            "path" = String, Query,
+            description = "The directory to report the disk usage of",
+        ),
+        (
+            "depth" = usize, Query,
+            description = "How many levels of subdirectories to include",
+        ),
+        (
+            "children" = usize, Query,
+            description = "How many of the largest subdirectories to include per directory",
+        ),
+        (
+            "largest_files" = Option<usize>, Query,
+            description = "Include a report of the largest files",
+        ),
+        (
+            "recently_grown" = Option<usize>, Query,
+            description = "Include a report of the directories that grew the most since the previous recount",
+        ),
+    ))]
+    pub async fn route(
+        server: GetServer,
+        permissions: GetPermissions,
+        Query(data): Query<Params>,
+    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+        let path = match server.filesystem.canonicalize(&data.path).await {
+            Ok(path) => path,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(ApiError::new("directory not found").to_json()),
+                );
+            }
+        };
+
+        if server
+            .filesystem
+            .is_outside_view(&path, permissions.as_ref())
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ApiError::new("directory not found").to_json()),
+            );
+        }
+
+        let metadata = server.filesystem.metadata(&path).await;
+        if !metadata.map(|m| m.is_dir()).unwrap_or(true) {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("path is not a directory").to_json()),
+            );
+        }
+
+        let name = Path::new("/").join(&path).to_string_lossy().to_string();
+        let depth = data.depth.min(MAX_DEPTH);
+        let children = data.children.min(MAX_CHILDREN);
+        let is_visible = |path: &Path| {
+            !server
+                .filesystem
+                .is_outside_view(path, permissions.as_ref())
+        };
+
+        let ((tree, recently_grown), checked) = server
+            .filesystem
+            .usage_tree(&path, |usage| {
+                let usage = match usage {
+                    Some(usage) => usage,
+                    None => &DiskUsage::default(),
+                };
+
+                let recently_grown = data.recently_grown.map(|limit| {
+                    let mut growth = Vec::new();
+                    collect_growth(Path::new(&name), usage, &mut growth, &is_visible);
+
+                    growth.sort_unstable_by_key(|growth| Reverse(growth.grown));
+                    growth.truncate(limit.min(MAX_REPORT_ENTRIES));
+
+                    growth
+                });
+
+                (
+                    build_node(name.clone(), &path, usage, depth, children, &is_visible),
+                    recently_grown,
+                )
+            })
+            .await;
+
+        let stale = !server.filesystem.is_usage_tracked()
+            && checked
+                .is_none_or(|checked| (chrono::Utc::now() - checked).num_seconds() > MAX_TREE_AGE);
+        if stale {
+            server.filesystem.request_usage_recount();
+        }
+
+        let largest_files = match data.largest_files {
+            Some(limit) => {
+                let limit = limit.min(MAX_REPORT_ENTRIES);
+                let (mut walker, root) = match server.filesystem.walk_dir(&path) {
+                    Ok(walker) => walker,
+                    Err(_) => {
+                        return (
+                            StatusCode::NOT_FOUND,
+                            axum::Json(ApiError::new("directory not found").to_json()),
+                        );
+                    }
+                };
+                walker
+                    .hidden(false)
+                    .git_ignore(false)
+                    .ignore(false)
+                    .git_exclude(false)
+                    .follow_links(false);
+
+                let server = server.clone();
+                let permissions = permissions.0.clone();
+                let largest_files = tokio::task::spawn_blocking(move || {
+                    let mut largest = BinaryHeap::new();
+
+                    for entry in walker.build().flatten() {
+                        let metadata = match entry.metadata() {
+                            Ok(metadata) if metadata.is_file() => metadata,
+                            _ => continue,
+                        };
+
+                        if server.filesystem.is_ignored_sync(entry.path(), false)
+                            || server
+                                .filesystem
+                                .is_outside_view(entry.path(), permissions.as_ref())
+                        {
+                            continue;
+                        }
+
+                        largest.push(Reverse((metadata.len(), entry.into_path())));
+                        if largest.len() > limit {
+                            largest.pop();
+                        }
+                    }
+
+                    largest
+                        .into_sorted_vec()
+                        .into_iter()
+                        .map(|Reverse((size, file))| UsageFile {
+                            path: Path::new("/")
+                                .join(&path)
+                                .join(file.strip_prefix(&root).unwrap_or(&file))
+                                .to_string_lossy()
+                                .to_string(),
+                            size,
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .await;
+
+                match largest_files {
+                    Ok(largest_files) => Some(largest_files),
+                    Err(_) => {
+                        return (
+                            StatusCode::EXPECTATION_FAILED,
+                            axum::Json(ApiError::new("failed to list largest files").to_json()),
+                        );
+                    }
+                }
+            }
+            None => None,
+        };
+
+        (
+            StatusCode::OK,
+            axum::Json(
+                serde_json::to_value(&Response {
+                    checked,
+                    stale,
+                    tree,
+                    largest_files,
+                    recently_grown,
+                })
+                .unwrap(),
+            ),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(get::route))
+        .with_state(state.clone())
+}
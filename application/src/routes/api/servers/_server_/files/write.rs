@@ -2,7 +2,16 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod post {
-    use crate::routes::{ApiError, api::servers::_server_::GetServer};
+    use crate::{
+        routes::{
+            ApiError, GetState,
+            api::servers::_server_::{GetPermissions, GetServer},
+        },
+        server::{
+            filesystem::encoding::{LineEnding, TextEncoding},
+            permissions::Permission,
+        },
+    };
     use axum::{
         body::Body,
         extract::Query,
@@ -17,6 +26,16 @@ mod post {
     #[derive(ToSchema, Deserialize)]
     pub struct Params {
         file: String,
+
+        encoding: Option<String>,
+        line_ending: Option<LineEnding>,
+        #[serde(default)]
+        bom: bool,
+    }
+
+    enum Content {
+        Stream(Body),
+        Encoded(Vec<u8>),
     }
 
     #[derive(ToSchema, Serialize)]
@@ -24,21 +43,42 @@ mod post {
 
     #[utoipa::path(post, path = "/", responses(
         (status = OK, body = inline(Response)),
+        (status = BAD_REQUEST, body = inline(ApiError)),
         (status = NOT_FOUND, body = inline(ApiError)),
+        (status = PRECONDITION_FAILED, body = inline(ApiError)),
         (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), params(
+        (
+            "file" = String, Query,
+            description = "The file to write to",
+        ),
+        (
+            "encoding" = Option<String>, Query,
+            description = "The encoding to convert the UTF-8 body to, like 'windows-1252' or 'utf-16le'",
+        ),
+        (
+            "line_ending" = Option<LineEnding>, Query,
+            description = "The line ending to normalize line breaks to",
+        ),
+        (
+            "bom" = bool, Query,
+            description = "Whether to prepend a byte order mark for unicode encodings",
+        ),
     ), request_body = String)]
     pub async fn route(
+        state: GetState,
         server: GetServer,
+        permissions: GetPermissions,
         headers: HeaderMap,
         Query(data): Query<Params>,
         body: Body,
-    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+    ) -> (StatusCode, HeaderMap, axum::Json<serde_json::Value>) {
         let path = match server.filesystem.canonicalize(&data.file).await {
             Ok(path) => path,
             Err(_) => PathBuf::from(data.file),
         };
 
-        let content_size: i64 = headers
+        let mut content_size: i64 = headers
             .get("Content-Length")
             .and_then(|v| v.to_str().ok())
             .and_then(|v| v.parse().ok())
@@ -52,17 +92,39 @@ mod post {
                 metadata.as_ref().map(|m| m.is_dir()).unwrap_or(false),
             )
             .await
+            || server
+                .filesystem
+                .is_outside_grants(
+                    &path,
+                    permissions.as_ref(),
+                    if metadata.is_ok() {
+                        Permission::FileUpdate
+                    } else {
+                        Permission::FileCreate
+                    },
+                )
+                .await
         {
             return (
                 StatusCode::NOT_FOUND,
+                HeaderMap::new(),
                 axum::Json(ApiError::new("file not found").to_json()),
             );
         }
 
+        if !crate::routes::conditional::matches_precondition(&headers, metadata.as_ref().ok()) {
+            return (
+                StatusCode::PRECONDITION_FAILED,
+                HeaderMap::new(),
+                axum::Json(ApiError::new("file has been modified").to_json()),
+            );
+        }
+
         let old_content_size = if let Ok(metadata) = metadata {
             if !metadata.is_file() {
                 return (
                     StatusCode::NOT_FOUND,
+                    HeaderMap::new(),
                     axum::Json(ApiError::new("file is not a file").to_json()),
                 );
             }
@@ -72,6 +134,58 @@ mod post {
             0
         };
 
+        let content = if data.encoding.is_some() || data.line_ending.is_some() || data.bom {
+            let encoding = match data.encoding.as_deref() {
+                Some(label) => match encoding_rs::Encoding::for_label(label.as_bytes()) {
+                    Some(encoding) => encoding,
+                    None => {
+                        return (
+                            StatusCode::BAD_REQUEST,
+                            HeaderMap::new(),
+                            axum::Json(ApiError::new("unknown encoding").to_json()),
+                        );
+                    }
+                },
+                None => encoding_rs::UTF_8,
+            };
+
+            let text = match axum::body::to_bytes(body, state.config.api.upload_limit * 1000 * 1000)
+                .await
+                .map(|bytes| String::from_utf8(bytes.into()))
+            {
+                Ok(Ok(text)) => text,
+                _ => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        HeaderMap::new(),
+                        axum::Json(ApiError::new("body is not valid utf-8").to_json()),
+                    );
+                }
+            };
+
+            let text_encoding = TextEncoding {
+                encoding,
+                bom: data.bom,
+                line_ending: data.line_ending,
+            };
+
+            let content = match text_encoding.encode(&text) {
+                Ok(content) => content,
+                Err(err) => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        HeaderMap::new(),
+                        axum::Json(ApiError::new(&err.to_string()).to_json()),
+                    );
+                }
+            };
+            content_size = content.len() as i64;
+
+            Content::Encoded(content)
+        } else {
+            Content::Stream(body)
+        };
+
         let parent = path.parent().unwrap();
         server.filesystem.create_dir_all(parent).await.unwrap();
 
@@ -82,15 +196,21 @@ mod post {
         {
             return (
                 StatusCode::EXPECTATION_FAILED,
+                HeaderMap::new(),
                 axum::Json(ApiError::new("failed to allocate space").to_json()),
             );
         }
 
         let mut file = server.filesystem.create(&path).await.unwrap();
-        let mut stream = body.into_data_stream();
+        match content {
+            Content::Stream(body) => {
+                let mut stream = body.into_data_stream();
 
-        while let Some(Ok(chunk)) = stream.next().await {
-            file.write_all(&chunk).await.unwrap();
+                while let Some(Ok(chunk)) = stream.next().await {
+                    file.write_all(&chunk).await.unwrap();
+                }
+            }
+            Content::Encoded(content) => file.write_all(&content).await.unwrap(),
         }
 
         file.flush().await.unwrap();
@@ -98,8 +218,14 @@ mod post {
 
         server.filesystem.chown_path(&path).await;
 
+        let mut response_headers = HeaderMap::new();
+        if let Ok(metadata) = server.filesystem.metadata(&path).await {
+            crate::routes::conditional::insert_headers(&mut response_headers, &metadata, false);
+        }
+
         (
             StatusCode::OK,
+            response_headers,
             axum::Json(serde_json::to_value(&Response {}).unwrap()),
         )
     }
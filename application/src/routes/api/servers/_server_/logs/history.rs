@@ -0,0 +1,103 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod get {
+    use crate::routes::{ApiError, api::servers::_server_::GetServer};
+    use axum::{extract::Query, http::StatusCode};
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Params {
+        from: Option<chrono::DateTime<chrono::Utc>>,
+        to: Option<chrono::DateTime<chrono::Utc>>,
+
+        search: Option<String>,
+        #[serde(default)]
+        regex: bool,
+        #[serde(default)]
+        case_insensitive: bool,
+
+        limit: Option<usize>,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        lines: Vec<crate::models::ConsoleLine>,
+    }
+
+    #[utoipa::path(get, path = "/", responses(
+        (status = OK, body = inline(Response)),
+        (status = BAD_REQUEST, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ))]
+    pub async fn route(
+        server: GetServer,
+        Query(data): Query<Params>,
+    ) -> (StatusCode, axum::Json<serde_json::Value>) {
+        if !server.console_history.is_enabled() {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                axum::Json(ApiError::new("console history is disabled").to_json()),
+            );
+        }
+
+        let search = match data.search {
+            Some(search) => match regex::RegexBuilder::new(&if data.regex {
+                search
+            } else {
+                regex::escape(&search)
+            })
+            .case_insensitive(data.case_insensitive)
+            .size_limit(1024 * 1024)
+            .build()
+            {
+                Ok(search) => Some(search),
+                Err(_) => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        axum::Json(ApiError::new("invalid search").to_json()),
+                    );
+                }
+            },
+            None => None,
+        };
+
+        let limit = data.limit.unwrap_or(100).min(1000);
+        let lines = match server
+            .console_history
+            .read(data.from, data.to, search, limit)
+            .await
+        {
+            Ok(lines) => lines,
+            Err(err) => {
+                tracing::error!(
+                    server = %server.uuid,
+                    "failed to read console history: {:#?}",
+                    err
+                );
+
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    axum::Json(ApiError::new("failed to read console history").to_json()),
+                );
+            }
+        };
+
+        (
+            StatusCode::OK,
+            axum::Json(
+                serde_json::to_value(&Response {
+                    lines: lines.iter().map(|line| line.to_api_response()).collect(),
+                })
+                .unwrap(),
+            ),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(get::route))
+        .with_state(state.clone())
+}
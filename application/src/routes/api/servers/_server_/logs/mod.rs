@@ -1,6 +1,8 @@
 use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
+mod history;
+
 mod get {
     use crate::routes::{GetState, api::servers::_server_::GetServer};
     use axum::extract::Query;
@@ -35,5 +37,6 @@ mod get {
 pub fn router(state: &State) -> OpenApiRouter<State> {
     OpenApiRouter::new()
         .routes(routes!(get::route))
+        .nest("/history", history::router(state))
         .with_state(state.clone())
 }
@@ -21,6 +21,27 @@ mod ws;
 
 pub type GetServer = axum::extract::Extension<crate::server::Server>;
 
+/// Grants of the user a request is made for, forwarded by the panel in the
+/// `X-User-Permissions` header as a JSON list. `None` if the request may touch the whole server
+pub type GetPermissions = axum::extract::Extension<Option<crate::server::permissions::Permissions>>;
+
+const PERMISSIONS_HEADER: &str = "X-User-Permissions";
+
+#[allow(clippy::result_unit_err)]
+pub fn user_permissions(
+    headers: &axum::http::HeaderMap,
+) -> Result<Option<crate::server::permissions::Permissions>, ()> {
+    match headers.get(PERMISSIONS_HEADER) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| serde_json::from_str(value).ok())
+            .map(Some)
+            .ok_or(()),
+        None => Ok(None),
+    }
+}
+
 pub async fn auth(
     state: GetState,
     Path(parts): Path<Vec<String>>,
@@ -71,7 +92,21 @@ pub async fn auth(
         }
     };
 
+    let permissions = match user_permissions(req.headers()) {
+        Ok(permissions) => permissions,
+        Err(()) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "application/json")
+                .body(Body::from(
+                    serde_json::to_string(&ApiError::new("invalid user permissions")).unwrap(),
+                ))
+                .unwrap());
+        }
+    };
+
     req.extensions_mut().insert(server);
+    req.extensions_mut().insert(permissions);
 
     Ok(next.run(req).await)
 }
@@ -122,3 +157,37 @@ pub fn router(state: &State) -> OpenApiRouter<State> {
         .route_layer(axum::middleware::from_fn_with_state(state.clone(), auth))
         .with_state(state.clone())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{PERMISSIONS_HEADER, user_permissions};
+    use crate::server::permissions::Permission;
+    use axum::http::HeaderMap;
+    use std::path::Path;
+
+    #[test]
+    fn missing_header_is_unrestricted() {
+        assert!(user_permissions(&HeaderMap::new()).unwrap().is_none());
+    }
+
+    #[test]
+    fn header_is_parsed() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            PERMISSIONS_HEADER,
+            r#"["file.read:/plugins/**"]"#.parse().unwrap(),
+        );
+
+        let permissions = user_permissions(&headers).unwrap().unwrap();
+        assert!(permissions.has_path_permission(Permission::FileRead, Path::new("plugins/a")));
+        assert!(!permissions.has_path_permission(Permission::FileRead, Path::new("eula.txt")));
+    }
+
+    #[test]
+    fn invalid_header_is_rejected() {
+        let mut headers = HeaderMap::new();
+        headers.insert(PERMISSIONS_HEADER, "file.read".parse().unwrap());
+
+        assert!(user_permissions(&headers).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod post {
-    use crate::routes::GetState;
+    use crate::routes::{GetState, api::servers::_server_::GetServer};
     use serde::{Deserialize, Serialize};
     use utoipa::ToSchema;
 
@@ -19,12 +19,17 @@ mod post {
     ))]
     pub async fn route(
         state: GetState,
+        server: GetServer,
         axum::Json(data): axum::Json<Payload>,
     ) -> axum::Json<serde_json::Value> {
         for jti in data.jtis {
             state.config.jwt.deny(&jti);
         }
 
+        // sftp logins for this server may have been granted with the same permissions
+        state.sftp_auth_cache.invalidate(&[], &[server.uuid], &[]);
+        state.webdav_auth_cache.invalidate(&[], &[server.uuid], &[]);
+
         axum::Json(serde_json::to_value(&Response {}).unwrap())
     }
 }
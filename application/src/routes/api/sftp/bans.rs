@@ -0,0 +1,65 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod get {
+    use crate::routes::GetState;
+    use serde::Serialize;
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        bans: Vec<crate::models::SftpBan>,
+    }
+
+    #[utoipa::path(get, path = "/", responses(
+        (status = OK, body = inline(Response)),
+    ))]
+    pub async fn route(state: GetState) -> axum::Json<serde_json::Value> {
+        let bans = state
+            .sftp_limiter
+            .bans()
+            .iter()
+            .map(|ban| ban.to_api_response())
+            .collect();
+
+        axum::Json(serde_json::to_value(&Response { bans }).unwrap())
+    }
+}
+
+mod delete {
+    use crate::routes::GetState;
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Payload {
+        /// clears all bans when empty
+        #[serde(default)]
+        #[schema(value_type = Vec<String>)]
+        ips: Vec<std::net::IpAddr>,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        removed: usize,
+    }
+
+    #[utoipa::path(delete, path = "/", responses(
+        (status = OK, body = inline(Response)),
+    ), request_body = inline(Payload))]
+    pub async fn route(
+        state: GetState,
+        axum::Json(data): axum::Json<Payload>,
+    ) -> axum::Json<serde_json::Value> {
+        let removed = state.sftp_limiter.unban(&data.ips).await;
+
+        axum::Json(serde_json::to_value(&Response { removed }).unwrap())
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(get::route))
+        .routes(routes!(delete::route))
+        .with_state(state.clone())
+}
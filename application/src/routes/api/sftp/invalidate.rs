@@ -0,0 +1,47 @@
+use super::State;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod post {
+    use crate::routes::GetState;
+    use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Payload {
+        #[serde(default)]
+        users: Vec<uuid::Uuid>,
+        #[serde(default)]
+        servers: Vec<uuid::Uuid>,
+        #[serde(default)]
+        usernames: Vec<String>,
+    }
+
+    #[derive(ToSchema, Serialize)]
+    struct Response {
+        invalidated: usize,
+    }
+
+    #[utoipa::path(post, path = "/", responses(
+        (status = OK, body = inline(Response)),
+    ), request_body = inline(Payload))]
+    pub async fn route(
+        state: GetState,
+        axum::Json(data): axum::Json<Payload>,
+    ) -> axum::Json<serde_json::Value> {
+        let invalidated =
+            state
+                .sftp_auth_cache
+                .invalidate(&data.users, &data.servers, &data.usernames);
+        state
+            .webdav_auth_cache
+            .invalidate(&data.users, &data.servers, &data.usernames);
+
+        axum::Json(serde_json::to_value(&Response { invalidated }).unwrap())
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(post::route))
+        .with_state(state.clone())
+}
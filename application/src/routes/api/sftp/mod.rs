@@ -0,0 +1,12 @@
+use super::State;
+use utoipa_axum::router::OpenApiRouter;
+
+mod bans;
+mod invalidate;
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .nest("/bans", bans::router(state))
+        .nest("/invalidate", invalidate::router(state))
+        .with_state(state.clone())
+}
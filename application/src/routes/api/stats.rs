@@ -2,6 +2,7 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod get {
+    use crate::routes::GetState;
     use serde::Serialize;
     use std::path::Path;
     use sysinfo::{Disks, Networks, System};
@@ -44,12 +45,13 @@ mod get {
         network: NetworkStats,
         memory: MemoryStats,
         disk: DiskStats,
+        transfers: crate::models::Throughput,
     }
 
     #[utoipa::path(get, path = "/", responses(
         (status = OK, body = inline(Response)),
     ))]
-    pub async fn route() -> axum::Json<Response> {
+    pub async fn route(state: GetState) -> axum::Json<Response> {
         let mut sys = System::new_all();
 
         let mut disks = Disks::new_with_refreshed_list();
@@ -117,6 +119,7 @@ mod get {
                 written: total_disk_write,
                 writing_rate: disk_write_rate,
             },
+            transfers: state.bandwidth.to_api_response(),
         })
     }
 }
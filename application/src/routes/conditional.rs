@@ -0,0 +1,351 @@
+use axum::{
+    body::Body,
+    http::{HeaderMap, StatusCode},
+};
+use cap_std::fs::MetadataExt;
+use std::ops::Range;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+const MAX_RANGES: usize = 16;
+
+/// Strong ETag derived from the inode, modification time and size of a file
+pub fn etag(metadata: &cap_std::fs::Metadata) -> String {
+    format!(
+        "\"{:x}-{:x}{:08x}-{:x}\"",
+        metadata.ino(),
+        metadata.mtime(),
+        metadata.mtime_nsec(),
+        metadata.len()
+    )
+}
+
+#[inline]
+pub fn last_modified(metadata: &cap_std::fs::Metadata) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::from_timestamp(metadata.mtime(), 0)
+}
+
+#[inline]
+fn format_http_date(date: chrono::DateTime<chrono::Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[inline]
+fn parse_http_date(value: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| date.to_utc())
+}
+
+fn etag_list_matches(value: &str, etag: &str, weak: bool) -> bool {
+    value.split(',').map(|tag| tag.trim()).any(|tag| {
+        if tag == "*" {
+            return true;
+        }
+
+        match tag.strip_prefix("W/") {
+            Some(tag) => weak && tag == etag,
+            None => tag == etag,
+        }
+    })
+}
+
+/// Adds the `ETag`, `Last-Modified` and `Accept-Ranges` headers for a file
+pub fn insert_headers(headers: &mut HeaderMap, metadata: &cap_std::fs::Metadata, ranges: bool) {
+    headers.insert("ETag", etag(metadata).parse().unwrap());
+    if let Some(modified) = last_modified(metadata) {
+        headers.insert("Last-Modified", format_http_date(modified).parse().unwrap());
+    }
+    if ranges {
+        headers.insert("Accept-Ranges", "bytes".parse().unwrap());
+    }
+}
+
+/// Checks `If-None-Match` and `If-Modified-Since`, returns `true` if the
+/// client's cached copy is still valid and a `304 Not Modified` should be sent
+pub fn is_not_modified(headers: &HeaderMap, metadata: &cap_std::fs::Metadata) -> bool {
+    if let Some(value) = headers.get("If-None-Match").and_then(|v| v.to_str().ok()) {
+        return etag_list_matches(value, &etag(metadata), true);
+    }
+
+    match (
+        headers
+            .get("If-Modified-Since")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_http_date),
+        last_modified(metadata),
+    ) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+/// Checks the `If-Match` precondition against the current state of a file,
+/// `metadata` is `None` if the file does not exist
+pub fn matches_precondition(headers: &HeaderMap, metadata: Option<&cap_std::fs::Metadata>) -> bool {
+    let value = match headers.get("If-Match").and_then(|v| v.to_str().ok()) {
+        Some(value) => value,
+        None => return true,
+    };
+
+    match metadata {
+        Some(metadata) => etag_list_matches(value, &etag(metadata), false),
+        None => false,
+    }
+}
+
+/// Parses the `Range` header, returns `Ok(None)` if the full file should be sent
+/// and `Err(())` if none of the requested ranges can be satisfied
+#[allow(clippy::result_unit_err)]
+pub fn parse_ranges(
+    headers: &HeaderMap,
+    metadata: &cap_std::fs::Metadata,
+) -> Result<Option<Vec<Range<u64>>>, ()> {
+    let value = match headers.get("Range").and_then(|v| v.to_str().ok()) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    if let Some(if_range) = headers.get("If-Range").and_then(|v| v.to_str().ok()) {
+        let fresh = if if_range.starts_with('"') {
+            if_range == etag(metadata)
+        } else {
+            matches!(
+                (parse_http_date(if_range), last_modified(metadata)),
+                (Some(date), Some(modified)) if modified <= date
+            )
+        };
+
+        if !fresh {
+            return Ok(None);
+        }
+    }
+
+    parse_range_specs(value, metadata.len())
+}
+
+/// Parses the value of a `Range` header against a file of `size` bytes
+#[allow(clippy::result_unit_err)]
+fn parse_range_specs(value: &str, size: u64) -> Result<Option<Vec<Range<u64>>>, ()> {
+    let specs = match value.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => return Ok(None),
+    };
+
+    let mut ranges = Vec::new();
+
+    for spec in specs.split(',').map(|spec| spec.trim()) {
+        let (start, end) = match spec.split_once('-') {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+
+        let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => start..end.saturating_add(1).min(size),
+            (Ok(start), Err(_)) if end.is_empty() => start..size,
+            (Err(_), Ok(suffix)) if start.is_empty() => size.saturating_sub(suffix)..size,
+            _ => return Ok(None),
+        };
+
+        if range.start < size && !range.is_empty() {
+            ranges.push(range);
+        }
+    }
+
+    if ranges.is_empty() {
+        return Err(());
+    }
+
+    if ranges.len() > MAX_RANGES {
+        return Ok(None);
+    }
+
+    Ok(Some(ranges))
+}
+
+/// Whether the request is answered with `304 Not Modified` or with a part of the file,
+/// ranges adding up to the whole file do not count
+pub fn is_partial_or_not_modified(headers: &HeaderMap, metadata: &cap_std::fs::Metadata) -> bool {
+    if is_not_modified(headers, metadata) {
+        return true;
+    }
+
+    match parse_ranges(headers, metadata) {
+        Ok(Some(ranges)) => {
+            ranges
+                .iter()
+                .map(|range| range.end - range.start)
+                .sum::<u64>()
+                < metadata.len()
+        }
+        _ => false,
+    }
+}
+
+/// Builds the response for a regular file, honoring conditional and range headers.
+/// `headers` are the response headers already prepared by the caller.
+pub async fn file_response(
+    request_headers: &HeaderMap,
+    mut headers: HeaderMap,
+    metadata: &cap_std::fs::Metadata,
+    mut file: tokio::fs::File,
+) -> (StatusCode, HeaderMap, Body) {
+    insert_headers(&mut headers, metadata, true);
+
+    if is_not_modified(request_headers, metadata) {
+        headers.remove("Content-Length");
+
+        return (StatusCode::NOT_MODIFIED, headers, Body::empty());
+    }
+
+    let size = metadata.len();
+    let ranges = match parse_ranges(request_headers, metadata) {
+        Ok(Some(ranges)) => ranges,
+        Ok(None) => {
+            headers.insert("Content-Length", size.into());
+
+            return (
+                StatusCode::OK,
+                headers,
+                Body::from_stream(tokio_util::io::ReaderStream::new(
+                    tokio::io::BufReader::new(file),
+                )),
+            );
+        }
+        Err(()) => {
+            headers.remove("Content-Length");
+            headers.insert("Content-Range", format!("bytes */{size}").parse().unwrap());
+
+            return (StatusCode::RANGE_NOT_SATISFIABLE, headers, Body::empty());
+        }
+    };
+
+    if let [range] = ranges.as_slice() {
+        if file
+            .seek(std::io::SeekFrom::Start(range.start))
+            .await
+            .is_err()
+        {
+            return (StatusCode::INTERNAL_SERVER_ERROR, headers, Body::empty());
+        }
+
+        headers.insert("Content-Length", (range.end - range.start).into());
+        headers.insert(
+            "Content-Range",
+            format!("bytes {}-{}/{}", range.start, range.end - 1, size)
+                .parse()
+                .unwrap(),
+        );
+
+        return (
+            StatusCode::PARTIAL_CONTENT,
+            headers,
+            Body::from_stream(tokio_util::io::ReaderStream::new(
+                file.take(range.end - range.start),
+            )),
+        );
+    }
+
+    let boundary = format!("{:032x}", rand::random::<u128>());
+    let content_type = headers
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+
+    let part_headers = ranges
+        .iter()
+        .map(|range| {
+            format!(
+                "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                range.start,
+                range.end - 1,
+                size
+            )
+        })
+        .collect::<Vec<_>>();
+    let trailer = format!("\r\n--{boundary}--\r\n");
+
+    let content_length = ranges
+        .iter()
+        .zip(&part_headers)
+        .map(|(range, header)| header.len() as u64 + range.end - range.start)
+        .sum::<u64>()
+        + trailer.len() as u64;
+
+    let (mut writer, reader) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        for (range, header) in ranges.into_iter().zip(part_headers) {
+            if writer.write_all(header.as_bytes()).await.is_err()
+                || file
+                    .seek(std::io::SeekFrom::Start(range.start))
+                    .await
+                    .is_err()
+            {
+                return;
+            }
+
+            let mut part = (&mut file).take(range.end - range.start);
+            if tokio::io::copy(&mut part, &mut writer).await.is_err() {
+                return;
+            }
+        }
+
+        writer.write_all(trailer.as_bytes()).await.ok();
+    });
+
+    headers.insert("Content-Length", content_length.into());
+    headers.insert(
+        "Content-Type",
+        format!("multipart/byteranges; boundary={boundary}")
+            .parse()
+            .unwrap(),
+    );
+
+    (
+        StatusCode::PARTIAL_CONTENT,
+        headers,
+        Body::from_stream(tokio_util::io::ReaderStream::new(reader)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_range_specs;
+
+    fn ranges(value: &str, size: u64) -> Result<Option<Vec<(u64, u64)>>, ()> {
+        parse_range_specs(value, size).map(|ranges| {
+            ranges.map(|ranges| {
+                ranges
+                    .into_iter()
+                    .map(|range| (range.start, range.end))
+                    .collect()
+            })
+        })
+    }
+
+    #[test]
+    fn range_end_is_clamped_to_size() {
+        assert_eq!(ranges("bytes=0-99", 10), Ok(Some(vec![(0, 10)])));
+        assert_eq!(ranges("bytes=2-", 10), Ok(Some(vec![(2, 10)])));
+        assert_eq!(ranges("bytes=-4", 10), Ok(Some(vec![(6, 10)])));
+    }
+
+    #[test]
+    fn range_end_does_not_overflow() {
+        assert_eq!(
+            ranges("bytes=0-18446744073709551615", 10),
+            Ok(Some(vec![(0, 10)]))
+        );
+        assert_eq!(
+            ranges("bytes=5-18446744073709551615", 10),
+            Ok(Some(vec![(5, 10)]))
+        );
+    }
+
+    #[test]
+    fn unsatisfiable_and_invalid_ranges() {
+        assert_eq!(ranges("bytes=10-20", 10), Err(()));
+        assert_eq!(ranges("bytes=5-2", 10), Ok(None));
+        assert_eq!(ranges("items=0-1", 10), Ok(None));
+    }
+}
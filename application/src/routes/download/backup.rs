@@ -2,7 +2,10 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod get {
-    use crate::routes::GetState;
+    use crate::{
+        routes::GetState,
+        server::throttle::{Direction, Throttle},
+    };
     use axum::{
         body::Body,
         extract::Query,
@@ -100,7 +103,17 @@ mod get {
         };
 
         match backup.download(&server).await {
-            Ok(response) => response,
+            Ok((status, headers, body)) => {
+                let throttle = Throttle::new(
+                    &state.config,
+                    &state.bandwidth,
+                    &server,
+                    Direction::Download,
+                )
+                .await;
+
+                (status, headers, throttle.body(body))
+            }
             Err(e) => {
                 tracing::error!("failed to download backup: {}", e);
 
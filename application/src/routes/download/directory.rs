@@ -2,7 +2,13 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod get {
-    use crate::routes::GetState;
+    use crate::{
+        routes::GetState,
+        server::{
+            permissions::Permission,
+            throttle::{Direction, Throttle},
+        },
+    };
     use axum::{
         body::Body,
         extract::Query,
@@ -26,6 +32,8 @@ mod get {
         pub file_path: String,
         pub server_uuid: uuid::Uuid,
         pub unique_id: String,
+        #[serde(default)]
+        pub permissions: Option<crate::server::permissions::Permissions>,
     }
 
     #[utoipa::path(get, path = "/", responses(
@@ -89,6 +97,14 @@ mod get {
             }
         };
 
+        let throttle = Throttle::new(
+            &state.config,
+            &state.bandwidth,
+            &server,
+            Direction::Download,
+        )
+        .await;
+
         let path = PathBuf::from(payload.file_path);
         let file_name = path.file_name().unwrap().to_string_lossy().to_string();
 
@@ -122,7 +138,9 @@ mod get {
                     return (
                         StatusCode::OK,
                         headers,
-                        Body::from_stream(tokio_util::io::ReaderStream::new(Box::pin(reader))),
+                        throttle.body(Body::from_stream(tokio_util::io::ReaderStream::new(
+                            Box::pin(reader),
+                        ))),
                     );
                 }
                 Err(err) => {
@@ -144,7 +162,13 @@ mod get {
 
         let metadata = server.filesystem.symlink_metadata(&path).await;
         if let Ok(metadata) = metadata {
-            if !metadata.is_dir() || server.filesystem.is_ignored(&path, metadata.is_dir()).await {
+            if !metadata.is_dir()
+                || server.filesystem.is_ignored(&path, metadata.is_dir()).await
+                || payload
+                    .permissions
+                    .as_ref()
+                    .is_some_and(|permissions| !permissions.is_path_visible(&path))
+            {
                 return (
                     StatusCode::NOT_FOUND,
                     HeaderMap::new(),
@@ -203,6 +227,22 @@ mod get {
                     continue;
                 }
 
+                if let Some(permissions) = &payload.permissions {
+                    let allowed = if metadata.is_dir() {
+                        permissions.is_path_visible(&path)
+                    } else {
+                        server.filesystem.has_path_permission_sync(
+                            &path,
+                            permissions,
+                            Permission::FileReadContent,
+                        )
+                    };
+
+                    if !allowed {
+                        continue;
+                    }
+                }
+
                 if metadata.is_dir() {
                     tar.append_dir(display_path, entry.path()).ok();
                 } else if metadata.is_file() {
@@ -255,9 +295,9 @@ mod get {
         (
             StatusCode::OK,
             headers,
-            Body::from_stream(tokio_util::io::ReaderStream::new(
+            throttle.body(Body::from_stream(tokio_util::io::ReaderStream::new(
                 tokio::io::BufReader::new(reader),
-            )),
+            ))),
         )
     }
 }
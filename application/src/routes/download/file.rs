@@ -2,15 +2,20 @@ use super::State;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 mod get {
-    use crate::routes::GetState;
+    use crate::{
+        routes::GetState,
+        server::{
+            permissions::Permission,
+            throttle::{Direction, Throttle},
+        },
+    };
     use axum::{
         body::Body,
         extract::Query,
         http::{HeaderMap, StatusCode},
     };
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
     use std::path::Path;
-    use tokio::io::BufReader;
     use utoipa::ToSchema;
 
     #[derive(ToSchema, Deserialize)]
@@ -26,31 +31,82 @@ mod get {
         pub file_path: String,
         pub server_uuid: uuid::Uuid,
         pub unique_id: String,
+        #[serde(default)]
+        pub permissions: Option<crate::server::permissions::Permissions>,
+    }
+
+    /// Issued with the response to a panel token so an interrupted download can be
+    /// resumed, it is only valid for requests answered with a part of the same file or
+    /// `304 Not Modified` while its ETag is unchanged, and never outlives the panel token
+    #[derive(Deserialize, Serialize)]
+    pub struct ResumeJwtPayload {
+        #[serde(flatten)]
+        pub base: crate::remote::jwt::BasePayload,
+
+        pub file_path: String,
+        pub server_uuid: uuid::Uuid,
+        pub resume_etag: String,
+        #[serde(default)]
+        pub permissions: Option<crate::server::permissions::Permissions>,
     }
 
+    const RESUME_TOKEN_LIFETIME: i64 = 15 * 60;
+
     #[utoipa::path(get, path = "/", responses(
         (status = OK, body = String),
+        (status = PARTIAL_CONTENT, body = String),
+        (status = NOT_MODIFIED, body = String),
         (status = UNAUTHORIZED, body = String),
         (status = NOT_FOUND, body = String),
+        (status = RANGE_NOT_SATISFIABLE, body = String),
         (status = EXPECTATION_FAILED, body = String),
     ), params(
         (
             "token" = String, Query,
-            description = "The JWT token to use for authentication",
+            description = "The JWT token to use for authentication, or the X-Resume-Token of an earlier response",
         ),
     ))]
     pub async fn route(
         state: GetState,
+        request_headers: HeaderMap,
         Query(data): Query<Params>,
     ) -> (StatusCode, HeaderMap, Body) {
-        let payload: FileJwtPayload = match state.config.jwt.verify(&data.token) {
-            Ok(payload) => payload,
+        // resume tokens are signed by us and can be reused until the panel token
+        // they were issued for expires, panel tokens can only be used once
+        let (payload, resumed) = match state.config.jwt.verify::<ResumeJwtPayload>(&data.token) {
+            Ok(payload) => (payload, true),
             Err(_) => {
-                return (
-                    StatusCode::UNAUTHORIZED,
-                    HeaderMap::new(),
-                    Body::from("Invalid token"),
-                );
+                let payload: FileJwtPayload = match state.config.jwt.verify(&data.token) {
+                    Ok(payload) => payload,
+                    Err(_) => {
+                        return (
+                            StatusCode::UNAUTHORIZED,
+                            HeaderMap::new(),
+                            Body::from("Invalid token"),
+                        );
+                    }
+                };
+
+                if payload.base.validate(&state.config.jwt)
+                    && !state.config.jwt.one_time_id(&payload.unique_id)
+                {
+                    return (
+                        StatusCode::UNAUTHORIZED,
+                        HeaderMap::new(),
+                        Body::from("Token has already been used"),
+                    );
+                }
+
+                (
+                    ResumeJwtPayload {
+                        base: payload.base,
+                        file_path: payload.file_path,
+                        server_uuid: payload.server_uuid,
+                        resume_etag: String::new(),
+                        permissions: payload.permissions,
+                    },
+                    false,
+                )
             }
         };
 
@@ -62,14 +118,6 @@ mod get {
             );
         }
 
-        if !state.config.jwt.one_time_id(&payload.unique_id) {
-            return (
-                StatusCode::UNAUTHORIZED,
-                HeaderMap::new(),
-                Body::from("Token has already been used"),
-            );
-        }
-
         let server = state
             .server_manager
             .get_servers()
@@ -91,7 +139,39 @@ mod get {
 
         let path = Path::new(&payload.file_path);
 
+        let throttle = Throttle::new(
+            &state.config,
+            &state.bandwidth,
+            &server,
+            Direction::Download,
+        )
+        .await;
+
+        if server
+            .filesystem
+            .is_outside_grants(
+                path,
+                payload.permissions.as_ref(),
+                Permission::FileReadContent,
+            )
+            .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                HeaderMap::new(),
+                Body::from("File not found"),
+            );
+        }
+
         if let Some((backup, path)) = server.filesystem.backup_fs(&server, path).await {
+            if resumed {
+                return (
+                    StatusCode::PRECONDITION_FAILED,
+                    HeaderMap::new(),
+                    Body::from("File has been modified"),
+                );
+            }
+
             match crate::server::filesystem::backup::reader(backup, &server, &path).await {
                 Ok((mut reader, size)) => {
                     let mut headers = HeaderMap::new();
@@ -120,8 +200,8 @@ mod get {
                     return (
                         StatusCode::OK,
                         headers,
-                        Body::from_stream(tokio_util::io::ReaderStream::new(Box::pin(
-                            async_reader,
+                        throttle.body(Body::from_stream(tokio_util::io::ReaderStream::new(
+                            Box::pin(async_reader),
                         ))),
                     );
                 }
@@ -165,6 +245,26 @@ mod get {
             }
         };
 
+        let etag = crate::routes::conditional::etag(&metadata);
+        if !payload.resume_etag.is_empty() && payload.resume_etag != etag {
+            return (
+                StatusCode::PRECONDITION_FAILED,
+                HeaderMap::new(),
+                Body::from("File has been modified"),
+            );
+        }
+
+        // a resume token must not be usable to download the whole file again
+        if resumed
+            && !crate::routes::conditional::is_partial_or_not_modified(&request_headers, &metadata)
+        {
+            return (
+                StatusCode::UNAUTHORIZED,
+                HeaderMap::new(),
+                Body::from("Resume tokens can only be used for partial or conditional requests"),
+            );
+        }
+
         let file = match server.filesystem.open(&path).await {
             Ok(file) => file,
             Err(_) => {
@@ -177,7 +277,6 @@ mod get {
         };
 
         let mut headers = HeaderMap::new();
-        headers.insert("Content-Length", metadata.len().into());
         headers.insert(
             "Content-Disposition",
             format!(
@@ -189,11 +288,35 @@ mod get {
         );
         headers.insert("Content-Type", "application/octet-stream".parse().unwrap());
 
-        (
-            StatusCode::OK,
-            headers,
-            Body::from_stream(tokio_util::io::ReaderStream::new(BufReader::new(file))),
-        )
+        if !resumed {
+            let now = chrono::Utc::now().timestamp();
+            let expiration_time = match payload.base.expiration_time {
+                Some(expiration_time) => expiration_time.min(now + RESUME_TOKEN_LIFETIME),
+                None => now + RESUME_TOKEN_LIFETIME,
+            };
+
+            let resume = ResumeJwtPayload {
+                base: crate::remote::jwt::BasePayload {
+                    expiration_time: Some(expiration_time),
+                    not_before: None,
+                    issued_at: Some(now),
+                    ..payload.base
+                },
+                resume_etag: etag,
+                ..payload
+            };
+            if let Ok(token) = state.config.jwt.sign(&resume)
+                && let Ok(token) = token.parse()
+            {
+                headers.insert("X-Resume-Token", token);
+            }
+        }
+
+        let (status, headers, body) =
+            crate::routes::conditional::file_response(&request_headers, headers, &metadata, file)
+                .await;
+
+        (status, headers, throttle.body(body))
     }
 }
 
@@ -5,8 +5,10 @@ use utoipa::ToSchema;
 use utoipa_axum::router::OpenApiRouter;
 
 pub mod api;
+mod conditional;
 mod download;
 mod upload;
+pub mod webdav;
 
 pub struct AppState {
     pub config: Arc<crate::config::Config>,
@@ -16,6 +18,11 @@ pub struct AppState {
     pub docker: Arc<Docker>,
     pub server_manager: Arc<crate::server::manager::Manager>,
     pub extension_manager: Arc<crate::extensions::manager::Manager>,
+    pub sftp_auth_cache: crate::sftp::cache::AuthCache,
+    pub sftp_limiter: crate::sftp::limiter::Limiter,
+    pub bandwidth: Arc<crate::server::throttle::Bandwidth>,
+    pub webdav_locks: webdav::locks::LockManager,
+    pub webdav_auth_cache: crate::sftp::cache::AuthCache,
 }
 
 #[derive(ToSchema, Serialize)]
@@ -42,6 +49,7 @@ pub fn router(state: &State) -> OpenApiRouter<State> {
     OpenApiRouter::new()
         .nest("/download", download::router(state))
         .nest("/upload", upload::router(state))
+        .nest("/webdav", webdav::router(state))
         .nest("/api", api::router(state))
         .with_state(state.clone())
 }
@@ -8,7 +8,11 @@ use utoipa_axum::{
 mod post {
     use crate::{
         routes::{ApiError, GetState},
-        server::activity::{Activity, ActivityEvent},
+        server::{
+            activity::{Activity, ActivityEvent},
+            permissions::Permission,
+            throttle::{Direction, Throttle},
+        },
     };
     use axum::{
         extract::{ConnectInfo, Multipart, Query},
@@ -38,6 +42,8 @@ mod post {
         pub server_uuid: uuid::Uuid,
         pub user_uuid: uuid::Uuid,
         pub unique_id: String,
+        #[serde(default)]
+        pub permissions: Option<crate::server::permissions::Permissions>,
     }
 
     #[utoipa::path(post, path = "/", responses(
@@ -115,6 +121,8 @@ mod post {
         }
 
         let user_ip = Some(state.config.find_ip(&headers, connect_info));
+        let throttle =
+            Throttle::new(&state.config, &state.bandwidth, &server, Direction::Upload).await;
 
         while let Ok(Some(mut field)) = multipart.next_field().await {
             let filename = match field.file_name() {
@@ -128,7 +136,16 @@ mod post {
             };
             let file_path = directory.join(filename);
 
-            if server.filesystem.is_ignored(&file_path, false).await {
+            if server.filesystem.is_ignored(&file_path, false).await
+                || server
+                    .filesystem
+                    .is_outside_grants(
+                        &file_path,
+                        payload.permissions.as_ref(),
+                        Permission::FileCreate,
+                    )
+                    .await
+            {
                 return (
                     StatusCode::NOT_FOUND,
                     axum::Json(ApiError::new("file not found").to_json()),
@@ -172,6 +189,7 @@ mod post {
                     );
                 }
 
+                throttle.consume(chunk.len()).await;
                 writer.write_all(&chunk).await.unwrap();
                 written_size += chunk.len();
             }
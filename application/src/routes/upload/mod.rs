@@ -2,9 +2,11 @@ use super::State;
 use utoipa_axum::router::OpenApiRouter;
 
 mod file;
+mod tus;
 
 pub fn router(state: &State) -> OpenApiRouter<State> {
     OpenApiRouter::new()
         .nest("/file", file::router(state))
+        .nest("/tus", tus::router(state))
         .with_state(state.clone())
 }
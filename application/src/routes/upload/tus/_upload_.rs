@@ -0,0 +1,522 @@
+use super::State;
+use axum::extract::DefaultBodyLimit;
+use serde::Deserialize;
+use utoipa::ToSchema;
+use utoipa_axum::{
+    router::{OpenApiRouter, UtoipaMethodRouterExt},
+    routes,
+};
+
+#[derive(ToSchema, Deserialize)]
+pub struct Params {
+    token: String,
+}
+
+mod head {
+    use crate::routes::GetState;
+    use axum::{
+        extract::{Path, Query},
+        http::{HeaderMap, StatusCode},
+    };
+
+    #[utoipa::path(head, path = "/", responses(
+        (status = OK, body = String),
+        (status = UNAUTHORIZED, body = String),
+        (status = NOT_FOUND, body = String),
+    ), params(
+        (
+            "upload" = uuid::Uuid,
+            description = "The upload ID",
+            example = "123e4567-e89b-12d3-a456-426614174000",
+        ),
+        (
+            "token" = String, Query,
+            description = "The JWT token used to create the upload",
+        ),
+    ))]
+    pub async fn route(
+        state: GetState,
+        Path(upload_id): Path<uuid::Uuid>,
+        Query(data): Query<super::Params>,
+    ) -> (StatusCode, HeaderMap) {
+        let upload = match super::super::TusUpload::load(&state.config, upload_id).await {
+            Ok(upload) => upload,
+            Err(_) => return (StatusCode::NOT_FOUND, super::super::tus_headers()),
+        };
+
+        if !super::super::verify_session_token(&state, &data.token, &upload) {
+            return (StatusCode::UNAUTHORIZED, super::super::tus_headers());
+        }
+
+        let mut headers = super::super::tus_headers();
+        headers.insert("Upload-Offset", upload.offset(&state.config).await.into());
+        headers.insert("Upload-Length", upload.length.into());
+
+        (StatusCode::OK, headers)
+    }
+}
+
+mod patch {
+    use super::super::TusUpload;
+    use crate::{
+        routes::{ApiError, GetState},
+        server::{
+            activity::{Activity, ActivityEvent},
+            throttle::{Direction, Throttle},
+        },
+    };
+    use axum::{
+        body::Body,
+        extract::{ConnectInfo, Path, Query},
+        http::{HeaderMap, StatusCode},
+    };
+    use futures::StreamExt;
+    use serde_json::json;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Non-standard status code defined by the tus checksum extension
+    const CHECKSUM_MISMATCH: u16 = 460;
+
+    async fn file_checksum(
+        path: &std::path::Path,
+        algorithm: super::super::ChecksumAlgorithm,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut hasher = algorithm.hasher();
+
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let bytes_read = file.read(&mut buffer).await?;
+            if bytes_read == 0 {
+                break;
+            }
+
+            hasher.update(&buffer[..bytes_read]);
+        }
+
+        Ok(hasher.finalize())
+    }
+
+    /// Moves the upload into the server directory, the upload is removed if this fails
+    async fn finish_upload(
+        state: &crate::routes::AppState,
+        server: &crate::server::Server,
+        upload: &TusUpload,
+    ) -> Result<(), anyhow::Error> {
+        let parent = upload.path.parent().unwrap_or(std::path::Path::new(""));
+
+        let replaced = match async {
+            server.filesystem.create_dir_all(parent).await?;
+
+            match server.filesystem.symlink_metadata(&upload.path).await {
+                Ok(metadata) if !metadata.is_file() => {
+                    Err(anyhow::anyhow!("destination is not a file"))
+                }
+                Ok(metadata) => Ok(metadata.len()),
+                Err(_) => Ok(0),
+            }
+        }
+        .await
+        {
+            Ok(replaced) => replaced,
+            Err(err) => {
+                upload.remove(&state.config, Some(server)).await;
+                return Err(err);
+            }
+        };
+
+        // other writes may have used up the space since the upload was created
+        if !server
+            .filesystem
+            .commit_upload(parent, upload.length, replaced)
+            .await
+        {
+            upload.remove(&state.config, Some(server)).await;
+            return Err(anyhow::anyhow!("failed to allocate space"));
+        }
+
+        let upload_dir = TusUpload::directory(&state.config);
+        let file_name = format!("{}.part", upload.identifier);
+        let destination = upload.path.clone();
+
+        let result = async {
+            let base_dir = server.filesystem.base_dir().await?;
+
+            tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+                let upload_dir =
+                    cap_std::fs::Dir::open_ambient_dir(upload_dir, cap_std::ambient_authority())?;
+
+                match upload_dir.rename(&file_name, &base_dir, &destination) {
+                    Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+                        upload_dir.copy(&file_name, &base_dir, &destination)?;
+                        upload_dir.remove_file(&file_name)
+                    }
+                    result => result,
+                }
+            })
+            .await??;
+
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+
+        if let Err(err) = result {
+            // the reservation was already moved into the server usage
+            server
+                .filesystem
+                .allocate_in_path(parent, replaced as i64 - upload.length as i64)
+                .await;
+            upload.remove(&state.config, None).await;
+
+            return Err(err);
+        }
+
+        server.filesystem.chown_path(&upload.path).await;
+
+        Ok(())
+    }
+
+    #[utoipa::path(patch, path = "/", responses(
+        (status = NO_CONTENT, body = String),
+        (status = UNAUTHORIZED, body = inline(ApiError)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = CONFLICT, body = inline(ApiError)),
+        (status = LOCKED, body = inline(ApiError)),
+        (status = UNSUPPORTED_MEDIA_TYPE, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), params(
+        (
+            "upload" = uuid::Uuid,
+            description = "The upload ID",
+            example = "123e4567-e89b-12d3-a456-426614174000",
+        ),
+        (
+            "token" = String, Query,
+            description = "The JWT token used to create the upload",
+        ),
+    ))]
+    pub async fn route(
+        state: GetState,
+        headers: HeaderMap,
+        connect_info: ConnectInfo<SocketAddr>,
+        Path(upload_id): Path<uuid::Uuid>,
+        Query(data): Query<super::Params>,
+        body: Body,
+    ) -> (StatusCode, HeaderMap, axum::Json<serde_json::Value>) {
+        let mut upload = match TusUpload::load(&state.config, upload_id).await {
+            Ok(upload) => upload,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    super::super::tus_headers(),
+                    axum::Json(ApiError::new("upload not found").to_json()),
+                );
+            }
+        };
+
+        if !super::super::verify_session_token(&state, &data.token, &upload) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                super::super::tus_headers(),
+                axum::Json(ApiError::new("invalid token").to_json()),
+            );
+        }
+
+        let _lock = match super::super::lock_upload(upload.identifier) {
+            Some(lock) => lock,
+            None => {
+                return (
+                    StatusCode::LOCKED,
+                    super::super::tus_headers(),
+                    axum::Json(ApiError::new("upload is already in progress").to_json()),
+                );
+            }
+        };
+
+        if headers
+            .get("Content-Type")
+            .and_then(|v| v.to_str().ok())
+            .is_none_or(|v| v != "application/offset+octet-stream")
+        {
+            return (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                super::super::tus_headers(),
+                axum::Json(ApiError::new("invalid content type").to_json()),
+            );
+        }
+
+        let server = state
+            .server_manager
+            .get_servers()
+            .await
+            .iter()
+            .find(|s| s.uuid == upload.server_uuid)
+            .cloned();
+
+        let server = match server {
+            Some(server) => server,
+            None => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    super::super::tus_headers(),
+                    axum::Json(ApiError::new("server not found").to_json()),
+                );
+            }
+        };
+
+        let offset = upload.offset(&state.config).await;
+        if headers
+            .get("Upload-Offset")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            != Some(offset)
+        {
+            return (
+                StatusCode::CONFLICT,
+                super::super::tus_headers(),
+                axum::Json(ApiError::new("upload offset does not match").to_json()),
+            );
+        }
+
+        let chunk_checksum = match headers.get("Upload-Checksum") {
+            Some(value) => match value.to_str().ok().and_then(super::super::parse_checksum) {
+                Some(checksum) => Some(checksum),
+                None => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        super::super::tus_headers(),
+                        axum::Json(ApiError::new("invalid checksum").to_json()),
+                    );
+                }
+            },
+            None => None,
+        };
+
+        let mut file = match tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(upload.data_path(&state.config))
+            .await
+        {
+            Ok(file) => file,
+            Err(_) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    super::super::tus_headers(),
+                    axum::Json(ApiError::new("upload not found").to_json()),
+                );
+            }
+        };
+
+        let mut hasher = chunk_checksum
+            .as_ref()
+            .map(|(algorithm, _)| algorithm.hasher());
+        let mut written = 0;
+        let mut error = None;
+
+        let throttle =
+            Throttle::new(&state.config, &state.bandwidth, &server, Direction::Upload).await;
+
+        let mut stream = body.into_data_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(_) => break,
+            };
+
+            throttle.consume(chunk.len()).await;
+
+            if offset + written + chunk.len() as u64 > upload.length {
+                error = Some((
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "upload is larger than the announced length",
+                ));
+                break;
+            }
+
+            if !server.filesystem.reserve_upload(chunk.len() as u64) {
+                error = Some((StatusCode::EXPECTATION_FAILED, "failed to allocate space"));
+                break;
+            }
+
+            if file.write_all(&chunk).await.is_err() {
+                server.filesystem.release_upload(chunk.len() as u64);
+
+                error = Some((StatusCode::EXPECTATION_FAILED, "failed to write upload"));
+                break;
+            }
+
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&chunk);
+            }
+            written += chunk.len() as u64;
+        }
+
+        file.flush().await.ok();
+
+        let checksum_mismatch = match (hasher, &chunk_checksum) {
+            (Some(hasher), Some((_, expected))) => {
+                error.is_some() || written == 0 || hasher.finalize() != *expected
+            }
+            _ => false,
+        };
+
+        if checksum_mismatch {
+            file.set_len(offset).await.ok();
+            server.filesystem.release_upload(written);
+            written = 0;
+
+            if error.is_none() {
+                error = Some((
+                    StatusCode::from_u16(CHECKSUM_MISMATCH).unwrap(),
+                    "checksum mismatch",
+                ));
+            }
+        }
+
+        drop(file);
+
+        upload.updated = chrono::Utc::now();
+        upload.save(&state.config).await.ok();
+
+        let mut response_headers = super::super::tus_headers();
+        response_headers.insert("Upload-Offset", (offset + written).into());
+
+        if let Some((status, message)) = error {
+            return (
+                status,
+                response_headers,
+                axum::Json(ApiError::new(message).to_json()),
+            );
+        }
+
+        if offset + written == upload.length {
+            if let Some((algorithm, expected)) = &upload.checksum {
+                match file_checksum(&upload.data_path(&state.config), *algorithm).await {
+                    Ok(checksum) if checksum == *expected => {}
+                    _ => {
+                        upload.remove(&state.config, Some(&server)).await;
+
+                        return (
+                            StatusCode::from_u16(CHECKSUM_MISMATCH).unwrap(),
+                            response_headers,
+                            axum::Json(ApiError::new("checksum mismatch").to_json()),
+                        );
+                    }
+                }
+            }
+
+            if server.filesystem.is_ignored(&upload.path, false).await {
+                upload.remove(&state.config, Some(&server)).await;
+
+                return (
+                    StatusCode::NOT_FOUND,
+                    response_headers,
+                    axum::Json(ApiError::new("file not found").to_json()),
+                );
+            }
+
+            if let Err(err) = finish_upload(&state, &server, &upload).await {
+                tracing::error!(
+                    server = %server.uuid,
+                    "failed to finish upload: {:#?}",
+                    err
+                );
+
+                return (
+                    StatusCode::EXPECTATION_FAILED,
+                    response_headers,
+                    axum::Json(ApiError::new("failed to finish upload").to_json()),
+                );
+            }
+
+            tokio::fs::remove_file(TusUpload::info_path(&state.config, upload.identifier))
+                .await
+                .ok();
+
+            server
+                .activity
+                .log_activity(Activity {
+                    event: ActivityEvent::FileUploaded,
+                    user: Some(upload.user_uuid),
+                    ip: Some(state.config.find_ip(&headers, connect_info)),
+                    metadata: Some(json!({
+                        "file": upload.path.file_name().map(|name| name.to_string_lossy()),
+                        "directory": upload.path.parent(),
+                    })),
+                    timestamp: chrono::Utc::now(),
+                })
+                .await;
+        }
+
+        (
+            StatusCode::NO_CONTENT,
+            response_headers,
+            axum::Json(json!({})),
+        )
+    }
+}
+
+mod delete {
+    use crate::routes::GetState;
+    use axum::{
+        extract::{Path, Query},
+        http::{HeaderMap, StatusCode},
+    };
+
+    #[utoipa::path(delete, path = "/", responses(
+        (status = NO_CONTENT, body = String),
+        (status = UNAUTHORIZED, body = String),
+        (status = NOT_FOUND, body = String),
+        (status = LOCKED, body = String),
+    ), params(
+        (
+            "upload" = uuid::Uuid,
+            description = "The upload ID",
+            example = "123e4567-e89b-12d3-a456-426614174000",
+        ),
+        (
+            "token" = String, Query,
+            description = "The JWT token used to create the upload",
+        ),
+    ))]
+    pub async fn route(
+        state: GetState,
+        Path(upload_id): Path<uuid::Uuid>,
+        Query(data): Query<super::Params>,
+    ) -> (StatusCode, HeaderMap) {
+        let upload = match super::super::TusUpload::load(&state.config, upload_id).await {
+            Ok(upload) => upload,
+            Err(_) => return (StatusCode::NOT_FOUND, super::super::tus_headers()),
+        };
+
+        if !super::super::verify_session_token(&state, &data.token, &upload) {
+            return (StatusCode::UNAUTHORIZED, super::super::tus_headers());
+        }
+
+        let _lock = match super::super::lock_upload(upload.identifier) {
+            Some(lock) => lock,
+            None => return (StatusCode::LOCKED, super::super::tus_headers()),
+        };
+
+        let server = state
+            .server_manager
+            .get_servers()
+            .await
+            .iter()
+            .find(|s| s.uuid == upload.server_uuid)
+            .cloned();
+
+        upload.remove(&state.config, server.as_ref()).await;
+
+        (StatusCode::NO_CONTENT, super::super::tus_headers())
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .routes(routes!(head::route))
+        .routes(routes!(patch::route).layer(DefaultBodyLimit::disable()))
+        .routes(routes!(delete::route))
+        .with_state(state.clone())
+}
@@ -0,0 +1,595 @@
+use super::State;
+use axum::http::HeaderMap;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha1::Digest;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+mod _upload_;
+
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,checksum,termination";
+pub const TUS_CHECKSUM_ALGORITHMS: &str = "md5,crc32,sha1,sha256";
+
+/// Uploads that have not received any data in this time are removed
+const UPLOAD_EXPIRATION: chrono::Duration = chrono::Duration::hours(24);
+/// How often the upload directory is checked for expired uploads
+const SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+static ACTIVE_UPLOADS: LazyLock<Mutex<HashSet<uuid::Uuid>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+#[derive(Deserialize)]
+pub struct UploadJwtPayload {
+    #[serde(flatten)]
+    pub base: crate::remote::jwt::BasePayload,
+
+    pub server_uuid: uuid::Uuid,
+    pub user_uuid: uuid::Uuid,
+    pub unique_id: String,
+    #[serde(default)]
+    pub permissions: Option<crate::server::permissions::Permissions>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TusUpload {
+    pub identifier: uuid::Uuid,
+    pub jwt_id: String,
+
+    pub server_uuid: uuid::Uuid,
+    pub user_uuid: uuid::Uuid,
+
+    pub path: PathBuf,
+    pub length: u64,
+    pub checksum: Option<(ChecksumAlgorithm, Vec<u8>)>,
+
+    pub updated: chrono::DateTime<chrono::Utc>,
+}
+
+impl TusUpload {
+    #[inline]
+    pub fn directory(config: &crate::config::Config) -> PathBuf {
+        Path::new(&config.system.tmp_directory).join("uploads")
+    }
+
+    #[inline]
+    pub fn data_path(&self, config: &crate::config::Config) -> PathBuf {
+        Self::directory(config).join(format!("{}.part", self.identifier))
+    }
+
+    #[inline]
+    pub fn info_path(config: &crate::config::Config, identifier: uuid::Uuid) -> PathBuf {
+        Self::directory(config).join(format!("{identifier}.json"))
+    }
+
+    pub async fn load(
+        config: &crate::config::Config,
+        identifier: uuid::Uuid,
+    ) -> Result<Self, anyhow::Error> {
+        let content = tokio::fs::read(Self::info_path(config, identifier)).await?;
+
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    pub async fn save(&self, config: &crate::config::Config) -> Result<(), anyhow::Error> {
+        tokio::fs::write(
+            Self::info_path(config, self.identifier),
+            serde_json::to_vec(self)?,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn offset(&self, config: &crate::config::Config) -> u64 {
+        tokio::fs::metadata(self.data_path(config))
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0)
+    }
+
+    /// Removes the upload from disk and releases the space charged to the server for it
+    pub async fn remove(
+        &self,
+        config: &crate::config::Config,
+        server: Option<&crate::server::Server>,
+    ) {
+        if let Some(server) = server {
+            server.filesystem.release_upload(self.offset(config).await);
+        }
+
+        tokio::fs::remove_file(self.data_path(config)).await.ok();
+        tokio::fs::remove_file(Self::info_path(config, self.identifier))
+            .await
+            .ok();
+    }
+
+    #[inline]
+    pub fn is_expired(&self) -> bool {
+        self.updated + UPLOAD_EXPIRATION < chrono::Utc::now()
+    }
+}
+
+/// Removes expired uploads. Reservations do not survive a restart, so the first sweep
+/// reserves the space of the remaining uploads again and drops the ones that no longer fit
+async fn sweep_uploads(state: &crate::routes::AppState, reserve: bool) {
+    let mut entries = match tokio::fs::read_dir(TusUpload::directory(&state.config)).await {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let identifier = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_suffix(".json"))
+            .and_then(|name| uuid::Uuid::parse_str(name).ok())
+        {
+            Some(identifier) => identifier,
+            None => continue,
+        };
+
+        let _lock = match lock_upload(identifier) {
+            Some(lock) => lock,
+            None => continue,
+        };
+
+        let upload = match TusUpload::load(&state.config, identifier).await {
+            Ok(upload) => upload,
+            Err(_) => continue,
+        };
+
+        let server = state
+            .server_manager
+            .get_servers()
+            .await
+            .iter()
+            .find(|s| s.uuid == upload.server_uuid)
+            .cloned();
+
+        if upload.is_expired() {
+            upload.remove(&state.config, server.as_ref()).await;
+        } else if reserve {
+            let reserved = match &server {
+                Some(server) => server
+                    .filesystem
+                    .reserve_upload(upload.offset(&state.config).await),
+                None => false,
+            };
+
+            if !reserved {
+                upload.remove(&state.config, None).await;
+            }
+        }
+    }
+}
+
+/// Marks an upload as in use, returns `None` if another request is already writing to it
+pub fn lock_upload(identifier: uuid::Uuid) -> Option<UploadLock> {
+    if ACTIVE_UPLOADS.lock().unwrap().insert(identifier) {
+        Some(UploadLock(identifier))
+    } else {
+        None
+    }
+}
+
+pub struct UploadLock(uuid::Uuid);
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        ACTIVE_UPLOADS.lock().unwrap().remove(&self.0);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Crc32,
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub fn parse(algorithm: &str) -> Option<Self> {
+        match algorithm.to_lowercase().as_str() {
+            "md5" => Some(Self::Md5),
+            "crc32" => Some(Self::Crc32),
+            "sha1" => Some(Self::Sha1),
+            "sha256" => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn hasher(self) -> ChecksumHasher {
+        match self {
+            Self::Md5 => ChecksumHasher::Md5(md5::Context::new()),
+            Self::Crc32 => ChecksumHasher::Crc32(crc32fast::Hasher::new()),
+            Self::Sha1 => ChecksumHasher::Sha1(sha1::Sha1::new()),
+            Self::Sha256 => ChecksumHasher::Sha256(sha2::Sha256::new()),
+        }
+    }
+}
+
+pub enum ChecksumHasher {
+    Md5(md5::Context),
+    Crc32(crc32fast::Hasher),
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+}
+
+impl ChecksumHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Md5(hasher) => hasher.consume(data),
+            Self::Crc32(hasher) => hasher.update(data),
+            Self::Sha1(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Md5(hasher) => hasher.compute().0.to_vec(),
+            Self::Crc32(hasher) => hasher.finalize().to_be_bytes().to_vec(),
+            Self::Sha1(hasher) => hasher.finalize().to_vec(),
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+/// Parses a `<algorithm> <base64 digest>` checksum as used by the tus checksum extension
+pub fn parse_checksum(value: &str) -> Option<(ChecksumAlgorithm, Vec<u8>)> {
+    let (algorithm, digest) = value.trim().split_once(' ')?;
+
+    Some((
+        ChecksumAlgorithm::parse(algorithm)?,
+        base64::engine::general_purpose::STANDARD
+            .decode(digest.trim())
+            .ok()?,
+    ))
+}
+
+/// Parses the comma separated `key base64(value)` pairs of the `Upload-Metadata` header
+pub fn parse_metadata(value: &str) -> Vec<(String, String)> {
+    value
+        .split(',')
+        .filter_map(|pair| {
+            let mut parts = pair.trim().splitn(2, ' ');
+            let key = parts.next()?.to_string();
+            let value = match parts.next() {
+                Some(value) => String::from_utf8(
+                    base64::engine::general_purpose::STANDARD
+                        .decode(value.trim())
+                        .ok()?,
+                )
+                .ok()?,
+                None => String::new(),
+            };
+
+            Some((key, value))
+        })
+        .collect()
+}
+
+#[inline]
+pub fn tus_headers() -> HeaderMap {
+    HeaderMap::from_iter([
+        (
+            "Tus-Resumable".parse().unwrap(),
+            TUS_VERSION.parse().unwrap(),
+        ),
+        (
+            "Cache-Control".parse().unwrap(),
+            "no-store".parse().unwrap(),
+        ),
+    ])
+}
+
+/// Verifies a token for an existing upload session, the token has to be the one
+/// used to create the session, but may expire while the session is still active
+pub fn verify_session_token(
+    state: &crate::routes::AppState,
+    token: &str,
+    upload: &TusUpload,
+) -> bool {
+    let payload: UploadJwtPayload = match state.config.jwt.verify(token) {
+        Ok(payload) => payload,
+        Err(_) => return false,
+    };
+
+    if payload.base.jwt_id != upload.jwt_id
+        || payload.server_uuid != upload.server_uuid
+        || payload.user_uuid != upload.user_uuid
+    {
+        return false;
+    }
+
+    state
+        .config
+        .jwt
+        .denied_jtokens
+        .read()
+        .unwrap()
+        .get(&payload.base.jwt_id)
+        .is_none_or(|expiration| *expiration <= chrono::Utc::now())
+}
+
+mod options {
+    use axum::http::{HeaderMap, StatusCode};
+
+    #[utoipa::path(options, path = "/", responses(
+        (status = NO_CONTENT, body = String),
+    ))]
+    pub async fn route() -> (StatusCode, HeaderMap) {
+        let mut headers = super::tus_headers();
+        headers.insert("Tus-Version", super::TUS_VERSION.parse().unwrap());
+        headers.insert("Tus-Extension", super::TUS_EXTENSIONS.parse().unwrap());
+        headers.insert(
+            "Tus-Checksum-Algorithm",
+            super::TUS_CHECKSUM_ALGORITHMS.parse().unwrap(),
+        );
+
+        (StatusCode::NO_CONTENT, headers)
+    }
+}
+
+mod post {
+    use super::TusUpload;
+    use crate::{
+        routes::{ApiError, GetState},
+        server::permissions::Permission,
+    };
+    use axum::{
+        extract::Query,
+        http::{HeaderMap, StatusCode},
+    };
+    use serde::Deserialize;
+    use std::path::PathBuf;
+    use utoipa::ToSchema;
+
+    #[derive(ToSchema, Deserialize)]
+    pub struct Params {
+        token: String,
+        #[serde(default)]
+        directory: String,
+    }
+
+    #[utoipa::path(post, path = "/", responses(
+        (status = CREATED, body = String),
+        (status = UNAUTHORIZED, body = inline(ApiError)),
+        (status = NOT_FOUND, body = inline(ApiError)),
+        (status = BAD_REQUEST, body = inline(ApiError)),
+        (status = EXPECTATION_FAILED, body = inline(ApiError)),
+    ), params(
+        (
+            "token" = String, Query,
+            description = "The JWT token to use for authentication",
+        ),
+        (
+            "directory" = String, Query,
+            description = "The directory to upload the file to",
+        ),
+    ))]
+    pub async fn route(
+        state: GetState,
+        headers: HeaderMap,
+        Query(data): Query<Params>,
+    ) -> (StatusCode, HeaderMap, axum::Json<serde_json::Value>) {
+        let payload: super::UploadJwtPayload = match state.config.jwt.verify(&data.token) {
+            Ok(payload) => payload,
+            Err(_) => {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    super::tus_headers(),
+                    axum::Json(ApiError::new("invalid token").to_json()),
+                );
+            }
+        };
+
+        if !payload.base.validate(&state.config.jwt) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                super::tus_headers(),
+                axum::Json(ApiError::new("invalid token").to_json()),
+            );
+        }
+
+        if !state.config.jwt.one_time_id(&payload.unique_id) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                super::tus_headers(),
+                axum::Json(ApiError::new("token has already been used").to_json()),
+            );
+        }
+
+        let server = state
+            .server_manager
+            .get_servers()
+            .await
+            .iter()
+            .find(|s| s.uuid == payload.server_uuid)
+            .cloned();
+
+        let server = match server {
+            Some(server) => server,
+            None => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    super::tus_headers(),
+                    axum::Json(ApiError::new("server not found").to_json()),
+                );
+            }
+        };
+
+        let length = match headers
+            .get("Upload-Length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+        {
+            Some(length) => length,
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    super::tus_headers(),
+                    axum::Json(ApiError::new("invalid upload length").to_json()),
+                );
+            }
+        };
+
+        let metadata = super::parse_metadata(
+            headers
+                .get("Upload-Metadata")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default(),
+        );
+
+        let filename = match metadata
+            .iter()
+            .find(|(key, _)| key == "filename" || key == "name")
+        {
+            Some((_, filename)) if !filename.is_empty() => PathBuf::from(filename),
+            _ => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    super::tus_headers(),
+                    axum::Json(ApiError::new("file name not found").to_json()),
+                );
+            }
+        };
+
+        let checksum = match metadata.iter().find(|(key, _)| key == "checksum") {
+            Some((_, checksum)) => match super::parse_checksum(checksum) {
+                Some(checksum) => Some(checksum),
+                None => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        super::tus_headers(),
+                        axum::Json(ApiError::new("invalid checksum").to_json()),
+                    );
+                }
+            },
+            None => None,
+        };
+
+        let directory = match server.filesystem.canonicalize(&data.directory).await {
+            Ok(directory) => directory,
+            Err(_) => PathBuf::from(data.directory),
+        };
+
+        let metadata = server.filesystem.metadata(&directory).await;
+        if !metadata.map(|m| m.is_dir()).unwrap_or(true) {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                super::tus_headers(),
+                axum::Json(ApiError::new("directory is not a directory").to_json()),
+            );
+        }
+
+        let file_path = server
+            .filesystem
+            .relative_path(&directory.join(filename.file_name().unwrap_or_default()));
+        if file_path.file_name().is_none()
+            || server.filesystem.is_ignored(&file_path, false).await
+            || server
+                .filesystem
+                .is_outside_grants(
+                    &file_path,
+                    payload.permissions.as_ref(),
+                    Permission::FileCreate,
+                )
+                .await
+        {
+            return (
+                StatusCode::NOT_FOUND,
+                super::tus_headers(),
+                axum::Json(ApiError::new("file not found").to_json()),
+            );
+        }
+
+        if server.filesystem.disk_limit() != 0
+            && server.filesystem.limiter_usage().await + length
+                > server.filesystem.disk_limit() as u64
+        {
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                super::tus_headers(),
+                axum::Json(ApiError::new("failed to allocate space").to_json()),
+            );
+        }
+
+        let upload = TusUpload {
+            identifier: uuid::Uuid::new_v4(),
+            jwt_id: payload.base.jwt_id,
+            server_uuid: server.uuid,
+            user_uuid: payload.user_uuid,
+            path: file_path,
+            length,
+            checksum,
+            updated: chrono::Utc::now(),
+        };
+
+        let upload_directory = TusUpload::directory(&state.config);
+        if let Err(err) = async {
+            tokio::fs::create_dir_all(&upload_directory).await?;
+            tokio::fs::File::create(upload.data_path(&state.config)).await?;
+            upload.save(&state.config).await
+        }
+        .await
+        {
+            tracing::error!(
+                server = %server.uuid,
+                "failed to create upload: {:#?}",
+                err
+            );
+
+            return (
+                StatusCode::EXPECTATION_FAILED,
+                super::tus_headers(),
+                axum::Json(ApiError::new("failed to create upload").to_json()),
+            );
+        }
+
+        let mut headers = super::tus_headers();
+        headers.insert(
+            "Location",
+            format!("/upload/tus/{}?token={}", upload.identifier, data.token)
+                .parse()
+                .unwrap(),
+        );
+        headers.insert("Upload-Offset", 0.into());
+
+        (
+            StatusCode::CREATED,
+            headers,
+            axum::Json(serde_json::json!({})),
+        )
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    tokio::spawn({
+        let state = state.clone();
+
+        async move {
+            sweep_uploads(&state, true).await;
+
+            loop {
+                tokio::time::sleep(SWEEP_INTERVAL).await;
+                sweep_uploads(&state, false).await;
+            }
+        }
+    });
+
+    OpenApiRouter::new()
+        .nest("/{upload}", _upload_::router(state))
+        .routes(routes!(options::route))
+        .routes(routes!(post::route))
+        .with_state(state.clone())
+}
@@ -0,0 +1,647 @@
+use super::Dav;
+use crate::server::{
+    activity::ActivityEvent,
+    permissions::Permission,
+    throttle::{Direction, Throttle},
+};
+use axum::{
+    body::Body,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+pub async fn get(dav: &Dav, head: bool) -> Result<Response, StatusCode> {
+    if !dav.has_any_permission(Permission::FileReadContent) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let metadata = dav
+        .server
+        .filesystem
+        .metadata(&dav.path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    if dav
+        .server
+        .filesystem
+        .is_hidden(&dav.path, metadata.is_dir(), &dav.user_permissions)
+        .await
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    if metadata.is_dir() {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    if dav
+        .server
+        .filesystem
+        .is_denied(
+            &dav.path,
+            false,
+            &dav.user_permissions,
+            Permission::FileReadContent,
+        )
+        .await
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let file = dav
+        .server
+        .filesystem
+        .open(&dav.path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Type", "application/octet-stream".parse().unwrap());
+
+    let (status, headers, body) =
+        crate::routes::conditional::file_response(&dav.headers, headers, &metadata, file).await;
+
+    if head {
+        return Ok((status, headers).into_response());
+    }
+
+    if status.is_success() {
+        dav.log_activity(
+            ActivityEvent::SftpRead,
+            json!({
+                "files": [dav.server.filesystem.relative_path(&dav.path)],
+                "bytes": headers
+                    .get("Content-Length")
+                    .and_then(|length| length.to_str().ok())
+                    .and_then(|length| length.parse::<u64>().ok())
+                    .unwrap_or(0),
+            }),
+        )
+        .await;
+    }
+
+    let throttle = Throttle::new(
+        &dav.state.config,
+        &dav.state.bandwidth,
+        &dav.server,
+        Direction::Download,
+    )
+    .await;
+
+    Ok((status, headers, throttle.body(body)).into_response())
+}
+
+pub async fn put(dav: &Dav, body: Body) -> Result<Response, StatusCode> {
+    if dav.is_read_only() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let metadata = dav.server.filesystem.metadata(&dav.path).await.ok();
+    if metadata
+        .as_ref()
+        .is_some_and(|metadata| !metadata.is_file())
+    {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    let permission = match metadata {
+        Some(_) => Permission::FileUpdate,
+        None => Permission::FileCreate,
+    };
+    if dav
+        .server
+        .filesystem
+        .is_denied(&dav.path, false, &dav.user_permissions, permission)
+        .await
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if !dav.has_parent(&dav.path).await {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    if !crate::routes::conditional::matches_precondition(&dav.headers, metadata.as_ref()) {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    }
+
+    if !dav.is_unlocked(&dav.path, false) {
+        return Err(StatusCode::LOCKED);
+    }
+
+    // the body is written next to the file and only replaces it once it arrived in full
+    let parent = dav.path.parent().unwrap_or(Path::new(""));
+    let temporary = parent.join(format!(
+        ".{}.{:08x}",
+        dav.path.file_name().unwrap_or_default().to_string_lossy(),
+        rand::random::<u32>()
+    ));
+
+    let mut file = dav
+        .server
+        .filesystem
+        .create(&temporary)
+        .await
+        .map_err(|_| StatusCode::CONFLICT)?;
+
+    let mut allocated = 0;
+    let written = write_body(dav, &mut file, parent, &mut allocated, body).await;
+    drop(file);
+
+    let replaced = match written {
+        Ok(()) => replace_file(dav, &temporary, metadata.as_ref()).await,
+        Err(status) => Err(status),
+    };
+    if let Err(status) = replaced {
+        dav.server
+            .filesystem
+            .allocate_in_path(parent, -allocated)
+            .await;
+        if let Ok(filesystem) = dav.server.filesystem.base_dir().await {
+            let temporary = dav.server.filesystem.relative_path(&temporary);
+            tokio::task::spawn_blocking(move || filesystem.remove_file(temporary))
+                .await
+                .ok();
+        }
+
+        return Err(status);
+    }
+
+    dav.log_activity(
+        match metadata {
+            Some(_) => ActivityEvent::SftpWrite,
+            None => ActivityEvent::SftpCreate,
+        },
+        json!({
+            "files": [dav.server.filesystem.relative_path(&dav.path)],
+        }),
+    )
+    .await;
+
+    Ok(match metadata {
+        Some(_) => StatusCode::NO_CONTENT,
+        None => StatusCode::CREATED,
+    }
+    .into_response())
+}
+
+/// Streams a PUT body into `file`, `allocated` keeps the bytes that were allocated so far
+async fn write_body(
+    dav: &Dav,
+    file: &mut tokio::fs::File,
+    parent: &Path,
+    allocated: &mut i64,
+    body: Body,
+) -> Result<(), StatusCode> {
+    let throttle = Throttle::new(
+        &dav.state.config,
+        &dav.state.bandwidth,
+        &dav.server,
+        Direction::Upload,
+    )
+    .await;
+    let upload_limit = dav.state.config.api.upload_limit * 1000 * 1000;
+
+    let mut written_size = 0;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+
+        if written_size + chunk.len() > upload_limit {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        throttle.consume(chunk.len()).await;
+        if !dav
+            .server
+            .filesystem
+            .allocate_in_path(parent, chunk.len() as i64)
+            .await
+        {
+            return Err(StatusCode::INSUFFICIENT_STORAGE);
+        }
+        *allocated += chunk.len() as i64;
+
+        file.write_all(&chunk)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        written_size += chunk.len();
+    }
+
+    file.flush()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Moves a fully written upload over the file at the request path,
+/// the replaced file keeps its permissions and no longer counts towards the disk usage
+async fn replace_file(
+    dav: &Dav,
+    temporary: &Path,
+    replaced: Option<&cap_std::fs::Metadata>,
+) -> Result<(), StatusCode> {
+    if let Some(replaced) = replaced {
+        dav.server
+            .filesystem
+            .set_permissions(temporary, replaced.permissions())
+            .await
+            .ok();
+    }
+
+    dav.server
+        .filesystem
+        .rename_path(temporary, &dav.path)
+        .await
+        .map_err(|_| StatusCode::CONFLICT)?;
+
+    if let Some(replaced) = replaced {
+        dav.server
+            .filesystem
+            .allocate_in_path(
+                dav.path.parent().unwrap_or(Path::new("")),
+                -(replaced.len() as i64),
+            )
+            .await;
+    }
+    dav.server.filesystem.chown_path(&dav.path).await;
+
+    Ok(())
+}
+
+pub async fn delete(dav: &Dav) -> Result<Response, StatusCode> {
+    if dav.is_read_only() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if dav.path.components().next().is_none() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let metadata = dav
+        .server
+        .filesystem
+        .symlink_metadata(&dav.path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    if dav
+        .server
+        .filesystem
+        .is_denied(
+            &dav.path,
+            metadata.is_dir(),
+            &dav.user_permissions,
+            Permission::FileDelete,
+        )
+        .await
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if !dav.is_unlocked(&dav.path, true) {
+        return Err(StatusCode::LOCKED);
+    }
+
+    if dav.server.filesystem.trash_path(&dav.path).await.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    dav.state
+        .webdav_locks
+        .remove_tree(dav.server.uuid, &dav.path);
+
+    dav.log_activity(
+        ActivityEvent::SftpDelete,
+        json!({
+            "files": [dav.server.filesystem.relative_path(&dav.path)],
+        }),
+    )
+    .await;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn mkcol(dav: &Dav, body: Body) -> Result<Response, StatusCode> {
+    let body = axum::body::to_bytes(body, 0)
+        .await
+        .map_err(|_| StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+    if !body.is_empty() {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    if dav.is_read_only() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if dav
+        .server
+        .filesystem
+        .symlink_metadata(&dav.path)
+        .await
+        .is_ok()
+    {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    if dav
+        .server
+        .filesystem
+        .is_denied(
+            &dav.path,
+            true,
+            &dav.user_permissions,
+            Permission::FileCreate,
+        )
+        .await
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if !dav.has_parent(&dav.path).await {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    if !dav.is_unlocked(&dav.path, false) {
+        return Err(StatusCode::LOCKED);
+    }
+
+    if dav.server.filesystem.create_dir(&dav.path).await.is_err() {
+        return Err(StatusCode::CONFLICT);
+    }
+    dav.server.filesystem.chown_path(&dav.path).await;
+
+    dav.log_activity(
+        ActivityEvent::SftpCreateDirectory,
+        json!({
+            "files": [dav.server.filesystem.relative_path(&dav.path)],
+        }),
+    )
+    .await;
+
+    Ok(StatusCode::CREATED.into_response())
+}
+
+/// Checks shared by `COPY` and `MOVE` and clears the destination if it may be replaced,
+/// returns whether the destination existed before
+async fn prepare_destination(
+    dav: &Dav,
+    destination: &Path,
+    is_dir: bool,
+) -> Result<bool, StatusCode> {
+    if destination == dav.path || (is_dir && destination.starts_with(&dav.path)) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if !dav.has_parent(destination).await {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    if !dav.is_unlocked(destination, true) {
+        return Err(StatusCode::LOCKED);
+    }
+
+    let existing = match dav.server.filesystem.symlink_metadata(destination).await {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(false),
+    };
+
+    if !dav.overwrite() {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    }
+
+    if dav
+        .server
+        .filesystem
+        .is_denied(
+            destination,
+            existing.is_dir(),
+            &dav.user_permissions,
+            Permission::FileDelete,
+        )
+        .await
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if dav.server.filesystem.trash_path(destination).await.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    dav.state
+        .webdav_locks
+        .remove_tree(dav.server.uuid, destination);
+
+    Ok(true)
+}
+
+pub async fn copy(dav: &Dav) -> Result<Response, StatusCode> {
+    if dav.is_read_only() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let destination = dav.destination()?;
+
+    // copying a collection into itself would keep finding its own copy
+    if destination.starts_with(&dav.path) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let metadata = dav
+        .server
+        .filesystem
+        .symlink_metadata(&dav.path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    if metadata.is_symlink() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if dav
+        .server
+        .filesystem
+        .is_denied(
+            &dav.path,
+            metadata.is_dir(),
+            &dav.user_permissions,
+            Permission::FileReadContent,
+        )
+        .await
+        || dav
+            .server
+            .filesystem
+            .is_denied(
+                &destination,
+                metadata.is_dir(),
+                &dav.user_permissions,
+                Permission::FileCreate,
+            )
+            .await
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let infinite = dav.header("Depth").is_none_or(|depth| depth.trim() != "0");
+    let existed = prepare_destination(dav, &destination, metadata.is_dir()).await?;
+
+    let mut pending = vec![(dav.path.clone(), destination.clone(), metadata)];
+    while let Some((source, target, metadata)) = pending.pop() {
+        if metadata.is_dir() {
+            if dav.server.filesystem.create_dir(&target).await.is_err() {
+                return Err(StatusCode::CONFLICT);
+            }
+            dav.server.filesystem.chown_path(&target).await;
+
+            if !infinite {
+                continue;
+            }
+
+            let mut directory = dav
+                .server
+                .filesystem
+                .read_dir(&source)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            while let Some(Ok(name)) = directory.next_entry().await {
+                let source = source.join(&name);
+                let metadata = match dav.server.filesystem.symlink_metadata(&source).await {
+                    Ok(metadata) => metadata,
+                    Err(_) => continue,
+                };
+
+                if metadata.is_symlink()
+                    || dav
+                        .server
+                        .filesystem
+                        .is_denied(
+                            &source,
+                            metadata.is_dir(),
+                            &dav.user_permissions,
+                            Permission::FileReadContent,
+                        )
+                        .await
+                {
+                    continue;
+                }
+
+                pending.push((source, target.join(&name), metadata));
+            }
+        } else {
+            if !dav
+                .server
+                .filesystem
+                .allocate_in_path(
+                    target.parent().unwrap_or(Path::new("")),
+                    metadata.len() as i64,
+                )
+                .await
+            {
+                return Err(StatusCode::INSUFFICIENT_STORAGE);
+            }
+
+            if dav.server.filesystem.copy(&source, &target).await.is_err() {
+                dav.server
+                    .filesystem
+                    .allocate_in_path(
+                        target.parent().unwrap_or(Path::new("")),
+                        -(metadata.len() as i64),
+                    )
+                    .await;
+
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+            dav.server.filesystem.chown_path(&target).await;
+        }
+    }
+
+    dav.log_activity(
+        ActivityEvent::SftpCreate,
+        json!({
+            "files": [dav.server.filesystem.relative_path(&destination)],
+        }),
+    )
+    .await;
+
+    Ok(match existed {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::CREATED,
+    }
+    .into_response())
+}
+
+pub async fn r#move(dav: &Dav) -> Result<Response, StatusCode> {
+    if dav.is_read_only() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if dav.path.components().next().is_none() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let destination: PathBuf = dav.destination()?;
+
+    let metadata = dav
+        .server
+        .filesystem
+        .symlink_metadata(&dav.path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    for path in [&dav.path, &destination] {
+        if dav
+            .server
+            .filesystem
+            .is_denied(
+                path,
+                metadata.is_dir(),
+                &dav.user_permissions,
+                Permission::FileUpdate,
+            )
+            .await
+        {
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+
+    if !dav.is_unlocked(&dav.path, true) {
+        return Err(StatusCode::LOCKED);
+    }
+
+    let existed = prepare_destination(dav, &destination, metadata.is_dir()).await?;
+
+    if dav
+        .server
+        .filesystem
+        .rename_path(&dav.path, &destination)
+        .await
+        .is_err()
+    {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    dav.state
+        .webdav_locks
+        .remove_tree(dav.server.uuid, &dav.path);
+
+    dav.log_activity(
+        ActivityEvent::SftpRename,
+        json!({
+            "files": [
+                {
+                    "from": dav.server.filesystem.relative_path(&dav.path),
+                    "to": dav.server.filesystem.relative_path(&destination),
+                }
+            ],
+        }),
+    )
+    .await;
+
+    Ok(match existed {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::CREATED,
+    }
+    .into_response())
+}
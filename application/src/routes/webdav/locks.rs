@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// seconds a lock is held when the client does not ask for a timeout
+const DEFAULT_TIMEOUT: u64 = 600;
+/// clients asking for infinite locks get this instead
+const MAX_TIMEOUT: u64 = 3600;
+
+#[derive(Clone)]
+pub struct Lock {
+    pub token: String,
+    pub server: uuid::Uuid,
+    pub user: uuid::Uuid,
+    pub path: PathBuf,
+
+    pub exclusive: bool,
+    pub infinite: bool,
+    /// serialized `owner` element the client sent
+    pub owner: String,
+    pub timeout: u64,
+    expires: Instant,
+}
+
+impl Lock {
+    /// Whether the lock applies to `path`, either directly or through a depth infinity lock
+    /// on one of its parents
+    #[inline]
+    fn covers(&self, path: &Path) -> bool {
+        self.path == path || (self.infinite && path.starts_with(&self.path))
+    }
+
+    #[inline]
+    fn is_expired(&self) -> bool {
+        self.expires <= Instant::now()
+    }
+}
+
+/// Parses a `Timeout` header, like `Second-3600, Infinite`
+pub fn parse_timeout(value: Option<&str>) -> u64 {
+    value
+        .and_then(|value| {
+            value
+                .split(',')
+                .map(|timeout| timeout.trim())
+                .find_map(|timeout| {
+                    if timeout.eq_ignore_ascii_case("infinite") {
+                        Some(MAX_TIMEOUT)
+                    } else {
+                        timeout.strip_prefix("Second-")?.parse().ok()
+                    }
+                })
+        })
+        .unwrap_or(DEFAULT_TIMEOUT)
+        .clamp(1, MAX_TIMEOUT)
+}
+
+/// Lock tokens submitted in an `If` header, tagged lists and etags are not evaluated
+pub fn submitted_tokens(value: Option<&str>) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut rest = value.unwrap_or_default();
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+
+        if rest[..end].starts_with("opaquelocktoken:") {
+            tokens.push(rest[..end].to_string());
+        }
+        rest = &rest[end + 1..];
+    }
+
+    tokens
+}
+
+/// Write locks of webdav clients, they only live in memory and are lost on restart
+#[derive(Default)]
+pub struct LockManager {
+    locks: Mutex<HashMap<String, Lock>>,
+}
+
+impl LockManager {
+    /// Locks `path`, fails if it conflicts with an existing lock
+    #[allow(clippy::too_many_arguments)]
+    pub fn lock(
+        &self,
+        server: uuid::Uuid,
+        user: uuid::Uuid,
+        path: &Path,
+        exclusive: bool,
+        infinite: bool,
+        owner: String,
+        timeout: u64,
+    ) -> Option<Lock> {
+        let mut locks = self.locks.lock().unwrap();
+        locks.retain(|_, lock| !lock.is_expired());
+
+        if locks.values().any(|lock| {
+            lock.server == server
+                && (lock.covers(path) || (infinite && lock.path.starts_with(path)))
+                && (exclusive || lock.exclusive)
+        }) {
+            return None;
+        }
+
+        let lock = Lock {
+            token: format!("opaquelocktoken:{}", uuid::Uuid::new_v4()),
+            server,
+            user,
+            path: path.to_path_buf(),
+            exclusive,
+            infinite,
+            owner,
+            timeout,
+            expires: Instant::now() + Duration::from_secs(timeout),
+        };
+        locks.insert(lock.token.clone(), lock.clone());
+
+        Some(lock)
+    }
+
+    /// Extends a lock covering `path` that was created by `user`
+    pub fn refresh(
+        &self,
+        server: uuid::Uuid,
+        user: uuid::Uuid,
+        path: &Path,
+        token: &str,
+        timeout: u64,
+    ) -> Option<Lock> {
+        let mut locks = self.locks.lock().unwrap();
+        locks.retain(|_, lock| !lock.is_expired());
+
+        let lock = locks
+            .get_mut(token)
+            .filter(|lock| lock.server == server && lock.user == user && lock.covers(path))?;
+        lock.timeout = timeout;
+        lock.expires = Instant::now() + Duration::from_secs(timeout);
+
+        Some(lock.clone())
+    }
+
+    /// Removes a lock covering `path` that was created by `user`
+    pub fn unlock(&self, server: uuid::Uuid, user: uuid::Uuid, path: &Path, token: &str) -> bool {
+        let mut locks = self.locks.lock().unwrap();
+
+        if locks
+            .get(token)
+            .is_some_and(|lock| lock.server == server && lock.user == user && lock.covers(path))
+        {
+            locks.remove(token);
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Active locks that apply to `path`
+    pub fn discover(&self, server: uuid::Uuid, path: &Path) -> Vec<Lock> {
+        self.locks
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|lock| lock.server == server && !lock.is_expired() && lock.covers(path))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether `user` may modify `path`, every lock on it has to be submitted.
+    /// With `deep` the locks of everything below `path` have to be submitted as well
+    pub fn is_allowed(
+        &self,
+        server: uuid::Uuid,
+        user: uuid::Uuid,
+        path: &Path,
+        deep: bool,
+        tokens: &[String],
+    ) -> bool {
+        self.locks.lock().unwrap().values().all(|lock| {
+            lock.server != server
+                || lock.is_expired()
+                || !(lock.covers(path) || (deep && lock.path.starts_with(path)))
+                || (lock.user == user && tokens.contains(&lock.token))
+        })
+    }
+
+    /// Drops the locks of `path` and everything below it, after it was deleted or moved
+    pub fn remove_tree(&self, server: uuid::Uuid, path: &Path) {
+        self.locks
+            .lock()
+            .unwrap()
+            .retain(|_, lock| lock.server != server || !lock.path.starts_with(path));
+    }
+}
@@ -0,0 +1,381 @@
+use super::{GetState, State};
+use crate::{
+    remote::AuthenticationType,
+    server::{
+        activity::{Activity, ActivityEvent},
+        permissions::{Permission, Permissions},
+    },
+};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, DefaultBodyLimit, Path as PathParams, Request},
+    http::{HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
+    routing::any,
+};
+use base64::Engine;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    path::{Component, Path, PathBuf},
+};
+use utoipa_axum::router::OpenApiRouter;
+
+mod files;
+pub mod locks;
+mod properties;
+mod xml;
+
+/// characters left alone when building hrefs
+const HREF: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+/// request bodies other than uploads are small xml documents
+const XML_BODY_LIMIT: usize = 64 * 1024;
+
+pub struct Dav {
+    pub state: State,
+    pub server: crate::server::Server,
+
+    pub user_ip: Option<IpAddr>,
+    pub user_uuid: uuid::Uuid,
+    pub user_permissions: Permissions,
+
+    pub headers: HeaderMap,
+    pub path: PathBuf,
+}
+
+impl Dav {
+    #[inline]
+    fn has_any_permission(&self, permission: Permission) -> bool {
+        self.user_permissions.has_any_permission(permission)
+    }
+
+    #[inline]
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// Percent encoded href of a path, collections end with a slash
+    fn href(&self, path: &Path, collection: bool) -> String {
+        let mut href = format!("/webdav/{}", self.server.uuid);
+
+        for component in path.components() {
+            href.push('/');
+            href.extend(utf8_percent_encode(
+                &component.as_os_str().to_string_lossy(),
+                HREF,
+            ));
+        }
+
+        if collection || path.components().next().is_none() {
+            href.push('/');
+        }
+
+        href
+    }
+
+    /// Resolves the `Destination` header of `COPY` and `MOVE` to a path of the same server
+    fn destination(&self) -> Result<PathBuf, StatusCode> {
+        let destination = self.header("Destination").ok_or(StatusCode::BAD_REQUEST)?;
+
+        // absolute urls are reduced to their path, the host is not checked
+        let path = match destination.split_once("://") {
+            Some((_, rest)) => rest.find('/').map(|start| &rest[start..]).unwrap_or("/"),
+            None => destination,
+        };
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+
+        let prefix = format!("/webdav/{}", self.server.uuid);
+        let path = match path.strip_prefix(&prefix) {
+            Some(path) if path.is_empty() || path.starts_with('/') => path,
+            _ => return Err(StatusCode::BAD_GATEWAY),
+        };
+
+        normalize_path(
+            &percent_decode_str(path)
+                .decode_utf8()
+                .map_err(|_| StatusCode::BAD_REQUEST)?,
+        )
+        .ok_or(StatusCode::BAD_REQUEST)
+    }
+
+    /// Whether the `Overwrite` header allows replacing an existing destination
+    #[inline]
+    fn overwrite(&self) -> bool {
+        !self
+            .header("Overwrite")
+            .is_some_and(|overwrite| overwrite.trim().eq_ignore_ascii_case("F"))
+    }
+
+    /// Whether the request submitted the tokens of every lock on `path`
+    fn is_unlocked(&self, path: &Path, deep: bool) -> bool {
+        self.state.webdav_locks.is_allowed(
+            self.server.uuid,
+            self.user_uuid,
+            path,
+            deep,
+            &locks::submitted_tokens(self.header("If")),
+        )
+    }
+
+    /// Whether modifications are refused for this node or the server right now
+    #[inline]
+    fn is_read_only(&self) -> bool {
+        self.state.config.system.sftp.read_only || self.server.is_locked_state()
+    }
+
+    async fn log_activity(&self, event: ActivityEvent, metadata: serde_json::Value) {
+        self.server
+            .activity
+            .log_activity(Activity {
+                event,
+                user: Some(self.user_uuid),
+                ip: self.user_ip,
+                metadata: Some(metadata),
+                timestamp: chrono::Utc::now(),
+            })
+            .await;
+    }
+
+    /// Checks that the parent of a new resource exists and is a collection
+    async fn has_parent(&self, path: &Path) -> bool {
+        match path.parent() {
+            Some(parent) => self
+                .server
+                .filesystem
+                .metadata(parent)
+                .await
+                .is_ok_and(|metadata| metadata.is_dir()),
+            None => false,
+        }
+    }
+}
+
+#[inline]
+fn status(status: StatusCode) -> Response {
+    status.into_response()
+}
+
+/// Turns a decoded request path into a path relative to the server root,
+/// paths leaving the root are refused
+fn normalize_path(path: &str) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(component) => normalized.push(component),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(normalized)
+}
+
+fn unauthorized() -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "WWW-Authenticate",
+        "Basic realm=\"wings\", charset=\"UTF-8\"".parse().unwrap(),
+    );
+
+    (StatusCode::UNAUTHORIZED, headers).into_response()
+}
+
+/// Authenticates the request with the same credentials and panel endpoint as sftp
+async fn authenticate(
+    state: &State,
+    headers: &HeaderMap,
+    user_ip: IpAddr,
+    server: uuid::Uuid,
+) -> Result<(crate::server::Server, uuid::Uuid, Permissions), Response> {
+    if state.config.system.sftp.disable_password_auth {
+        return Err(status(StatusCode::FORBIDDEN));
+    }
+
+    let credentials = match headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|value| {
+            base64::engine::general_purpose::STANDARD
+                .decode(value.trim())
+                .ok()
+        })
+        .and_then(|value| String::from_utf8(value).ok())
+    {
+        Some(credentials) => credentials,
+        None => return Err(unauthorized()),
+    };
+    let (username, password) = match credentials.split_once(':') {
+        Some(credentials) => credentials,
+        None => return Err(unauthorized()),
+    };
+
+    // file managers send a request for every file they look at, so logins are
+    // cached here even when the sftp auth cache is disabled
+    let ttl = state.config.api.webdav_auth_cache_ttl;
+    let cached = match state.sftp_limiter.is_locked(Some(user_ip), username) {
+        true => None,
+        false => state
+            .webdav_auth_cache
+            .get(AuthenticationType::Password, username, password, ttl),
+    };
+
+    let (user, server_uuid, permissions) = match cached {
+        Some(cached) => (cached.user, cached.server, cached.permissions),
+        None => match crate::sftp::auth::authenticate(
+            state,
+            Some(user_ip),
+            AuthenticationType::Password,
+            username,
+            password,
+            true,
+        )
+        .await
+        {
+            Some((user, server, permissions)) => {
+                if ttl > 0 {
+                    state.webdav_auth_cache.prune(ttl);
+                    state.webdav_auth_cache.insert(
+                        AuthenticationType::Password,
+                        username,
+                        password,
+                        user,
+                        server,
+                        permissions.clone(),
+                    );
+                }
+
+                (user, server, permissions)
+            }
+            None => return Err(unauthorized()),
+        },
+    };
+
+    if server_uuid != server {
+        return Err(status(StatusCode::FORBIDDEN));
+    }
+
+    let server = match state
+        .server_manager
+        .get_servers()
+        .await
+        .iter()
+        .find(|s| s.uuid == server)
+        .cloned()
+    {
+        Some(server) => server,
+        None => return Err(status(StatusCode::NOT_FOUND)),
+    };
+
+    Ok((server, user, permissions))
+}
+
+fn options() -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert("DAV", "1, 2".parse().unwrap());
+    headers.insert("MS-Author-Via", "DAV".parse().unwrap());
+    headers.insert(
+        "Allow",
+        "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, COPY, MOVE, PROPFIND, PROPPATCH, LOCK, UNLOCK"
+            .parse()
+            .unwrap(),
+    );
+
+    (StatusCode::OK, headers).into_response()
+}
+
+async fn xml_body(body: Body) -> Result<String, Response> {
+    let body = axum::body::to_bytes(body, XML_BODY_LIMIT)
+        .await
+        .map_err(|_| status(StatusCode::PAYLOAD_TOO_LARGE))?;
+
+    String::from_utf8(body.to_vec()).map_err(|_| status(StatusCode::BAD_REQUEST))
+}
+
+async fn handle(
+    state: GetState,
+    connect_info: ConnectInfo<SocketAddr>,
+    PathParams(params): PathParams<HashMap<String, String>>,
+    request: Request,
+) -> Response {
+    if state.config.api.disable_webdav {
+        return status(StatusCode::NOT_FOUND);
+    }
+
+    // clients probe for dav support before they send credentials
+    if request.method() == Method::OPTIONS {
+        return options();
+    }
+
+    let server = match params.get("server").and_then(|server| server.parse().ok()) {
+        Some(server) => server,
+        None => return status(StatusCode::NOT_FOUND),
+    };
+    let path = match normalize_path(params.get("path").map(|p| p.as_str()).unwrap_or_default()) {
+        Some(path) => path,
+        None => return status(StatusCode::BAD_REQUEST),
+    };
+
+    let (parts, body) = request.into_parts();
+    let user_ip = state.config.find_ip(&parts.headers, connect_info);
+
+    let (server, user_uuid, user_permissions) =
+        match authenticate(&state, &parts.headers, user_ip, server).await {
+            Ok(authenticated) => authenticated,
+            Err(response) => return response,
+        };
+
+    let dav = Dav {
+        state: state.0,
+        server,
+        user_ip: Some(user_ip),
+        user_uuid,
+        user_permissions,
+        headers: parts.headers,
+        path,
+    };
+
+    let response = match parts.method.as_str() {
+        "GET" => files::get(&dav, false).await,
+        "HEAD" => files::get(&dav, true).await,
+        "PUT" => files::put(&dav, body).await,
+        "DELETE" => files::delete(&dav).await,
+        "MKCOL" => files::mkcol(&dav, body).await,
+        "COPY" => files::copy(&dav).await,
+        "MOVE" => files::r#move(&dav).await,
+        "PROPFIND" => match xml_body(body).await {
+            Ok(body) => properties::propfind(&dav, &body).await,
+            Err(response) => return response,
+        },
+        "PROPPATCH" => match xml_body(body).await {
+            Ok(body) => properties::proppatch(&dav, &body).await,
+            Err(response) => return response,
+        },
+        "LOCK" => match xml_body(body).await {
+            Ok(body) => properties::lock(&dav, &body).await,
+            Err(response) => return response,
+        },
+        "UNLOCK" => properties::unlock(&dav).await,
+        _ => Err(StatusCode::METHOD_NOT_ALLOWED),
+    };
+
+    match response {
+        Ok(response) => response,
+        Err(code) => status(code),
+    }
+}
+
+pub fn router(state: &State) -> OpenApiRouter<State> {
+    OpenApiRouter::new()
+        .route("/{server}", any(handle))
+        .route("/{server}/", any(handle))
+        .route("/{server}/{*path}", any(handle))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone())
+}
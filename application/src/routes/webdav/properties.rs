@@ -0,0 +1,513 @@
+use super::{
+    Dav,
+    locks::{self, Lock},
+    xml::{self, DAV},
+};
+use crate::server::{activity::ActivityEvent, permissions::Permission};
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use cap_std::fs::Metadata;
+use serde_json::json;
+use std::path::Path;
+
+/// properties returned for `allprop`, the quota properties have to be asked for
+const PROPERTIES: &[&str] = &[
+    "creationdate",
+    "displayname",
+    "getcontentlength",
+    "getcontenttype",
+    "getetag",
+    "getlastmodified",
+    "resourcetype",
+    "supportedlock",
+    "lockdiscovery",
+];
+/// windows explorer sets these after every upload and gives up if they are refused
+const WIN32_NAMESPACE: &str = "urn:schemas-microsoft-com:";
+
+enum PropFind {
+    All,
+    Names,
+    Properties(Vec<(String, String)>),
+}
+
+fn parse_propfind(body: &str) -> Option<PropFind> {
+    if body.trim().is_empty() {
+        return Some(PropFind::All);
+    }
+
+    let root = xml::parse(body)?;
+    if !root.is(DAV, "propfind") {
+        return None;
+    }
+
+    if root.child(DAV, "propname").is_some() {
+        Some(PropFind::Names)
+    } else if let Some(prop) = root.child(DAV, "prop") {
+        Some(PropFind::Properties(
+            prop.children
+                .iter()
+                .map(|property| (property.namespace.clone(), property.name.clone()))
+                .collect(),
+        ))
+    } else {
+        Some(PropFind::All)
+    }
+}
+
+#[inline]
+fn status_line(status: StatusCode) -> String {
+    format!("HTTP/1.1 {status}")
+}
+
+/// Writes an empty element for a property that may live outside of the dav namespace
+fn empty_property(namespace: &str, name: &str) -> String {
+    if namespace == DAV {
+        format!("<D:{name}/>")
+    } else {
+        format!("<{name} xmlns=\"{}\"/>", xml::escape(namespace))
+    }
+}
+
+fn multistatus(responses: String) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Content-Type",
+        "application/xml; charset=utf-8".parse().unwrap(),
+    );
+
+    (
+        StatusCode::MULTI_STATUS,
+        headers,
+        format!(
+            "{}<D:multistatus xmlns:D=\"DAV:\">{responses}</D:multistatus>",
+            xml::HEADER
+        ),
+    )
+        .into_response()
+}
+
+impl Dav {
+    fn active_lock(&self, lock: &Lock) -> String {
+        format!(
+            "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope>{}</D:lockscope>\
+            <D:depth>{}</D:depth><D:owner>{}</D:owner><D:timeout>Second-{}</D:timeout>\
+            <D:locktoken><D:href>{}</D:href></D:locktoken><D:lockroot><D:href>{}</D:href></D:lockroot>\
+            </D:activelock>",
+            if lock.exclusive {
+                "<D:exclusive/>"
+            } else {
+                "<D:shared/>"
+            },
+            if lock.infinite { "infinity" } else { "0" },
+            lock.owner,
+            lock.timeout,
+            lock.token,
+            xml::escape(&self.href(&lock.path, false)),
+        )
+    }
+
+    /// Value of a live property, `None` if the resource does not have it.
+    /// `quota` is the used and available space, if the server has a disk limit
+    fn property(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        quota: Option<(u64, u64)>,
+        name: &str,
+    ) -> Option<String> {
+        let modified = crate::routes::conditional::last_modified(metadata);
+
+        Some(match name {
+            "creationdate" => metadata
+                .created()
+                .ok()
+                .map(|created| chrono::DateTime::<chrono::Utc>::from(created.into_std()))
+                .or(modified)?
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            "displayname" => xml::escape(&path.file_name()?.to_string_lossy()),
+            "getcontentlength" if metadata.is_file() => metadata.len().to_string(),
+            "getcontenttype" if metadata.is_file() => "application/octet-stream".to_string(),
+            "getetag" if metadata.is_file() => {
+                xml::escape(&crate::routes::conditional::etag(metadata))
+            }
+            "getlastmodified" => modified?.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            "resourcetype" => match metadata.is_dir() {
+                true => "<D:collection/>".to_string(),
+                false => String::new(),
+            },
+            "supportedlock" => "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+                <D:locktype><D:write/></D:locktype></D:lockentry>\
+                <D:lockentry><D:lockscope><D:shared/></D:lockscope>\
+                <D:locktype><D:write/></D:locktype></D:lockentry>"
+                .to_string(),
+            "lockdiscovery" => self
+                .state
+                .webdav_locks
+                .discover(self.server.uuid, path)
+                .iter()
+                .map(|lock| self.active_lock(lock))
+                .collect(),
+            "quota-used-bytes" => quota?.0.to_string(),
+            "quota-available-bytes" => quota?.1.to_string(),
+            _ => return None,
+        })
+    }
+
+    fn propfind_response(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        quota: Option<(u64, u64)>,
+        request: &PropFind,
+    ) -> String {
+        let mut found = String::new();
+        let mut missing = String::new();
+
+        match request {
+            PropFind::All => {
+                for name in PROPERTIES {
+                    if let Some(value) = self.property(path, metadata, quota, name) {
+                        found.push_str(&format!("<D:{name}>{value}</D:{name}>"));
+                    }
+                }
+            }
+            PropFind::Names => {
+                for name in PROPERTIES {
+                    if self.property(path, metadata, quota, name).is_some() {
+                        found.push_str(&format!("<D:{name}/>"));
+                    }
+                }
+            }
+            PropFind::Properties(properties) => {
+                for (namespace, name) in properties {
+                    let value = match namespace == DAV {
+                        true => self.property(path, metadata, quota, name),
+                        false => None,
+                    };
+
+                    match value {
+                        Some(value) => found.push_str(&format!("<D:{name}>{value}</D:{name}>")),
+                        None => missing.push_str(&empty_property(namespace, name)),
+                    }
+                }
+            }
+        }
+
+        let mut response = format!(
+            "<D:response><D:href>{}</D:href>",
+            xml::escape(&self.href(path, metadata.is_dir()))
+        );
+        for (properties, status) in [(found, StatusCode::OK), (missing, StatusCode::NOT_FOUND)] {
+            if !properties.is_empty() {
+                response.push_str(&format!(
+                    "<D:propstat><D:prop>{properties}</D:prop><D:status>{}</D:status></D:propstat>",
+                    status_line(status)
+                ));
+            }
+        }
+        response.push_str("</D:response>");
+
+        response
+    }
+}
+
+pub async fn propfind(dav: &Dav, body: &str) -> Result<Response, StatusCode> {
+    if !dav.has_any_permission(Permission::FileRead) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let request = parse_propfind(body).ok_or(StatusCode::BAD_REQUEST)?;
+    let depth = match dav.header("Depth").map(|depth| depth.trim()) {
+        Some("0") => 0,
+        Some("1") => 1,
+        // listing a whole server in one response is too expensive
+        _ => return Err(StatusCode::FORBIDDEN),
+    };
+
+    let metadata = dav
+        .server
+        .filesystem
+        .symlink_metadata(&dav.path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    if dav
+        .server
+        .filesystem
+        .is_hidden(&dav.path, metadata.is_dir(), &dav.user_permissions)
+        .await
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    // the usage can be expensive to look up, so it is only done once and when asked for
+    let quota = match &request {
+        PropFind::Properties(properties)
+            if dav.server.filesystem.disk_limit() > 0
+                && properties
+                    .iter()
+                    .any(|(namespace, name)| namespace == DAV && name.starts_with("quota-")) =>
+        {
+            let usage = dav.server.filesystem.limiter_usage().await;
+
+            Some((
+                usage,
+                (dav.server.filesystem.disk_limit() as u64).saturating_sub(usage),
+            ))
+        }
+        _ => None,
+    };
+
+    let mut responses = dav.propfind_response(&dav.path, &metadata, quota, &request);
+
+    if depth == 1 && metadata.is_dir() {
+        let mut directory = dav
+            .server
+            .filesystem
+            .read_dir(&dav.path)
+            .await
+            .map_err(|_| StatusCode::NOT_FOUND)?;
+
+        let mut entries = 0;
+        while let Some(Ok(name)) = directory.next_entry().await {
+            if entries >= dav.state.config.api.directory_entry_limit {
+                break;
+            }
+
+            let path = dav.path.join(name);
+            let metadata = match dav.server.filesystem.symlink_metadata(&path).await {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            if dav
+                .server
+                .filesystem
+                .is_hidden(&path, metadata.is_dir(), &dav.user_permissions)
+                .await
+            {
+                continue;
+            }
+
+            responses.push_str(&dav.propfind_response(&path, &metadata, quota, &request));
+            entries += 1;
+        }
+    }
+
+    Ok(multistatus(responses))
+}
+
+/// Dead properties are not stored, the timestamps windows sets are accepted and
+/// ignored while everything else is refused
+pub async fn proppatch(dav: &Dav, body: &str) -> Result<Response, StatusCode> {
+    if dav.is_read_only() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let root = xml::parse(body).ok_or(StatusCode::BAD_REQUEST)?;
+    if !root.is(DAV, "propertyupdate") {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let metadata = dav
+        .server
+        .filesystem
+        .symlink_metadata(&dav.path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    if dav
+        .server
+        .filesystem
+        .is_denied(
+            &dav.path,
+            metadata.is_dir(),
+            &dav.user_permissions,
+            Permission::FileUpdate,
+        )
+        .await
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if !dav.is_unlocked(&dav.path, false) {
+        return Err(StatusCode::LOCKED);
+    }
+
+    let properties = root
+        .children
+        .iter()
+        .filter(|update| update.is(DAV, "set") || update.is(DAV, "remove"))
+        .filter_map(|update| update.child(DAV, "prop"))
+        .flat_map(|prop| prop.children.iter())
+        .collect::<Vec<_>>();
+
+    let status = if properties
+        .iter()
+        .all(|property| property.namespace == WIN32_NAMESPACE)
+    {
+        StatusCode::OK
+    } else {
+        StatusCode::FORBIDDEN
+    };
+
+    let mut response = format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>",
+        xml::escape(&dav.href(&dav.path, metadata.is_dir()))
+    );
+    for property in properties {
+        response.push_str(&empty_property(&property.namespace, &property.name));
+    }
+    response.push_str(&format!(
+        "</D:prop><D:status>{}</D:status></D:propstat></D:response>",
+        status_line(status)
+    ));
+
+    Ok(multistatus(response))
+}
+
+fn lock_response(dav: &Dav, lock: &Lock, status: StatusCode) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Content-Type",
+        "application/xml; charset=utf-8".parse().unwrap(),
+    );
+    if let Ok(token) = format!("<{}>", lock.token).parse() {
+        headers.insert("Lock-Token", token);
+    }
+
+    (
+        status,
+        headers,
+        format!(
+            "{}<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>",
+            xml::HEADER,
+            dav.active_lock(lock)
+        ),
+    )
+        .into_response()
+}
+
+pub async fn lock(dav: &Dav, body: &str) -> Result<Response, StatusCode> {
+    if dav.is_read_only() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let timeout = locks::parse_timeout(dav.header("Timeout"));
+
+    // an empty body refreshes a lock the client already holds
+    if body.trim().is_empty() {
+        let lock = locks::submitted_tokens(dav.header("If"))
+            .iter()
+            .find_map(|token| {
+                dav.state.webdav_locks.refresh(
+                    dav.server.uuid,
+                    dav.user_uuid,
+                    &dav.path,
+                    token,
+                    timeout,
+                )
+            })
+            .ok_or(StatusCode::PRECONDITION_FAILED)?;
+
+        return Ok(lock_response(dav, &lock, StatusCode::OK));
+    }
+
+    let root = xml::parse(body).ok_or(StatusCode::BAD_REQUEST)?;
+    if !root.is(DAV, "lockinfo") {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let exclusive = root
+        .child(DAV, "lockscope")
+        .is_none_or(|scope| scope.child(DAV, "shared").is_none());
+    let owner = root
+        .child(DAV, "owner")
+        .map(|owner| {
+            let mut serialized = xml::escape(owner.text.trim());
+            for child in &owner.children {
+                child.write(&mut serialized);
+            }
+
+            serialized
+        })
+        .unwrap_or_default();
+    let infinite = dav.header("Depth").is_none_or(|depth| depth.trim() != "0");
+
+    let metadata = dav.server.filesystem.symlink_metadata(&dav.path).await.ok();
+    let permission = match metadata {
+        Some(_) => Permission::FileUpdate,
+        None => Permission::FileCreate,
+    };
+    if dav
+        .server
+        .filesystem
+        .is_denied(
+            &dav.path,
+            metadata.as_ref().is_some_and(|metadata| metadata.is_dir()),
+            &dav.user_permissions,
+            permission,
+        )
+        .await
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let lock = dav
+        .state
+        .webdav_locks
+        .lock(
+            dav.server.uuid,
+            dav.user_uuid,
+            &dav.path,
+            exclusive,
+            infinite,
+            owner,
+            timeout,
+        )
+        .ok_or(StatusCode::LOCKED)?;
+
+    if metadata.is_some() {
+        return Ok(lock_response(dav, &lock, StatusCode::OK));
+    }
+
+    // locking an unmapped url creates an empty file, clients upload into it afterwards
+    if !dav.has_parent(&dav.path).await || dav.server.filesystem.create(&dav.path).await.is_err() {
+        dav.state
+            .webdav_locks
+            .unlock(dav.server.uuid, dav.user_uuid, &dav.path, &lock.token);
+
+        return Err(StatusCode::CONFLICT);
+    }
+
+    dav.server.filesystem.chown_path(&dav.path).await;
+    dav.log_activity(
+        ActivityEvent::SftpCreate,
+        json!({
+            "files": [dav.server.filesystem.relative_path(&dav.path)],
+        }),
+    )
+    .await;
+
+    Ok(lock_response(dav, &lock, StatusCode::CREATED))
+}
+
+pub async fn unlock(dav: &Dav) -> Result<Response, StatusCode> {
+    let token = dav
+        .header("Lock-Token")
+        .map(|token| token.trim().trim_start_matches('<').trim_end_matches('>'))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    if dav
+        .state
+        .webdav_locks
+        .unlock(dav.server.uuid, dav.user_uuid, &dav.path, token)
+    {
+        Ok(StatusCode::NO_CONTENT.into_response())
+    } else {
+        Err(StatusCode::CONFLICT)
+    }
+}
@@ -0,0 +1,241 @@
+//! Just enough xml for the request bodies webdav clients send, namespaces are
+//! resolved while comments, doctypes and processing instructions are skipped
+
+pub const DAV: &str = "DAV:";
+pub const HEADER: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n";
+
+pub struct Element {
+    pub namespace: String,
+    pub name: String,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    #[inline]
+    pub fn is(&self, namespace: &str, name: &str) -> bool {
+        self.namespace == namespace && self.name == name
+    }
+
+    #[inline]
+    pub fn child(&self, namespace: &str, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.is(namespace, name))
+    }
+
+    /// Serializes the element, every element declares its own default namespace
+    pub fn write(&self, out: &mut String) {
+        out.push_str(&format!(
+            "<{} xmlns=\"{}\">",
+            self.name,
+            escape(&self.namespace)
+        ));
+        out.push_str(&escape(&self.text));
+        for child in &self.children {
+            child.write(out);
+        }
+        out.push_str(&format!("</{}>", self.name));
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+            {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => entity
+                    .strip_prefix('#')
+                    .and_then(|decimal| decimal.parse().ok())
+                    .and_then(char::from_u32),
+            },
+        };
+
+        match c {
+            Some(c) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    unescaped.push_str(rest);
+
+    unescaped
+}
+
+fn attributes(mut input: &str) -> Vec<(&str, String)> {
+    let mut attributes = Vec::new();
+
+    loop {
+        input = input.trim_start();
+
+        let equals = match input.find('=') {
+            Some(equals) => equals,
+            None => break,
+        };
+        let name = input[..equals].trim();
+        let value = input[equals + 1..].trim_start();
+
+        let quote = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => break,
+        };
+        let end = match value[1..].find(quote) {
+            Some(end) => end + 1,
+            None => break,
+        };
+
+        attributes.push((name, unescape(&value[1..end])));
+        input = &value[end + 1..];
+    }
+
+    attributes
+}
+
+struct Open {
+    element: Element,
+    namespaces: Vec<(String, String)>,
+}
+
+fn resolve<'a>(stack: &'a [Open], namespaces: &'a [(String, String)], prefix: &str) -> &'a str {
+    namespaces
+        .iter()
+        .chain(stack.iter().rev().flat_map(|open| open.namespaces.iter()))
+        .find(|(declared, _)| declared == prefix)
+        .map(|(_, namespace)| namespace.as_str())
+        .unwrap_or_default()
+}
+
+/// Parses a document and returns its root element, `None` if it is not well formed
+pub fn parse(input: &str) -> Option<Element> {
+    let mut stack: Vec<Open> = Vec::new();
+    let mut rest = input;
+
+    while let Some(start) = rest.find('<') {
+        if let Some(open) = stack.last_mut() {
+            open.element.text.push_str(&unescape(&rest[..start]));
+        }
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = &comment[comment.find("-->")? + 3..];
+            continue;
+        }
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>")?;
+            if let Some(open) = stack.last_mut() {
+                open.element.text.push_str(&cdata[..end]);
+            }
+
+            rest = &cdata[end + 3..];
+            continue;
+        }
+        if rest.starts_with("<?") || rest.starts_with("<!") {
+            rest = &rest[rest.find('>')? + 1..];
+            continue;
+        }
+
+        let end = rest.find('>')?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let open = stack.pop()?;
+            let local = name.trim().rsplit(':').next().unwrap_or_default();
+            if local != open.element.name {
+                return None;
+            }
+
+            match stack.last_mut() {
+                Some(parent) => parent.element.children.push(open.element),
+                None => return Some(open.element),
+            }
+
+            continue;
+        }
+
+        let (tag, closed) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let (name, attributes) = match tag.trim().split_once(char::is_whitespace) {
+            Some((name, attributes)) => (name, self::attributes(attributes)),
+            None => (tag.trim(), Vec::new()),
+        };
+        if name.is_empty() {
+            return None;
+        }
+
+        let namespaces = attributes
+            .into_iter()
+            .filter_map(|(attribute, value)| match attribute {
+                "xmlns" => Some((String::new(), value)),
+                _ => attribute
+                    .strip_prefix("xmlns:")
+                    .map(|prefix| (prefix.to_string(), value)),
+            })
+            .collect::<Vec<_>>();
+
+        let (prefix, local) = name.split_once(':').unwrap_or(("", name));
+        let element = Element {
+            namespace: resolve(&stack, &namespaces, prefix).to_string(),
+            name: local.to_string(),
+            children: Vec::new(),
+            text: String::new(),
+        };
+
+        if closed {
+            match stack.last_mut() {
+                Some(parent) => parent.element.children.push(element),
+                None => return Some(element),
+            }
+        } else {
+            stack.push(Open {
+                element,
+                namespaces,
+            });
+        }
+    }
+
+    None
+}
@@ -1,8 +1,8 @@
 use serde::Serialize;
-use std::{collections::VecDeque, net::IpAddr, sync::Arc};
-use tokio::sync::Mutex;
+use std::{collections::VecDeque, net::IpAddr, path::Path, sync::Arc};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub enum ActivityEvent {
     #[serde(rename = "server:power.start")]
     PowerStart,
@@ -26,11 +26,43 @@ pub enum ActivityEvent {
     SftpRename,
     #[serde(rename = "server:sftp.delete")]
     SftpDelete,
+    #[serde(rename = "server:sftp.login-failed")]
+    SftpLoginFailed,
+    #[serde(rename = "server:sftp.login")]
+    SftpLogin,
+    #[serde(rename = "server:sftp.logout")]
+    SftpLogout,
+    #[serde(rename = "server:sftp.read")]
+    SftpRead,
+    #[serde(rename = "server:sftp.chmod")]
+    SftpChmod,
+    #[serde(rename = "server:sftp.symlink")]
+    SftpSymlink,
 
     #[serde(rename = "server:file.uploaded")]
     FileUploaded,
 }
 
+impl ActivityEvent {
+    #[inline]
+    pub fn is_sftp(&self) -> bool {
+        matches!(
+            self,
+            ActivityEvent::SftpWrite
+                | ActivityEvent::SftpCreate
+                | ActivityEvent::SftpCreateDirectory
+                | ActivityEvent::SftpRename
+                | ActivityEvent::SftpDelete
+                | ActivityEvent::SftpLoginFailed
+                | ActivityEvent::SftpLogin
+                | ActivityEvent::SftpLogout
+                | ActivityEvent::SftpRead
+                | ActivityEvent::SftpChmod
+                | ActivityEvent::SftpSymlink
+        )
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct ApiActivity {
     user: Option<uuid::Uuid>,
@@ -52,6 +84,9 @@ pub struct Activity {
 }
 
 pub struct ActivityManager {
+    server: uuid::Uuid,
+    config: Arc<crate::config::Config>,
+
     activities: Arc<Mutex<VecDeque<Activity>>>,
     schedule_handle: tokio::task::JoinHandle<()>,
 }
@@ -61,6 +96,9 @@ impl ActivityManager {
         let activities = Arc::new(Mutex::new(VecDeque::new()));
 
         Self {
+            server,
+            config: Arc::clone(config),
+
             activities: Arc::clone(&activities),
             schedule_handle: tokio::spawn({
                 let config = Arc::clone(config);
@@ -115,8 +153,46 @@ impl ActivityManager {
     }
 
     pub async fn log_activity(&self, activity: Activity) {
+        if self.config.system.sftp.audit_log && activity.event.is_sftp() {
+            self.audit(&activity).await;
+        }
+
         self.activities.lock().await.push_back(activity);
     }
+
+    /// Appends an activity to the local sftp audit log, which is never rotated or truncated by wings
+    async fn audit(&self, activity: &Activity) {
+        let mut line = serde_json::to_vec(&ApiActivity {
+            user: activity.user,
+            server: self.server,
+            event: activity.event,
+            metadata: activity.metadata.clone(),
+            ip: activity.ip.map(|ip| ip.to_string()),
+            timestamp: activity.timestamp,
+        })
+        .unwrap();
+        line.push(b'\n');
+
+        let path = Path::new(&self.config.system.log_directory).join("sftp_audit.log");
+        let result = async {
+            tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?
+                .write_all(&line)
+                .await
+        }
+        .await;
+
+        if let Err(err) = result {
+            tracing::error!(
+                server = %self.server,
+                "failed to write sftp audit log: {:#?}",
+                err
+            );
+        }
+    }
 }
 
 impl Drop for ActivityManager {
@@ -68,6 +68,9 @@ nestify::nest! {
             pub disk_space: u64,
             pub threads: Option<String>,
             pub oom_disabled: bool,
+            #[serde(default)]
+            /// MB/s for file transfers of this server, unlimited when unset
+            pub transfer_limit: Option<u64>,
         },
         pub mounts: Vec<Mount>,
         #[schema(inline)]
@@ -303,6 +303,7 @@ impl Container {
 
                                 check_startup(&line);
                                 if allow_ratelimit().await {
+                                    server.console_history.write(&line);
                                     stdout_sender.send(line).unwrap();
                                 }
 
@@ -317,6 +318,7 @@ impl Container {
 
                                 check_startup(&line);
                                 if allow_ratelimit().await {
+                                    server.console_history.write(&line);
                                     stdout_sender.send(line).unwrap();
                                 }
 
@@ -332,6 +334,7 @@ impl Container {
                                 .trim()
                                 .to_string();
                                 if allow_ratelimit().await {
+                                    server.console_history.write(&line);
                                     stdout_sender.send(line).unwrap();
                                 }
 
@@ -353,6 +356,7 @@ impl Container {
                     let line = String::from_utf8_lossy(&buffer[line_start..])
                         .trim()
                         .to_string();
+                    server.console_history.write(&line);
                     stdout_sender.send(line).unwrap();
                 }
             }),
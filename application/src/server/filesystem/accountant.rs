@@ -0,0 +1,256 @@
+use super::usage::DiskUsage;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc,
+    },
+};
+use tokio::sync::RwLock;
+
+const BATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Keeps the usage tree of a server up to date from filesystem notifications, so the
+/// disk checker only has to walk the whole directory for reconciliation.
+/// Every changed directory is re-read on its own, only new subdirectories are walked.
+pub struct DiskAccountant {
+    base_path: PathBuf,
+    disk_usage: Arc<RwLock<DiskUsage>>,
+    disk_usage_cached: Arc<AtomicU64>,
+}
+
+impl DiskAccountant {
+    /// Starts the accountant on its own thread, `active` is set while the watcher is running.
+    /// If the directory cannot be watched, like at the inotify limit, the accountant stops
+    /// and the disk checker goes back to walking the directory on its usual interval
+    pub fn spawn(
+        base_path: PathBuf,
+        disk_usage: Arc<RwLock<DiskUsage>>,
+        disk_usage_cached: Arc<AtomicU64>,
+        active: Arc<AtomicBool>,
+        abort: Arc<AtomicBool>,
+    ) {
+        let accountant = Self {
+            base_path,
+            disk_usage,
+            disk_usage_cached,
+        };
+
+        std::thread::spawn(move || {
+            while !abort.load(Ordering::Relaxed) {
+                let (tx, rx) = mpsc::channel();
+
+                // symlinks created by the server must not pull other directories into the watch
+                let mut watcher = match RecommendedWatcher::new(
+                    tx,
+                    notify::Config::default().with_follow_symlinks(false),
+                ) {
+                    Ok(watcher) => watcher,
+                    Err(err) => {
+                        tracing::warn!(
+                            path = %accountant.base_path.display(),
+                            "failed to create disk usage watcher: {:#?}",
+                            err
+                        );
+                        return;
+                    }
+                };
+
+                if let Err(err) = watcher.watch(&accountant.base_path, RecursiveMode::Recursive) {
+                    tracing::warn!(
+                        path = %accountant.base_path.display(),
+                        "failed to watch server directory for disk usage: {:#?}",
+                        err
+                    );
+
+                    return;
+                }
+
+                active.store(true, Ordering::Relaxed);
+                accountant.run(&rx, &abort);
+                active.store(false, Ordering::Relaxed);
+            }
+        });
+    }
+
+    fn run(&self, rx: &mpsc::Receiver<notify::Result<notify::Event>>, abort: &AtomicBool) {
+        loop {
+            if abort.load(Ordering::Relaxed) {
+                return;
+            }
+
+            let event = match rx.recv_timeout(BATCH_INTERVAL) {
+                Ok(event) => event,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            };
+
+            let mut directories = HashSet::new();
+            let deadline = std::time::Instant::now() + BATCH_INTERVAL;
+            let mut event = Some(event);
+
+            while let Some(next) = event.take() {
+                match next {
+                    Ok(next) if next.need_rescan() => {
+                        directories.insert(PathBuf::new());
+                    }
+                    Ok(next) => {
+                        if next.kind.is_access() {
+                            continue;
+                        }
+
+                        for path in next.paths {
+                            if let Some(parent) = path
+                                .strip_prefix(&self.base_path)
+                                .ok()
+                                .and_then(|path| path.parent())
+                            {
+                                directories.insert(parent.to_path_buf());
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        tracing::debug!(
+                            path = %self.base_path.display(),
+                            "disk usage watcher error: {:#?}",
+                            err
+                        );
+
+                        // the watcher stopped for the base directory, start over
+                        if !self.base_path.exists() {
+                            return;
+                        }
+                    }
+                }
+
+                event = rx
+                    .recv_timeout(deadline.saturating_duration_since(std::time::Instant::now()))
+                    .ok();
+            }
+
+            let mut directories = directories.into_iter().collect::<Vec<_>>();
+            directories.sort_by_key(|path| path.components().count());
+
+            for directory in directories {
+                self.reconcile_directory(&directory);
+            }
+        }
+    }
+
+    /// Re-reads a single directory and applies the size difference of its direct
+    /// files and appeared or vanished subdirectories to the usage tree
+    fn reconcile_directory(&self, directory: &Path) {
+        let components = directory
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+
+        if !self.is_directory(directory) {
+            return;
+        }
+
+        let entries = match self.base_path.join(directory).read_dir() {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        let mut files_size = 0;
+        let mut subdirectories = HashMap::new();
+        for entry in entries.flatten() {
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            if metadata.is_dir() {
+                subdirectories.insert(entry.file_name().to_string_lossy().to_string(), metadata);
+            } else {
+                files_size += metadata.len();
+            }
+        }
+
+        let mut disk_usage = self.disk_usage.blocking_write();
+        let known = match disk_usage.get(&components) {
+            Some(usage) => usage.entries.keys().cloned().collect::<HashSet<_>>(),
+            None if components.is_empty() => HashSet::new(),
+            // the parent directory picks this directory up once it is reconciled
+            None => return,
+        };
+
+        for name in known
+            .iter()
+            .filter(|name| !subdirectories.contains_key(*name))
+        {
+            let mut path = components.clone();
+            path.push(name.clone());
+
+            if let Some(removed) = disk_usage.remove_path(&path) {
+                self.update_cached(-(removed.size as i64));
+            }
+        }
+
+        for (name, metadata) in subdirectories
+            .iter()
+            .filter(|(name, _)| !known.contains(*name))
+        {
+            let mut scanned = DiskUsage::scan(&self.base_path.join(directory).join(name));
+            scanned.size += metadata.len();
+
+            let mut path = components.clone();
+            path.push(name.clone());
+
+            let size = scanned.size;
+            if disk_usage.add_directory(&path, scanned) {
+                self.update_cached(size as i64);
+            }
+        }
+
+        let usage = match disk_usage.get(&components) {
+            Some(usage) => usage,
+            None => return,
+        };
+        let known_files_size = usage
+            .size
+            .saturating_sub(usage.entries.values().map(|entry| entry.size).sum());
+
+        let delta = files_size as i64 - known_files_size as i64;
+        if delta != 0 {
+            disk_usage.update_size(&components, delta);
+            self.update_cached(delta);
+        }
+    }
+
+    /// Whether `directory` is a directory inside of the base path, without any symlink
+    /// on the way there that could point outside of it
+    fn is_directory(&self, directory: &Path) -> bool {
+        let mut path = self.base_path.clone();
+
+        for component in directory.components() {
+            path.push(component);
+
+            match path.symlink_metadata() {
+                Ok(metadata) if metadata.is_dir() => {}
+                _ => return false,
+            }
+        }
+
+        true
+    }
+
+    #[inline]
+    fn update_cached(&self, delta: i64) {
+        if delta >= 0 {
+            self.disk_usage_cached
+                .fetch_add(delta as u64, Ordering::Relaxed);
+        } else {
+            self.disk_usage_cached
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                    Some(current.saturating_sub(delta.unsigned_abs()))
+                })
+                .ok();
+        }
+    }
+}
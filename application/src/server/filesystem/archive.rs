@@ -1,9 +1,13 @@
+use crate::server::permissions::Permission;
 use std::{
     fs::Permissions,
     io::{SeekFrom, Write},
-    os::unix::fs::PermissionsExt,
-    path::PathBuf,
-    sync::Arc,
+    os::{fd::AsRawFd, unix::fs::PermissionsExt},
+    path::{Component, Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
 };
 use tokio::{
     fs::File,
@@ -26,6 +30,198 @@ pub enum ArchiveType {
     None,
     Tar,
     Zip,
+    SevenZip,
+    Rar,
+}
+
+pub struct ArchiveEntry {
+    pub path: PathBuf,
+    pub directory: bool,
+    pub size: u64,
+    pub mode: u32,
+    pub modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl ArchiveEntry {
+    pub fn to_api_response(&self) -> crate::models::ArchiveEntry {
+        crate::models::ArchiveEntry {
+            name: self.path.to_string_lossy().to_string(),
+            directory: self.directory,
+            size: self.size,
+            mode_bits: format!("{:o}", self.mode & 0o777),
+            modified: self.modified,
+        }
+    }
+}
+
+/// Strips `.` and leading `/` components from an entry path, returns `None`
+/// if the path tries to escape its root
+pub fn normalize_entry_path(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(component) => normalized.push(component),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    if normalized.as_os_str().is_empty() {
+        return None;
+    }
+
+    Some(normalized)
+}
+
+/// Maps an entry path to its path relative to the extraction destination.
+/// Selected entries are extracted by their file name, with subtrees keeping
+/// their structure below it. An empty selection extracts everything as-is.
+fn selected_path(selection: &[PathBuf], path: &Path) -> Option<PathBuf> {
+    if selection.is_empty() {
+        return Some(path.to_path_buf());
+    }
+
+    selection.iter().find_map(|selected| {
+        let relative = path.strip_prefix(selected).ok()?;
+        let name = selected.file_name()?;
+
+        if relative.as_os_str().is_empty() {
+            Some(PathBuf::from(name))
+        } else {
+            Some(Path::new(name).join(relative))
+        }
+    })
+}
+
+struct ProgressWriter<W: Write> {
+    inner: W,
+    progress: Arc<AtomicU64>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<W: Write> Write for ProgressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(std::io::Error::other("extraction cancelled"));
+        }
+
+        let written = self.inner.write(buf)?;
+        self.progress.fetch_add(written as u64, Ordering::Relaxed);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[inline]
+fn seven_zip_mode(entry: &sevenz_rust::SevenZArchiveEntry) -> Option<u32> {
+    if entry.has_windows_attributes && entry.windows_attributes & 0x8000 != 0 {
+        Some(entry.windows_attributes >> 16)
+    } else {
+        None
+    }
+}
+
+fn parse_rar_mode(attributes: &str) -> Option<u32> {
+    if attributes.len() != 10 {
+        return None;
+    }
+
+    let mut mode = 0;
+    for (i, c) in attributes.get(1..)?.chars().enumerate() {
+        if c != '-' {
+            mode |= 1 << (8 - i);
+        }
+    }
+
+    Some(mode)
+}
+
+/// Parses the output of `unrar lt` into the names stored in the archive and their entries,
+/// symbolic links are left out
+fn parse_rar_listing(output: &str, limit: usize) -> Vec<(String, ArchiveEntry)> {
+    let mut entries = Vec::new();
+    let mut current: Option<(String, ArchiveEntry)> = None;
+
+    for line in output.lines() {
+        let (key, value) = match line.split_once(": ") {
+            Some((key, value)) => (key.trim(), value.trim_end()),
+            None => continue,
+        };
+
+        match key {
+            "Name" => {
+                if entries.len() >= limit {
+                    break;
+                }
+
+                entries.extend(current.take());
+                current = normalize_entry_path(Path::new(value)).map(|path| {
+                    (
+                        value.to_string(),
+                        ArchiveEntry {
+                            path,
+                            directory: false,
+                            size: 0,
+                            mode: 0o644,
+                            modified: None,
+                        },
+                    )
+                });
+            }
+            "Type" if value.contains("link") => current = None,
+            _ => {
+                let entry = match current.as_mut() {
+                    Some((_, entry)) => entry,
+                    None => continue,
+                };
+
+                match key {
+                    "Type" => entry.directory = value == "Directory",
+                    "Size" => entry.size = value.parse().unwrap_or(0),
+                    "mtime" => {
+                        entry.modified = value
+                            .get(..19)
+                            .and_then(|value| {
+                                chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+                                    .ok()
+                            })
+                            .map(|modified| modified.and_utc())
+                    }
+                    "Attributes" => {
+                        if let Some(mode) = parse_rar_mode(value) {
+                            entry.mode = mode;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    if entries.len() < limit {
+        entries.extend(current);
+    }
+
+    entries
+}
+
+/// Whether an entry extracted to `path` has to be left out
+#[inline]
+fn is_skipped(
+    server: &crate::server::Server,
+    permissions: Option<&crate::server::permissions::Permissions>,
+    path: &Path,
+    is_dir: bool,
+) -> bool {
+    server.filesystem.is_ignored_sync(path, is_dir)
+        || server
+            .filesystem
+            .is_outside_grants_sync(path, permissions, Permission::FileCreate)
 }
 
 pub struct Archive {
@@ -37,6 +233,13 @@ pub struct Archive {
 
     pub file: File,
     pub path: PathBuf,
+
+    pub progress: Arc<AtomicU64>,
+    pub total: Arc<AtomicU64>,
+    pub cancelled: Arc<AtomicBool>,
+
+    /// restricts extracted entries to the grants of a user
+    pub permissions: Option<crate::server::permissions::Permissions>,
 }
 
 impl Archive {
@@ -57,9 +260,11 @@ impl Archive {
             _ => CompressionType::None,
         };
 
-        let archive_format = match path.extension() {
-            Some(ext) if ext == "tar" => ArchiveType::Tar,
-            Some(ext) if ext == "zip" => ArchiveType::Zip,
+        let archive_format = match (inferred.map(|f| f.mime_type()), path.extension()) {
+            (Some("application/x-7z-compressed"), _) => ArchiveType::SevenZip,
+            (Some("application/vnd.rar"), _) => ArchiveType::Rar,
+            (_, Some(ext)) if ext == "tar" => ArchiveType::Tar,
+            (_, Some(ext)) if ext == "zip" => ArchiveType::Zip,
             _ => path.file_stem().map_or(ArchiveType::None, |stem| {
                 if stem.to_str().is_some_and(|s| s.ends_with(".tar")) {
                     ArchiveType::Tar
@@ -76,6 +281,10 @@ impl Archive {
             header,
             file,
             path,
+            progress: Arc::new(AtomicU64::new(0)),
+            total: Arc::new(AtomicU64::new(0)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            permissions: None,
         })
     }
 
@@ -189,11 +398,197 @@ impl Archive {
         Some(reader)
     }
 
+    /// Builds an `unrar` invocation for the opened archive. The file is passed by its
+    /// descriptor so `unrar` cannot be tricked into following a symlink out of the server.
+    fn rar_command(&self, command: &str, switches: &[&str]) -> std::process::Command {
+        let mut rar_command = std::process::Command::new("unrar");
+        rar_command
+            .arg(command)
+            .args(["-p-", "-cfg-"])
+            .args(switches)
+            .arg(format!(
+                "/proc/{}/fd/{}",
+                std::process::id(),
+                self.file.as_raw_fd()
+            ))
+            .env("TZ", "UTC")
+            .stdin(std::process::Stdio::null());
+
+        rar_command
+    }
+
+    /// Lists the entries of the archive without extracting them, tar archives
+    /// still have to be decompressed while reading through them
+    pub async fn entries(mut self, limit: usize) -> Result<Vec<ArchiveEntry>, anyhow::Error> {
+        if matches!(self.archive, ArchiveType::None) {
+            let path = match self.path.file_stem() {
+                Some(stem) => PathBuf::from(stem),
+                None => return Ok(Vec::new()),
+            };
+            let metadata = self.file.metadata().await?;
+
+            return Ok(vec![ArchiveEntry {
+                path,
+                directory: false,
+                size: self.estimated_size().await.unwrap_or(0),
+                mode: metadata.permissions().mode(),
+                modified: metadata.modified().ok().map(|modified| modified.into()),
+            }]);
+        }
+
+        let reader = match self.archive {
+            ArchiveType::Tar => self.reader().await,
+            _ => None,
+        };
+
+        tokio::task::spawn_blocking(move || -> Result<Vec<ArchiveEntry>, anyhow::Error> {
+            let mut entries = Vec::new();
+
+            match self.archive {
+                ArchiveType::Tar => {
+                    let reader = match reader {
+                        Some(reader) => reader,
+                        None => return Err(anyhow::anyhow!("failed to open archive for reading")),
+                    };
+                    let mut archive = tar::Archive::new(SyncIoBridge::new(reader));
+
+                    for entry in archive.entries()? {
+                        let entry = entry?;
+                        let header = entry.header();
+
+                        let path = match normalize_entry_path(&entry.path()?) {
+                            Some(path) => path,
+                            None => continue,
+                        };
+
+                        entries.push(ArchiveEntry {
+                            path,
+                            directory: header.entry_type() == tar::EntryType::Directory,
+                            size: header.size().unwrap_or(0),
+                            mode: header.mode().unwrap_or(0o644),
+                            modified: header.mtime().ok().and_then(|mtime| {
+                                chrono::DateTime::from_timestamp(mtime as i64, 0)
+                            }),
+                        });
+
+                        if entries.len() >= limit {
+                            break;
+                        }
+                    }
+                }
+                ArchiveType::Zip => {
+                    let file = self.file.try_into_std().unwrap();
+                    let mut archive = zip::ZipArchive::new(file)?;
+
+                    for i in 0..archive.len().min(limit) {
+                        let entry = archive.by_index_raw(i)?;
+
+                        let path = match entry.enclosed_name() {
+                            Some(path) => path,
+                            None => continue,
+                        };
+
+                        entries.push(ArchiveEntry {
+                            path,
+                            directory: entry.is_dir(),
+                            size: entry.size(),
+                            mode: entry.unix_mode().unwrap_or(0o644),
+                            modified: entry.last_modified().and_then(|modified| {
+                                chrono::NaiveDate::from_ymd_opt(
+                                    modified.year() as i32,
+                                    modified.month() as u32,
+                                    modified.day() as u32,
+                                )?
+                                .and_hms_opt(
+                                    modified.hour() as u32,
+                                    modified.minute() as u32,
+                                    modified.second() as u32,
+                                )
+                                .map(|modified| modified.and_utc())
+                            }),
+                        });
+                    }
+                }
+                ArchiveType::SevenZip => {
+                    let mut file = self.file.try_into_std().unwrap();
+                    let length = file.metadata()?.len();
+
+                    let archive = sevenz_rust::Archive::read(&mut file, length, &[])?;
+
+                    for entry in archive.files.iter().filter(|entry| !entry.is_anti_item) {
+                        if seven_zip_mode(entry).is_some_and(|mode| mode & 0o170000 == 0o120000) {
+                            continue;
+                        }
+
+                        let path =
+                            match normalize_entry_path(Path::new(&entry.name.replace('\\', "/"))) {
+                                Some(path) => path,
+                                None => continue,
+                            };
+
+                        entries.push(ArchiveEntry {
+                            path,
+                            directory: entry.is_directory,
+                            size: entry.size,
+                            mode: seven_zip_mode(entry).unwrap_or(0o644),
+                            modified: entry
+                                .has_last_modified_date
+                                .then(|| {
+                                    chrono::DateTime::from_timestamp(
+                                        entry.last_modified_date.to_unix_time(),
+                                        0,
+                                    )
+                                })
+                                .flatten(),
+                        });
+
+                        if entries.len() >= limit {
+                            break;
+                        }
+                    }
+                }
+                ArchiveType::Rar => {
+                    let output = self.rar_command("lt", &[]).output()?;
+                    if !output.status.success() {
+                        return Err(anyhow::anyhow!(
+                            "unrar exited with {}: {}",
+                            output.status,
+                            String::from_utf8_lossy(&output.stderr).trim()
+                        ));
+                    }
+
+                    entries = parse_rar_listing(&String::from_utf8_lossy(&output.stdout), limit)
+                        .into_iter()
+                        .map(|(_, entry)| entry)
+                        .collect();
+                }
+                ArchiveType::None => unreachable!(),
+            }
+
+            Ok(entries)
+        })
+        .await?
+    }
+
+    #[inline]
     pub async fn extract(
         self,
         filesystem: Arc<cap_std::fs::Dir>,
         destination: PathBuf,
         reader: Option<Box<dyn AsyncRead + Send + Unpin>>,
+    ) -> Result<(), anyhow::Error> {
+        self.extract_entries(filesystem, destination, reader, Vec::new())
+            .await
+    }
+
+    /// Extracts the `selection` of entries (files or whole subtrees) into `destination`,
+    /// an empty selection extracts the entire archive
+    pub async fn extract_entries(
+        self,
+        filesystem: Arc<cap_std::fs::Dir>,
+        destination: PathBuf,
+        reader: Option<Box<dyn AsyncRead + Send + Unpin>>,
+        selection: Vec<PathBuf>,
     ) -> Result<(), anyhow::Error> {
         if matches!(self.archive, ArchiveType::None) {
             let file_name = match self.path.file_stem() {
@@ -201,12 +596,26 @@ impl Archive {
                 None => destination,
             };
 
+            if self
+                .server
+                .filesystem
+                .is_outside_grants(
+                    &file_name,
+                    self.permissions.as_ref(),
+                    Permission::FileCreate,
+                )
+                .await
+            {
+                return Err(anyhow::anyhow!("destination is not permitted"));
+            }
+
             let mut writer =
                 super::writer::AsyncFileSystemWriter::new(self.server.clone(), file_name, None)
                     .await?;
 
-            tokio::io::copy(&mut reader.unwrap(), &mut writer).await?;
+            let copied = tokio::io::copy(&mut reader.unwrap(), &mut writer).await?;
             writer.flush().await?;
+            self.progress.fetch_add(copied, Ordering::Relaxed);
 
             return Ok(());
         }
@@ -218,16 +627,19 @@ impl Archive {
                     let mut archive = tar::Archive::new(sync_reader);
 
                     for mut entry in archive.entries().unwrap().flatten() {
-                        let path = entry.path().unwrap();
-
-                        if path.is_absolute() {
-                            continue;
-                        }
+                        let path = match normalize_entry_path(&entry.path().unwrap())
+                            .and_then(|path| selected_path(&selection, &path))
+                        {
+                            Some(path) => path,
+                            None => continue,
+                        };
 
                         let destination_path = destination.join(path);
                         let header = entry.header();
 
-                        if self.server.filesystem.is_ignored_sync(
+                        if is_skipped(
+                            &self.server,
+                            self.permissions.as_ref(),
                             &destination_path,
                             header.entry_type() == tar::EntryType::Directory,
                         ) {
@@ -243,7 +655,7 @@ impl Archive {
                                     .create_dir_all(destination_path.parent().unwrap())
                                     .unwrap();
 
-                                let mut writer = super::writer::FileSystemWriter::new(
+                                let writer = super::writer::FileSystemWriter::new(
                                     self.server.clone(),
                                     destination_path,
                                     header.mode().map(Permissions::from_mode).ok(),
@@ -257,8 +669,14 @@ impl Archive {
                                 )
                                 .unwrap();
 
-                                std::io::copy(&mut entry, &mut writer).unwrap();
-                                writer.flush().unwrap();
+                                let mut writer = ProgressWriter {
+                                    inner: writer,
+                                    progress: Arc::clone(&self.progress),
+                                    cancelled: Arc::clone(&self.cancelled),
+                                };
+
+                                std::io::copy(&mut entry, &mut writer)?;
+                                writer.flush()?;
                             }
                             _ => {}
                         }
@@ -268,25 +686,29 @@ impl Archive {
                     let file = self.file.try_into_std().unwrap();
 
                     let mut archive = zip::ZipArchive::new(file)?;
+                    self.total.store(
+                        archive.decompressed_size().unwrap_or(0) as u64,
+                        Ordering::Relaxed,
+                    );
 
                     for i in 0..archive.len() {
                         let mut entry = archive.by_index(i)?;
-                        let path = match entry.enclosed_name() {
+                        let path = match entry
+                            .enclosed_name()
+                            .and_then(|path| selected_path(&selection, &path))
+                        {
                             Some(path) => path,
                             None => continue,
                         };
 
-                        if path.is_absolute() {
-                            continue;
-                        }
-
                         let destination_path = destination.join(path);
 
-                        if self
-                            .server
-                            .filesystem
-                            .is_ignored_sync(&destination_path, entry.is_dir())
-                        {
+                        if is_skipped(
+                            &self.server,
+                            self.permissions.as_ref(),
+                            &destination_path,
+                            entry.is_dir(),
+                        ) {
                             continue;
                         }
 
@@ -297,18 +719,102 @@ impl Archive {
                                 .create_dir_all(destination_path.parent().unwrap())
                                 .unwrap();
 
-                            let mut writer = super::writer::FileSystemWriter::new(
+                            let writer = super::writer::FileSystemWriter::new(
                                 self.server.clone(),
                                 destination_path,
                                 entry.unix_mode().map(Permissions::from_mode),
                                 None,
                             )?;
 
+                            let mut writer = ProgressWriter {
+                                inner: writer,
+                                progress: Arc::clone(&self.progress),
+                                cancelled: Arc::clone(&self.cancelled),
+                            };
+
                             std::io::copy(&mut entry, &mut writer)?;
                             writer.flush()?;
                         }
                     }
                 }
+                ArchiveType::SevenZip => {
+                    let file = self.file.try_into_std().unwrap();
+                    let length = file.metadata()?.len();
+
+                    let mut archive = sevenz_rust::SevenZReader::new(
+                        file,
+                        length,
+                        sevenz_rust::Password::empty(),
+                    )?;
+                    self.total.store(
+                        archive.archive().files.iter().map(|entry| entry.size).sum(),
+                        Ordering::Relaxed,
+                    );
+
+                    archive.for_each_entries(|entry, reader| {
+                        let mode = seven_zip_mode(entry);
+                        let is_symlink = mode.is_some_and(|mode| mode & 0o170000 == 0o120000);
+
+                        let path =
+                            match normalize_entry_path(Path::new(&entry.name.replace('\\', "/")))
+                                .and_then(|path| selected_path(&selection, &path))
+                            {
+                                Some(path) if !entry.is_anti_item && !is_symlink => path,
+                                _ => {
+                                    std::io::copy(reader, &mut std::io::sink())
+                                        .map_err(sevenz_rust::Error::io)?;
+                                    return Ok(true);
+                                }
+                            };
+
+                        let destination_path = destination.join(path);
+
+                        if is_skipped(
+                            &self.server,
+                            self.permissions.as_ref(),
+                            &destination_path,
+                            entry.is_directory,
+                        ) {
+                            std::io::copy(reader, &mut std::io::sink())
+                                .map_err(sevenz_rust::Error::io)?;
+                            return Ok(true);
+                        }
+
+                        if entry.is_directory {
+                            filesystem
+                                .create_dir_all(&destination_path)
+                                .map_err(sevenz_rust::Error::io)?;
+                        } else {
+                            if let Some(parent) = destination_path.parent() {
+                                filesystem
+                                    .create_dir_all(parent)
+                                    .map_err(sevenz_rust::Error::io)?;
+                            }
+
+                            let writer = super::writer::FileSystemWriter::new(
+                                self.server.clone(),
+                                destination_path,
+                                mode.map(Permissions::from_mode),
+                                entry
+                                    .has_last_modified_date
+                                    .then(|| entry.last_modified_date.into()),
+                            )
+                            .map_err(|err| sevenz_rust::Error::other(err.to_string()))?;
+
+                            let mut writer = ProgressWriter {
+                                inner: writer,
+                                progress: Arc::clone(&self.progress),
+                                cancelled: Arc::clone(&self.cancelled),
+                            };
+
+                            std::io::copy(reader, &mut writer).map_err(sevenz_rust::Error::io)?;
+                            writer.flush().map_err(sevenz_rust::Error::io)?;
+                        }
+
+                        Ok(true)
+                    })?;
+                }
+                ArchiveType::Rar => self.extract_rar(&filesystem, &destination, &selection)?,
                 ArchiveType::None => unreachable!(),
             }
 
@@ -316,4 +822,227 @@ impl Archive {
         })
         .await?
     }
+
+    /// `unrar` cannot write into the server's directory handle, so the archive is unpacked
+    /// once into a temporary directory and the entries are copied over from there like the
+    /// entries of any other archive. This keeps quota, progress and ignore checks per entry
+    /// while solid archives are only decompressed a single time.
+    fn extract_rar(
+        &self,
+        filesystem: &cap_std::fs::Dir,
+        destination: &Path,
+        selection: &[PathBuf],
+    ) -> Result<(), anyhow::Error> {
+        let output = self.rar_command("lt", &[]).output()?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "unrar exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let listing = parse_rar_listing(&String::from_utf8_lossy(&output.stdout), usize::MAX);
+
+        // the whole archive is unpacked outside of the server first, so it has to fit
+        // into the disk limit and the temporary directory even if only some of its
+        // entries are selected
+        let unpacked_size = listing
+            .iter()
+            .filter(|(_, entry)| !entry.directory)
+            .map(|(_, entry)| entry.size)
+            .sum::<u64>();
+        let disk_limit = self.server.filesystem.disk_limit();
+        if disk_limit != 0 && unpacked_size > disk_limit as u64 {
+            return Err(anyhow::anyhow!(
+                "archive is larger than the disk limit of the server"
+            ));
+        }
+
+        let entries = listing
+            .into_iter()
+            .filter_map(|(_, entry)| {
+                let path = selected_path(selection, &entry.path)?;
+
+                Some((destination.join(path), entry))
+            })
+            .collect::<Vec<_>>();
+
+        self.total.store(
+            entries
+                .iter()
+                .filter(|(_, entry)| !entry.directory)
+                .map(|(_, entry)| entry.size)
+                .sum(),
+            Ordering::Relaxed,
+        );
+
+        let unpacked = TemporaryDirectory::create(
+            Path::new(&self.server.config.system.tmp_directory).join(format!(
+                "{}-rar-{}",
+                self.server.uuid,
+                uuid::Uuid::new_v4()
+            )),
+        )?;
+
+        if available_space(&unpacked.0).is_some_and(|available| unpacked_size > available) {
+            return Err(anyhow::anyhow!(
+                "archive is larger than the free space in the temporary directory"
+            ));
+        }
+
+        let mut child = self
+            .rar_command("x", &["-inul", "-o+", "-y"])
+            .arg(format!("{}/", unpacked.0.display()))
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()?;
+        let status = loop {
+            if self.cancelled.load(Ordering::Relaxed) {
+                child.kill().ok();
+                child.wait().ok();
+
+                return Err(anyhow::anyhow!("extraction cancelled"));
+            }
+
+            match child.try_wait()? {
+                Some(status) => break status,
+                None => std::thread::sleep(std::time::Duration::from_millis(100)),
+            }
+        };
+        if !status.success() {
+            return Err(anyhow::anyhow!("unrar exited with {status}"));
+        }
+
+        // the listing skips links, opening through the directory handle makes sure
+        // no link unpacked by unrar can point the copy outside of it
+        let unpacked_dir =
+            cap_std::fs::Dir::open_ambient_dir(&unpacked.0, cap_std::ambient_authority())?;
+
+        for (destination_path, entry) in entries {
+            if self.cancelled.load(Ordering::Relaxed) {
+                return Err(anyhow::anyhow!("extraction cancelled"));
+            }
+
+            if is_skipped(
+                &self.server,
+                self.permissions.as_ref(),
+                &destination_path,
+                entry.directory,
+            ) {
+                continue;
+            }
+
+            if entry.directory {
+                filesystem.create_dir_all(&destination_path)?;
+                continue;
+            }
+
+            let mut reader = unpacked_dir.open(&entry.path).map_err(|err| {
+                anyhow::anyhow!(
+                    "failed to read {} from the archive: {err}",
+                    entry.path.display()
+                )
+            })?;
+
+            if let Some(parent) = destination_path.parent() {
+                filesystem.create_dir_all(parent)?;
+            }
+
+            let writer = super::writer::FileSystemWriter::new(
+                self.server.clone(),
+                destination_path,
+                Some(Permissions::from_mode(entry.mode)),
+                entry.modified.map(|modified| modified.into()),
+            )?;
+            let mut writer = ProgressWriter {
+                inner: writer,
+                progress: Arc::clone(&self.progress),
+                cancelled: Arc::clone(&self.cancelled),
+            };
+
+            std::io::copy(&mut reader, &mut writer)?;
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Removed together with everything in it once dropped
+struct TemporaryDirectory(PathBuf);
+
+impl TemporaryDirectory {
+    fn create(path: PathBuf) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(&path)?;
+
+        Ok(Self(path))
+    }
+}
+
+impl Drop for TemporaryDirectory {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// Free space on the disk holding `path`, `None` if the disk cannot be found
+fn available_space(path: &Path) -> Option<u64> {
+    let path = std::fs::canonicalize(path).ok()?;
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+
+    disks
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_rar_listing;
+    use std::path::Path;
+
+    #[test]
+    fn rar_listing() {
+        let output = "
+Archive: world.rar
+Details: RAR 5
+
+        Name: world/level.dat
+        Type: File
+        Size: 1234
+       mtime: 2024-05-01 12:30:00,000000000
+  Attributes: -rw-r-----
+
+        Name: world/region
+        Type: Directory
+  Attributes: drwxr-xr-x
+
+        Name: world/latest
+        Type: Unix symbolic link
+      Target: level.dat
+
+        Name: ../escape.txt
+        Type: File
+        Size: 1
+";
+
+        let entries = parse_rar_listing(output, usize::MAX);
+        assert_eq!(entries.len(), 2);
+
+        let (name, entry) = &entries[0];
+        assert_eq!(name, "world/level.dat");
+        assert_eq!(entry.path, Path::new("world/level.dat"));
+        assert!(!entry.directory);
+        assert_eq!(entry.size, 1234);
+        assert_eq!(entry.mode, 0o640);
+        assert!(entry.modified.is_some());
+
+        let (_, entry) = &entries[1];
+        assert_eq!(entry.path, Path::new("world/region"));
+        assert!(entry.directory);
+
+        assert_eq!(parse_rar_listing(output, 1).len(), 1);
+    }
 }
@@ -0,0 +1,303 @@
+use cap_std::fs::PermissionsExt;
+use serde::Deserialize;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+use utoipa::ToSchema;
+
+#[derive(ToSchema, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[schema(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    Overwrite,
+    Skip,
+    #[default]
+    Rename,
+}
+
+/// Picks a `name copy`, `name copy 1`, ... name that does not exist in `parent` yet
+pub fn available_name(filesystem: &cap_std::fs::Dir, parent: &Path, name: &str) -> String {
+    let path = Path::new(name);
+    let mut extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| format!(".{}", ext))
+        .unwrap_or_default();
+    let mut base_name = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(name)
+        .to_string();
+
+    if base_name.ends_with(".tar") {
+        extension = format!(".tar{}", extension);
+        base_name.truncate(base_name.len() - 4);
+    }
+
+    for i in 0..50 {
+        let suffix = if i == 0 {
+            " copy".to_string()
+        } else {
+            format!(" copy {}", i)
+        };
+
+        let new_name = format!("{}{}{}", base_name, suffix, extension);
+        if !filesystem.exists(parent.join(&new_name)) {
+            return new_name;
+        }
+    }
+
+    format!(
+        "{} copy.{}{}",
+        base_name,
+        chrono::Utc::now().to_rfc3339(),
+        extension
+    )
+}
+
+pub struct Copy {
+    pub total: u64,
+
+    pub sources: Vec<PathBuf>,
+    pub destination: PathBuf,
+    pub conflict: ConflictPolicy,
+    pub server: crate::server::Server,
+}
+
+impl Copy {
+    /// Prepares copying `sources` into the `destination` directory, all paths are relative
+    /// to the server root and have been checked against the denylist by the caller
+    pub async fn new(
+        server: crate::server::Server,
+        sources: Vec<PathBuf>,
+        destination: PathBuf,
+        conflict: ConflictPolicy,
+    ) -> Result<Self, anyhow::Error> {
+        for source in &sources {
+            if destination.starts_with(source) {
+                return Err(anyhow::anyhow!("cannot copy a directory into itself"));
+            }
+        }
+
+        let total = tokio::task::spawn_blocking({
+            let server = server.clone();
+            let sources = sources.clone();
+
+            move || -> Result<u64, anyhow::Error> {
+                let mut total = 0;
+
+                for source in sources {
+                    let (mut walker, _) = server.filesystem.walk_dir(&source)?;
+
+                    for entry in walker
+                        .hidden(false)
+                        .git_ignore(false)
+                        .ignore(false)
+                        .git_exclude(false)
+                        .follow_links(false)
+                        .build()
+                        .flatten()
+                    {
+                        if let Ok(metadata) = entry.metadata() {
+                            if metadata.is_file() {
+                                total += metadata.len();
+                            }
+                        }
+                    }
+                }
+
+                Ok(total)
+            }
+        })
+        .await??;
+
+        Ok(Self {
+            total,
+            sources,
+            destination,
+            conflict,
+            server,
+        })
+    }
+
+    /// Starts the copy as an operation, cancelling it stops after the current chunk
+    /// and keeps the files that were already copied
+    pub fn start(self) -> super::operations::Operation {
+        let mut operation = super::operations::Operation::new(
+            super::operations::OperationKind::Copy,
+            &self.destination,
+        );
+        operation.total.store(self.total, Ordering::Relaxed);
+        operation.abortable = false;
+
+        let progress = Arc::clone(&operation.progress);
+        let cancelled = Arc::clone(&operation.cancelled);
+        let sources = self.sources;
+        let destination = self.destination;
+        let conflict = self.conflict;
+        let server = self.server;
+
+        operation.start(server.clone(), async move {
+            let result = tokio::task::spawn_blocking({
+                let server = server.clone();
+                let destination = destination.clone();
+
+                move || -> Result<(), anyhow::Error> {
+                    let filesystem = server.filesystem.sync_base_dir()?;
+
+                    for source in sources {
+                        let name = match source.file_name() {
+                            Some(name) => name.to_string_lossy().to_string(),
+                            None => continue,
+                        };
+
+                        let target = if filesystem.exists(destination.join(&name)) {
+                            match conflict {
+                                ConflictPolicy::Skip => continue,
+                                ConflictPolicy::Overwrite => destination.join(&name),
+                                ConflictPolicy::Rename => destination.join(available_name(
+                                    &filesystem,
+                                    &destination,
+                                    &name,
+                                )),
+                            }
+                        } else {
+                            destination.join(&name)
+                        };
+
+                        copy_entry(
+                            &server,
+                            &filesystem,
+                            &source,
+                            &target,
+                            conflict,
+                            &progress,
+                            &cancelled,
+                        )?;
+                    }
+
+                    Ok(())
+                }
+            })
+            .await;
+
+            server.filesystem.chown_path(&destination).await;
+
+            result?
+        });
+
+        operation
+    }
+}
+
+fn copy_entry(
+    server: &crate::server::Server,
+    filesystem: &cap_std::fs::Dir,
+    source: &Path,
+    target: &Path,
+    conflict: ConflictPolicy,
+    progress: &AtomicU64,
+    cancelled: &AtomicBool,
+) -> Result<(), anyhow::Error> {
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(anyhow::anyhow!("copy cancelled"));
+    }
+
+    let metadata = filesystem.symlink_metadata(source)?;
+
+    if server.filesystem.is_ignored_sync(source, metadata.is_dir())
+        || server.filesystem.is_ignored_sync(target, metadata.is_dir())
+    {
+        return Ok(());
+    }
+
+    if metadata.is_dir() {
+        match filesystem.symlink_metadata(target) {
+            Ok(existing) if existing.is_dir() => {}
+            Ok(_) => return Ok(()),
+            Err(_) => {
+                filesystem.create_dir(target)?;
+                filesystem.set_permissions(target, metadata.permissions())?;
+            }
+        }
+
+        for entry in filesystem.read_dir(source)? {
+            let name = entry?.file_name();
+
+            copy_entry(
+                server,
+                filesystem,
+                &source.join(&name),
+                &target.join(&name),
+                conflict,
+                progress,
+                cancelled,
+            )?;
+        }
+
+        if let Ok(modified) = metadata.modified() {
+            filesystem
+                .open(target)
+                .and_then(|file| file.into_std().set_modified(modified.into_std()))
+                .ok();
+        }
+    } else if metadata.is_symlink() {
+        if filesystem.symlink_metadata(target).is_err() {
+            let link = filesystem.read_link(source)?;
+            filesystem.symlink(link, target).ok();
+        }
+    } else if metadata.is_file() {
+        if let Ok(existing) = filesystem.symlink_metadata(target) {
+            // a symlink would be followed and its target overwritten instead
+            if matches!(conflict, ConflictPolicy::Skip)
+                || existing.is_dir()
+                || existing.is_symlink()
+            {
+                progress.fetch_add(metadata.len(), Ordering::Relaxed);
+                return Ok(());
+            }
+
+            if let Some(parent) = target.parent() {
+                futures::executor::block_on(
+                    server
+                        .filesystem
+                        .allocate_in_path(parent, -(existing.len() as i64)),
+                );
+            }
+        }
+
+        let mut reader = filesystem.open(source)?;
+        let mut writer = super::writer::FileSystemWriter::new(
+            server.clone(),
+            target.to_path_buf(),
+            Some(std::os::unix::fs::PermissionsExt::from_mode(
+                metadata.permissions().mode(),
+            )),
+            metadata.modified().ok().map(|modified| modified.into_std()),
+        )?;
+
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(anyhow::anyhow!("copy cancelled"));
+            }
+
+            let bytes_read = std::io::Read::read(&mut reader, &mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+
+            writer.write_all(&buffer[..bytes_read])?;
+            progress.fetch_add(bytes_read as u64, Ordering::Relaxed);
+        }
+
+        writer.flush()?;
+    }
+
+    Ok(())
+}
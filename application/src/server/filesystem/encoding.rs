@@ -0,0 +1,125 @@
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+/// bytes looked at when guessing the encoding and line endings of a file
+pub const SAMPLE_SIZE: usize = 64 * 1024;
+
+#[derive(ToSchema, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[schema(rename_all = "lowercase")]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "lf",
+            LineEnding::Crlf => "crlf",
+            LineEnding::Cr => "cr",
+        }
+    }
+
+    #[inline]
+    fn separator(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+pub struct TextEncoding {
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+    /// `None` when the sample does not contain a line break
+    pub line_ending: Option<LineEnding>,
+}
+
+impl TextEncoding {
+    /// Guesses the encoding of a file from its first bytes, a BOM always wins
+    /// over the statistical guess
+    pub fn detect(sample: &[u8]) -> Self {
+        let (encoding, bom) = match Encoding::for_bom(sample) {
+            Some((encoding, _)) => (encoding, true),
+            None => {
+                let mut detector = chardetng::EncodingDetector::new();
+                detector.feed(sample, sample.len() < SAMPLE_SIZE);
+
+                (detector.guess(None, true), false)
+            }
+        };
+
+        let (text, _) = encoding.decode_with_bom_removal(sample);
+        let line_ending = text.find(['\r', '\n']).map(|index| {
+            let bytes = text.as_bytes();
+
+            match bytes[index] {
+                b'\n' => LineEnding::Lf,
+                _ if bytes.get(index + 1) == Some(&b'\n') => LineEnding::Crlf,
+                _ => LineEnding::Cr,
+            }
+        });
+
+        Self {
+            encoding,
+            bom,
+            line_ending,
+        }
+    }
+
+    #[inline]
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        self.encoding.decode_with_bom_removal(bytes).0.into_owned()
+    }
+
+    /// Converts UTF-8 text from the editor back into this encoding,
+    /// line breaks are normalized when a line ending is set. Fails if the
+    /// text contains characters the encoding cannot represent
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, anyhow::Error> {
+        let text = match self.line_ending {
+            Some(line_ending) => std::borrow::Cow::Owned(
+                text.replace("\r\n", "\n")
+                    .replace('\r', "\n")
+                    .replace('\n', line_ending.separator()),
+            ),
+            None => std::borrow::Cow::Borrowed(text),
+        };
+
+        let mut bytes = Vec::with_capacity(text.len() + 3);
+        if self.bom {
+            // only unicode encodings have a byte order mark
+            let mark: &[u8] = match self.encoding {
+                encoding if encoding == UTF_8 => b"\xEF\xBB\xBF",
+                encoding if encoding == UTF_16LE => b"\xFF\xFE",
+                encoding if encoding == UTF_16BE => b"\xFE\xFF",
+                _ => b"",
+            };
+            bytes.extend_from_slice(mark);
+        }
+
+        // encoding_rs only decodes utf-16, encoding it falls back to utf-8
+        if self.encoding == UTF_16LE {
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        } else if self.encoding == UTF_16BE {
+            bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+        } else {
+            let (encoded, _, unmappable) = self.encoding.encode(&text);
+            if unmappable {
+                return Err(anyhow::anyhow!(
+                    "text contains characters that cannot be encoded as {}",
+                    self.encoding.name()
+                ));
+            }
+
+            bytes.extend_from_slice(&encoded);
+        }
+
+        Ok(bytes)
+    }
+}
@@ -1,4 +1,7 @@
-use crate::server::backup::InternalBackup;
+use crate::server::{
+    backup::InternalBackup,
+    permissions::{Permission, Permissions},
+};
 use cap_std::fs::{Metadata, PermissionsExt};
 use ignore::WalkBuilder;
 use std::{
@@ -14,11 +17,19 @@ use tokio::{
     sync::{RwLock, RwLockReadGuard},
 };
 
+pub mod accountant;
 pub mod archive;
 pub mod backup;
+pub mod copy;
+pub mod encoding;
 pub mod limiter;
+pub mod nbt;
+pub mod operations;
 pub mod pull;
-mod usage;
+pub mod region;
+pub mod trash;
+pub mod usage;
+pub mod watcher;
 pub mod writer;
 
 pub struct AsyncCapReadDir(Option<cap_std::fs::ReadDir>);
@@ -69,6 +80,7 @@ impl AsyncReadDir {
 pub struct Filesystem {
     uuid: uuid::Uuid,
     checker_abort: Arc<AtomicBool>,
+    checker_trigger: std::sync::mpsc::SyncSender<()>,
     config: Arc<crate::config::Config>,
 
     pub base_path: PathBuf,
@@ -77,9 +89,14 @@ pub struct Filesystem {
     disk_limit: AtomicI64,
     disk_usage_cached: Arc<AtomicU64>,
     disk_usage: Arc<RwLock<usage::DiskUsage>>,
+    disk_usage_checked: Arc<AtomicI64>,
+    accountant_active: Arc<AtomicBool>,
+    trash_usage: Arc<AtomicU64>,
+    upload_usage: Arc<AtomicU64>,
     disk_ignored: Arc<RwLock<ignore::overrides::Override>>,
 
-    pub pulls: RwLock<HashMap<uuid::Uuid, Arc<RwLock<pull::Download>>>>,
+    pub operations: RwLock<HashMap<uuid::Uuid, Arc<operations::Operation>>>,
+    pub watcher: watcher::FilesystemWatcher,
 }
 
 impl Filesystem {
@@ -93,6 +110,8 @@ impl Filesystem {
         let base_path = Path::new(&config.system.data_directory).join(uuid.to_string());
         let disk_usage = Arc::new(RwLock::new(usage::DiskUsage::default()));
         let disk_usage_cached = Arc::new(AtomicU64::new(0));
+        let trash_usage = Arc::new(AtomicU64::new(0));
+        let upload_usage = Arc::new(AtomicU64::new(0));
         let mut disk_ignored = ignore::overrides::OverrideBuilder::new(&base_path);
 
         for entry in deny_list {
@@ -100,12 +119,38 @@ impl Filesystem {
         }
 
         let checker_abort = Arc::new(AtomicBool::new(false));
+        let disk_usage_checked = Arc::new(AtomicI64::new(0));
+        let (checker_sender, checker_trigger) = std::sync::mpsc::sync_channel(1);
+        let accountant_active = Arc::new(AtomicBool::new(false));
+
+        if config.system.disk_watcher.enabled
+            && matches!(
+                config.system.disk_limiter_mode,
+                crate::config::SystemDiskLimiterMode::None
+            )
+        {
+            accountant::DiskAccountant::spawn(
+                base_path.clone(),
+                Arc::clone(&disk_usage),
+                Arc::clone(&disk_usage_cached),
+                Arc::clone(&accountant_active),
+                Arc::clone(&checker_abort),
+            );
+        }
+        let disk_ignored = Arc::new(RwLock::new(disk_ignored.build().unwrap()));
 
         std::thread::spawn({
             let disk_usage = Arc::clone(&disk_usage);
             let disk_usage_cached = Arc::clone(&disk_usage_cached);
+            let trash_usage = Arc::clone(&trash_usage);
+            let upload_usage = Arc::clone(&upload_usage);
             let checker_abort = Arc::clone(&checker_abort);
+            let disk_usage_checked = Arc::clone(&disk_usage_checked);
             let base_path = base_path.clone();
+            let accountant_active = Arc::clone(&accountant_active);
+            let reconcile_interval = config.system.disk_watcher.reconcile_interval;
+            let trash_path = trash::directory(&config, uuid);
+            let trash_retention_days = config.system.trash.retention_days;
 
             move || {
                 loop {
@@ -118,53 +163,26 @@ impl Filesystem {
                         "checking disk usage"
                     );
 
-                    let mut tmp_disk_usage = usage::DiskUsage::default();
-
-                    fn recursive_size(
-                        path: &Path,
-                        relative_path: &[String],
-                        disk_usage: &mut usage::DiskUsage,
-                    ) -> u64 {
-                        let mut total_size = 0;
-                        let metadata = match path.symlink_metadata() {
-                            Ok(metadata) => metadata,
-                            Err(_) => return 0,
-                        };
-
-                        total_size += metadata.len();
-
-                        if metadata.is_dir() {
-                            if let Ok(entries) = path.read_dir() {
-                                for entry in entries.flatten() {
-                                    let path = entry.path();
-                                    let metadata = match path.symlink_metadata() {
-                                        Ok(metadata) => metadata,
-                                        Err(_) => continue,
-                                    };
-
-                                    let file_name = entry.file_name().to_string_lossy().to_string();
-                                    let mut new_path = relative_path.to_vec();
-                                    new_path.push(file_name);
-
-                                    total_size += metadata.len();
-
-                                    if metadata.is_dir() {
-                                        let size = recursive_size(&path, &new_path, disk_usage);
-                                        disk_usage.update_size(&new_path, size as i64);
-                                    }
-                                }
-                            }
-                        }
-
-                        total_size
+                    let mut tmp_disk_usage = usage::DiskUsage::scan(&base_path);
+                    let total_trash_size = trash::cleanup_sync(&trash_path, trash_retention_days);
+
+                    let mut disk_usage = disk_usage.blocking_write();
+                    if disk_usage_checked.load(Ordering::Relaxed) == 0 {
+                        tmp_disk_usage.reset_previous();
+                    } else {
+                        tmp_disk_usage.set_previous(Some(&disk_usage));
                     }
 
-                    let total_size = recursive_size(&base_path, &[], &mut tmp_disk_usage);
-                    let total_entry_size =
-                        tmp_disk_usage.entries.values().map(|e| e.size).sum::<u64>();
+                    let total_size = tmp_disk_usage.size;
+                    *disk_usage = tmp_disk_usage;
+                    drop(disk_usage);
 
-                    *disk_usage.blocking_write() = tmp_disk_usage;
-                    disk_usage_cached.store(total_size + total_entry_size, Ordering::Relaxed);
+                    trash_usage.store(total_trash_size, Ordering::Relaxed);
+                    disk_usage_cached.store(
+                        total_size + total_trash_size + upload_usage.load(Ordering::Relaxed),
+                        Ordering::Relaxed,
+                    );
+                    disk_usage_checked.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
 
                     tracing::debug!(
                         path = %base_path.display(),
@@ -172,14 +190,27 @@ impl Filesystem {
                         disk_usage_cached.load(Ordering::Relaxed)
                     );
 
-                    std::thread::sleep(std::time::Duration::from_secs(check_interval));
+                    let interval = if accountant_active.load(Ordering::Relaxed) {
+                        reconcile_interval.max(check_interval)
+                    } else {
+                        check_interval
+                    };
+
+                    if let Err(std::sync::mpsc::RecvTimeoutError::Disconnected) =
+                        checker_trigger.recv_timeout(std::time::Duration::from_secs(interval))
+                    {
+                        break;
+                    }
                 }
             }
         });
 
+        let watcher = watcher::FilesystemWatcher::new(base_path.clone(), Arc::clone(&disk_ignored));
+
         Self {
             uuid,
             checker_abort,
+            checker_trigger: checker_sender,
             config: Arc::clone(&config),
 
             base_path,
@@ -188,9 +219,14 @@ impl Filesystem {
             disk_limit: AtomicI64::new(disk_limit as i64),
             disk_usage_cached,
             disk_usage,
-            disk_ignored: Arc::new(RwLock::new(disk_ignored.build().unwrap())),
-
-            pulls: RwLock::new(HashMap::new()),
+            disk_usage_checked,
+            accountant_active,
+            trash_usage,
+            upload_usage,
+            disk_ignored,
+
+            operations: RwLock::new(HashMap::new()),
+            watcher,
         }
     }
 
@@ -220,27 +256,185 @@ impl Filesystem {
             .is_ignore()
     }
 
-    pub async fn pulls(
+    /// Whether `permission` is granted for `path` as it is resolved on disk. The grant has
+    /// to cover the entry itself with its parent directories resolved and, for a symlink,
+    /// its target, so no symlink inside of a grant can reach files outside of it
+    pub async fn has_path_permission(
         &self,
-    ) -> RwLockReadGuard<'_, HashMap<uuid::Uuid, Arc<RwLock<pull::Download>>>> {
-        if let Ok(mut pulls) = self.pulls.try_write() {
-            for key in pulls.keys().cloned().collect::<Vec<_>>() {
-                if let Some(download) = pulls.get(&key) {
-                    if download
-                        .read()
-                        .await
-                        .task
-                        .as_ref()
-                        .map(|t| t.is_finished())
-                        .unwrap_or(true)
-                    {
-                        pulls.remove(&key);
-                    }
-                }
+        path: &Path,
+        permissions: &Permissions,
+        permission: Permission,
+    ) -> bool {
+        if permissions.has_permission(permission) {
+            return true;
+        }
+        if !permissions.has_any_permission(permission) {
+            return false;
+        }
+
+        let filesystem = match self.base_dir().await {
+            Ok(filesystem) => filesystem,
+            Err(_) => return false,
+        };
+        let path = self.relative_path(path);
+
+        match tokio::task::spawn_blocking(move || resolve_grant_paths(&filesystem, path)).await {
+            Ok(Some(paths)) => paths
+                .iter()
+                .all(|path| permissions.has_path_permission(permission, path)),
+            _ => false,
+        }
+    }
+
+    pub fn has_path_permission_sync(
+        &self,
+        path: &Path,
+        permissions: &Permissions,
+        permission: Permission,
+    ) -> bool {
+        if permissions.has_permission(permission) {
+            return true;
+        }
+        if !permissions.has_any_permission(permission) {
+            return false;
+        }
+
+        let filesystem = match self.sync_base_dir() {
+            Ok(filesystem) => filesystem,
+            Err(_) => return false,
+        };
+
+        match resolve_grant_paths(&filesystem, self.relative_path(path)) {
+            Some(paths) => paths
+                .iter()
+                .all(|path| permissions.has_path_permission(permission, path)),
+            None => false,
+        }
+    }
+
+    /// Whether `path` is denylisted or outside of the `permission` grants of a user
+    pub async fn is_denied(
+        &self,
+        path: &Path,
+        is_dir: bool,
+        permissions: &Permissions,
+        permission: Permission,
+    ) -> bool {
+        !self
+            .has_path_permission(path, permissions, permission)
+            .await
+            || self.is_ignored(path, is_dir).await
+    }
+
+    pub fn is_denied_sync(
+        &self,
+        path: &Path,
+        is_dir: bool,
+        permissions: &Permissions,
+        permission: Permission,
+    ) -> bool {
+        !self.has_path_permission_sync(path, permissions, permission)
+            || self.is_ignored_sync(path, is_dir)
+    }
+
+    /// Whether `path` should be left out of directory listings for a user,
+    /// parent directories of a path scoped grant stay visible
+    pub async fn is_hidden(&self, path: &Path, is_dir: bool, permissions: &Permissions) -> bool {
+        !permissions.is_path_visible(&self.relative_path(path))
+            || self.is_ignored(path, is_dir).await
+    }
+
+    pub fn is_hidden_sync(&self, path: &Path, is_dir: bool, permissions: &Permissions) -> bool {
+        !permissions.is_path_visible(&self.relative_path(path))
+            || self.is_ignored_sync(path, is_dir)
+    }
+
+    /// Whether `path` is outside of the `permission` grants an api request was restricted to,
+    /// requests without grants come from the panel itself and may touch the whole volume
+    pub async fn is_outside_grants(
+        &self,
+        path: &Path,
+        permissions: Option<&Permissions>,
+        permission: Permission,
+    ) -> bool {
+        match permissions {
+            Some(permissions) => {
+                !self
+                    .has_path_permission(path, permissions, permission)
+                    .await
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_outside_grants_sync(
+        &self,
+        path: &Path,
+        permissions: Option<&Permissions>,
+        permission: Permission,
+    ) -> bool {
+        permissions.is_some_and(|permissions| {
+            !self.has_path_permission_sync(path, permissions, permission)
+        })
+    }
+
+    /// Whether `path` has to be left out of results for an api request restricted to
+    /// `permissions`, parent directories of a path scoped grant stay visible
+    #[inline]
+    pub fn is_outside_view(&self, path: &Path, permissions: Option<&Permissions>) -> bool {
+        permissions
+            .is_some_and(|permissions| !permissions.is_path_visible(&self.relative_path(path)))
+    }
+
+    pub async fn operations(
+        &self,
+    ) -> RwLockReadGuard<'_, HashMap<uuid::Uuid, Arc<operations::Operation>>> {
+        if let Ok(mut operations) = self.operations.try_write() {
+            operations.retain(|_, operation| !operation.is_finished());
+        }
+
+        self.operations.read().await
+    }
+
+    pub async fn add_operation(
+        &self,
+        operation: operations::Operation,
+    ) -> Arc<operations::Operation> {
+        let operation = Arc::new(operation);
+
+        self.operations
+            .write()
+            .await
+            .insert(operation.identifier, Arc::clone(&operation));
+
+        operation
+    }
+
+    /// Cancels and forgets a running operation, `kind` restricts which operations may be cancelled
+    pub async fn cancel_operation(
+        &self,
+        identifier: uuid::Uuid,
+        kind: Option<operations::OperationKind>,
+    ) -> bool {
+        let mut operations = self.operations.write().await;
+
+        match operations.get(&identifier) {
+            Some(operation) if kind.is_none_or(|kind| operation.kind == kind) => {
+                operation.cancel();
+                operations.remove(&identifier);
+
+                true
             }
+            _ => false,
         }
+    }
 
-        self.pulls.read().await
+    pub async fn count_operations(&self, kind: operations::OperationKind) -> usize {
+        self.operations()
+            .await
+            .values()
+            .filter(|operation| operation.kind == kind)
+            .count()
     }
 
     #[inline]
@@ -415,6 +609,11 @@ impl Filesystem {
         }
     }
 
+    #[inline]
+    pub async fn trash_path(&self, path: &Path) -> Result<(), anyhow::Error> {
+        trash::trash_path(self, path).await
+    }
+
     pub async fn rename_path(
         &self,
         old_path: impl Into<PathBuf>,
@@ -585,6 +784,21 @@ impl Filesystem {
         Ok(tokio::fs::File::from_std(file.into_std()))
     }
 
+    pub async fn open_read_write(
+        &self,
+        path: impl Into<PathBuf>,
+    ) -> Result<tokio::fs::File, anyhow::Error> {
+        let filesystem = self.base_dir().await?;
+
+        let path = self.relative_path(&path.into());
+        let file = tokio::task::spawn_blocking(move || {
+            filesystem.open_with(path, cap_std::fs::OpenOptions::new().read(true).write(true))
+        })
+        .await??;
+
+        Ok(tokio::fs::File::from_std(file.into_std()))
+    }
+
     pub async fn copy(
         &self,
         from: impl Into<PathBuf>,
@@ -662,6 +876,22 @@ impl Filesystem {
         Ok(())
     }
 
+    pub async fn hard_link(
+        &self,
+        target: impl Into<PathBuf>,
+        link: impl Into<PathBuf>,
+    ) -> Result<(), anyhow::Error> {
+        let filesystem = self.base_dir().await?;
+
+        let target = self.relative_path(&target.into());
+        let link = self.relative_path(&link.into());
+
+        tokio::task::spawn_blocking(move || filesystem.hard_link(target, &filesystem, link))
+            .await??;
+
+        Ok(())
+    }
+
     /// Allocates (or deallocates) space for a path in the filesystem.
     /// Updates both the disk_usage map for directories and the cached total.
     ///
@@ -702,6 +932,79 @@ impl Filesystem {
         true
     }
 
+    /// Reserves space for an upload that is still being written outside of the server
+    /// directory, reservations are kept apart from the scanned usage so rescans keep them
+    ///
+    /// Returns `true` if the reservation was successful, `false` if it would exceed disk limit
+    pub fn reserve_upload(&self, size: u64) -> bool {
+        let current_usage = self.disk_usage_cached.load(Ordering::Relaxed);
+        if self.disk_limit() != 0 && current_usage + size > self.disk_limit() as u64 {
+            return false;
+        }
+
+        self.upload_usage.fetch_add(size, Ordering::Relaxed);
+        self.disk_usage_cached.fetch_add(size, Ordering::Relaxed);
+
+        true
+    }
+
+    pub fn release_upload(&self, size: u64) {
+        for usage in [&self.upload_usage, &self.disk_usage_cached] {
+            usage
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |usage| {
+                    Some(usage.saturating_sub(size))
+                })
+                .ok();
+        }
+    }
+
+    /// Moves a finished upload of `size` bytes from its reservation into the usage of `path`,
+    /// `replaced` is the size of the file it overwrites. Fails without any changes if the
+    /// volume would end up over its disk limit
+    pub async fn commit_upload(&self, path: &Path, size: u64, replaced: u64) -> bool {
+        self.release_upload(size);
+
+        if self
+            .allocate_in_path(path, size as i64 - replaced as i64)
+            .await
+        {
+            return true;
+        }
+
+        self.upload_usage.fetch_add(size, Ordering::Relaxed);
+        self.disk_usage_cached.fetch_add(size, Ordering::Relaxed);
+
+        false
+    }
+
+    /// Runs `f` against the cached usage tree of `path`, together with the time of the last recount
+    pub async fn usage_tree<T>(
+        &self,
+        path: &Path,
+        f: impl FnOnce(Option<&usage::DiskUsage>) -> T,
+    ) -> (T, Option<chrono::DateTime<chrono::Utc>>) {
+        let checked = match self.disk_usage_checked.load(Ordering::Relaxed) {
+            0 => None,
+            checked => chrono::DateTime::from_timestamp(checked, 0),
+        };
+
+        let disk_usage = self.disk_usage.read().await;
+
+        (f(disk_usage.get(&self.path_to_components(path))), checked)
+    }
+
+    /// Whether the usage tree is kept up to date by the [`accountant::DiskAccountant`]
+    #[inline]
+    pub fn is_usage_tracked(&self) -> bool {
+        self.accountant_active.load(Ordering::Relaxed)
+    }
+
+    /// Wakes up the disk checker to recount the usage tree in the background
+    #[inline]
+    pub fn request_usage_recount(&self) {
+        self.checker_trigger.try_send(()).ok();
+    }
+
     #[inline]
     pub async fn allocate_in_path(&self, path: &Path, delta: i64) -> bool {
         let components = self.path_to_components(path);
@@ -859,6 +1162,9 @@ impl Filesystem {
                 err
             );
         }
+
+        trash::destroy(self).await;
+        self.watcher.destroy().await;
     }
 
     #[inline]
@@ -1006,8 +1312,89 @@ impl Filesystem {
     }
 }
 
+/// The paths a grant has to cover for an operation on `path`, relative to `filesystem`:
+/// the entry itself with its parent directories resolved, and its target if it is a
+/// symlink. `None` if the path cannot be resolved without leaving the volume
+fn resolve_grant_paths(filesystem: &cap_std::fs::Dir, path: PathBuf) -> Option<Vec<PathBuf>> {
+    let name = match path.file_name() {
+        Some(name) => name.to_owned(),
+        None => return Some(vec![path]),
+    };
+
+    // directories that do not exist yet cannot be symlinks
+    let mut missing = Vec::new();
+    let mut existing = path.parent().unwrap_or(Path::new(""));
+    let mut resolved = loop {
+        if existing.as_os_str().is_empty() {
+            break PathBuf::new();
+        }
+
+        match filesystem.canonicalize(existing) {
+            Ok(resolved) => break resolved,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                missing.push(existing.file_name()?.to_owned());
+                existing = existing.parent().unwrap_or(Path::new(""));
+            }
+            Err(_) => return None,
+        }
+    };
+    resolved.extend(missing.iter().rev());
+    resolved.push(name);
+
+    let mut paths = Vec::with_capacity(2);
+    if filesystem
+        .symlink_metadata(&resolved)
+        .is_ok_and(|metadata| metadata.is_symlink())
+    {
+        paths.push(filesystem.canonicalize(&resolved).ok()?);
+    }
+    paths.push(resolved);
+
+    Some(paths)
+}
+
 impl Drop for Filesystem {
     fn drop(&mut self) {
         self.checker_abort.store(true, Ordering::Relaxed);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::resolve_grant_paths;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn grant_paths_follow_symlinks() {
+        let root = std::env::temp_dir().join(format!("wings-grants-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("plugins")).unwrap();
+        std::fs::create_dir_all(root.join("world")).unwrap();
+        std::fs::write(root.join("world/level.dat"), b"").unwrap();
+        std::os::unix::fs::symlink("../world", root.join("plugins/world")).unwrap();
+        std::os::unix::fs::symlink("../world/level.dat", root.join("plugins/level.dat")).unwrap();
+        std::os::unix::fs::symlink("/", root.join("plugins/host")).unwrap();
+
+        let dir = cap_std::fs::Dir::open_ambient_dir(&root, cap_std::ambient_authority()).unwrap();
+        let paths = |path: &str| resolve_grant_paths(&dir, PathBuf::from(path));
+
+        assert_eq!(
+            paths("plugins/world/level.dat"),
+            Some(vec![PathBuf::from("world/level.dat")])
+        );
+        assert_eq!(
+            paths("plugins/level.dat"),
+            Some(vec![
+                PathBuf::from("world/level.dat"),
+                PathBuf::from("plugins/level.dat")
+            ])
+        );
+        assert_eq!(
+            paths("plugins/new/config.yml"),
+            Some(vec![PathBuf::from("plugins/new/config.yml")])
+        );
+        assert_eq!(paths("plugins/host/etc/passwd"), None);
+        assert_eq!(paths(""), Some(vec![Path::new("").to_path_buf()]));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,345 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use utoipa::ToSchema;
+
+/// same nesting limit as the game itself
+const MAX_DEPTH: usize = 512;
+/// decompressed data larger than this is refused, guards against decompression bombs
+const MAX_SIZE: u64 = 128 * 1024 * 1024;
+/// every tag is a separate allocation, so their total count is limited as well
+const MAX_NODES: usize = 2 * 1024 * 1024;
+
+#[derive(ToSchema, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[schema(rename_all = "lowercase")]
+pub enum NbtCompression {
+    None,
+    Gzip,
+    Zlib,
+}
+
+impl NbtCompression {
+    /// Guesses the compression from the first bytes of a file
+    #[inline]
+    pub fn detect(data: &[u8]) -> Self {
+        match data {
+            [0x1f, 0x8b, ..] => NbtCompression::Gzip,
+            [0x78, second, ..] if (0x7800 | *second as u16).is_multiple_of(31) => {
+                NbtCompression::Zlib
+            }
+            _ => NbtCompression::None,
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let mut buffer = Vec::new();
+
+        match self {
+            NbtCompression::None => buffer.extend_from_slice(data),
+            NbtCompression::Gzip => {
+                flate2::read::GzDecoder::new(data)
+                    .take(MAX_SIZE + 1)
+                    .read_to_end(&mut buffer)?;
+            }
+            NbtCompression::Zlib => {
+                flate2::read::ZlibDecoder::new(data)
+                    .take(MAX_SIZE + 1)
+                    .read_to_end(&mut buffer)?;
+            }
+        }
+
+        if buffer.len() as u64 > MAX_SIZE {
+            return Err(anyhow::anyhow!("decompressed nbt data is too large"));
+        }
+
+        Ok(buffer)
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(match self {
+            NbtCompression::None => data.to_vec(),
+            NbtCompression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            NbtCompression::Zlib => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+        })
+    }
+}
+
+/// A NBT tag, serialized as `{"type": "int", "value": 1}` so numeric types
+/// survive a round trip through JSON
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(indexmap::IndexMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    #[inline]
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
+
+    fn write(&self, buffer: &mut Vec<u8>) -> Result<(), anyhow::Error> {
+        match self {
+            Tag::Byte(value) => buffer.extend_from_slice(&value.to_be_bytes()),
+            Tag::Short(value) => buffer.extend_from_slice(&value.to_be_bytes()),
+            Tag::Int(value) => buffer.extend_from_slice(&value.to_be_bytes()),
+            Tag::Long(value) => buffer.extend_from_slice(&value.to_be_bytes()),
+            Tag::Float(value) => buffer.extend_from_slice(&value.to_be_bytes()),
+            Tag::Double(value) => buffer.extend_from_slice(&value.to_be_bytes()),
+            Tag::ByteArray(values) => {
+                buffer.extend_from_slice(&(values.len() as i32).to_be_bytes());
+                buffer.extend(values.iter().map(|value| *value as u8));
+            }
+            Tag::String(value) => write_string(buffer, value)?,
+            Tag::List(values) => {
+                let id = values.first().map(|value| value.id()).unwrap_or(0);
+                if values.iter().any(|value| value.id() != id) {
+                    return Err(anyhow::anyhow!("list elements must have the same type"));
+                }
+
+                buffer.push(id);
+                buffer.extend_from_slice(&(values.len() as i32).to_be_bytes());
+                for value in values {
+                    value.write(buffer)?;
+                }
+            }
+            Tag::Compound(values) => {
+                for (name, value) in values {
+                    buffer.push(value.id());
+                    write_string(buffer, name)?;
+                    value.write(buffer)?;
+                }
+
+                buffer.push(0);
+            }
+            Tag::IntArray(values) => {
+                buffer.extend_from_slice(&(values.len() as i32).to_be_bytes());
+                for value in values {
+                    buffer.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Tag::LongArray(values) => {
+                buffer.extend_from_slice(&(values.len() as i32).to_be_bytes());
+                for value in values {
+                    buffer.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_string(buffer: &mut Vec<u8>, value: &str) -> Result<(), anyhow::Error> {
+    let value = cesu8::to_java_cesu8(value);
+    let length: u16 = value
+        .len()
+        .try_into()
+        .context("string is longer than 65535 bytes")?;
+
+    buffer.extend_from_slice(&length.to_be_bytes());
+    buffer.extend_from_slice(&value);
+
+    Ok(())
+}
+
+/// Smallest number of bytes a tag of the given type takes up inside a list
+#[inline]
+fn min_tag_size(id: u8) -> usize {
+    match id {
+        1 | 10 => 1,
+        2 | 8 => 2,
+        3 | 5 | 7 | 11 | 12 => 4,
+        4 | 6 => 8,
+        9 => 5,
+        _ => 1,
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    nodes: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], anyhow::Error> {
+        if self.data.len() < length {
+            return Err(anyhow::anyhow!("unexpected end of nbt data"));
+        }
+
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+
+        Ok(taken)
+    }
+
+    #[inline]
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], anyhow::Error> {
+        Ok(self.take(N)?.try_into()?)
+    }
+
+    /// Reads a length prefix, checking it against the remaining data so corrupt
+    /// files cannot make us allocate huge buffers
+    fn length(&mut self, element_size: usize) -> Result<usize, anyhow::Error> {
+        let length = i32::from_be_bytes(self.array()?);
+        if length < 0 || length as usize * element_size > self.data.len() {
+            return Err(anyhow::anyhow!("invalid nbt length {length}"));
+        }
+
+        Ok(length as usize)
+    }
+
+    fn string(&mut self) -> Result<String, anyhow::Error> {
+        let length = u16::from_be_bytes(self.array()?) as usize;
+        let value = self.take(length)?;
+
+        Ok(cesu8::from_java_cesu8(value)
+            .map(|value| value.into_owned())
+            .unwrap_or_else(|_| String::from_utf8_lossy(value).into_owned()))
+    }
+
+    fn tag(&mut self, id: u8, depth: usize) -> Result<Tag, anyhow::Error> {
+        if depth > MAX_DEPTH {
+            return Err(anyhow::anyhow!("nbt data is nested too deeply"));
+        }
+
+        self.nodes += 1;
+        if self.nodes > MAX_NODES {
+            return Err(anyhow::anyhow!("nbt data contains too many tags"));
+        }
+
+        Ok(match id {
+            1 => Tag::Byte(i8::from_be_bytes(self.array()?)),
+            2 => Tag::Short(i16::from_be_bytes(self.array()?)),
+            3 => Tag::Int(i32::from_be_bytes(self.array()?)),
+            4 => Tag::Long(i64::from_be_bytes(self.array()?)),
+            5 => Tag::Float(f32::from_be_bytes(self.array()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.array()?)),
+            7 => {
+                let length = self.length(1)?;
+
+                Tag::ByteArray(self.take(length)?.iter().map(|b| *b as i8).collect())
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let element = u8::from_be_bytes(self.array()?);
+                let length = self.length(min_tag_size(element))?;
+
+                let mut values = Vec::with_capacity(length);
+                for _ in 0..length {
+                    values.push(self.tag(element, depth + 1)?);
+                }
+
+                Tag::List(values)
+            }
+            10 => {
+                let mut values = indexmap::IndexMap::new();
+
+                loop {
+                    let id = u8::from_be_bytes(self.array()?);
+                    if id == 0 {
+                        break;
+                    }
+
+                    let name = self.string()?;
+                    values.insert(name, self.tag(id, depth + 1)?);
+                }
+
+                Tag::Compound(values)
+            }
+            11 => {
+                let length = self.length(4)?;
+
+                Tag::IntArray(
+                    self.take(length * 4)?
+                        .chunks_exact(4)
+                        .map(|chunk| i32::from_be_bytes(chunk.try_into().unwrap()))
+                        .collect(),
+                )
+            }
+            12 => {
+                let length = self.length(8)?;
+
+                Tag::LongArray(
+                    self.take(length * 8)?
+                        .chunks_exact(8)
+                        .map(|chunk| i64::from_be_bytes(chunk.try_into().unwrap()))
+                        .collect(),
+                )
+            }
+            id => return Err(anyhow::anyhow!("unknown nbt tag type {id}")),
+        })
+    }
+}
+
+/// The named root compound of a NBT file
+pub struct Nbt {
+    pub name: String,
+    pub tag: Tag,
+}
+
+impl Nbt {
+    /// Parses uncompressed NBT data
+    pub fn parse(data: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut reader = Reader { data, nodes: 0 };
+
+        let id = u8::from_be_bytes(reader.array()?);
+        if id != 10 {
+            return Err(anyhow::anyhow!("nbt root is not a compound"));
+        }
+
+        let name = reader.string()?;
+        let tag = reader.tag(id, 0)?;
+
+        Ok(Self { name, tag })
+    }
+
+    /// Serializes to uncompressed NBT data
+    pub fn to_bytes(&self) -> Result<Vec<u8>, anyhow::Error> {
+        if !matches!(self.tag, Tag::Compound(_)) {
+            return Err(anyhow::anyhow!("nbt root must be a compound"));
+        }
+
+        let mut buffer = Vec::new();
+        buffer.push(self.tag.id());
+        write_string(&mut buffer, &self.name)?;
+        self.tag.write(&mut buffer)?;
+
+        Ok(buffer)
+    }
+}
@@ -0,0 +1,214 @@
+use crate::server::websocket::{WebsocketEvent, WebsocketMessage};
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+use utoipa::ToSchema;
+
+#[derive(ToSchema, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[schema(rename_all = "lowercase")]
+pub enum OperationKind {
+    Pull,
+    Copy,
+    Compress,
+    Decompress,
+    Delete,
+}
+
+impl OperationKind {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationKind::Pull => "pull",
+            OperationKind::Copy => "copy",
+            OperationKind::Compress => "compress",
+            OperationKind::Decompress => "decompress",
+            OperationKind::Delete => "delete",
+        }
+    }
+}
+
+/// A long running file operation, `progress` and `total` are in bytes for pulls, copies,
+/// (de)compression and in entries for deletions.
+/// Async work is aborted when the operation is cancelled, work that runs on a blocking
+/// thread sets `abortable` to false and checks `cancelled` on its own so it can clean up.
+pub struct Operation {
+    pub identifier: uuid::Uuid,
+    pub kind: OperationKind,
+    pub path: PathBuf,
+
+    pub progress: Arc<AtomicU64>,
+    pub total: Arc<AtomicU64>,
+    pub cancelled: Arc<AtomicBool>,
+    pub abortable: bool,
+    completed: Arc<AtomicBool>,
+
+    started: std::time::Instant,
+    started_at: chrono::DateTime<chrono::Utc>,
+
+    work: Option<tokio::task::AbortHandle>,
+    pub task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Operation {
+    pub fn new(kind: OperationKind, path: impl AsRef<Path>) -> Self {
+        Self {
+            identifier: uuid::Uuid::new_v4(),
+            kind,
+            path: path.as_ref().to_path_buf(),
+            progress: Arc::new(AtomicU64::new(0)),
+            total: Arc::new(AtomicU64::new(0)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            abortable: true,
+            completed: Arc::new(AtomicBool::new(false)),
+            started: std::time::Instant::now(),
+            started_at: chrono::Utc::now(),
+            work: None,
+            task: None,
+        }
+    }
+
+    /// Runs `work` in the background, progress is broadcast every second
+    /// and an `operation completed` event is sent once it finishes
+    pub fn start(
+        &mut self,
+        server: crate::server::Server,
+        work: impl Future<Output = Result<(), anyhow::Error>> + Send + 'static,
+    ) {
+        let identifier = self.identifier;
+        let kind = self.kind;
+        let path = self.path.clone();
+        let progress = Arc::clone(&self.progress);
+        let total = Arc::clone(&self.total);
+        let cancelled = Arc::clone(&self.cancelled);
+        let completed = Arc::clone(&self.completed);
+
+        let work = tokio::spawn(work);
+        if self.abortable {
+            self.work = Some(work.abort_handle());
+        }
+
+        let task = tokio::spawn(async move {
+            let progress_task = tokio::spawn({
+                let server = server.clone();
+
+                async move {
+                    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+                    interval.tick().await;
+
+                    loop {
+                        interval.tick().await;
+
+                        server
+                            .websocket
+                            .send(WebsocketMessage::new(
+                                WebsocketEvent::ServerOperationProgress,
+                                &[
+                                    identifier.to_string(),
+                                    kind.as_str().to_string(),
+                                    progress.load(Ordering::Relaxed).to_string(),
+                                    total.load(Ordering::Relaxed).to_string(),
+                                ],
+                            ))
+                            .ok();
+                    }
+                }
+            });
+
+            let result = work.await;
+            progress_task.abort();
+
+            let status = match result {
+                _ if cancelled.load(Ordering::Relaxed) => "cancelled",
+                Ok(Ok(())) => {
+                    completed.store(true, Ordering::Relaxed);
+
+                    "completed"
+                }
+                Ok(Err(err)) => {
+                    tracing::error!(
+                        server = %server.uuid,
+                        path = %path.display(),
+                        operation = kind.as_str(),
+                        "file operation failed: {:#?}",
+                        err
+                    );
+
+                    "failed"
+                }
+                Err(_) => "failed",
+            };
+
+            server
+                .websocket
+                .send(WebsocketMessage::new(
+                    WebsocketEvent::ServerOperationCompleted,
+                    &[
+                        identifier.to_string(),
+                        kind.as_str().to_string(),
+                        status.to_string(),
+                    ],
+                ))
+                .ok();
+        });
+
+        self.task = Some(task);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+
+        if let Some(work) = &self.work {
+            work.abort();
+        }
+    }
+
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.task.as_ref().is_none_or(|task| task.is_finished())
+    }
+
+    /// Waits until the operation has finished and returns whether it completed successfully,
+    /// used by requests that run in the foreground
+    pub async fn wait(&self) -> bool {
+        while !self.is_finished() {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        self.completed.load(Ordering::Relaxed)
+    }
+
+    /// Estimates the remaining seconds from the average rate since the operation started
+    pub fn eta(&self) -> Option<u64> {
+        let progress = self.progress.load(Ordering::Relaxed);
+        let total = self.total.load(Ordering::Relaxed);
+
+        if progress == 0 || total <= progress {
+            return None;
+        }
+
+        let elapsed = self.started.elapsed().as_secs_f64();
+
+        Some((elapsed / progress as f64 * (total - progress) as f64).ceil() as u64)
+    }
+
+    pub fn to_api_response(&self) -> crate::models::Operation {
+        crate::models::Operation {
+            identifier: self.identifier,
+            kind: self.kind,
+            path: Path::new("/")
+                .join(&self.path)
+                .to_string_lossy()
+                .to_string(),
+            progress: self.progress.load(Ordering::Relaxed),
+            total: self.total.load(Ordering::Relaxed),
+            started: self.started_at,
+            eta: self.eta(),
+        }
+    }
+}
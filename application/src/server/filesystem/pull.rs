@@ -1,47 +1,256 @@
 use anyhow::Context;
 use rand::Rng;
+use serde::Deserialize;
+use sha1::Digest;
 use std::{
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
-    sync::{
-        Arc, LazyLock,
-        atomic::{AtomicU64, Ordering},
-    },
+    sync::{Arc, atomic::Ordering},
 };
 use tokio::io::AsyncWriteExt;
+use utoipa::ToSchema;
 
-static DOWNLOAD_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
-    reqwest::Client::builder()
+const RESUME_ATTEMPTS: usize = 5;
+
+#[derive(ToSchema, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+#[schema(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+enum Hasher {
+    Md5(md5::Context),
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+}
+
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Md5 => Hasher::Md5(md5::Context::new()),
+            HashAlgorithm::Sha1 => Hasher::Sha1(sha1::Sha1::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Sha512 => Hasher::Sha512(sha2::Sha512::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.consume(data),
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Md5(hasher) => format!("{:x}", hasher.compute()),
+            Hasher::Sha1(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Sha512(hasher) => format!("{:x}", hasher.finalize()),
+        }
+    }
+}
+
+/// Whether an address is outside of the public internet (loopback, private, link-local,
+/// carrier-grade NAT, documentation, multicast, ...)
+pub fn is_private_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || octets[0] == 0
+                || (octets[0] == 100 && (octets[1] & 0b1100_0000) == 64)
+                || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0)
+                || (octets[0] == 198 && (octets[1] & 0xfe) == 18)
+                || octets[0] >= 240
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_private_address(IpAddr::V4(ip));
+            }
+
+            let segments = ip.segments();
+
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (segments[0] & 0xfe00) == 0xfc00
+                || (segments[0] & 0xffc0) == 0xfe80
+                || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+                || (segments[0] == 0x0064 && segments[1] == 0xff9b)
+        }
+    }
+}
+
+#[inline]
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => {
+            host.eq_ignore_ascii_case(domain)
+                || host
+                    .to_ascii_lowercase()
+                    .ends_with(&format!(".{}", domain.to_ascii_lowercase()))
+        }
+        None => host.eq_ignore_ascii_case(pattern),
+    }
+}
+
+/// Checks a url (the initial one and every redirect) against the remote download policy,
+/// resolved addresses are checked by [`PolicyResolver`] when connecting
+fn check_url(
+    policy: &crate::config::ApiRemoteDownload,
+    url: &reqwest::Url,
+) -> Result<(), anyhow::Error> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow::anyhow!("unsupported url scheme {}", url.scheme()));
+    }
+
+    let host = match url.host_str() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return Err(anyhow::anyhow!("url has no host")),
+    };
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        if policy.block_private_networks && is_private_address(ip) {
+            return Err(anyhow::anyhow!("destination address is not allowed"));
+        }
+    }
+
+    let host = host.trim_end_matches('.');
+
+    if policy
+        .denied_hosts
+        .iter()
+        .any(|pattern| host_matches(pattern, host))
+    {
+        return Err(anyhow::anyhow!("destination host is not allowed"));
+    }
+
+    if !policy.allowed_hosts.is_empty()
+        && !policy
+            .allowed_hosts
+            .iter()
+            .any(|pattern| host_matches(pattern, host))
+    {
+        return Err(anyhow::anyhow!("destination host is not allowed"));
+    }
+
+    Ok(())
+}
+
+/// Resolves hostnames and drops every address the policy does not allow, this also
+/// covers redirects and prevents DNS rebinding between the check and the connection
+struct PolicyResolver {
+    block_private_networks: bool,
+}
+
+impl reqwest::dns::Resolve for PolicyResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let block_private_networks = self.block_private_networks;
+
+        Box::pin(async move {
+            let addresses = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| !block_private_networks || !is_private_address(address.ip()))
+                .collect::<Vec<SocketAddr>>();
+
+            if addresses.is_empty() {
+                return Err(
+                    format!("{} does not resolve to an allowed address", name.as_str()).into(),
+                );
+            }
+
+            Ok(Box::new(addresses.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+fn client(policy: &crate::config::ApiRemoteDownload) -> Result<reqwest::Client, anyhow::Error> {
+    let redirect_policy = {
+        let policy = policy.clone();
+
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > policy.max_redirects {
+                return attempt.error("too many redirects");
+            }
+
+            match check_url(&policy, attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(err) => attempt.error(err.to_string()),
+            }
+        })
+    };
+
+    Ok(reqwest::Client::builder()
         .user_agent("Pterodactyl Panel (https://pterodactyl.io)")
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .unwrap()
-});
+        .connect_timeout(std::time::Duration::from_secs(30))
+        .read_timeout(std::time::Duration::from_secs(30))
+        .redirect(redirect_policy)
+        .dns_resolver(Arc::new(PolicyResolver {
+            block_private_networks: policy.block_private_networks,
+        }))
+        .build()?)
+}
+
+/// The first byte of a `Content-Range: bytes <start>-<end>/<size>` response
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    let value = response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    let (start, _) = value.strip_prefix("bytes ")?.split_once('-')?;
+
+    start.trim().parse().ok()
+}
 
 pub struct Download {
-    pub identifier: uuid::Uuid,
-    pub progress: Arc<AtomicU64>,
     pub total: u64,
     pub destination: PathBuf,
     pub server: crate::server::Server,
-    pub response: Option<reqwest::Response>,
+    pub response: reqwest::Response,
 
-    pub task: Option<tokio::task::JoinHandle<()>>,
+    client: reqwest::Client,
+    max_size: u64,
+    hash: Option<(HashAlgorithm, String)>,
 }
 
 impl Download {
     pub async fn new(
         server: crate::server::Server,
+        policy: &crate::config::ApiRemoteDownload,
         destination: &Path,
         file_name: Option<String>,
         url: String,
         use_header: bool,
+        hash: Option<(HashAlgorithm, String)>,
     ) -> Result<Self, anyhow::Error> {
-        let response = DOWNLOAD_CLIENT
-            .get(&url)
+        let url = reqwest::Url::parse(&url).context("invalid download url")?;
+        check_url(policy, &url)?;
+
+        let client = client(policy)?;
+        let response = client
+            .get(url)
             .send()
             .await
             .context("failed to send download request")?;
         let mut real_destination = destination.to_path_buf();
+        let max_size = policy.max_size * 1024 * 1024;
 
         if !response.status().is_success() {
             return Err(anyhow::anyhow!(
@@ -50,6 +259,16 @@ impl Download {
             ));
         }
 
+        if max_size != 0
+            && response
+                .content_length()
+                .is_some_and(|size| size > max_size)
+        {
+            return Err(anyhow::anyhow!(
+                "file is larger than the maximum download size"
+            ));
+        }
+
         'header_check: {
             if use_header {
                 if let Some(header) = response.headers().get("Content-Disposition") {
@@ -87,45 +306,164 @@ impl Download {
         }
 
         Ok(Self {
-            identifier: uuid::Uuid::new_v4(),
-            progress: Arc::new(AtomicU64::new(0)),
             total: response.content_length().unwrap_or(0),
             destination: real_destination,
             server,
-            response: Some(response),
-            task: None,
+            response,
+            client,
+            max_size,
+            hash: hash.map(|(algorithm, hash)| (algorithm, hash.to_lowercase())),
         })
     }
 
-    pub fn start(&mut self) {
-        let progress = Arc::clone(&self.progress);
-        let destination = self.destination.clone();
-        let server = self.server.clone();
-        let mut response = self.response.take().unwrap();
+    /// Starts streaming the response into the destination as a pull operation
+    pub fn start(self) -> super::operations::Operation {
+        let mut operation = super::operations::Operation::new(
+            super::operations::OperationKind::Pull,
+            &self.destination,
+        );
+        operation.total.store(self.total, Ordering::Relaxed);
+
+        let progress = Arc::clone(&operation.progress);
+        let total = Arc::clone(&operation.total);
+        let destination = self.destination;
+        let server = self.server;
+        let client = self.client;
+        let max_size = self.max_size;
+        let expected_hash = self.hash;
+        let mut response = self.response;
+
+        operation.start(server.clone(), async move {
+            let url = response.url().clone();
+            let resumable = response
+                .headers()
+                .get(reqwest::header::ACCEPT_RANGES)
+                .is_some_and(|value| value.as_bytes() == b"bytes");
+            let validator = response
+                .headers()
+                .get(reqwest::header::ETAG)
+                .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+                .or_else(|| response.headers().get(reqwest::header::LAST_MODIFIED))
+                .cloned();
+
+            let mut writer = super::writer::AsyncFileSystemWriter::new(
+                server.clone(),
+                destination.clone(),
+                None,
+            )
+            .await?;
+            let mut hasher = expected_hash
+                .as_ref()
+                .map(|(algorithm, _)| Hasher::new(*algorithm));
+            let mut written = 0;
+            let mut attempts = 0;
+
+            loop {
+                let chunk = match response.chunk().await {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => break,
+                    Err(err) => {
+                        attempts += 1;
+                        if !resumable || attempts > RESUME_ATTEMPTS {
+                            return Err(err.into());
+                        }
+
+                        tracing::debug!(
+                            server = %server.uuid,
+                            url = %url,
+                            "download interrupted, resuming at {} bytes: {:#?}",
+                            written,
+                            err
+                        );
+                        tokio::time::sleep(std::time::Duration::from_secs(attempts as u64)).await;
+
+                        // without a validator there is no telling whether the file changed
+                        let mut request = client.get(url.clone());
+                        if let Some(validator) = &validator {
+                            request = request
+                                .header(reqwest::header::RANGE, format!("bytes={}-", written))
+                                .header(reqwest::header::IF_RANGE, validator);
+                        }
+
+                        response = request.send().await?;
+                        if response.status() == reqwest::StatusCode::PARTIAL_CONTENT
+                            && content_range_start(&response) == Some(written)
+                        {
+                            continue;
+                        }
+
+                        if response.status() != reqwest::StatusCode::OK {
+                            response = client.get(url.clone()).send().await?;
+                        }
+                        if response.status() != reqwest::StatusCode::OK {
+                            return Err(anyhow::anyhow!(
+                                "server did not restart the download: code {}",
+                                response.status()
+                            ));
+                        }
+
+                        tracing::debug!(
+                            server = %server.uuid,
+                            url = %url,
+                            "download could not be resumed, restarting"
+                        );
+
+                        writer.flush().await?;
+                        drop(writer);
+                        server.filesystem.truncate_path(&destination).await.ok();
+
+                        writer = super::writer::AsyncFileSystemWriter::new(
+                            server.clone(),
+                            destination.clone(),
+                            None,
+                        )
+                        .await?;
+                        hasher = expected_hash
+                            .as_ref()
+                            .map(|(algorithm, _)| Hasher::new(*algorithm));
+                        written = 0;
+
+                        progress.store(0, Ordering::Relaxed);
+                        total.store(response.content_length().unwrap_or(0), Ordering::Relaxed);
 
-        let task = tokio::task::spawn(async move {
-            let mut writer = super::writer::AsyncFileSystemWriter::new(server, destination, None)
-                .await
-                .unwrap();
+                        continue;
+                    }
+                };
+
+                written += chunk.len() as u64;
+                if max_size != 0 && written > max_size {
+                    return Err(anyhow::anyhow!(
+                        "file is larger than the maximum download size"
+                    ));
+                }
 
-            while let Ok(Some(chunk)) = response.chunk().await {
-                writer.write_all(&chunk).await.unwrap();
+                writer.write_all(&chunk).await?;
+                if let Some(hasher) = &mut hasher {
+                    hasher.update(&chunk);
+                }
                 progress.fetch_add(chunk.len() as u64, Ordering::Relaxed);
             }
 
-            writer.flush().await.unwrap();
-        });
+            writer.flush().await?;
+            drop(writer);
 
-        self.task = Some(task);
-    }
+            if let (Some(hasher), Some((_, expected))) = (hasher, expected_hash) {
+                let hash = hasher.finalize();
 
-    pub fn to_api_response(&self) -> crate::models::Download {
-        let progress = self.progress.load(Ordering::Relaxed);
+                if hash != expected {
+                    server.filesystem.truncate_path(&destination).await.ok();
 
-        crate::models::Download {
-            identifier: self.identifier,
-            progress,
-            total: self.total,
-        }
+                    return Err(anyhow::anyhow!(
+                        "downloaded file hash {} does not match {}",
+                        hash,
+                        expected
+                    ));
+                }
+            }
+
+            Ok(())
+        });
+
+        operation
     }
 }
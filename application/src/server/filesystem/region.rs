@@ -0,0 +1,280 @@
+use super::nbt::{Nbt, NbtCompression};
+use std::{io::SeekFrom, path::Path};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+const SECTOR_SIZE: u64 = 4096;
+const HEADER_SIZE: u64 = SECTOR_SIZE * 2;
+const CHUNKS: usize = 32 * 32;
+
+/// Marks chunks that are stored in a separate `c.x.z.mcc` file next to the region
+const EXTERNAL_FLAG: u8 = 0x80;
+
+pub struct RegionChunk {
+    pub x: i32,
+    pub z: i32,
+    pub sector: u32,
+    pub sectors: u8,
+    pub timestamp: u32,
+}
+
+impl RegionChunk {
+    pub fn to_api_response(&self) -> crate::models::RegionChunk {
+        crate::models::RegionChunk {
+            x: self.x,
+            z: self.z,
+            offset: self.sector as u64 * SECTOR_SIZE,
+            size: self.sectors as u64 * SECTOR_SIZE,
+            modified: if self.timestamp == 0 {
+                None
+            } else {
+                chrono::DateTime::from_timestamp(self.timestamp as i64, 0)
+            },
+        }
+    }
+}
+
+/// A Minecraft anvil region file (`r.x.z.mca`), the first sector holds the location
+/// of each of its 32x32 chunks and the second sector their last modification time
+pub struct Region {
+    file: tokio::fs::File,
+    length: u64,
+
+    /// region coordinates parsed from the file name, chunk coordinates are
+    /// global when they are known and local to the region otherwise
+    position: (i32, i32),
+
+    locations: [u32; CHUNKS],
+    timestamps: [u32; CHUNKS],
+}
+
+impl Region {
+    pub async fn open(mut file: tokio::fs::File, path: &Path) -> Result<Self, anyhow::Error> {
+        let length = file.metadata().await?.len();
+
+        let mut locations = [0; CHUNKS];
+        let mut timestamps = [0; CHUNKS];
+
+        // the game creates empty region files, those simply have no chunks
+        if length > 0 {
+            if length < HEADER_SIZE {
+                return Err(anyhow::anyhow!("region file is truncated"));
+            }
+
+            let mut header = vec![0; HEADER_SIZE as usize];
+            file.read_exact(&mut header).await?;
+
+            for (index, entry) in header.chunks_exact(4).enumerate() {
+                let value = u32::from_be_bytes(entry.try_into().unwrap());
+
+                if index < CHUNKS {
+                    locations[index] = value;
+                } else {
+                    timestamps[index - CHUNKS] = value;
+                }
+            }
+        }
+
+        let position = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| {
+                let mut parts = name.split('.');
+
+                match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                    (Some("r"), Some(x), Some(z), Some("mca" | "mcr")) => {
+                        Some((x.parse().ok()?, z.parse().ok()?))
+                    }
+                    _ => None,
+                }
+            })
+            .unwrap_or((0, 0));
+
+        Ok(Self {
+            file,
+            length,
+            position,
+            locations,
+            timestamps,
+        })
+    }
+
+    /// Whether the chunk coordinates belong to this region
+    #[inline]
+    pub fn contains(&self, x: i32, z: i32) -> bool {
+        x.div_euclid(32) == self.position.0 && z.div_euclid(32) == self.position.1
+    }
+
+    /// Maps chunk coordinates to a header index
+    #[inline]
+    fn index(x: i32, z: i32) -> usize {
+        (x.rem_euclid(32) + z.rem_euclid(32) * 32) as usize
+    }
+
+    pub fn chunks(&self) -> Vec<RegionChunk> {
+        let mut chunks = Vec::new();
+
+        for (index, location) in self.locations.iter().enumerate() {
+            if *location == 0 {
+                continue;
+            }
+
+            chunks.push(RegionChunk {
+                x: self.position.0 * 32 + (index % 32) as i32,
+                z: self.position.1 * 32 + (index / 32) as i32,
+                sector: location >> 8,
+                sectors: (location & 0xff) as u8,
+                timestamp: self.timestamps[index],
+            });
+        }
+
+        chunks
+    }
+
+    pub async fn read_chunk(
+        &mut self,
+        x: i32,
+        z: i32,
+    ) -> Result<Option<(NbtCompression, Nbt)>, anyhow::Error> {
+        let location = self.locations[Self::index(x, z)];
+        if location == 0 {
+            return Ok(None);
+        }
+
+        let offset = (location >> 8) as u64 * SECTOR_SIZE;
+        let sectors = (location & 0xff) as u64;
+        if offset < HEADER_SIZE || offset + sectors * SECTOR_SIZE > self.length {
+            return Err(anyhow::anyhow!(
+                "chunk location is outside of the region file"
+            ));
+        }
+
+        self.file.seek(SeekFrom::Start(offset)).await?;
+        let length = self.file.read_u32().await? as u64;
+        if length == 0 || length + 4 > sectors * SECTOR_SIZE {
+            return Err(anyhow::anyhow!("chunk length is invalid"));
+        }
+
+        let compression = match self.file.read_u8().await? {
+            1 => NbtCompression::Gzip,
+            2 => NbtCompression::Zlib,
+            3 => NbtCompression::None,
+            compression if compression & EXTERNAL_FLAG != 0 => {
+                return Err(anyhow::anyhow!("chunk is stored in an external file"));
+            }
+            compression => {
+                return Err(anyhow::anyhow!(
+                    "unsupported chunk compression {compression}"
+                ));
+            }
+        };
+
+        let mut data = vec![0; length as usize - 1];
+        self.file.read_exact(&mut data).await?;
+
+        let data = compression.decompress(&data)?;
+
+        Ok(Some((compression, Nbt::parse(&data)?)))
+    }
+
+    /// Serializes a chunk the way it is stored in a region file, including
+    /// the length and compression prefix
+    pub fn encode_chunk(compression: NbtCompression, nbt: &Nbt) -> Result<Vec<u8>, anyhow::Error> {
+        let data = compression.compress(&nbt.to_bytes()?)?;
+
+        let mut buffer = Vec::with_capacity(data.len() + 5);
+        buffer.extend_from_slice(&(data.len() as u32 + 1).to_be_bytes());
+        buffer.push(match compression {
+            NbtCompression::Gzip => 1,
+            NbtCompression::Zlib => 2,
+            NbtCompression::None => 3,
+        });
+        buffer.extend_from_slice(&data);
+
+        let padded = buffer.len().div_ceil(SECTOR_SIZE as usize) * SECTOR_SIZE as usize;
+        if padded / SECTOR_SIZE as usize > u8::MAX as usize {
+            return Err(anyhow::anyhow!("chunk is too large for a region file"));
+        }
+        buffer.resize(padded, 0);
+
+        Ok(buffer)
+    }
+
+    /// Returns the sector an encoded chunk will be written to and how many bytes
+    /// the region file grows by, chunks are rewritten in place when they still fit
+    pub fn placement(&self, x: i32, z: i32, length: usize) -> (u32, u64) {
+        let location = self.locations[Self::index(x, z)];
+        let sectors = length as u64 / SECTOR_SIZE;
+
+        if location != 0 && sectors <= (location & 0xff) as u64 {
+            return (location >> 8, 0);
+        }
+
+        let end = self.length.max(HEADER_SIZE).div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+
+        (
+            (end / SECTOR_SIZE) as u32,
+            end + sectors * SECTOR_SIZE - self.length,
+        )
+    }
+
+    pub async fn write_chunk(&mut self, x: i32, z: i32, data: &[u8]) -> Result<(), anyhow::Error> {
+        let index = Self::index(x, z);
+        let (sector, _) = self.placement(x, z, data.len());
+
+        self.file
+            .seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE))
+            .await?;
+        self.file.write_all(data).await?;
+
+        self.locations[index] = (sector << 8) | (data.len() as u64 / SECTOR_SIZE) as u32;
+        self.timestamps[index] = chrono::Utc::now().timestamp() as u32;
+        self.write_header(index).await?;
+
+        self.length = self
+            .length
+            .max(HEADER_SIZE)
+            .max(sector as u64 * SECTOR_SIZE + data.len() as u64);
+
+        Ok(())
+    }
+
+    /// Removes chunks from the header so the game regenerates them, their sectors
+    /// are left in place and reused once the region is rewritten by the game
+    pub async fn delete_chunks(&mut self, chunks: &[(i32, i32)]) -> Result<usize, anyhow::Error> {
+        let mut deleted = 0;
+
+        for (x, z) in chunks {
+            let index = Self::index(*x, *z);
+            if self.locations[index] == 0 {
+                continue;
+            }
+
+            self.locations[index] = 0;
+            self.timestamps[index] = 0;
+            self.write_header(index).await?;
+
+            deleted += 1;
+        }
+
+        Ok(deleted)
+    }
+
+    async fn write_header(&mut self, index: usize) -> Result<(), anyhow::Error> {
+        self.file.seek(SeekFrom::Start(index as u64 * 4)).await?;
+        self.file.write_u32(self.locations[index]).await?;
+
+        self.file
+            .seek(SeekFrom::Start(SECTOR_SIZE + index as u64 * 4))
+            .await?;
+        self.file.write_u32(self.timestamps[index]).await?;
+
+        Ok(())
+    }
+
+    pub async fn sync(&mut self) -> Result<(), anyhow::Error> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,411 @@
+use cap_std::fs::Dir;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::Ordering},
+};
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct TrashEntry {
+    pub identifier: uuid::Uuid,
+    pub path: PathBuf,
+    pub size: u64,
+    pub directory: bool,
+    pub deleted: chrono::DateTime<chrono::Utc>,
+}
+
+impl TrashEntry {
+    pub fn to_api_response(&self) -> crate::models::TrashEntry {
+        crate::models::TrashEntry {
+            identifier: self.identifier,
+            path: self.path.to_string_lossy().to_string(),
+            size: self.size,
+            directory: self.directory,
+            deleted: self.deleted,
+        }
+    }
+}
+
+#[inline]
+pub fn directory(config: &crate::config::Config, server: uuid::Uuid) -> PathBuf {
+    Path::new(&config.system.data_directory)
+        .join(".trash")
+        .join(server.to_string())
+}
+
+fn read_entries(path: &Path) -> Vec<TrashEntry> {
+    let mut entries = Vec::new();
+
+    if let Ok(directory) = std::fs::read_dir(path) {
+        for entry in directory.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+
+            if let Some(entry) = std::fs::read(&path)
+                .ok()
+                .and_then(|content| serde_json::from_slice::<TrashEntry>(&content).ok())
+            {
+                entries.push(entry);
+            }
+        }
+    }
+
+    entries.sort_by_key(|entry| entry.deleted);
+
+    entries
+}
+
+fn remove_entry(path: &Path, entry: &TrashEntry) {
+    let data_path = path.join(entry.identifier.to_string());
+
+    if entry.directory {
+        std::fs::remove_dir_all(&data_path).ok();
+    } else {
+        std::fs::remove_file(&data_path).ok();
+    }
+
+    std::fs::remove_file(path.join(format!("{}.json", entry.identifier))).ok();
+}
+
+/// Removes all expired entries and returns the size of the remaining trash.
+/// Used by the disk checker thread, so this is fully blocking.
+pub fn cleanup_sync(path: &Path, retention_days: u64) -> u64 {
+    // retention periods too large to represent never expire
+    let retention = i64::try_from(retention_days)
+        .ok()
+        .and_then(chrono::Duration::try_days);
+
+    let mut total_size = 0;
+
+    for entry in read_entries(path) {
+        if retention_days > 0
+            && retention
+                .and_then(|retention| entry.deleted.checked_add_signed(retention))
+                .is_some_and(|expires| expires < chrono::Utc::now())
+        {
+            tracing::debug!(
+                path = %path.display(),
+                entry = %entry.identifier,
+                "removing expired trash entry"
+            );
+
+            remove_entry(path, &entry);
+            continue;
+        }
+
+        total_size += entry.size;
+    }
+
+    total_size
+}
+
+fn copy_entry(from_dir: &Dir, from: &Path, to_dir: &Dir, to: &Path) -> std::io::Result<()> {
+    let metadata = from_dir.symlink_metadata(from)?;
+
+    if metadata.is_dir() {
+        to_dir.create_dir(to)?;
+
+        for entry in from_dir.read_dir(from)? {
+            let name = entry?.file_name();
+
+            copy_entry(from_dir, &from.join(&name), to_dir, &to.join(&name))?;
+        }
+
+        to_dir.set_permissions(to, metadata.permissions())?;
+    } else if metadata.is_symlink() {
+        if let Ok(target) = from_dir.read_link(from) {
+            to_dir.symlink(target, to).ok();
+        }
+    } else {
+        from_dir.copy(from, to_dir, to)?;
+    }
+
+    Ok(())
+}
+
+/// Renames an entry between two directories, falling back to a copy
+/// when both live on different filesystems (e.g. btrfs subvolumes or zfs datasets)
+fn move_entry(from_dir: &Dir, from: &Path, to_dir: &Dir, to: &Path) -> std::io::Result<()> {
+    match from_dir.rename(from, to_dir, to) {
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_entry(from_dir, from, to_dir, to)?;
+
+            if from_dir.symlink_metadata(from)?.is_dir() {
+                from_dir.remove_dir_all(from)
+            } else {
+                from_dir.remove_file(from)
+            }
+        }
+        result => result,
+    }
+}
+
+async fn open_directory(filesystem: &super::Filesystem) -> Result<Arc<Dir>, anyhow::Error> {
+    let path = directory(&filesystem.config, filesystem.uuid);
+
+    let dir = tokio::task::spawn_blocking(move || -> std::io::Result<Dir> {
+        if !path.exists() {
+            std::fs::create_dir_all(&path)?;
+            std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o700))?;
+        }
+
+        Dir::open_ambient_dir(&path, cap_std::ambient_authority())
+    })
+    .await??;
+
+    Ok(Arc::new(dir))
+}
+
+#[inline]
+fn disk_share(filesystem: &super::Filesystem) -> Option<u64> {
+    match filesystem.disk_limit() {
+        0 => None,
+        limit => Some(limit as u64 * filesystem.config.system.trash.disk_share / 100),
+    }
+}
+
+fn release_usage(filesystem: &super::Filesystem, size: u64) {
+    filesystem
+        .trash_usage
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |usage| {
+            Some(usage.saturating_sub(size))
+        })
+        .ok();
+    filesystem
+        .disk_usage_cached
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |usage| {
+            Some(usage.saturating_sub(size))
+        })
+        .ok();
+}
+
+/// Counts an entry towards the trash usage, the inverse of [`release_usage`]
+fn claim_usage(filesystem: &super::Filesystem, size: u64) {
+    filesystem.trash_usage.fetch_add(size, Ordering::Relaxed);
+    filesystem
+        .disk_usage_cached
+        .fetch_add(size, Ordering::Relaxed);
+}
+
+pub async fn list(filesystem: &super::Filesystem) -> Vec<TrashEntry> {
+    let path = directory(&filesystem.config, filesystem.uuid);
+
+    tokio::task::spawn_blocking(move || read_entries(&path))
+        .await
+        .unwrap_or_default()
+}
+
+pub async fn find(filesystem: &super::Filesystem, identifier: uuid::Uuid) -> Option<TrashEntry> {
+    list(filesystem)
+        .await
+        .into_iter()
+        .find(|entry| entry.identifier == identifier)
+}
+
+pub async fn trash_path(filesystem: &super::Filesystem, path: &Path) -> Result<(), anyhow::Error> {
+    if !filesystem.config.system.trash.enabled {
+        return filesystem.truncate_path(path).await;
+    }
+
+    let path = filesystem.relative_path(path);
+    if path.components().next().is_none() {
+        return Err(anyhow::anyhow!("cannot trash the root directory"));
+    }
+
+    let metadata = filesystem.symlink_metadata(&path).await?;
+    let components = filesystem.path_to_components(&path);
+
+    let size = if metadata.is_dir() {
+        let disk_usage = filesystem.disk_usage.read().await;
+        disk_usage.get_size(&components).unwrap_or(0)
+    } else {
+        metadata.len()
+    };
+
+    if let Some(share) = disk_share(filesystem) {
+        if size > share {
+            tracing::debug!(
+                path = %path.display(),
+                "entry is larger than the trash disk share, deleting permanently"
+            );
+
+            return filesystem.truncate_path(&path).await;
+        }
+
+        if filesystem.trash_usage.load(Ordering::Relaxed) + size > share {
+            // oldest entries first
+            for entry in list(filesystem).await {
+                if filesystem.trash_usage.load(Ordering::Relaxed) + size <= share {
+                    break;
+                }
+
+                purge_entry(filesystem, entry).await?;
+            }
+        }
+    }
+
+    let base_dir = filesystem.base_dir().await?;
+    let trash_dir = open_directory(filesystem).await?;
+
+    let entry = TrashEntry {
+        identifier: uuid::Uuid::new_v4(),
+        path: path.clone(),
+        size,
+        directory: metadata.is_dir(),
+        deleted: chrono::Utc::now(),
+    };
+
+    tokio::task::spawn_blocking({
+        let entry = entry.clone();
+
+        move || -> Result<(), anyhow::Error> {
+            move_entry(
+                &base_dir,
+                &entry.path,
+                &trash_dir,
+                Path::new(&entry.identifier.to_string()),
+            )?;
+            trash_dir.write(
+                format!("{}.json", entry.identifier),
+                serde_json::to_vec(&entry)?,
+            )?;
+
+            Ok(())
+        }
+    })
+    .await??;
+
+    if metadata.is_dir() {
+        filesystem.allocate_in_path(&path, -(size as i64)).await;
+        filesystem.disk_usage.write().await.remove_path(&components);
+    } else {
+        filesystem
+            .allocate_in_path(path.parent().unwrap_or(Path::new("")), -(size as i64))
+            .await;
+    }
+
+    claim_usage(filesystem, size);
+
+    Ok(())
+}
+
+pub async fn restore(
+    filesystem: &super::Filesystem,
+    identifier: uuid::Uuid,
+    destination: Option<PathBuf>,
+) -> Result<PathBuf, anyhow::Error> {
+    let entry = match find(filesystem, identifier).await {
+        Some(entry) => entry,
+        None => return Err(anyhow::anyhow!("trash entry not found")),
+    };
+
+    let destination = filesystem.relative_path(&destination.unwrap_or(entry.path.clone()));
+    if destination.components().next().is_none() {
+        return Err(anyhow::anyhow!("cannot restore to the root directory"));
+    }
+
+    if filesystem.symlink_metadata(&destination).await.is_ok() {
+        return Err(anyhow::anyhow!("destination already exists"));
+    }
+
+    if filesystem.is_ignored(&destination, entry.directory).await {
+        return Err(anyhow::anyhow!("destination is ignored"));
+    }
+
+    if let Some(parent) = destination.parent() {
+        filesystem.create_dir_all(parent).await?;
+    }
+
+    let allocation_path = if entry.directory {
+        destination.clone()
+    } else {
+        destination.parent().unwrap_or(Path::new("")).to_path_buf()
+    };
+
+    let base_dir = filesystem.base_dir().await?;
+    let trash_dir = open_directory(filesystem).await?;
+
+    release_usage(filesystem, entry.size);
+    if !filesystem
+        .allocate_in_path(&allocation_path, entry.size as i64)
+        .await
+    {
+        claim_usage(filesystem, entry.size);
+
+        return Err(anyhow::anyhow!("failed to allocate space"));
+    }
+
+    let size = entry.size;
+
+    let moved = tokio::task::spawn_blocking({
+        let destination = destination.clone();
+
+        move || -> std::io::Result<std::io::Result<()>> {
+            move_entry(
+                &trash_dir,
+                Path::new(&entry.identifier.to_string()),
+                &base_dir,
+                &destination,
+            )?;
+
+            Ok(trash_dir.remove_file(format!("{}.json", entry.identifier)))
+        }
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|moved| moved.map_err(anyhow::Error::from));
+
+    // the entry is still in the trash if it could not be moved
+    let removed = match moved {
+        Ok(removed) => removed,
+        Err(err) => {
+            filesystem
+                .allocate_in_path(&allocation_path, -(size as i64))
+                .await;
+            claim_usage(filesystem, size);
+
+            return Err(err);
+        }
+    };
+    removed?;
+
+    filesystem.chown_path(&destination).await;
+
+    Ok(destination)
+}
+
+pub async fn purge(
+    filesystem: &super::Filesystem,
+    identifier: uuid::Uuid,
+) -> Result<(), anyhow::Error> {
+    let entry = match find(filesystem, identifier).await {
+        Some(entry) => entry,
+        None => return Err(anyhow::anyhow!("trash entry not found")),
+    };
+
+    purge_entry(filesystem, entry).await
+}
+
+/// Purges an entry taken from [`list`], without looking it up again
+pub async fn purge_entry(
+    filesystem: &super::Filesystem,
+    entry: TrashEntry,
+) -> Result<(), anyhow::Error> {
+    let path = directory(&filesystem.config, filesystem.uuid);
+    let size = entry.size;
+
+    tokio::task::spawn_blocking(move || remove_entry(&path, &entry)).await?;
+    release_usage(filesystem, size);
+
+    Ok(())
+}
+
+pub async fn destroy(filesystem: &super::Filesystem) {
+    let path = directory(&filesystem.config, filesystem.uuid);
+
+    if tokio::fs::metadata(&path).await.is_ok() {
+        tokio::fs::remove_dir_all(&path).await.ok();
+    }
+}
@@ -1,12 +1,75 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
 #[derive(Default)]
 pub struct DiskUsage {
     pub size: u64,
     pub entries: HashMap<String, DiskUsage>,
+
+    /// Amount of files below this directory, only refreshed by [`DiskUsage::scan`]
+    pub files: u64,
+    /// Size of this directory at the previous scan, used to report growth
+    pub previous_size: u64,
 }
 
 impl DiskUsage {
+    /// Walks `path` without following symlinks and builds a fresh usage tree
+    pub fn scan(path: &Path) -> Self {
+        let mut disk_usage = Self::default();
+
+        if let Ok(entries) = path.read_dir() {
+            for entry in entries.flatten() {
+                let metadata = match entry.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(_) => continue,
+                };
+
+                if metadata.is_dir() {
+                    let mut child = Self::scan(&entry.path());
+                    child.size += metadata.len();
+
+                    disk_usage.size += child.size;
+                    disk_usage.files += child.files;
+                    disk_usage
+                        .entries
+                        .insert(entry.file_name().to_string_lossy().to_string(), child);
+                } else {
+                    disk_usage.size += metadata.len();
+                    disk_usage.files += 1;
+                }
+            }
+        }
+
+        disk_usage
+    }
+
+    /// Carries over the sizes of the `previous` tree, directories missing
+    /// from it are treated as empty
+    pub fn set_previous(&mut self, previous: Option<&DiskUsage>) {
+        self.previous_size = previous.map_or(0, |previous| previous.size);
+
+        for (name, entry) in self.entries.iter_mut() {
+            entry.set_previous(previous.and_then(|previous| previous.entries.get(name)));
+        }
+    }
+
+    /// Marks the current sizes as the previous ones, nothing is reported as grown afterwards
+    pub fn reset_previous(&mut self) {
+        self.previous_size = self.size;
+
+        for entry in self.entries.values_mut() {
+            entry.reset_previous();
+        }
+    }
+
+    pub fn get(&self, path: &[String]) -> Option<&DiskUsage> {
+        let mut current = self;
+        for component in path {
+            current = current.entries.get(component)?;
+        }
+
+        Some(current)
+    }
+
     pub fn get_size(&self, path: &[String]) -> Option<u64> {
         if path.is_empty() {
             return Some(self.size);
@@ -23,8 +86,10 @@ impl DiskUsage {
     }
 
     pub fn update_size(&mut self, path: &[String], delta: i64) {
-        if path.is_empty() {
-            return;
+        if delta >= 0 {
+            self.size = self.size.saturating_add(delta as u64);
+        } else {
+            self.size = self.size.saturating_sub(delta.unsigned_abs());
         }
 
         let mut current = self;
@@ -48,7 +113,10 @@ impl DiskUsage {
             return None;
         }
 
-        self.recursive_remove(path)
+        let removed = self.recursive_remove(path)?;
+        self.size = self.size.saturating_sub(removed.size);
+
+        Some(removed)
     }
 
     fn recursive_remove(&mut self, path: &[String]) -> Option<DiskUsage> {
@@ -73,6 +141,8 @@ impl DiskUsage {
     }
 
     pub fn clear(&mut self) {
+        self.size = 0;
+        self.files = 0;
         self.entries.clear();
     }
 
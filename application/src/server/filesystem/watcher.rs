@@ -0,0 +1,279 @@
+use notify::{EventKind, RecursiveMode, Watcher, event::ModifyKind, event::RenameMode};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::{Mutex, RwLock, broadcast, mpsc};
+
+const DEBOUNCE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+/// Changes are kept apart from the console channel so bursts do not push out console lines
+const CHANGE_CHANNEL_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileChangeAction {
+    Created,
+    Modified,
+    Deleted,
+    Renamed,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FileChange {
+    pub action: FileChangeAction,
+    pub directory: bool,
+
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<PathBuf>,
+}
+
+struct ActiveWatcher {
+    watcher: notify::RecommendedWatcher,
+    task: tokio::task::JoinHandle<()>,
+}
+
+pub struct FilesystemWatcher {
+    base_path: PathBuf,
+    sender: broadcast::Sender<FileChange>,
+    disk_ignored: Arc<RwLock<ignore::overrides::Override>>,
+
+    subscriptions: Mutex<HashMap<PathBuf, usize>>,
+    active: Mutex<Option<ActiveWatcher>>,
+}
+
+impl FilesystemWatcher {
+    pub fn new(base_path: PathBuf, disk_ignored: Arc<RwLock<ignore::overrides::Override>>) -> Self {
+        Self {
+            base_path,
+            sender: broadcast::channel(CHANGE_CHANNEL_SIZE).0,
+            disk_ignored,
+            subscriptions: Mutex::new(HashMap::new()),
+            active: Mutex::new(None),
+        }
+    }
+
+    fn start(&self) -> Result<ActiveWatcher, anyhow::Error> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                tx.send(event).ok();
+            }
+        })?;
+
+        let task = tokio::spawn({
+            let base_path = self.base_path.clone();
+            let sender = self.sender.clone();
+            let disk_ignored = Arc::clone(&self.disk_ignored);
+
+            async move {
+                while let Some(event) = rx.recv().await {
+                    let mut changes: Vec<FileChange> = Vec::new();
+                    let mut pending_rename: Option<PathBuf> = None;
+
+                    let deadline = tokio::time::Instant::now() + DEBOUNCE_INTERVAL;
+                    let mut event = Some(event);
+
+                    loop {
+                        if let Some(event) = event.take() {
+                            collect_changes(&base_path, event, &mut changes, &mut pending_rename);
+                        }
+
+                        match tokio::time::timeout_at(deadline, rx.recv()).await {
+                            Ok(Some(next)) => event = Some(next),
+                            Ok(None) | Err(_) => break,
+                        }
+                    }
+
+                    if let Some(from) = pending_rename {
+                        push_change(&mut changes, FileChangeAction::Deleted, from, None);
+                    }
+
+                    let disk_ignored = disk_ignored.read().await;
+                    for mut change in changes {
+                        change.directory =
+                            tokio::fs::symlink_metadata(base_path.join(&change.path))
+                                .await
+                                .is_ok_and(|m| m.is_dir());
+
+                        if disk_ignored
+                            .matched(&change.path, change.directory)
+                            .invert()
+                            .is_ignore()
+                        {
+                            continue;
+                        }
+
+                        change.path = Path::new("/").join(&change.path);
+                        change.from = change.from.map(|from| Path::new("/").join(from));
+
+                        sender.send(change).ok();
+                    }
+                }
+            }
+        });
+
+        Ok(ActiveWatcher { watcher, task })
+    }
+
+    /// Receives the changes of every subscribed directory, paths are relative to the
+    /// server root with a leading slash
+    #[inline]
+    pub fn changes(&self) -> broadcast::Receiver<FileChange> {
+        self.sender.subscribe()
+    }
+
+    /// Subscribes to changes of the direct children of `path` (relative to the server root).
+    /// The underlying watcher is only running while at least one subscription exists.
+    pub async fn subscribe(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let mut active = self.active.lock().await;
+
+        if let Some(count) = subscriptions.get_mut(path) {
+            *count += 1;
+            return Ok(());
+        }
+
+        let active_watcher = match active.as_mut() {
+            Some(active_watcher) => active_watcher,
+            None => active.insert(self.start()?),
+        };
+
+        let result = active_watcher
+            .watcher
+            .watch(&self.base_path.join(path), RecursiveMode::NonRecursive);
+
+        if let Err(err) = result {
+            if subscriptions.is_empty() {
+                active.take();
+            }
+
+            return Err(err.into());
+        }
+
+        subscriptions.insert(path.to_path_buf(), 1);
+
+        Ok(())
+    }
+
+    pub async fn unsubscribe(&self, path: &Path) {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let mut active = self.active.lock().await;
+
+        match subscriptions.get_mut(path) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                return;
+            }
+            Some(_) => {
+                subscriptions.remove(path);
+            }
+            None => return,
+        }
+
+        if subscriptions.is_empty() {
+            if let Some(active) = active.take() {
+                active.task.abort();
+            }
+        } else if let Some(active) = active.as_mut() {
+            active.watcher.unwatch(&self.base_path.join(path)).ok();
+        }
+    }
+
+    pub async fn destroy(&self) {
+        self.subscriptions.lock().await.clear();
+
+        if let Some(active) = self.active.lock().await.take() {
+            active.task.abort();
+        }
+    }
+}
+
+fn push_change(
+    changes: &mut Vec<FileChange>,
+    action: FileChangeAction,
+    path: PathBuf,
+    from: Option<PathBuf>,
+) {
+    if let Some(index) = changes.iter().position(|c| c.path == path) {
+        let action = match (changes[index].action, action) {
+            (FileChangeAction::Created, FileChangeAction::Modified) => FileChangeAction::Created,
+            (FileChangeAction::Created, FileChangeAction::Deleted) => {
+                changes.remove(index);
+                return;
+            }
+            (FileChangeAction::Deleted, FileChangeAction::Created) => FileChangeAction::Modified,
+            (_, action) => action,
+        };
+
+        changes[index].action = action;
+        if from.is_some() {
+            changes[index].from = from;
+        }
+
+        return;
+    }
+
+    changes.push(FileChange {
+        action,
+        directory: false,
+        path,
+        from,
+    });
+}
+
+fn collect_changes(
+    base_path: &Path,
+    event: notify::Event,
+    changes: &mut Vec<FileChange>,
+    pending_rename: &mut Option<PathBuf>,
+) {
+    let mut paths = event.paths.into_iter().filter_map(|path| {
+        path.strip_prefix(base_path)
+            .ok()
+            .map(|path| path.to_path_buf())
+    });
+
+    match event.kind {
+        EventKind::Create(_) => {
+            for path in paths {
+                push_change(changes, FileChangeAction::Created, path, None);
+            }
+        }
+        EventKind::Remove(_) => {
+            for path in paths {
+                push_change(changes, FileChangeAction::Deleted, path, None);
+            }
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            if let (Some(from), Some(to)) = (paths.next(), paths.next()) {
+                push_change(changes, FileChangeAction::Renamed, to, Some(from));
+            }
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            if let Some(from) = pending_rename.take() {
+                push_change(changes, FileChangeAction::Deleted, from, None);
+            }
+
+            *pending_rename = paths.next();
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            for path in paths {
+                match pending_rename.take() {
+                    Some(from) => push_change(changes, FileChangeAction::Renamed, path, Some(from)),
+                    None => push_change(changes, FileChangeAction::Created, path, None),
+                }
+            }
+        }
+        EventKind::Modify(ModifyKind::Name(_)) => {}
+        EventKind::Modify(_) => {
+            for path in paths {
+                push_change(changes, FileChangeAction::Modified, path, None);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,354 @@
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::io::AsyncWriteExt;
+
+/// lines waiting to be written, console output beyond this is not kept
+const QUEUE_SIZE: usize = 1024;
+const CURRENT_FILE: &str = "console.log";
+
+pub struct HistoryLine {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub line: String,
+}
+
+impl HistoryLine {
+    fn parse(line: &str) -> Option<Self> {
+        let (timestamp, line) = line.split_once(' ')?;
+
+        Some(Self {
+            timestamp: chrono::DateTime::parse_from_rfc3339(timestamp)
+                .ok()?
+                .to_utc(),
+            line: line.to_string(),
+        })
+    }
+
+    pub fn to_api_response(&self) -> crate::models::ConsoleLine {
+        crate::models::ConsoleLine {
+            timestamp: self.timestamp,
+            line: self.line.clone(),
+        }
+    }
+}
+
+#[inline]
+pub fn directory(config: &crate::config::Config, server: uuid::Uuid) -> PathBuf {
+    Path::new(&config.system.log_directory)
+        .join("console")
+        .join(server.to_string())
+}
+
+/// Rotated files newest first, with the time they were rotated at in microseconds.
+/// A file that is still being compressed is listed instead of its unfinished archive
+fn rotated_files(directory: &Path) -> Vec<(i64, PathBuf, bool)> {
+    let mut files = match std::fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let name = name.to_str()?.strip_prefix("console-")?;
+
+                let (micros, compressed) = match name.strip_suffix(".log.gz") {
+                    Some(micros) => (micros, true),
+                    None => (name.strip_suffix(".log")?, false),
+                };
+
+                Some((micros.parse::<i64>().ok()?, entry.path(), compressed))
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => return Vec::new(),
+    };
+
+    files.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.2.cmp(&b.2)));
+    files.dedup_by_key(|(micros, _, _)| *micros);
+
+    files
+}
+
+fn compress(path: &Path) -> std::io::Result<()> {
+    let temporary = path.with_extension("log.gz.tmp");
+
+    let mut encoder = GzEncoder::new(File::create(&temporary)?, Compression::default());
+    std::io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+
+    std::fs::rename(&temporary, path.with_extension("log.gz"))?;
+    std::fs::remove_file(path)
+}
+
+/// Removes rotated files past the configured age or total size, newest files are kept first
+fn prune(directory: &Path, config: &crate::config::SystemConsoleHistory) {
+    let max_size = config.max_size.saturating_mul(1024 * 1024);
+    // ages too large to represent keep every file
+    let oldest = i64::try_from(config.max_age)
+        .ok()
+        .and_then(chrono::Duration::try_days)
+        .and_then(|max_age| chrono::Utc::now().checked_sub_signed(max_age))
+        .map(|oldest| oldest.timestamp_micros())
+        .unwrap_or(i64::MIN);
+
+    let mut total_size = 0;
+    for (micros, path, _) in rotated_files(directory) {
+        total_size += std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+
+        if (config.max_size > 0 && total_size > max_size) || (config.max_age > 0 && micros < oldest)
+        {
+            std::fs::remove_file(&path).ok();
+        }
+    }
+}
+
+/// Compresses files left uncompressed by an interrupted rotation
+fn recover(directory: &Path) {
+    for (_, path, compressed) in rotated_files(directory) {
+        if !compressed {
+            std::fs::remove_file(path.with_extension("log.gz.tmp")).ok();
+            std::fs::remove_file(path.with_extension("log.gz")).ok();
+
+            if let Err(err) = compress(&path) {
+                tracing::error!(
+                    path = %path.display(),
+                    "failed to compress console history: {:#?}",
+                    err
+                );
+            }
+        }
+    }
+}
+
+fn rotate(directory: &Path, config: &crate::config::SystemConsoleHistory) -> std::io::Result<()> {
+    let rotated = directory.join(format!(
+        "console-{}.log",
+        chrono::Utc::now().timestamp_micros()
+    ));
+    std::fs::rename(directory.join(CURRENT_FILE), &rotated)?;
+
+    compress(&rotated)?;
+    prune(directory, config);
+
+    Ok(())
+}
+
+struct Writer {
+    server: uuid::Uuid,
+    config: Arc<crate::config::Config>,
+    directory: PathBuf,
+
+    file: Option<tokio::io::BufWriter<tokio::fs::File>>,
+    size: u64,
+}
+
+impl Writer {
+    async fn write(&mut self, lines: &[String]) -> std::io::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                tokio::fs::create_dir_all(&self.directory).await?;
+
+                let file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.directory.join(CURRENT_FILE))
+                    .await?;
+                self.size = file.metadata().await?.len();
+
+                self.file.insert(tokio::io::BufWriter::new(file))
+            }
+        };
+
+        for line in lines {
+            file.write_all(line.as_bytes()).await?;
+            self.size += line.len() as u64;
+        }
+        file.flush().await?;
+
+        if self.size >= self.config.system.console_history.max_file_size.max(1) * 1024 * 1024 {
+            self.file = None;
+
+            let directory = self.directory.clone();
+            let config = Arc::clone(&self.config);
+            tokio::task::spawn_blocking(move || rotate(&directory, &config.system.console_history))
+                .await??;
+        }
+
+        Ok(())
+    }
+
+    async fn run(mut self, mut receiver: tokio::sync::mpsc::Receiver<String>) {
+        let directory = self.directory.clone();
+        let config = Arc::clone(&self.config);
+        tokio::task::spawn_blocking(move || {
+            recover(&directory);
+            prune(&directory, &config.system.console_history);
+        })
+        .await
+        .ok();
+
+        let mut lines = Vec::with_capacity(QUEUE_SIZE);
+        while receiver.recv_many(&mut lines, QUEUE_SIZE).await > 0 {
+            if let Err(err) = self.write(&lines).await {
+                tracing::error!(
+                    server = %self.server,
+                    "failed to write console history: {:#?}",
+                    err
+                );
+
+                self.file = None;
+            }
+
+            lines.clear();
+        }
+    }
+}
+
+/// Console output of a server, kept in rotating files that are compressed once full
+pub struct ConsoleHistory {
+    directory: PathBuf,
+
+    sender: Option<tokio::sync::mpsc::Sender<String>>,
+    writer: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl ConsoleHistory {
+    pub fn new(server: uuid::Uuid, config: &Arc<crate::config::Config>) -> Self {
+        let directory = directory(config, server);
+
+        if !config.system.console_history.enabled {
+            return Self {
+                directory,
+                sender: None,
+                writer: None,
+            };
+        }
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(QUEUE_SIZE);
+        let writer = Writer {
+            server,
+            config: Arc::clone(config),
+            directory: directory.clone(),
+            file: None,
+            size: 0,
+        };
+
+        Self {
+            directory,
+            sender: Some(sender),
+            writer: Some(tokio::spawn(writer.run(receiver))),
+        }
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    /// Queues a line of console output, it is dropped if the writer falls behind
+    pub fn write(&self, line: &str) {
+        if let Some(sender) = &self.sender {
+            sender
+                .try_send(format!(
+                    "{} {}\n",
+                    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+                    line.replace('\n', " ")
+                ))
+                .ok();
+        }
+    }
+
+    /// Reads the newest `limit` lines between `from` and `to` that match `search`, oldest first
+    pub async fn read(
+        &self,
+        from: Option<chrono::DateTime<chrono::Utc>>,
+        to: Option<chrono::DateTime<chrono::Utc>>,
+        search: Option<regex::Regex>,
+        limit: usize,
+    ) -> std::io::Result<Vec<HistoryLine>> {
+        let directory = self.directory.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut files = vec![(None, directory.join(CURRENT_FILE), false)];
+            files.extend(
+                rotated_files(&directory)
+                    .into_iter()
+                    .map(|(micros, path, compressed)| (Some(micros), path, compressed)),
+            );
+
+            let mut lines: Vec<Vec<HistoryLine>> = Vec::new();
+            let mut remaining = limit;
+
+            for (rotated, path, compressed) in files {
+                if remaining == 0 {
+                    break;
+                }
+                // a file only holds lines written before it was rotated
+                if let (Some(from), Some(rotated)) = (from, rotated) {
+                    if rotated < from.timestamp_micros() {
+                        break;
+                    }
+                }
+
+                let file = match File::open(&path) {
+                    Ok(file) => file,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err),
+                };
+                let reader: Box<dyn Read> = if compressed {
+                    Box::new(GzDecoder::new(file))
+                } else {
+                    Box::new(file)
+                };
+
+                let mut matches = VecDeque::with_capacity(remaining.min(QUEUE_SIZE));
+                for line in BufReader::new(reader).lines().map_while(Result::ok) {
+                    let line = match HistoryLine::parse(&line) {
+                        Some(line) => line,
+                        None => continue,
+                    };
+
+                    if from.is_some_and(|from| line.timestamp < from)
+                        || to.is_some_and(|to| line.timestamp > to)
+                        || search
+                            .as_ref()
+                            .is_some_and(|search| !search.is_match(&line.line))
+                    {
+                        continue;
+                    }
+
+                    if matches.len() == remaining {
+                        matches.pop_front();
+                    }
+                    matches.push_back(line);
+                }
+
+                remaining -= matches.len();
+                lines.push(matches.into());
+            }
+
+            Ok(lines.into_iter().rev().flatten().collect())
+        })
+        .await?
+    }
+
+    /// Removes all history of the server
+    pub async fn destroy(&self) {
+        if let Some(writer) = &self.writer {
+            writer.abort();
+        }
+
+        tokio::fs::remove_dir_all(&self.directory).await.ok();
+    }
+}
+
+impl Drop for ConsoleHistory {
+    fn drop(&mut self) {
+        if let Some(writer) = &self.writer {
+            writer.abort();
+        }
+    }
+}
@@ -17,11 +17,13 @@ pub mod backup;
 pub mod configuration;
 pub mod container;
 pub mod filesystem;
+pub mod history;
 pub mod installation;
 pub mod manager;
 pub mod permissions;
 pub mod resources;
 pub mod state;
+pub mod throttle;
 pub mod transfer;
 pub mod websocket;
 
@@ -39,6 +41,7 @@ pub struct InnerServer {
 
     pub container: RwLock<Option<Arc<container::Container>>>,
     pub activity: activity::ActivityManager,
+    pub console_history: history::ConsoleHistory,
 
     pub state: state::ServerStateLock,
     pub outgoing_transfer: RwLock<Option<transfer::OutgoingServerTransfer>>,
@@ -56,6 +59,7 @@ pub struct InnerServer {
     crash_handled: AtomicBool,
 
     pub filesystem: filesystem::Filesystem,
+    pub bandwidth: Arc<throttle::Bandwidth>,
 }
 
 pub struct Server(Arc<InnerServer>);
@@ -71,6 +75,8 @@ impl Server {
             "creating server instance"
         );
 
+        let (rx, tx) = tokio::sync::broadcast::channel(128);
+
         let filesystem = filesystem::Filesystem::new(
             configuration.uuid,
             configuration.build.disk_space * 1024 * 1024,
@@ -79,10 +85,9 @@ impl Server {
             &configuration.egg.file_denylist,
         );
 
-        let (rx, tx) = tokio::sync::broadcast::channel(128);
-
         let state = state::ServerStateLock::new(rx.clone());
         let activity = activity::ActivityManager::new(configuration.uuid, &config);
+        let console_history = history::ConsoleHistory::new(configuration.uuid, &config);
 
         Self(Arc::new(InnerServer {
             uuid: configuration.uuid,
@@ -98,6 +103,7 @@ impl Server {
 
             container: RwLock::new(None),
             activity,
+            console_history,
 
             state,
             outgoing_transfer: RwLock::new(None),
@@ -115,6 +121,7 @@ impl Server {
             crash_handled: AtomicBool::new(false),
 
             filesystem,
+            bandwidth: Default::default(),
         }))
     }
 
@@ -582,6 +589,28 @@ impl Server {
         client: &bollard::Docker,
         lines: usize,
     ) -> Result<String, bollard::errors::Error> {
+        if self.console_history.is_enabled() {
+            match self.console_history.read(None, None, None, lines).await {
+                Ok(history) if !history.is_empty() => {
+                    let mut logs = String::new();
+                    for line in history {
+                        logs.push_str(&line.line);
+                        logs.push('\n');
+                    }
+
+                    return Ok(logs);
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::error!(
+                        server = %self.uuid,
+                        "failed to read console history: {:#?}",
+                        err
+                    );
+                }
+            }
+        }
+
         let container = match &*self.container.read().await {
             Some(container) => container.docker_id.clone(),
             None => {
@@ -1146,7 +1175,10 @@ impl Server {
         tokio::spawn({
             let server = self.clone();
 
-            async move { server.filesystem.destroy().await }
+            async move {
+                server.console_history.destroy().await;
+                server.filesystem.destroy().await
+            }
         });
     }
 
@@ -1155,6 +1187,7 @@ impl Server {
             "state": self.state.get_state(),
             "is_suspended": self.suspended.load(Ordering::SeqCst),
             "utilization": self.resource_usage().await,
+            "bandwidth": self.bandwidth.to_api_response(),
             "configuration": *self.configuration.read().await,
         })
     }
@@ -1,10 +1,12 @@
 use serde::{
-    Deserialize, Deserializer, Serialize,
+    Deserialize, Deserializer, Serialize, Serializer,
     de::{SeqAccess, Visitor},
+    ser::SerializeSeq,
 };
 use std::{
     marker::PhantomData,
     ops::{Deref, DerefMut},
+    path::{Component, Path, PathBuf},
 };
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
@@ -58,13 +60,65 @@ impl Permission {
     }
 }
 
-#[derive(Debug, Default, Clone, Serialize)]
-pub struct Permissions(Vec<Permission>);
+/// A permission that only applies to paths matching a glob, e.g. `file.update:/plugins/**`
+#[derive(Debug, Clone)]
+pub struct ScopedPermission {
+    pub permission: Permission,
+    pub pattern: String,
+
+    matcher: globset::GlobMatcher,
+    base: PathBuf,
+    recursive: bool,
+}
+
+impl ScopedPermission {
+    fn parse(permission: Permission, pattern: &str) -> Option<Self> {
+        let relative = pattern.trim_start_matches('/');
+        let matcher = globset::GlobBuilder::new(relative)
+            .literal_separator(true)
+            .build()
+            .ok()?
+            .compile_matcher();
+
+        // the directory the grant is rooted in, the part of the pattern without wildcards
+        let base = Path::new(relative)
+            .components()
+            .take_while(|component| {
+                !component
+                    .as_os_str()
+                    .to_string_lossy()
+                    .contains(['*', '?', '[', '{'])
+            })
+            .collect();
+
+        Some(Self {
+            permission,
+            pattern: pattern.to_string(),
+            matcher,
+            recursive: relative.ends_with("/**")
+                && base == Path::new(&relative[..relative.len() - 3]),
+            base,
+        })
+    }
+
+    /// `dir/**` also covers `dir` itself, other patterns only the paths they match
+    #[inline]
+    fn matches(&self, path: &Path) -> bool {
+        (self.recursive && path == self.base) || self.matcher.is_match(path)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Permissions {
+    permissions: Vec<Permission>,
+    scoped: Vec<ScopedPermission>,
+}
 
 impl Permissions {
+    /// Whether the permission is granted for the whole server, scoped grants are not included
     #[inline]
     pub fn has_permission(&self, permission: Permission) -> bool {
-        for p in self.0.iter().copied() {
+        for p in self.permissions.iter().copied() {
             if permission.matches(p) {
                 return true;
             }
@@ -72,19 +126,104 @@ impl Permissions {
 
         false
     }
+
+    /// Whether the permission is granted for any path, only usable to reject requests early,
+    /// the paths themselves still have to be checked with [`Self::has_path_permission`]
+    #[inline]
+    pub fn has_any_permission(&self, permission: Permission) -> bool {
+        self.has_permission(permission)
+            || self
+                .scoped
+                .iter()
+                .any(|scoped| permission.matches(scoped.permission))
+    }
+
+    /// Whether the permission is granted for `path`, relative to the server root
+    pub fn has_path_permission(&self, permission: Permission, path: &Path) -> bool {
+        if self.has_permission(permission) {
+            return true;
+        }
+
+        let path = normalize(path);
+
+        self.scoped
+            .iter()
+            .any(|scoped| permission.matches(scoped.permission) && scoped.matches(&path))
+    }
+
+    /// Whether `path` may show up in directory listings, this includes the parent
+    /// directories of a grant so it can be navigated to
+    pub fn is_path_visible(&self, path: &Path) -> bool {
+        if self.has_path_permission(Permission::FileRead, path) {
+            return true;
+        }
+
+        let path = normalize(path);
+
+        self.scoped.iter().any(|scoped| {
+            Permission::FileRead.matches(scoped.permission) && scoped.base.starts_with(&path)
+        })
+    }
+
+    #[inline]
+    pub fn is_scoped(&self) -> bool {
+        !self.scoped.is_empty()
+    }
+
+    pub fn to_strings(&self) -> Vec<String> {
+        let mut permissions = Vec::with_capacity(self.permissions.len() + self.scoped.len());
+
+        for permission in self.permissions.iter() {
+            permissions.push(permission_name(*permission));
+        }
+        for scoped in self.scoped.iter() {
+            permissions.push(format!(
+                "{}:{}",
+                permission_name(scoped.permission),
+                scoped.pattern
+            ));
+        }
+
+        permissions
+    }
+}
+
+#[inline]
+fn permission_name(permission: Permission) -> String {
+    serde_json::to_value(permission)
+        .ok()
+        .and_then(|value| value.as_str().map(|value| value.to_string()))
+        .unwrap_or_default()
+}
+
+#[inline]
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => {}
+        }
+    }
+
+    normalized
 }
 
 impl Deref for Permissions {
     type Target = Vec<Permission>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.permissions
     }
 }
 
 impl DerefMut for Permissions {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.permissions
     }
 }
 
@@ -107,17 +246,155 @@ impl<'de> Deserialize<'de> for Permissions {
                 A: SeqAccess<'de>,
             {
                 let mut permissions = Vec::new();
+                let mut scoped = Vec::new();
 
                 while let Ok(Some(result)) = seq.next_element::<serde_json::Value>() {
-                    if let Ok(permission) = serde_json::from_value::<Permission>(result) {
+                    if let Some((permission, pattern)) =
+                        result.as_str().and_then(|value| value.split_once(':'))
+                    {
+                        if let Ok(permission) = serde_json::from_value::<Permission>(
+                            serde_json::Value::String(permission.to_string()),
+                        ) {
+                            if let Some(permission) = ScopedPermission::parse(permission, pattern) {
+                                scoped.push(permission);
+                            }
+                        }
+                    } else if let Ok(permission) = serde_json::from_value::<Permission>(result) {
                         permissions.push(permission);
                     }
                 }
 
-                Ok(Permissions(permissions))
+                Ok(Permissions {
+                    permissions,
+                    scoped,
+                })
             }
         }
 
         deserializer.deserialize_seq(PermissionsVisitor(PhantomData))
     }
 }
+
+impl Serialize for Permissions {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let permissions = self.to_strings();
+
+        let mut seq = serializer.serialize_seq(Some(permissions.len()))?;
+        for permission in permissions {
+            seq.serialize_element(&permission)?;
+        }
+
+        seq.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission, Permissions};
+    use std::path::Path;
+
+    fn permissions(grants: &[&str]) -> Permissions {
+        serde_json::from_value(serde_json::json!(grants)).unwrap()
+    }
+
+    #[test]
+    fn global_grants_cover_every_path() {
+        let permissions = permissions(&["file.read", "file.read-content"]);
+
+        assert!(!permissions.is_scoped());
+        assert!(permissions.has_permission(Permission::FileRead));
+        assert!(permissions.has_path_permission(Permission::FileReadContent, Path::new("a/b")));
+        assert!(!permissions.has_path_permission(Permission::FileUpdate, Path::new("a/b")));
+    }
+
+    #[test]
+    fn scoped_grants_are_not_global() {
+        let permissions = permissions(&["file.update:/plugins/**"]);
+
+        assert!(permissions.is_scoped());
+        assert!(!permissions.has_permission(Permission::FileUpdate));
+        assert!(permissions.has_any_permission(Permission::FileUpdate));
+        assert!(!permissions.has_any_permission(Permission::FileDelete));
+    }
+
+    // list_directory, search, usage, trash and operations listings
+    #[test]
+    fn listing_shows_grants_and_their_parents() {
+        let permissions = permissions(&["file.read:/world/region/*.mca"]);
+
+        assert!(permissions.is_path_visible(Path::new("")));
+        assert!(permissions.is_path_visible(Path::new("world")));
+        assert!(permissions.is_path_visible(Path::new("world/region")));
+        assert!(permissions.is_path_visible(Path::new("world/region/r.0.0.mca")));
+        assert!(!permissions.is_path_visible(Path::new("world/region/r.0.0.mca.bak")));
+        assert!(!permissions.is_path_visible(Path::new("world/playerdata")));
+        assert!(!permissions.is_path_visible(Path::new("server.properties")));
+    }
+
+    // contents, fingerprints, nbt, region, archive entries and compress
+    #[test]
+    fn reading_content_is_limited_to_the_grant() {
+        let permissions = permissions(&["file.read:/**", "file.read-content:/config/*.yml"]);
+
+        assert!(
+            permissions
+                .has_path_permission(Permission::FileReadContent, Path::new("config/paper.yml"))
+        );
+        assert!(
+            permissions
+                .has_path_permission(Permission::FileReadContent, Path::new("/config/paper.yml"))
+        );
+        assert!(!permissions.has_path_permission(
+            Permission::FileReadContent,
+            Path::new("config/nested/paper.yml")
+        ));
+        assert!(!permissions.has_path_permission(
+            Permission::FileReadContent,
+            Path::new("config/../server.properties")
+        ));
+    }
+
+    // write, create_directory, copy, rename, chmod, decompress, pull and trash restore
+    #[test]
+    fn writing_is_limited_to_the_grant() {
+        let permissions = permissions(&["file.create:/plugins/**", "file.update:/plugins/**"]);
+
+        assert!(permissions.has_path_permission(Permission::FileCreate, Path::new("plugins")));
+        assert!(
+            permissions.has_path_permission(Permission::FileCreate, Path::new("plugins/a/b.jar"))
+        );
+        assert!(
+            permissions
+                .has_path_permission(Permission::FileUpdate, Path::new("plugins/config.yml"))
+        );
+        assert!(
+            !permissions
+                .has_path_permission(Permission::FileCreate, Path::new("plugins/../server.jar"))
+        );
+        assert!(!permissions.has_path_permission(Permission::FileUpdate, Path::new("eula.txt")));
+    }
+
+    // delete and trash purge
+    #[test]
+    fn deleting_is_limited_to_the_grant() {
+        let permissions = permissions(&["file.delete:/logs/*.gz"]);
+
+        assert!(
+            permissions.has_path_permission(Permission::FileDelete, Path::new("logs/1.log.gz"))
+        );
+        assert!(!permissions.has_path_permission(Permission::FileDelete, Path::new("logs")));
+        assert!(
+            !permissions.has_path_permission(Permission::FileDelete, Path::new("logs/latest.log"))
+        );
+    }
+
+    #[test]
+    fn grants_round_trip() {
+        let grants = ["file.read", "file.update:/plugins/**"];
+
+        assert_eq!(permissions(&grants).to_strings(), grants);
+    }
+}
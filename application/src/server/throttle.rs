@@ -0,0 +1,241 @@
+use axum::body::Body;
+use futures::StreamExt;
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[derive(Clone, Copy)]
+pub enum Direction {
+    /// data leaving the node, like sftp reads and http downloads
+    Download,
+    /// data written to the node, like sftp writes and http uploads
+    Upload,
+}
+
+struct BucketState {
+    tokens: f64,
+    refilled: Instant,
+
+    window_start: Instant,
+    window_bytes: u64,
+    throughput: u64,
+}
+
+/// Token bucket for a single direction, the rate is passed on every call
+/// so transfers sharing a bucket can be limited differently
+pub struct Bucket {
+    state: Mutex<BucketState>,
+    transferred: AtomicU64,
+}
+
+impl Default for Bucket {
+    fn default() -> Self {
+        let now = Instant::now();
+
+        Self {
+            state: Mutex::new(BucketState {
+                tokens: 0.0,
+                refilled: now,
+                window_start: now,
+                window_bytes: 0,
+                throughput: 0,
+            }),
+            transferred: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Bucket {
+    /// Takes `bytes` from the bucket and returns how long the caller has to wait
+    /// before sending them, a rate of 0 only counts the bytes
+    fn take(&self, bytes: u64, rate: u64) -> Duration {
+        self.transferred.fetch_add(bytes, Ordering::Relaxed);
+
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        let window = now.duration_since(state.window_start);
+        if window >= Duration::from_secs(1) {
+            state.throughput = (state.window_bytes as f64 / window.as_secs_f64()) as u64;
+            state.window_start = now;
+            state.window_bytes = 0;
+        }
+        state.window_bytes += bytes;
+
+        if rate == 0 {
+            return Duration::ZERO;
+        }
+
+        // allow bursts of up to one second worth of data
+        let elapsed = now.duration_since(state.refilled).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * rate as f64).min(rate as f64);
+        state.refilled = now;
+
+        state.tokens -= bytes as f64;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / rate as f64)
+        }
+    }
+
+    /// Total bytes that went through the bucket
+    #[inline]
+    pub fn transferred(&self) -> u64 {
+        self.transferred.load(Ordering::Relaxed)
+    }
+
+    /// Bytes per second over the last full second
+    pub fn throughput(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+
+        if state.window_start.elapsed() >= Duration::from_secs(2) {
+            0
+        } else {
+            state.throughput
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Bandwidth {
+    pub download: Bucket,
+    pub upload: Bucket,
+}
+
+impl Bandwidth {
+    #[inline]
+    pub fn bucket(&self, direction: Direction) -> &Bucket {
+        match direction {
+            Direction::Download => &self.download,
+            Direction::Upload => &self.upload,
+        }
+    }
+
+    pub fn to_api_response(&self) -> crate::models::Throughput {
+        crate::models::Throughput {
+            downloaded: self.download.transferred(),
+            downloading_rate: self.download.throughput(),
+            uploaded: self.upload.transferred(),
+            uploading_rate: self.upload.throughput(),
+        }
+    }
+}
+
+/// The node wide, server and session buckets a single transfer is limited by
+#[derive(Clone)]
+pub struct Throttle {
+    direction: Direction,
+    buckets: Vec<(Arc<Bandwidth>, u64)>,
+}
+
+impl Throttle {
+    /// Creates a throttle for a transfer of `server` with a fresh session bucket,
+    /// clone it to share the session bucket between transfers. The limits are read
+    /// once here, changes to them only apply to throttles created afterwards
+    pub async fn new(
+        config: &crate::config::Config,
+        global: &Arc<Bandwidth>,
+        server: &super::Server,
+        direction: Direction,
+    ) -> Self {
+        let server_limit = server
+            .configuration
+            .read()
+            .await
+            .build
+            .transfer_limit
+            .unwrap_or_default();
+
+        Self {
+            direction,
+            buckets: vec![
+                (Arc::clone(global), config.throttles.transfer_limit),
+                (Arc::clone(&server.bandwidth), server_limit),
+                (
+                    Arc::new(Bandwidth::default()),
+                    config.throttles.session_transfer_limit,
+                ),
+            ],
+        }
+    }
+
+    /// Bytes the session transferred so far, in both directions
+    #[inline]
+    pub fn session(&self) -> &Bandwidth {
+        // the session bucket is always the last one
+        &self.buckets[self.buckets.len() - 1].0
+    }
+
+    /// The same buckets for the other direction
+    pub fn reversed(&self) -> Self {
+        Self {
+            direction: match self.direction {
+                Direction::Download => Direction::Upload,
+                Direction::Upload => Direction::Download,
+            },
+            buckets: self.buckets.clone(),
+        }
+    }
+
+    /// Waits until `bytes` may be transferred
+    pub async fn consume(&self, bytes: usize) {
+        let wait = self
+            .buckets
+            .iter()
+            .map(|(bandwidth, limit)| {
+                bandwidth
+                    .bucket(self.direction)
+                    .take(bytes as u64, limit * 1024 * 1024)
+            })
+            .max()
+            .unwrap_or_default();
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Like `tokio::io::copy`, but waits for the throttle between chunks
+    pub async fn copy(
+        &self,
+        reader: &mut (impl AsyncRead + Unpin + ?Sized),
+        writer: &mut (impl AsyncWrite + Unpin + ?Sized),
+    ) -> Result<u64, std::io::Error> {
+        let mut buffer = vec![0; 64 * 1024];
+        let mut copied = 0;
+
+        loop {
+            let bytes_read = reader.read(&mut buffer).await?;
+            if bytes_read == 0 {
+                break;
+            }
+
+            self.consume(bytes_read).await;
+            writer.write_all(&buffer[..bytes_read]).await?;
+            copied += bytes_read as u64;
+        }
+
+        Ok(copied)
+    }
+
+    /// Wraps a response body so it is streamed no faster than the throttle allows
+    pub fn body(self, body: Body) -> Body {
+        Body::from_stream(body.into_data_stream().then(move |chunk| {
+            let throttle = self.clone();
+
+            async move {
+                if let Ok(chunk) = &chunk {
+                    throttle.consume(chunk.len()).await;
+                }
+
+                chunk
+            }
+        }))
+    }
+}
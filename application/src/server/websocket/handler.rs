@@ -9,7 +9,7 @@ use axum::{
     response::Response,
 };
 use futures_util::{SinkExt, StreamExt};
-use std::{net::SocketAddr, pin::Pin, sync::Arc};
+use std::{collections::HashSet, net::SocketAddr, path::PathBuf, pin::Pin, sync::Arc};
 use tokio::sync::{Mutex, RwLock, broadcast::error::RecvError};
 
 pub async fn handle_ws(
@@ -47,10 +47,13 @@ pub async fn handle_ws(
         let (sender, mut reciever) = socket.split();
         let sender = Arc::new(Mutex::new(sender));
         let socket_jwt = Arc::new(RwLock::new(None));
+        let watched_directories: Arc<RwLock<HashSet<PathBuf>>> =
+            Arc::new(RwLock::new(HashSet::new()));
 
         let writer = {
             let state = Arc::clone(&state);
             let socket_jwt = Arc::clone(&socket_jwt);
+            let watched_directories = Arc::clone(&watched_directories);
             let sender = Arc::clone(&sender);
             let server = server.clone();
 
@@ -90,7 +93,13 @@ pub async fn handle_ws(
                     {
                         Ok(Some((message, jwt))) => {
                             match super::message_handler::handle_message(
-                                &state, user_ip, &server, &sender, &jwt, message,
+                                &state,
+                                user_ip,
+                                &server,
+                                &sender,
+                                &jwt,
+                                &watched_directories,
+                                message,
                             )
                             .await
                             {
@@ -125,7 +134,7 @@ pub async fn handle_ws(
         };
 
         let mut futures: Vec<Pin<Box<dyn futures_util::Future<Output = ()> + Send>>> =
-            Vec::with_capacity(4);
+            Vec::with_capacity(5);
 
         // Server Listener
         futures.push({
@@ -174,6 +183,12 @@ pub async fn handle_ws(
                                     continue;
                                 }
                             }
+                            websocket::WebsocketEvent::ServerOperationProgress
+                            | websocket::WebsocketEvent::ServerOperationCompleted => {
+                                if !socket_jwt.permissions.has_permission(Permission::FileRead) {
+                                    continue;
+                                }
+                            }
                             _ => {}
                         }
 
@@ -183,6 +198,73 @@ pub async fn handle_ws(
             })
         });
 
+        // File Change Listener
+        futures.push({
+            let socket_jwt = Arc::clone(&socket_jwt);
+            let watched_directories = Arc::clone(&watched_directories);
+            let sender = Arc::clone(&sender);
+            let mut reciever = server.filesystem.watcher.changes();
+            let server = server.clone();
+
+            Box::pin(async move {
+                loop {
+                    let change = match reciever.recv().await {
+                        Ok(change) => change,
+                        Err(RecvError::Closed) => break,
+                        Err(RecvError::Lagged(_)) => {
+                            tracing::debug!(
+                                server = %server.uuid,
+                                "file changes lagged behind, changes dropped"
+                            );
+                            continue;
+                        }
+                    };
+
+                    let socket_jwt = socket_jwt.read().await;
+                    let socket_jwt = match socket_jwt.as_ref() {
+                        Some(jwt) => jwt,
+                        None => continue,
+                    };
+
+                    if !socket_jwt
+                        .permissions
+                        .has_any_permission(Permission::FileRead)
+                    {
+                        continue;
+                    }
+
+                    let watched_directories = watched_directories.read().await;
+                    let is_watched = |path: &std::path::Path| {
+                        path.parent().is_some_and(|parent| {
+                            watched_directories.contains(&server.filesystem.relative_path(parent))
+                        })
+                    };
+
+                    if !is_watched(&change.path) && !change.from.as_deref().is_some_and(is_watched)
+                    {
+                        continue;
+                    }
+
+                    let is_visible =
+                        |path: &std::path::Path| socket_jwt.permissions.is_path_visible(path);
+
+                    if !is_visible(&change.path) && !change.from.as_deref().is_some_and(is_visible)
+                    {
+                        continue;
+                    }
+
+                    super::send_message(
+                        &sender,
+                        websocket::WebsocketMessage::new(
+                            websocket::WebsocketEvent::ServerFileChanged,
+                            &[serde_json::to_string(&change).unwrap()],
+                        ),
+                    )
+                    .await;
+                }
+            })
+        });
+
         // Stdout Listener
         futures.push({
             let state = Arc::clone(&state);
@@ -276,5 +358,9 @@ pub async fn handle_ws(
                 );
             }
         }
+
+        for path in watched_directories.write().await.drain() {
+            server.filesystem.watcher.unsubscribe(&path).await;
+        }
     })
 }
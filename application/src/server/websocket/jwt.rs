@@ -62,16 +62,7 @@ pub async fn handle_jwt(
                                 return Err(JwtError::CloseSocket);
                             }
 
-                            let mut permissions = Vec::new();
-                            for permission in jwt.permissions.iter() {
-                                permissions.push(
-                                    serde_json::to_value(permission)
-                                        .unwrap()
-                                        .as_str()
-                                        .unwrap()
-                                        .to_string(),
-                                );
-                            }
+                            let permissions = jwt.permissions.to_strings();
 
                             super::send_message(
                                 sender,
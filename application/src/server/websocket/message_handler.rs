@@ -7,8 +7,8 @@ use anyhow::Context;
 use axum::extract::ws::{Message, WebSocket};
 use futures_util::stream::SplitSink;
 use serde_json::json;
-use std::net::IpAddr;
-use tokio::sync::Mutex;
+use std::{collections::HashSet, net::IpAddr, path::PathBuf};
+use tokio::sync::{Mutex, RwLock};
 
 pub async fn handle_message(
     state: &crate::routes::AppState,
@@ -16,6 +16,7 @@ pub async fn handle_message(
     server: &crate::server::Server,
     sender: &Mutex<SplitSink<WebSocket, Message>>,
     socket_jwt: &WebsocketJwtPayload,
+    watched_directories: &RwLock<HashSet<PathBuf>>,
     message: super::WebsocketMessage,
 ) -> Result<(), anyhow::Error> {
     let user_ip = Some(user_ip);
@@ -254,6 +255,63 @@ pub async fn handle_message(
                 }
             }
         }
+        WebsocketEvent::WatchDirectory => {
+            if !socket_jwt
+                .permissions
+                .has_any_permission(Permission::FileRead)
+            {
+                tracing::debug!(
+                    server = %server.uuid,
+                    "jwt does not have permission to watch directories: {:?}",
+                    socket_jwt.permissions
+                );
+
+                return Ok(());
+            }
+
+            let raw_path = message.args.first().map_or("", |v| v.as_str());
+            let path = server
+                .filesystem
+                .canonicalize(raw_path)
+                .await
+                .context("failed to resolve watched directory")?;
+
+            let metadata = server.filesystem.metadata(&path).await?;
+            if !metadata.is_dir()
+                || server
+                    .filesystem
+                    .is_hidden(&path, true, &socket_jwt.permissions)
+                    .await
+            {
+                return Ok(());
+            }
+
+            let mut watched_directories = watched_directories.write().await;
+            if watched_directories.contains(&path) {
+                return Ok(());
+            }
+
+            server
+                .filesystem
+                .watcher
+                .subscribe(&path)
+                .await
+                .context("failed to watch directory")?;
+            watched_directories.insert(path);
+        }
+        WebsocketEvent::UnwatchDirectory => {
+            let raw_path = message.args.first().map_or("", |v| v.as_str());
+            let path = match server.filesystem.canonicalize(raw_path).await {
+                Ok(path) => path,
+                Err(_) => server
+                    .filesystem
+                    .relative_path(std::path::Path::new(raw_path)),
+            };
+
+            if watched_directories.write().await.remove(&path) {
+                server.filesystem.watcher.unsubscribe(&path).await;
+            }
+        }
         _ => {
             tracing::debug!(
                 "received websocket message that will not be handled: {:?}",
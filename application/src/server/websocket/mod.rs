@@ -40,6 +40,10 @@ pub enum WebsocketEvent {
     SendCommand,
     #[serde(rename = "send stats")]
     SendStats,
+    #[serde(rename = "watch directory")]
+    WatchDirectory,
+    #[serde(rename = "unwatch directory")]
+    UnwatchDirectory,
     #[serde(rename = "daemon error")]
     Error,
     #[serde(rename = "jwt error")]
@@ -67,6 +71,12 @@ pub enum WebsocketEvent {
     ServerTransferLogs,
     #[serde(rename = "transfer status")]
     ServerTransferStatus,
+    #[serde(rename = "file changed")]
+    ServerFileChanged,
+    #[serde(rename = "operation progress")]
+    ServerOperationProgress,
+    #[serde(rename = "operation completed")]
+    ServerOperationCompleted,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
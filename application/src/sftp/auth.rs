@@ -1,10 +1,142 @@
-use crate::{remote::AuthenticationType, routes::State, server::permissions::Permissions};
+use crate::{
+    remote::AuthenticationType,
+    routes::State,
+    server::{
+        activity::{Activity, ActivityEvent},
+        permissions::{Permission, Permissions},
+        throttle::{Direction, Throttle},
+    },
+};
 use russh::{
     Channel, ChannelId, MethodSet,
     server::{Auth, Msg, Session},
 };
 use russh_sftp::protocol::StatusCode;
-use std::{collections::HashMap, net::IpAddr, sync::Arc};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::Arc,
+    time::Instant,
+};
+
+/// Resolves a sftp login through the panel, successful logins are cached for a short time
+/// and can be used past that while the panel cannot be reached. Rejected credentials only
+/// count towards the lockout when `count_failure` is set
+pub async fn authenticate(
+    state: &State,
+    user_ip: Option<IpAddr>,
+    r#type: AuthenticationType,
+    username: &str,
+    secret: &str,
+    count_failure: bool,
+) -> Option<(uuid::Uuid, uuid::Uuid, Permissions)> {
+    let config = &state.config.system.sftp.auth_cache;
+    let cache = &state.sftp_auth_cache;
+
+    if state.sftp_limiter.is_locked(user_ip, username) {
+        tracing::debug!(
+            username = username,
+            "rejected sftp login from locked out address or username"
+        );
+
+        return None;
+    }
+
+    if let Some(cached) = cache.get(r#type, username, secret, config.ttl) {
+        return Some((cached.user, cached.server, cached.permissions));
+    }
+
+    match state
+        .config
+        .client
+        .get_sftp_auth(r#type, username, secret)
+        .await
+    {
+        Ok((user, server, permissions)) => {
+            state.sftp_limiter.success(user_ip, username);
+
+            if config.ttl > 0 || config.stale_ttl > 0 {
+                cache.prune(config.ttl.max(config.stale_ttl));
+                cache.insert(r#type, username, secret, user, server, permissions.clone());
+            }
+
+            Some((user, server, permissions))
+        }
+        Err(err) => {
+            tracing::debug!(
+                username = username,
+                "failed to authenticate ({:?}): {:#?}",
+                r#type,
+                err
+            );
+
+            // the panel answered, so the credentials are no longer valid
+            if err.status().is_some_and(|status| status.is_client_error()) {
+                cache.remove(r#type, username, secret);
+
+                if count_failure {
+                    state.sftp_limiter.failure(user_ip, username).await;
+                    log_failure(
+                        state,
+                        user_ip,
+                        match r#type {
+                            AuthenticationType::Password => "password",
+                            AuthenticationType::PublicKey => "public_key",
+                        },
+                        username,
+                    )
+                    .await;
+                }
+
+                return None;
+            }
+
+            let cached = cache.get(r#type, username, secret, config.stale_ttl)?;
+            tracing::warn!(
+                username = username,
+                "panel is unreachable, using cached sftp authentication"
+            );
+
+            Some((cached.user, cached.server, cached.permissions))
+        }
+    }
+}
+
+/// Logs a failed login to the server the username points at, usernames are
+/// formatted as `username.server` with the short server uuid
+pub async fn log_failure(state: &State, user_ip: Option<IpAddr>, method: &str, username: &str) {
+    let short = match username.rsplit_once('.') {
+        Some((_, short)) if short.len() == 8 => short.to_lowercase(),
+        _ => return,
+    };
+
+    let server = match state
+        .server_manager
+        .get_servers()
+        .await
+        .iter()
+        .find(|s| s.uuid.to_string().starts_with(&short))
+        .cloned()
+    {
+        Some(server) => server,
+        None => return,
+    };
+
+    server
+        .activity
+        .log_activity(Activity {
+            event: ActivityEvent::SftpLoginFailed,
+            user: None,
+            ip: user_ip,
+            metadata: Some(json!({
+                "username": username,
+                "method": method,
+            })),
+            timestamp: chrono::Utc::now(),
+        })
+        .await;
+}
 
 pub struct SshSession {
     pub state: State,
@@ -15,6 +147,59 @@ pub struct SshSession {
     pub user_permissions: Permissions,
 
     pub clients: HashMap<ChannelId, Channel<Msg>>,
+    pub ptys: HashSet<ChannelId>,
+    /// channels served by a console, scp or rsync task, which closes them itself
+    pub detached: HashSet<ChannelId>,
+
+    pub connection: Option<super::limiter::ConnectionPermit>,
+    /// shared by all channels, so the session limit applies to the whole connection
+    pub throttle: Option<Throttle>,
+    pub login: Option<Login>,
+    /// agents offer every key they hold, only the first rejected key of a
+    /// connection counts towards the lockout
+    pub publickey_rejected: bool,
+}
+
+/// The accepted login of a session, for the audit trail
+pub struct Login {
+    pub username: String,
+    pub method: &'static str,
+    pub time: Instant,
+}
+
+impl Drop for SshSession {
+    fn drop(&mut self) {
+        let (server, login) = match (&self.server, &self.login) {
+            (Some(server), Some(login)) => (server.clone(), login),
+            _ => return,
+        };
+
+        let (downloaded, uploaded) = match &self.throttle {
+            Some(throttle) => (
+                throttle.session().download.transferred(),
+                throttle.session().upload.transferred(),
+            ),
+            None => (0, 0),
+        };
+
+        let activity = Activity {
+            event: ActivityEvent::SftpLogout,
+            user: self.user_uuid,
+            ip: self.user_ip,
+            metadata: Some(json!({
+                "username": login.username,
+                "method": login.method,
+                "duration": login.time.elapsed().as_secs(),
+                "bytes_read": downloaded,
+                "bytes_written": uploaded,
+            })),
+            timestamp: chrono::Utc::now(),
+        };
+
+        tokio::spawn(async move {
+            server.activity.log_activity(activity).await;
+        });
+    }
 }
 
 impl SshSession {
@@ -31,11 +216,62 @@ impl SshSession {
     pub async fn get_channel(&mut self, channel_id: ChannelId) -> Channel<Msg> {
         self.clients.remove(&channel_id).unwrap()
     }
+
+    async fn throttle(&mut self, server: &crate::server::Server) -> Throttle {
+        if let Some(throttle) = &self.throttle {
+            return throttle.clone();
+        }
+
+        let throttle = Throttle::new(
+            &self.state.config,
+            &self.state.bandwidth,
+            server,
+            Direction::Download,
+        )
+        .await;
+        self.throttle = Some(throttle.clone());
+
+        throttle
+    }
+
+    fn shell_session(&mut self, channel_id: ChannelId) -> Option<super::shell::ShellSession> {
+        Some(super::shell::ShellSession {
+            state: Arc::clone(&self.state),
+            server: self.server.clone()?,
+
+            user_ip: self.user_ip,
+            user_uuid: self.user_uuid,
+            user_permissions: self.user_permissions.clone(),
+
+            pty: self.ptys.remove(&channel_id),
+        })
+    }
 }
 
 impl russh::server::Handler for SshSession {
     type Error = Box<dyn std::error::Error + Send + Sync>;
 
+    async fn auth_succeeded(&mut self, session: &mut Session) -> Result<(), Self::Error> {
+        if let (Some(server), Some(login)) = (&self.server, &self.login) {
+            server
+                .activity
+                .log_activity(Activity {
+                    event: ActivityEvent::SftpLogin,
+                    user: self.user_uuid,
+                    ip: self.user_ip,
+                    metadata: Some(json!({
+                        "username": login.username,
+                        "method": login.method,
+                        "client": String::from_utf8_lossy(session.remote_sshid()),
+                    })),
+                    timestamp: chrono::Utc::now(),
+                })
+                .await;
+        }
+
+        Ok(())
+    }
+
     async fn auth_none(&mut self, _user: &str) -> Result<Auth, Self::Error> {
         Ok(Auth::Reject {
             proceed_with_methods: Some(self.get_auth_methods()),
@@ -51,23 +287,18 @@ impl russh::server::Handler for SshSession {
             });
         }
 
-        let (user, server, permissions) = match self
-            .state
-            .config
-            .client
-            .get_sftp_auth(AuthenticationType::Password, username, password)
-            .await
+        let (user, server, permissions) = match authenticate(
+            &self.state,
+            self.user_ip,
+            AuthenticationType::Password,
+            username,
+            password,
+            true,
+        )
+        .await
         {
-            Ok((user, server, permissions)) => (user, server, permissions),
-            Err(err) => {
-                tracing::debug!(
-                    username = username,
-                    "failed to authenticate (password): {:#?}",
-                    err
-                );
-
-                return Ok(Auth::reject());
-            }
+            Some((user, server, permissions)) => (user, server, permissions),
+            None => return Ok(Auth::reject()),
         };
 
         self.user_uuid = Some(user);
@@ -96,6 +327,11 @@ impl russh::server::Handler for SshSession {
         }
 
         self.server = Some(server);
+        self.login = Some(Login {
+            username: username.to_string(),
+            method: "password",
+            time: Instant::now(),
+        });
 
         Ok(Auth::Accept)
     }
@@ -105,25 +341,96 @@ impl russh::server::Handler for SshSession {
         username: &str,
         public_key: &russh::keys::ssh_key::PublicKey,
     ) -> Result<Auth, Self::Error> {
-        let (user, server, permissions) = match self
+        let (user, server, permissions) = match authenticate(
+            &self.state,
+            self.user_ip,
+            AuthenticationType::PublicKey,
+            username,
+            &public_key.to_openssh().unwrap(),
+            !self.publickey_rejected,
+        )
+        .await
+        {
+            Some((user, server, permissions)) => (user, server, permissions),
+            None => {
+                self.publickey_rejected = true;
+
+                return Ok(Auth::Reject {
+                    proceed_with_methods: Some(self.get_auth_methods()),
+                    partial_success: false,
+                });
+            }
+        };
+
+        self.user_uuid = Some(user);
+        self.user_permissions = permissions;
+
+        let server = match self
             .state
-            .config
-            .client
-            .get_sftp_auth(
-                AuthenticationType::PublicKey,
-                username,
-                &public_key.to_openssh().unwrap(),
-            )
+            .server_manager
+            .get_servers()
             .await
+            .iter()
+            .find(|s| s.uuid == server)
+            .cloned()
         {
+            Some(server) => server,
+            None => return Ok(Auth::reject()),
+        };
+
+        if server.is_locked_state() {
+            return Ok(Auth::reject());
+        }
+
+        self.server = Some(server);
+        self.login = Some(Login {
+            username: username.to_string(),
+            method: "public_key",
+            time: Instant::now(),
+        });
+
+        Ok(Auth::Accept)
+    }
+
+    async fn auth_openssh_certificate(
+        &mut self,
+        username: &str,
+        certificate: &russh::keys::ssh_key::Certificate,
+    ) -> Result<Auth, Self::Error> {
+        if self.state.sftp_limiter.is_locked(self.user_ip, username) {
+            return Ok(Auth::reject());
+        }
+
+        let (user, server, permissions) = match super::certificate::validate(
+            &self.state.config,
+            username,
+            self.user_ip,
+            certificate,
+        ) {
             Ok((user, server, permissions)) => (user, server, permissions),
             Err(err) => {
                 tracing::debug!(
                     username = username,
-                    "failed to authenticate (public_key): {:#?}",
+                    key_id = certificate.key_id(),
+                    "failed to authenticate (certificate): {}",
                     err
                 );
 
+                if !self
+                    .state
+                    .config
+                    .system
+                    .sftp
+                    .trusted_user_ca_keys
+                    .is_empty()
+                {
+                    self.state
+                        .sftp_limiter
+                        .failure(self.user_ip, username)
+                        .await;
+                    log_failure(&self.state, self.user_ip, "certificate", username).await;
+                }
+
                 return Ok(Auth::Reject {
                     proceed_with_methods: Some(self.get_auth_methods()),
                     partial_success: false,
@@ -131,6 +438,8 @@ impl russh::server::Handler for SshSession {
             }
         };
 
+        self.state.sftp_limiter.success(self.user_ip, username);
+
         self.user_uuid = Some(user);
         self.user_permissions = permissions;
 
@@ -152,6 +461,11 @@ impl russh::server::Handler for SshSession {
         }
 
         self.server = Some(server);
+        self.login = Some(Login {
+            username: username.to_string(),
+            method: "certificate",
+            time: Instant::now(),
+        });
 
         Ok(Auth::Accept)
     }
@@ -171,7 +485,128 @@ impl russh::server::Handler for SshSession {
         channel: ChannelId,
         session: &mut Session,
     ) -> Result<(), Self::Error> {
-        session.close(channel)?;
+        if !self.detached.remove(&channel) {
+            session.close(channel)?;
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn pty_request(
+        &mut self,
+        channel_id: ChannelId,
+        _term: &str,
+        _col_width: u32,
+        _row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        _modes: &[(russh::Pty, u32)],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.ptys.insert(channel_id);
+        session.channel_success(channel_id)?;
+
+        Ok(())
+    }
+
+    async fn shell_request(
+        &mut self,
+        channel_id: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let shell = match self.shell_session(channel_id) {
+            Some(shell) => shell,
+            None => return Err(Box::new(StatusCode::PermissionDenied)),
+        };
+
+        if self.state.config.system.sftp.disable_shell
+            || !shell
+                .user_permissions
+                .has_permission(Permission::ControlConsole)
+        {
+            session.channel_failure(channel_id)?;
+
+            return Ok(());
+        }
+
+        let channel = self.get_channel(channel_id).await;
+        session.channel_success(channel_id)?;
+        self.detached.insert(channel_id);
+        tokio::spawn(shell.run(channel));
+
+        Ok(())
+    }
+
+    async fn exec_request(
+        &mut self,
+        channel_id: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let server = match &self.server {
+            Some(server) => server.clone(),
+            None => return Err(Box::new(StatusCode::PermissionDenied)),
+        };
+
+        let command = String::from_utf8_lossy(data).to_string();
+        let args = shell_words::split(&command).unwrap_or_default();
+
+        match args.first().map(|arg| arg.as_str()) {
+            Some("scp") => {
+                let throttle = self.throttle(&server).await;
+                let scp = super::scp::ScpSession {
+                    state: Arc::clone(&self.state),
+                    server,
+
+                    user_ip: self.user_ip,
+                    user_uuid: self.user_uuid,
+                    user_permissions: self.user_permissions.clone(),
+
+                    throttle,
+                };
+
+                let channel = self.get_channel(channel_id).await;
+                session.channel_success(channel_id)?;
+                self.detached.insert(channel_id);
+                tokio::spawn(scp.run(channel, args[1..].to_vec()));
+            }
+            Some("rsync") => {
+                let throttle = self.throttle(&server).await;
+                let rsync = super::rsync::RsyncSession {
+                    state: Arc::clone(&self.state),
+                    server,
+
+                    user_ip: self.user_ip,
+                    user_uuid: self.user_uuid,
+                    user_permissions: self.user_permissions.clone(),
+
+                    throttle,
+                };
+
+                let channel = self.get_channel(channel_id).await;
+                session.channel_success(channel_id)?;
+                self.detached.insert(channel_id);
+                tokio::spawn(rsync.run(channel, args[1..].to_vec()));
+            }
+            _ => {
+                let shell = match self.shell_session(channel_id) {
+                    Some(shell) => shell,
+                    None => return Err(Box::new(StatusCode::PermissionDenied)),
+                };
+
+                if self.state.config.system.sftp.disable_shell {
+                    session.channel_failure(channel_id)?;
+
+                    return Ok(());
+                }
+
+                let channel = self.get_channel(channel_id).await;
+                session.channel_success(channel_id)?;
+                self.detached.insert(channel_id);
+                tokio::spawn(shell.exec(channel, command));
+            }
+        }
 
         Ok(())
     }
@@ -189,6 +624,7 @@ impl russh::server::Handler for SshSession {
 
         if name == "sftp" {
             let channel = self.get_channel(channel_id).await;
+            let throttle = self.throttle(&server).await;
             let sftp = super::SftpSession {
                 state: Arc::clone(&self.state),
                 server,
@@ -199,6 +635,9 @@ impl russh::server::Handler for SshSession {
 
                 handle_id: 0,
                 handles: HashMap::new(),
+
+                write_throttle: throttle.reversed(),
+                read_throttle: throttle,
             };
 
             session.channel_success(channel_id)?;
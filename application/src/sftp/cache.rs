@@ -0,0 +1,124 @@
+use crate::{remote::AuthenticationType, server::permissions::Permissions};
+use hmac::Mac;
+use std::{collections::HashMap, sync::RwLock, time::Instant};
+
+#[derive(Clone)]
+pub struct CachedAuth {
+    pub user: uuid::Uuid,
+    pub server: uuid::Uuid,
+    pub permissions: Permissions,
+
+    username: String,
+    authenticated: Instant,
+}
+
+/// Successful sftp logins, keyed by a keyed hash of the credentials so neither
+/// passwords nor public keys are kept in memory
+pub struct AuthCache {
+    key: hmac::Hmac<sha2::Sha256>,
+    entries: RwLock<HashMap<[u8; 32], CachedAuth>>,
+}
+
+impl Default for AuthCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuthCache {
+    pub fn new() -> Self {
+        Self {
+            key: hmac::Hmac::new_from_slice(&rand::random::<[u8; 32]>()).unwrap(),
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn hash(&self, r#type: AuthenticationType, username: &str, secret: &str) -> [u8; 32] {
+        let mut mac = self.key.clone();
+        mac.update(&[r#type as u8]);
+        mac.update(username.as_bytes());
+        mac.update(&[0]);
+        mac.update(secret.as_bytes());
+
+        mac.finalize().into_bytes().into()
+    }
+
+    /// Returns a cached login that is at most `max_age` seconds old
+    pub fn get(
+        &self,
+        r#type: AuthenticationType,
+        username: &str,
+        secret: &str,
+        max_age: u64,
+    ) -> Option<CachedAuth> {
+        let hash = self.hash(r#type, username, secret);
+
+        self.entries
+            .read()
+            .unwrap()
+            .get(&hash)
+            .filter(|entry| entry.authenticated.elapsed().as_secs() < max_age)
+            .cloned()
+    }
+
+    pub fn insert(
+        &self,
+        r#type: AuthenticationType,
+        username: &str,
+        secret: &str,
+        user: uuid::Uuid,
+        server: uuid::Uuid,
+        permissions: Permissions,
+    ) {
+        let hash = self.hash(r#type, username, secret);
+
+        self.entries.write().unwrap().insert(
+            hash,
+            CachedAuth {
+                user,
+                server,
+                permissions,
+                username: username.to_string(),
+                authenticated: Instant::now(),
+            },
+        );
+    }
+
+    pub fn remove(&self, r#type: AuthenticationType, username: &str, secret: &str) {
+        let hash = self.hash(r#type, username, secret);
+
+        self.entries.write().unwrap().remove(&hash);
+    }
+
+    /// Drops entries matching any of the given users, servers or usernames,
+    /// everything is dropped when all of them are empty
+    pub fn invalidate(
+        &self,
+        users: &[uuid::Uuid],
+        servers: &[uuid::Uuid],
+        usernames: &[String],
+    ) -> usize {
+        let mut entries = self.entries.write().unwrap();
+        let count = entries.len();
+
+        if users.is_empty() && servers.is_empty() && usernames.is_empty() {
+            entries.clear();
+        } else {
+            entries.retain(|_, entry| {
+                !users.contains(&entry.user)
+                    && !servers.contains(&entry.server)
+                    && !usernames.contains(&entry.username)
+            });
+        }
+
+        count - entries.len()
+    }
+
+    /// Drops entries that can no longer be used, not even as a stale fallback
+    pub fn prune(&self, max_age: u64) {
+        self.entries
+            .write()
+            .unwrap()
+            .retain(|_, entry| entry.authenticated.elapsed().as_secs() < max_age);
+    }
+}
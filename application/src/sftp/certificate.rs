@@ -0,0 +1,125 @@
+use crate::server::permissions::Permissions;
+use russh::keys::ssh_key::{Certificate, Fingerprint, HashAlg, PublicKey, certificate::CertType};
+use std::net::IpAddr;
+
+/// user uuid the certificate authenticates as
+pub const EXTENSION_USER: &str = "user@wings";
+/// uuid of the server the certificate grants access to
+pub const EXTENSION_SERVER: &str = "server@wings";
+/// comma separated permissions, in the same format the panel uses
+pub const EXTENSION_PERMISSIONS: &str = "permissions@wings";
+
+/// Validates a user certificate against the trusted certificate authorities, the
+/// login username has to be one of its principals
+pub fn validate(
+    config: &crate::config::Config,
+    username: &str,
+    ip: Option<IpAddr>,
+    certificate: &Certificate,
+) -> Result<(uuid::Uuid, uuid::Uuid, Permissions), &'static str> {
+    let authorities = config
+        .system
+        .sftp
+        .trusted_user_ca_keys
+        .iter()
+        .filter_map(|key| match PublicKey::from_openssh(key) {
+            Ok(key) => Some(key.fingerprint(HashAlg::Sha256)),
+            Err(err) => {
+                tracing::warn!(
+                    "failed to parse trusted sftp certificate authority: {}",
+                    err
+                );
+
+                None
+            }
+        })
+        .collect::<Vec<Fingerprint>>();
+
+    if authorities.is_empty() {
+        return Err("no certificate authorities are trusted");
+    }
+
+    if certificate.cert_type() != CertType::User {
+        return Err("certificate is not a user certificate");
+    }
+
+    certificate
+        .validate_at(chrono::Utc::now().timestamp().max(0) as u64, &authorities)
+        .map_err(|_| "certificate is expired or not signed by a trusted authority")?;
+
+    if !certificate
+        .valid_principals()
+        .iter()
+        .any(|principal| principal == username)
+    {
+        return Err("username is not a principal of the certificate");
+    }
+
+    for (name, value) in certificate.critical_options().iter() {
+        match name.as_str() {
+            "source-address" => {
+                if !ip.is_some_and(|ip| matches_source_address(value, ip)) {
+                    return Err("address is not allowed by the certificate");
+                }
+            }
+            _ => return Err("certificate has unsupported critical options"),
+        }
+    }
+
+    let extensions = certificate.extensions();
+
+    let user = extensions
+        .get(EXTENSION_USER)
+        .and_then(|user| user.parse().ok())
+        .ok_or("certificate has no valid user extension")?;
+    let server = extensions
+        .get(EXTENSION_SERVER)
+        .and_then(|server| server.parse().ok())
+        .ok_or("certificate has no valid server extension")?;
+    let permissions = extensions
+        .get(EXTENSION_PERMISSIONS)
+        .and_then(|permissions| {
+            serde_json::from_value::<Permissions>(serde_json::Value::from(
+                permissions
+                    .split(',')
+                    .map(|permission| permission.trim())
+                    .filter(|permission| !permission.is_empty())
+                    .collect::<Vec<_>>(),
+            ))
+            .ok()
+        })
+        .ok_or("certificate has no valid permissions extension")?;
+
+    Ok((user, server, permissions))
+}
+
+/// Matches against an OpenSSH `source-address` list of addresses and cidr ranges
+fn matches_source_address(list: &str, ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+
+    list.split(',').any(|entry| {
+        let (address, prefix) = match entry.trim().split_once('/') {
+            Some((address, prefix)) => match prefix.parse::<u32>() {
+                Ok(prefix) => (address, Some(prefix)),
+                Err(_) => return false,
+            },
+            None => (entry.trim(), None),
+        };
+
+        match (address.parse::<IpAddr>(), ip) {
+            (Ok(IpAddr::V4(network)), IpAddr::V4(ip)) => {
+                let prefix = prefix.unwrap_or(32).min(32);
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (Ok(IpAddr::V6(network)), IpAddr::V6(ip)) => {
+                let prefix = prefix.unwrap_or(128).min(128);
+                let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    })
+}
@@ -0,0 +1,376 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// bans are persisted, so keep the list from growing without bounds
+const BAN_LIMIT: usize = 1024;
+/// lockouts that cannot be represented end this far in the future instead
+const FAR_FUTURE: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Ban {
+    pub ip: IpAddr,
+    pub failures: u32,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub expires: chrono::DateTime<chrono::Utc>,
+}
+
+impl Ban {
+    pub fn to_api_response(&self) -> crate::models::SftpBan {
+        crate::models::SftpBan {
+            ip: self.ip.to_string(),
+            failures: self.failures,
+            created: self.created,
+            expires: self.expires,
+        }
+    }
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            last: Instant::now(),
+            locked_until: None,
+        }
+    }
+
+    #[inline]
+    fn is_locked(&self) -> bool {
+        self.locked_until
+            .is_some_and(|locked_until| locked_until > Instant::now())
+    }
+
+    #[inline]
+    fn is_expired(&self, window: Duration) -> bool {
+        let since = match self.locked_until {
+            Some(locked_until) => locked_until.max(self.last),
+            None => self.last,
+        };
+
+        since.elapsed() > window
+    }
+
+    /// Counts a failure and returns the lockout duration once the limit is reached,
+    /// every failure past the limit doubles the lockout
+    fn fail(&mut self, limits: &crate::config::SystemSftpLimits) -> Option<Duration> {
+        if self.is_expired(Duration::from_secs(limits.failure_window)) {
+            self.count = 0;
+        }
+
+        self.count += 1;
+        self.last = Instant::now();
+
+        if limits.max_failures == 0 || self.count < limits.max_failures {
+            return None;
+        }
+
+        let exponent = (self.count - limits.max_failures).min(31);
+        let lockout = Duration::from_secs(
+            limits
+                .lockout
+                .saturating_mul(1 << exponent)
+                .min(limits.max_lockout.max(limits.lockout)),
+        );
+        self.locked_until = Some(
+            self.last
+                .checked_add(lockout)
+                .unwrap_or_else(|| self.last + FAR_FUTURE),
+        );
+
+        Some(lockout)
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    connections: HashMap<IpAddr, usize>,
+    total_connections: usize,
+
+    ip_failures: HashMap<IpAddr, Failures>,
+    /// keyed by address as well, so failures elsewhere cannot lock out the real user
+    username_failures: HashMap<(String, Option<IpAddr>), Failures>,
+    bans: HashMap<IpAddr, Ban>,
+}
+
+/// Held by a ssh session for as long as its connection is open
+pub struct ConnectionPermit {
+    inner: Arc<Mutex<Inner>>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.total_connections = inner.total_connections.saturating_sub(1);
+        if let Some(connections) = inner.connections.get_mut(&self.ip) {
+            *connections -= 1;
+
+            if *connections == 0 {
+                inner.connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Connection limits and brute force protection for the sftp server
+pub struct Limiter {
+    config: Arc<crate::config::Config>,
+    path: PathBuf,
+
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Limiter {
+    pub fn new(config: Arc<crate::config::Config>) -> Self {
+        let path = Path::new(&config.system.data_directory)
+            .join(".sftp")
+            .join("bans.json");
+
+        let mut inner = Inner::default();
+        if let Some(bans) = std::fs::read(&path)
+            .ok()
+            .and_then(|content| serde_json::from_slice::<Vec<Ban>>(&content).ok())
+        {
+            let now = chrono::Utc::now();
+
+            for ban in bans.into_iter().filter(|ban| ban.expires > now) {
+                inner.bans.insert(ban.ip, ban);
+            }
+        }
+
+        Self {
+            config,
+            path,
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    #[inline]
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.config.system.sftp.limits.trusted_ips.contains(&ip)
+    }
+
+    /// Reserves a connection slot for the address, fails if the address is banned
+    /// or any of the connection limits has been reached
+    pub fn connect(&self, ip: IpAddr) -> Result<ConnectionPermit, &'static str> {
+        let limits = &self.config.system.sftp.limits;
+        let trusted = self.is_trusted(ip);
+        let mut inner = self.inner.lock().unwrap();
+
+        if !trusted {
+            if inner
+                .bans
+                .get(&ip)
+                .is_some_and(|ban| ban.expires > chrono::Utc::now())
+            {
+                return Err("address is banned");
+            }
+
+            if limits.max_connections > 0 && inner.total_connections >= limits.max_connections {
+                return Err("too many connections");
+            }
+
+            if limits.max_connections_per_ip > 0
+                && inner.connections.get(&ip).copied().unwrap_or_default()
+                    >= limits.max_connections_per_ip
+            {
+                return Err("too many connections from address");
+            }
+        }
+
+        inner.total_connections += 1;
+        *inner.connections.entry(ip).or_default() += 1;
+
+        Ok(ConnectionPermit {
+            inner: Arc::clone(&self.inner),
+            ip,
+        })
+    }
+
+    /// Whether login attempts from the address, or for the username from that address,
+    /// are currently refused
+    pub fn is_locked(&self, ip: Option<IpAddr>, username: &str) -> bool {
+        if ip.is_some_and(|ip| self.is_trusted(ip)) {
+            return false;
+        }
+
+        let inner = self.inner.lock().unwrap();
+
+        ip.is_some_and(|ip| {
+            inner
+                .bans
+                .get(&ip)
+                .is_some_and(|ban| ban.expires > chrono::Utc::now())
+        }) || inner
+            .username_failures
+            .get(&(username.to_lowercase(), ip))
+            .is_some_and(|failures| failures.is_locked())
+    }
+
+    /// Records a rejected login, addresses that reach the failure limit are banned
+    pub async fn failure(&self, ip: Option<IpAddr>, username: &str) {
+        if ip.is_some_and(|ip| self.is_trusted(ip)) {
+            return;
+        }
+
+        let limits = &self.config.system.sftp.limits;
+        let window = Duration::from_secs(limits.failure_window);
+
+        let bans = {
+            let mut inner = self.inner.lock().unwrap();
+
+            inner
+                .ip_failures
+                .retain(|_, failures| !failures.is_expired(window));
+            inner
+                .username_failures
+                .retain(|_, failures| !failures.is_expired(window));
+
+            if let Some(lockout) = inner
+                .username_failures
+                .entry((username.to_lowercase(), ip))
+                .or_insert_with(Failures::new)
+                .fail(limits)
+            {
+                tracing::warn!(
+                    username = username,
+                    "locked sftp username for {}s after too many failed logins",
+                    lockout.as_secs()
+                );
+            }
+
+            let ip = match ip {
+                Some(ip) => ip,
+                None => return,
+            };
+
+            let failures = inner.ip_failures.entry(ip).or_insert_with(Failures::new);
+            let lockout = match failures.fail(limits) {
+                Some(lockout) => lockout,
+                None => return,
+            };
+            let count = failures.count;
+
+            tracing::warn!(
+                ip = %ip,
+                "banned sftp address for {}s after too many failed logins",
+                lockout.as_secs()
+            );
+
+            let now = chrono::Utc::now();
+            inner.bans.retain(|_, ban| ban.expires > now);
+            if inner.bans.len() >= BAN_LIMIT && !inner.bans.contains_key(&ip) {
+                if let Some(oldest) = inner
+                    .bans
+                    .values()
+                    .min_by_key(|ban| ban.expires)
+                    .map(|ban| ban.ip)
+                {
+                    inner.bans.remove(&oldest);
+                }
+            }
+
+            inner.bans.insert(
+                ip,
+                Ban {
+                    ip,
+                    failures: count,
+                    created: now,
+                    expires: chrono::Duration::from_std(lockout)
+                        .ok()
+                        .and_then(|lockout| now.checked_add_signed(lockout))
+                        .unwrap_or_else(|| now + chrono::Duration::from_std(FAR_FUTURE).unwrap()),
+                },
+            );
+
+            inner.bans.values().cloned().collect::<Vec<_>>()
+        };
+
+        self.persist(bans).await;
+    }
+
+    /// Resets the failure counters after a successful login
+    pub fn success(&self, ip: Option<IpAddr>, username: &str) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(ip) = ip {
+            inner.ip_failures.remove(&ip);
+        }
+        inner
+            .username_failures
+            .remove(&(username.to_lowercase(), ip));
+    }
+
+    pub fn bans(&self) -> Vec<Ban> {
+        let now = chrono::Utc::now();
+        let mut bans = self
+            .inner
+            .lock()
+            .unwrap()
+            .bans
+            .values()
+            .filter(|ban| ban.expires > now)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        bans.sort_by_key(|ban| ban.created);
+
+        bans
+    }
+
+    /// Lifts the bans of the given addresses, or all bans when empty
+    pub async fn unban(&self, ips: &[IpAddr]) -> usize {
+        let (count, bans) = {
+            let mut inner = self.inner.lock().unwrap();
+            let count = inner.bans.len();
+
+            if ips.is_empty() {
+                inner.bans.clear();
+                inner.ip_failures.clear();
+                inner.username_failures.clear();
+            } else {
+                inner.bans.retain(|ip, _| !ips.contains(ip));
+                inner.ip_failures.retain(|ip, _| !ips.contains(ip));
+                inner
+                    .username_failures
+                    .retain(|(_, ip), _| ip.is_none_or(|ip| !ips.contains(&ip)));
+            }
+
+            (
+                count - inner.bans.len(),
+                inner.bans.values().cloned().collect::<Vec<_>>(),
+            )
+        };
+
+        if count > 0 {
+            self.persist(bans).await;
+        }
+
+        count
+    }
+
+    async fn persist(&self, bans: Vec<Ban>) {
+        if let Err(err) = tokio::fs::create_dir_all(self.path.parent().unwrap()).await {
+            tracing::error!("failed to create sftp ban directory: {:#?}", err);
+
+            return;
+        }
+
+        if let Err(err) = tokio::fs::write(&self.path, serde_json::to_vec(&bans).unwrap()).await {
+            tracing::error!("failed to write sftp ban list: {:#?}", err);
+        }
+    }
+}
@@ -13,7 +13,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sha1::Digest;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::SeekFrom,
     net::{IpAddr, SocketAddr},
     os::unix::fs::FileExt,
@@ -23,7 +23,13 @@ use std::{
 use sysinfo::Disks;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
-mod auth;
+pub mod auth;
+pub mod cache;
+pub mod certificate;
+pub mod limiter;
+mod rsync;
+mod scp;
+mod shell;
 
 pub struct Server {
     pub state: State,
@@ -42,6 +48,74 @@ impl russh::server::Server for Server {
             user_permissions: Default::default(),
 
             clients: HashMap::new(),
+            ptys: HashSet::new(),
+            detached: HashSet::new(),
+
+            connection: None,
+            throttle: None,
+            login: None,
+            publickey_rejected: false,
+        }
+    }
+}
+
+impl Server {
+    /// Like `run_on_address`, but drops banned addresses and connections over the
+    /// configured limits before the ssh handshake
+    pub async fn run(
+        &mut self,
+        config: Arc<russh::server::Config>,
+        address: SocketAddr,
+    ) -> Result<(), std::io::Error> {
+        use russh::server::Server as _;
+
+        let listener = tokio::net::TcpListener::bind(address).await?;
+
+        loop {
+            let (socket, address) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    tracing::debug!("failed to accept sftp connection: {:#?}", err);
+
+                    // errors like running out of file descriptors persist for a while
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+
+            let connection = match self.state.sftp_limiter.connect(address.ip()) {
+                Ok(connection) => connection,
+                Err(reason) => {
+                    tracing::debug!(
+                        ip = %address.ip(),
+                        "rejected sftp connection: {}",
+                        reason
+                    );
+                    continue;
+                }
+            };
+
+            let mut handler = self.new_client(Some(address));
+            handler.connection = Some(connection);
+
+            let config = Arc::clone(&config);
+            tokio::spawn(async move {
+                if config.nodelay {
+                    socket.set_nodelay(true).ok();
+                }
+
+                let session = match russh::server::run_stream(config, socket, handler).await {
+                    Ok(session) => session,
+                    Err(err) => {
+                        tracing::debug!("failed to set up sftp connection: {:#?}", err);
+                        return;
+                    }
+                };
+
+                if let Err(err) = session.await {
+                    tracing::debug!("sftp connection closed with error: {:#?}", err);
+                }
+            });
         }
     }
 }
@@ -53,6 +127,10 @@ struct FileHandle {
     file: Arc<std::fs::File>,
     consumed: u64,
     size: u64,
+
+    /// logged with the bytes written once the handle is closed
+    write_event: Option<ActivityEvent>,
+    written: u64,
 }
 
 struct DirHandle {
@@ -89,6 +167,9 @@ struct SftpSession {
 
     handle_id: u64,
     handles: HashMap<String, ServerHandle>,
+
+    read_throttle: crate::server::throttle::Throttle,
+    write_throttle: crate::server::throttle::Throttle,
 }
 
 impl SftpSession {
@@ -133,14 +214,189 @@ impl SftpSession {
     }
 
     #[inline]
-    fn has_permission(&self, permission: Permission) -> bool {
-        self.user_permissions.has_permission(permission)
+    fn has_any_permission(&self, permission: Permission) -> bool {
+        self.user_permissions.has_any_permission(permission)
+    }
+
+    /// Shared by `rename` and `posix-rename@openssh.com`, the latter atomically
+    /// replaces an existing file at the new path
+    async fn rename_entry(
+        &self,
+        old_path: String,
+        new_path: String,
+        overwrite: bool,
+    ) -> Result<(), StatusCode> {
+        if self.state.config.system.sftp.read_only {
+            return Err(StatusCode::PermissionDenied);
+        }
+
+        if !self.has_any_permission(Permission::FileUpdate) {
+            return Err(StatusCode::PermissionDenied);
+        }
+
+        let old_path = match self.server.filesystem.canonicalize(&old_path).await {
+            Ok(path) => path,
+            Err(_) => return Err(StatusCode::NoSuchFile),
+        };
+        let new_path = PathBuf::from(new_path);
+
+        let old_metadata = match self.server.filesystem.symlink_metadata(&old_path).await {
+            Ok(metadata) => metadata,
+            Err(_) => return Err(StatusCode::NoSuchFile),
+        };
+        let new_metadata = self
+            .server
+            .filesystem
+            .symlink_metadata(&new_path)
+            .await
+            .ok();
+
+        if new_metadata
+            .as_ref()
+            .is_some_and(|metadata| !overwrite || metadata.is_dir() || old_metadata.is_dir())
+            || self
+                .server
+                .filesystem
+                .is_denied(
+                    &old_path,
+                    old_metadata.is_dir(),
+                    &self.user_permissions,
+                    Permission::FileUpdate,
+                )
+                .await
+            || self
+                .server
+                .filesystem
+                .is_denied(
+                    &new_path,
+                    old_metadata.is_dir(),
+                    &self.user_permissions,
+                    Permission::FileUpdate,
+                )
+                .await
+        {
+            return Err(StatusCode::Failure);
+        }
+
+        if new_metadata.is_some()
+            && self
+                .server
+                .filesystem
+                .is_denied(
+                    &new_path,
+                    false,
+                    &self.user_permissions,
+                    Permission::FileDelete,
+                )
+                .await
+        {
+            return Err(StatusCode::PermissionDenied);
+        }
+
+        let activity = Activity {
+            event: ActivityEvent::SftpRename,
+            user: self.user_uuid,
+            ip: self.user_ip,
+            metadata: Some(json!({
+                "files": [
+                    {
+                        "from": self.server.filesystem.relative_path(&old_path),
+                        "to": self.server.filesystem.relative_path(&new_path),
+                    }
+                ],
+            })),
+            timestamp: chrono::Utc::now(),
+        };
+
+        if self
+            .server
+            .filesystem
+            .rename_path(old_path, &new_path)
+            .await
+            .is_err()
+        {
+            return Err(StatusCode::NoSuchFile);
+        }
+
+        // the replaced file no longer takes up space
+        if let Some(metadata) = new_metadata {
+            self.server
+                .filesystem
+                .allocate_in_path(
+                    new_path.parent().unwrap_or(Path::new("")),
+                    -(metadata.len() as i64),
+                )
+                .await;
+        }
+
+        self.server.activity.log_activity(activity).await;
+
+        Ok(())
     }
 
     #[inline]
     fn allow_action(&self) -> bool {
         !self.server.is_locked_state()
     }
+
+    /// What a file handle was used for, logged when it is closed
+    fn handle_activities(&self, handle: FileHandle) -> Vec<Activity> {
+        let path = self.server.filesystem.relative_path(&handle.path);
+        let mut activities = Vec::new();
+
+        if handle.consumed > 0 {
+            activities.push(Activity {
+                event: ActivityEvent::SftpRead,
+                user: self.user_uuid,
+                ip: self.user_ip,
+                metadata: Some(json!({
+                    "files": [path],
+                    "bytes": handle.consumed,
+                })),
+                timestamp: chrono::Utc::now(),
+            });
+        }
+
+        if let Some(event) = handle.write_event {
+            activities.push(Activity {
+                event,
+                user: self.user_uuid,
+                ip: self.user_ip,
+                metadata: Some(json!({
+                    "files": [path],
+                    "bytes": handle.written,
+                })),
+                timestamp: chrono::Utc::now(),
+            });
+        }
+
+        activities
+    }
+}
+
+impl Drop for SftpSession {
+    fn drop(&mut self) {
+        let activities = std::mem::take(&mut self.handles)
+            .into_values()
+            .filter_map(|handle| match handle {
+                ServerHandle::File(handle) => Some(self.handle_activities(handle)),
+                ServerHandle::Dir(_) => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+
+        if activities.is_empty() {
+            return;
+        }
+
+        // clients that disconnect without closing their handles still leave a trace
+        let server = self.server.clone();
+        tokio::spawn(async move {
+            for activity in activities {
+                server.activity.log_activity(activity).await;
+            }
+        });
+    }
 }
 
 impl russh_sftp::server::Handler for SftpSession {
@@ -164,12 +420,26 @@ impl russh_sftp::server::Handler for SftpSession {
                 ("space-available".to_string(), "1".to_string()),
                 ("limits@openssh.com".to_string(), "1".to_string()),
                 ("statvfs@openssh.com".to_string(), "2".to_string()),
+                ("posix-rename@openssh.com".to_string(), "1".to_string()),
+                ("hardlink@openssh.com".to_string(), "1".to_string()),
+                ("fsync@openssh.com".to_string(), "1".to_string()),
+                ("lsetstat@openssh.com".to_string(), "1".to_string()),
+                ("expand-path@openssh.com".to_string(), "1".to_string()),
+                (
+                    "users-groups-by-id@openssh.com".to_string(),
+                    "1".to_string(),
+                ),
+                ("copy-data".to_string(), "1".to_string()),
             ]),
         })
     }
 
     async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
-        self.handles.remove(&handle);
+        if let Some(ServerHandle::File(handle)) = self.handles.remove(&handle) {
+            for activity in self.handle_activities(handle) {
+                self.server.activity.log_activity(activity).await;
+            }
+        }
 
         Ok(Status {
             id,
@@ -206,7 +476,7 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::Failure);
         }
 
-        if !self.has_permission(Permission::FileRead) {
+        if !self.has_any_permission(Permission::FileRead) {
             return Err(StatusCode::PermissionDenied);
         }
 
@@ -215,7 +485,12 @@ impl russh_sftp::server::Handler for SftpSession {
             Err(_) => return Err(StatusCode::NoSuchFile),
         };
 
-        if self.server.filesystem.is_ignored(&path, true).await {
+        if self
+            .server
+            .filesystem
+            .is_hidden(&path, true, &self.user_permissions)
+            .await
+        {
             return Err(StatusCode::NoSuchFile);
         }
 
@@ -275,7 +550,7 @@ impl russh_sftp::server::Handler for SftpSession {
             if self
                 .server
                 .filesystem
-                .is_ignored(&path, metadata.is_dir())
+                .is_hidden(&path, metadata.is_dir(), &self.user_permissions)
                 .await
             {
                 continue;
@@ -309,7 +584,7 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::PermissionDenied);
         }
 
-        if !self.has_permission(Permission::FileDelete) {
+        if !self.has_any_permission(Permission::FileDelete) {
             return Err(StatusCode::PermissionDenied);
         }
 
@@ -318,11 +593,6 @@ impl russh_sftp::server::Handler for SftpSession {
             Err(_) => return Err(StatusCode::NoSuchFile),
         };
 
-        let parent = match path.parent() {
-            Some(parent) => parent,
-            None => return Err(StatusCode::NoSuchFile),
-        };
-
         if let Ok(metadata) = self.server.filesystem.symlink_metadata(&path).await {
             if !metadata.is_file() {
                 return Err(StatusCode::NoSuchFile);
@@ -331,20 +601,21 @@ impl russh_sftp::server::Handler for SftpSession {
             if self
                 .server
                 .filesystem
-                .is_ignored(&path, metadata.is_dir())
+                .is_denied(
+                    &path,
+                    metadata.is_dir(),
+                    &self.user_permissions,
+                    Permission::FileDelete,
+                )
                 .await
             {
                 return Err(StatusCode::NoSuchFile);
             }
 
-            if self.server.filesystem.truncate_path(&path).await.is_err() {
+            if self.server.filesystem.trash_path(&path).await.is_err() {
                 return Err(StatusCode::NoSuchFile);
             }
 
-            self.server
-                .filesystem
-                .allocate_in_path(parent, -(metadata.len() as i64))
-                .await;
             self.server
                 .activity
                 .log_activity(Activity {
@@ -376,7 +647,7 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::PermissionDenied);
         }
 
-        if !self.has_permission(Permission::FileDelete) {
+        if !self.has_any_permission(Permission::FileDelete) {
             return Err(StatusCode::PermissionDenied);
         }
 
@@ -393,12 +664,17 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::NoSuchFile);
         }
 
-        if self.server.filesystem.is_ignored(&path, true).await {
+        if self
+            .server
+            .filesystem
+            .is_denied(&path, true, &self.user_permissions, Permission::FileDelete)
+            .await
+        {
             return Err(StatusCode::NoSuchFile);
         }
 
         if path != self.server.filesystem.base_path
-            && self.server.filesystem.truncate_path(&path).await.is_err()
+            && self.server.filesystem.trash_path(&path).await.is_err()
         {
             return Err(StatusCode::NoSuchFile);
         }
@@ -438,14 +714,18 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::PermissionDenied);
         }
 
-        if !self.has_permission(Permission::FileCreate) {
+        if !self.has_any_permission(Permission::FileCreate) {
             return Err(StatusCode::PermissionDenied);
         }
 
         let path = Path::new(&path);
 
         if self.server.filesystem.symlink_metadata(&path).await.is_ok()
-            || self.server.filesystem.is_ignored(path, true).await
+            || self
+                .server
+                .filesystem
+                .is_denied(path, true, &self.user_permissions, Permission::FileCreate)
+                .await
         {
             return Err(StatusCode::NoSuchFile);
         }
@@ -497,71 +777,7 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::PermissionDenied);
         }
 
-        if self.state.config.system.sftp.read_only {
-            return Err(StatusCode::PermissionDenied);
-        }
-
-        if !self.has_permission(Permission::FileUpdate) {
-            return Err(StatusCode::PermissionDenied);
-        }
-
-        let old_path = match self.server.filesystem.canonicalize(&old_path).await {
-            Ok(path) => path,
-            Err(_) => return Err(StatusCode::NoSuchFile),
-        };
-        let new_path = PathBuf::from(new_path);
-
-        let old_metadata = match self.server.filesystem.symlink_metadata(&old_path).await {
-            Ok(metadata) => metadata,
-            Err(_) => return Err(StatusCode::NoSuchFile),
-        };
-
-        if self
-            .server
-            .filesystem
-            .symlink_metadata(&new_path)
-            .await
-            .is_ok()
-            || self
-                .server
-                .filesystem
-                .is_ignored(&old_path, old_metadata.is_dir())
-                .await
-            || self
-                .server
-                .filesystem
-                .is_ignored(&new_path, old_metadata.is_dir())
-                .await
-        {
-            return Err(StatusCode::Failure);
-        }
-
-        let activity = Activity {
-            event: ActivityEvent::SftpRename,
-            user: self.user_uuid,
-            ip: self.user_ip,
-            metadata: Some(json!({
-                "files": [
-                    {
-                        "from": self.server.filesystem.relative_path(&old_path),
-                        "to": self.server.filesystem.relative_path(&new_path),
-                    }
-                ],
-            })),
-            timestamp: chrono::Utc::now(),
-        };
-
-        if self
-            .server
-            .filesystem
-            .rename_path(old_path, new_path)
-            .await
-            .is_err()
-        {
-            return Err(StatusCode::NoSuchFile);
-        }
-
-        self.server.activity.log_activity(activity).await;
+        self.rename_entry(old_path, new_path, false).await?;
 
         Ok(Status {
             id,
@@ -585,7 +801,7 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::PermissionDenied);
         }
 
-        if !self.has_permission(Permission::FileUpdate) {
+        if !self.has_any_permission(Permission::FileUpdate) {
             return Err(StatusCode::PermissionDenied);
         }
 
@@ -602,7 +818,12 @@ impl russh_sftp::server::Handler for SftpSession {
         if self
             .server
             .filesystem
-            .is_ignored(&path, metadata.is_dir())
+            .is_denied(
+                &path,
+                metadata.is_dir(),
+                &self.user_permissions,
+                Permission::FileUpdate,
+            )
             .await
         {
             return Err(StatusCode::NoSuchFile);
@@ -614,9 +835,23 @@ impl russh_sftp::server::Handler for SftpSession {
 
             self.server
                 .filesystem
-                .set_permissions(&path, permissions)
+                .set_permissions(&path, permissions.clone())
                 .await
                 .map_err(|_| StatusCode::Failure)?;
+
+            self.server
+                .activity
+                .log_activity(Activity {
+                    event: ActivityEvent::SftpChmod,
+                    user: self.user_uuid,
+                    ip: self.user_ip,
+                    metadata: Some(json!({
+                        "files": [self.server.filesystem.relative_path(&path)],
+                        "mode": format!("{:o}", permissions.mode()),
+                    })),
+                    timestamp: chrono::Utc::now(),
+                })
+                .await;
         }
 
         Ok(Status {
@@ -655,7 +890,7 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::PermissionDenied);
         }
 
-        if !self.has_permission(Permission::FileRead) {
+        if !self.has_any_permission(Permission::FileRead) {
             return Err(StatusCode::PermissionDenied);
         }
 
@@ -672,7 +907,7 @@ impl russh_sftp::server::Handler for SftpSession {
         if self
             .server
             .filesystem
-            .is_ignored(&path, metadata.is_dir())
+            .is_hidden(&path, metadata.is_dir(), &self.user_permissions)
             .await
         {
             return Err(StatusCode::NoSuchFile);
@@ -713,7 +948,7 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::PermissionDenied);
         }
 
-        if !self.has_permission(Permission::FileRead) {
+        if !self.has_any_permission(Permission::FileRead) {
             return Err(StatusCode::PermissionDenied);
         }
 
@@ -727,7 +962,7 @@ impl russh_sftp::server::Handler for SftpSession {
         if self
             .server
             .filesystem
-            .is_ignored(path, metadata.is_dir())
+            .is_hidden(path, metadata.is_dir(), &self.user_permissions)
             .await
         {
             return Err(StatusCode::NoSuchFile);
@@ -746,7 +981,7 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::PermissionDenied);
         }
 
-        if !self.has_permission(Permission::FileRead) {
+        if !self.has_any_permission(Permission::FileRead) {
             return Err(StatusCode::PermissionDenied);
         }
 
@@ -763,7 +998,12 @@ impl russh_sftp::server::Handler for SftpSession {
         if self
             .server
             .filesystem
-            .is_ignored(&path, metadata.is_dir())
+            .is_denied(
+                &path,
+                metadata.is_dir(),
+                &self.user_permissions,
+                Permission::FileRead,
+            )
             .await
         {
             return Err(StatusCode::NoSuchFile);
@@ -791,7 +1031,7 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::PermissionDenied);
         }
 
-        if !self.has_permission(Permission::FileCreate) {
+        if !self.has_any_permission(Permission::FileCreate) {
             return Err(StatusCode::PermissionDenied);
         }
 
@@ -813,7 +1053,26 @@ impl russh_sftp::server::Handler for SftpSession {
         if self
             .server
             .filesystem
-            .is_ignored(&targetpath, metadata.is_dir())
+            .is_denied(
+                &targetpath,
+                metadata.is_dir(),
+                &self.user_permissions,
+                Permission::FileRead,
+            )
+            .await
+        {
+            return Err(StatusCode::NoSuchFile);
+        }
+
+        if self
+            .server
+            .filesystem
+            .is_denied(
+                &linkpath,
+                false,
+                &self.user_permissions,
+                Permission::FileCreate,
+            )
             .await
         {
             return Err(StatusCode::NoSuchFile);
@@ -832,11 +1091,12 @@ impl russh_sftp::server::Handler for SftpSession {
         self.server
             .activity
             .log_activity(Activity {
-                event: ActivityEvent::SftpCreate,
+                event: ActivityEvent::SftpSymlink,
                 user: self.user_uuid,
                 ip: self.user_ip,
                 metadata: Some(json!({
                     "files": [self.server.filesystem.relative_path(&linkpath)],
+                    "target": self.server.filesystem.relative_path(&targetpath),
                 })),
                 timestamp: chrono::Utc::now(),
             })
@@ -871,23 +1131,44 @@ impl russh_sftp::server::Handler for SftpSession {
         };
 
         if (pflags.contains(OpenFlags::WRITE) || pflags.contains(OpenFlags::APPEND))
-            && !self.has_permission(Permission::FileUpdate)
+            && !self.has_any_permission(Permission::FileUpdate)
         {
             return Err(StatusCode::PermissionDenied);
         }
-        if pflags.contains(OpenFlags::CREATE) && !self.has_permission(Permission::FileCreate) {
+        if pflags.contains(OpenFlags::CREATE) && !self.has_any_permission(Permission::FileCreate) {
             return Err(StatusCode::PermissionDenied);
         }
-        if pflags.contains(OpenFlags::TRUNCATE) && !self.has_permission(Permission::FileDelete) {
+        if pflags.contains(OpenFlags::TRUNCATE) && !self.has_any_permission(Permission::FileDelete)
+        {
             return Err(StatusCode::PermissionDenied);
         }
-        if pflags.contains(OpenFlags::READ) && !self.has_permission(Permission::FileReadContent) {
+        if pflags.contains(OpenFlags::READ) && !self.has_any_permission(Permission::FileReadContent)
+        {
             return Err(StatusCode::PermissionDenied);
         }
 
         let path = match self.server.filesystem.canonicalize(&filename).await {
             Ok(path) => path,
-            Err(_) => PathBuf::from(filename.strip_prefix("/").unwrap_or(&filename)),
+            Err(_) => {
+                // the file does not exist yet, resolve the directory it will be created in
+                // so a symlinked parent cannot lead the grant checks below elsewhere
+                let filename = Path::new(&filename);
+                let (Some(parent), Some(name)) = (filename.parent(), filename.file_name()) else {
+                    return Err(StatusCode::NoSuchFile);
+                };
+
+                let path = match self.server.filesystem.canonicalize(parent).await {
+                    Ok(parent) => parent.join(name),
+                    Err(_) => return Err(StatusCode::NoSuchFile),
+                };
+
+                // a dangling symlink would be followed when the file gets created
+                if self.server.filesystem.symlink_metadata(&path).await.is_ok() {
+                    return Err(StatusCode::PermissionDenied);
+                }
+
+                path
+            }
         };
 
         match self.server.filesystem.metadata(&path).await {
@@ -907,6 +1188,24 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::NoSuchFile);
         }
 
+        for (flag, permission) in [
+            (OpenFlags::WRITE, Permission::FileUpdate),
+            (OpenFlags::APPEND, Permission::FileUpdate),
+            (OpenFlags::CREATE, Permission::FileCreate),
+            (OpenFlags::TRUNCATE, Permission::FileDelete),
+            (OpenFlags::READ, Permission::FileReadContent),
+        ] {
+            if pflags.contains(flag)
+                && !self
+                    .server
+                    .filesystem
+                    .has_path_permission(&path, &self.user_permissions, permission)
+                    .await
+            {
+                return Err(StatusCode::PermissionDenied);
+            }
+        }
+
         let mut activity_event = None;
         if pflags.contains(OpenFlags::TRUNCATE) || pflags.contains(OpenFlags::CREATE) {
             activity_event = Some(ActivityEvent::SftpCreate);
@@ -936,21 +1235,6 @@ impl russh_sftp::server::Handler for SftpSession {
 
         let path_components = self.server.filesystem.path_to_components(&path);
 
-        if let Some(event) = activity_event {
-            self.server
-                .activity
-                .log_activity(Activity {
-                    event,
-                    user: self.user_uuid,
-                    ip: self.user_ip,
-                    metadata: Some(json!({
-                        "files": [self.server.filesystem.relative_path(&path)],
-                    })),
-                    timestamp: chrono::Utc::now(),
-                })
-                .await;
-        }
-
         let handle = self.next_handle_id();
 
         self.handles.insert(
@@ -961,6 +1245,9 @@ impl russh_sftp::server::Handler for SftpSession {
                 file: Arc::new(file.into_std()),
                 consumed: 0,
                 size: metadata.len(),
+
+                write_event: activity_event,
+                written: 0,
             }),
         );
 
@@ -1002,6 +1289,7 @@ impl russh_sftp::server::Handler for SftpSession {
         .map_err(|_| StatusCode::Failure)?;
 
         handle.consumed += buf.len() as u64;
+        self.read_throttle.consume(buf.len()).await;
 
         Ok(Data { id, data: buf })
     }
@@ -1027,6 +1315,7 @@ impl russh_sftp::server::Handler for SftpSession {
         }
 
         data.truncate(448 * 1024);
+        self.write_throttle.consume(data.len()).await;
 
         if !self
             .server
@@ -1040,6 +1329,7 @@ impl russh_sftp::server::Handler for SftpSession {
             return Err(StatusCode::Failure);
         }
 
+        let bytes = data.len() as u64;
         tokio::task::spawn_blocking({
             let file = Arc::clone(&handle.file);
 
@@ -1048,6 +1338,7 @@ impl russh_sftp::server::Handler for SftpSession {
         .await
         .map_err(|_| StatusCode::Failure)?
         .map_err(|_| StatusCode::Failure)?;
+        handle.written += bytes;
 
         Ok(Status {
             id,
@@ -1071,7 +1362,7 @@ impl russh_sftp::server::Handler for SftpSession {
 
         match command.as_str() {
             "check-file" | "check-file-name" => {
-                if !self.has_permission(Permission::FileRead) {
+                if !self.has_any_permission(Permission::FileRead) {
                     return Err(StatusCode::PermissionDenied);
                 }
 
@@ -1113,7 +1404,12 @@ impl russh_sftp::server::Handler for SftpSession {
                     if self
                         .server
                         .filesystem
-                        .is_ignored(&path, metadata.is_dir())
+                        .is_denied(
+                            &path,
+                            metadata.is_dir(),
+                            &self.user_permissions,
+                            Permission::FileReadContent,
+                        )
                         .await
                     {
                         return Err(StatusCode::NoSuchFile);
@@ -1327,8 +1623,8 @@ impl russh_sftp::server::Handler for SftpSession {
                 }
             }
             "copy-file" => {
-                if !self.has_permission(Permission::FileReadContent)
-                    || !self.has_permission(Permission::FileCreate)
+                if !self.has_any_permission(Permission::FileReadContent)
+                    || !self.has_any_permission(Permission::FileCreate)
                 {
                     return Err(StatusCode::PermissionDenied);
                 }
@@ -1359,12 +1655,36 @@ impl russh_sftp::server::Handler for SftpSession {
                     return Err(StatusCode::NoSuchFile);
                 }
 
-                if self.server.filesystem.is_ignored(&source_path, false).await {
+                if self
+                    .server
+                    .filesystem
+                    .is_denied(
+                        &source_path,
+                        false,
+                        &self.user_permissions,
+                        Permission::FileReadContent,
+                    )
+                    .await
+                {
                     return Err(StatusCode::NoSuchFile);
                 }
 
                 let destination_path = Path::new(&request.destination);
 
+                if self
+                    .server
+                    .filesystem
+                    .is_denied(
+                        destination_path,
+                        false,
+                        &self.user_permissions,
+                        Permission::FileCreate,
+                    )
+                    .await
+                {
+                    return Err(StatusCode::NoSuchFile);
+                }
+
                 if let Ok(metadata) = self.server.filesystem.metadata(destination_path).await {
                     if !metadata.is_file() && request.overwrite == 0 {
                         return Err(StatusCode::NoSuchFile);
@@ -1551,6 +1871,494 @@ impl russh_sftp::server::Handler for SftpSession {
                     },
                 ))
             }
+            "posix-rename@openssh.com" => {
+                #[derive(Deserialize)]
+                struct PosixRenameRequest {
+                    old_path: String,
+                    new_path: String,
+                }
+
+                let request: PosixRenameRequest = match russh_sftp::de::from_bytes(&mut data.into())
+                {
+                    Ok(request) => request,
+                    Err(_) => return Err(StatusCode::BadMessage),
+                };
+
+                self.rename_entry(request.old_path, request.new_path, true)
+                    .await?;
+
+                Ok(russh_sftp::protocol::Packet::Status(Status {
+                    id,
+                    status_code: StatusCode::Ok,
+                    error_message: "Ok".to_string(),
+                    language_tag: "en-US".to_string(),
+                }))
+            }
+            "hardlink@openssh.com" => {
+                if self.state.config.system.sftp.read_only {
+                    return Err(StatusCode::PermissionDenied);
+                }
+
+                if !self.has_any_permission(Permission::FileReadContent)
+                    || !self.has_any_permission(Permission::FileUpdate)
+                    || !self.has_any_permission(Permission::FileCreate)
+                {
+                    return Err(StatusCode::PermissionDenied);
+                }
+
+                #[derive(Deserialize)]
+                struct HardlinkRequest {
+                    old_path: String,
+                    new_path: String,
+                }
+
+                let request: HardlinkRequest = match russh_sftp::de::from_bytes(&mut data.into()) {
+                    Ok(request) => request,
+                    Err(_) => return Err(StatusCode::BadMessage),
+                };
+
+                let old_path = match self.server.filesystem.canonicalize(&request.old_path).await {
+                    Ok(path) => path,
+                    Err(_) => return Err(StatusCode::NoSuchFile),
+                };
+                let new_path = Path::new(&request.new_path);
+
+                let metadata = match self.server.filesystem.symlink_metadata(&old_path).await {
+                    Ok(metadata) => metadata,
+                    Err(_) => return Err(StatusCode::NoSuchFile),
+                };
+
+                if !metadata.is_file()
+                    || self
+                        .server
+                        .filesystem
+                        .is_denied(
+                            &old_path,
+                            false,
+                            &self.user_permissions,
+                            Permission::FileReadContent,
+                        )
+                        .await
+                {
+                    return Err(StatusCode::NoSuchFile);
+                }
+
+                // both paths share the same inode, writing through the link
+                // would change the original file
+                if self
+                    .server
+                    .filesystem
+                    .is_denied(
+                        &old_path,
+                        false,
+                        &self.user_permissions,
+                        Permission::FileUpdate,
+                    )
+                    .await
+                {
+                    return Err(StatusCode::PermissionDenied);
+                }
+
+                if self
+                    .server
+                    .filesystem
+                    .symlink_metadata(new_path)
+                    .await
+                    .is_ok()
+                    || self
+                        .server
+                        .filesystem
+                        .is_denied(
+                            new_path,
+                            false,
+                            &self.user_permissions,
+                            Permission::FileCreate,
+                        )
+                        .await
+                {
+                    return Err(StatusCode::Failure);
+                }
+
+                // disk usage is tracked per path, so the link counts as a copy
+                if !self
+                    .server
+                    .filesystem
+                    .allocate_in_path(
+                        new_path.parent().unwrap_or(Path::new("")),
+                        metadata.len() as i64,
+                    )
+                    .await
+                {
+                    return Err(StatusCode::Failure);
+                }
+
+                if self
+                    .server
+                    .filesystem
+                    .hard_link(&old_path, new_path)
+                    .await
+                    .is_err()
+                {
+                    self.server
+                        .filesystem
+                        .allocate_in_path(
+                            new_path.parent().unwrap_or(Path::new("")),
+                            -(metadata.len() as i64),
+                        )
+                        .await;
+
+                    return Err(StatusCode::Failure);
+                }
+
+                self.server
+                    .activity
+                    .log_activity(Activity {
+                        event: ActivityEvent::SftpCreate,
+                        user: self.user_uuid,
+                        ip: self.user_ip,
+                        metadata: Some(json!({
+                            "files": [self.server.filesystem.relative_path(new_path)],
+                        })),
+                        timestamp: chrono::Utc::now(),
+                    })
+                    .await;
+
+                Ok(russh_sftp::protocol::Packet::Status(Status {
+                    id,
+                    status_code: StatusCode::Ok,
+                    error_message: "Ok".to_string(),
+                    language_tag: "en-US".to_string(),
+                }))
+            }
+            "fsync@openssh.com" => {
+                #[derive(Deserialize)]
+                struct FsyncRequest {
+                    handle: String,
+                }
+
+                let request: FsyncRequest = match russh_sftp::de::from_bytes(&mut data.into()) {
+                    Ok(request) => request,
+                    Err(_) => return Err(StatusCode::BadMessage),
+                };
+
+                let file = match self.handles.get(&request.handle) {
+                    Some(ServerHandle::File(handle)) => Arc::clone(&handle.file),
+                    _ => return Err(StatusCode::NoSuchFile),
+                };
+
+                tokio::task::spawn_blocking(move || file.sync_all())
+                    .await
+                    .map_err(|_| StatusCode::Failure)?
+                    .map_err(|_| StatusCode::Failure)?;
+
+                Ok(russh_sftp::protocol::Packet::Status(Status {
+                    id,
+                    status_code: StatusCode::Ok,
+                    error_message: "Ok".to_string(),
+                    language_tag: "en-US".to_string(),
+                }))
+            }
+            "lsetstat@openssh.com" => {
+                #[derive(Deserialize)]
+                struct LsetstatRequest {
+                    path: String,
+                    attrs: FileAttributes,
+                }
+
+                let request: LsetstatRequest = match russh_sftp::de::from_bytes(&mut data.into()) {
+                    Ok(request) => request,
+                    Err(_) => return Err(StatusCode::BadMessage),
+                };
+
+                let metadata = match self
+                    .server
+                    .filesystem
+                    .symlink_metadata(Path::new(&request.path))
+                    .await
+                {
+                    Ok(metadata) => metadata,
+                    Err(_) => return Err(StatusCode::NoSuchFile),
+                };
+
+                if !metadata.is_symlink() {
+                    return self
+                        .setstat(id, request.path, request.attrs)
+                        .await
+                        .map(russh_sftp::protocol::Packet::Status);
+                }
+
+                if self.state.config.system.sftp.read_only
+                    || !self.has_any_permission(Permission::FileUpdate)
+                {
+                    return Err(StatusCode::PermissionDenied);
+                }
+
+                if self
+                    .server
+                    .filesystem
+                    .is_denied(
+                        Path::new(&request.path),
+                        false,
+                        &self.user_permissions,
+                        Permission::FileUpdate,
+                    )
+                    .await
+                {
+                    return Err(StatusCode::NoSuchFile);
+                }
+
+                // linux has no permissions on symlinks themselves, and changing
+                // anything else about the link itself is not supported either
+                let attrs = &request.attrs;
+                if attrs.size.is_some()
+                    || attrs.uid.is_some()
+                    || attrs.gid.is_some()
+                    || attrs.permissions.is_some()
+                    || attrs.atime.is_some()
+                    || attrs.mtime.is_some()
+                {
+                    return Err(StatusCode::OpUnsupported);
+                }
+
+                Ok(russh_sftp::protocol::Packet::Status(Status {
+                    id,
+                    status_code: StatusCode::Ok,
+                    error_message: "Ok".to_string(),
+                    language_tag: "en-US".to_string(),
+                }))
+            }
+            "expand-path@openssh.com" => {
+                #[derive(Deserialize)]
+                struct ExpandPathRequest {
+                    path: String,
+                }
+
+                let request: ExpandPathRequest = match russh_sftp::de::from_bytes(&mut data.into())
+                {
+                    Ok(request) => request,
+                    Err(_) => return Err(StatusCode::BadMessage),
+                };
+
+                // the home directory is the server root
+                let path = match request.path.strip_prefix('~') {
+                    Some(path) if path.is_empty() || path.starts_with('/') => format!("/{path}"),
+                    _ => request.path,
+                };
+
+                self.realpath(id, path)
+                    .await
+                    .map(russh_sftp::protocol::Packet::Name)
+            }
+            "users-groups-by-id@openssh.com" => {
+                #[derive(Deserialize)]
+                struct UsersGroupsByIdRequest {
+                    #[serde(deserialize_with = "russh_sftp::de::data_deserialize")]
+                    uids: Vec<u8>,
+                    #[serde(deserialize_with = "russh_sftp::de::data_deserialize")]
+                    gids: Vec<u8>,
+                }
+
+                let request: UsersGroupsByIdRequest =
+                    match russh_sftp::de::from_bytes(&mut data.into()) {
+                        Ok(request) => request,
+                        Err(_) => return Err(StatusCode::BadMessage),
+                    };
+
+                if !request.uids.len().is_multiple_of(4) || !request.gids.len().is_multiple_of(4) {
+                    return Err(StatusCode::BadMessage);
+                }
+
+                // only the user owning the server files has a known name
+                let names = |ids: &[u8], own_id: u32| {
+                    let mut names = Vec::new();
+
+                    for id in ids.chunks_exact(4) {
+                        let id = u32::from_be_bytes([id[0], id[1], id[2], id[3]]);
+                        let name = if id == own_id {
+                            self.state.config.system.username.as_str()
+                        } else {
+                            ""
+                        };
+
+                        names.extend_from_slice(&(name.len() as u32).to_be_bytes());
+                        names.extend_from_slice(name.as_bytes());
+                    }
+
+                    names
+                };
+
+                #[derive(Serialize)]
+                struct UsersGroupsByIdReply {
+                    #[serde(serialize_with = "russh_sftp::ser::data_serialize")]
+                    usernames: Vec<u8>,
+                    #[serde(serialize_with = "russh_sftp::ser::data_serialize")]
+                    groupnames: Vec<u8>,
+                }
+
+                Ok(russh_sftp::protocol::Packet::ExtendedReply(
+                    russh_sftp::protocol::ExtendedReply {
+                        id,
+                        data: russh_sftp::ser::to_bytes(&UsersGroupsByIdReply {
+                            usernames: names(&request.uids, self.state.config.system.user.uid),
+                            groupnames: names(&request.gids, self.state.config.system.user.gid),
+                        })
+                        .unwrap()
+                        .into(),
+                    },
+                ))
+            }
+            "copy-data" => {
+                if self.state.config.system.sftp.read_only {
+                    return Err(StatusCode::PermissionDenied);
+                }
+
+                #[derive(Deserialize)]
+                struct CopyDataRequest {
+                    read_handle: String,
+                    read_offset: u64,
+                    read_length: u64,
+                    write_handle: String,
+                    write_offset: u64,
+                }
+
+                let request: CopyDataRequest = match russh_sftp::de::from_bytes(&mut data.into()) {
+                    Ok(request) => request,
+                    Err(_) => return Err(StatusCode::BadMessage),
+                };
+
+                let (read_file, read_path) = match self.handles.get(&request.read_handle) {
+                    Some(ServerHandle::File(handle)) => {
+                        (Arc::clone(&handle.file), handle.path.clone())
+                    }
+                    _ => return Err(StatusCode::NoSuchFile),
+                };
+                let (write_file, write_path, write_components) =
+                    match self.handles.get(&request.write_handle) {
+                        Some(ServerHandle::File(handle)) => (
+                            Arc::clone(&handle.file),
+                            handle.path.clone(),
+                            handle.path_components.clone(),
+                        ),
+                        _ => return Err(StatusCode::NoSuchFile),
+                    };
+
+                if !self
+                    .server
+                    .filesystem
+                    .has_path_permission(
+                        &read_path,
+                        &self.user_permissions,
+                        Permission::FileReadContent,
+                    )
+                    .await
+                    || !self
+                        .server
+                        .filesystem
+                        .has_path_permission(
+                            &write_path,
+                            &self.user_permissions,
+                            Permission::FileUpdate,
+                        )
+                        .await
+                {
+                    return Err(StatusCode::PermissionDenied);
+                }
+
+                let read_size = read_file.metadata().map_err(|_| StatusCode::Failure)?.len();
+                let write_size = write_file
+                    .metadata()
+                    .map_err(|_| StatusCode::Failure)?
+                    .len();
+
+                let length = match request.read_length {
+                    0 => read_size.saturating_sub(request.read_offset),
+                    length => length.min(read_size.saturating_sub(request.read_offset)),
+                };
+                let write_end = match request.write_offset.checked_add(length) {
+                    Some(write_end) => write_end,
+                    None => return Err(StatusCode::Failure),
+                };
+
+                // like openssh, refuse copies within a file where the ranges overlap
+                if request.read_handle == request.write_handle
+                    && request.read_offset < write_end
+                    && request.write_offset < request.read_offset + length
+                {
+                    return Err(StatusCode::Failure);
+                }
+
+                let growth = write_end.saturating_sub(write_size);
+                if i64::try_from(growth).is_err() {
+                    return Err(StatusCode::Failure);
+                }
+
+                if !self
+                    .server
+                    .filesystem
+                    .allocate_in_path_raw(
+                        &write_components[0..write_components.len() - 1],
+                        growth as i64,
+                    )
+                    .await
+                {
+                    return Err(StatusCode::Failure);
+                }
+
+                let copied = tokio::task::spawn_blocking({
+                    let write_file = Arc::clone(&write_file);
+
+                    move || {
+                        let mut buffer = vec![0; 256 * 1024];
+                        let mut copied = 0;
+
+                        while copied < length {
+                            let chunk = buffer.len().min((length - copied) as usize);
+                            let bytes_read = read_file
+                                .read_at(&mut buffer[..chunk], request.read_offset + copied)?;
+                            if bytes_read == 0 {
+                                break;
+                            }
+
+                            write_file.write_all_at(
+                                &buffer[..bytes_read],
+                                request.write_offset + copied,
+                            )?;
+                            copied += bytes_read as u64;
+                        }
+
+                        Ok::<_, std::io::Error>(())
+                    }
+                })
+                .await;
+                let copied = matches!(copied, Ok(Ok(())));
+
+                // a failed or short copy grows the file by less than what was allocated
+                let grown = match write_file.metadata() {
+                    Ok(metadata) => metadata.len().saturating_sub(write_size).min(growth),
+                    Err(_) if copied => growth,
+                    Err(_) => 0,
+                };
+                if grown < growth {
+                    self.server
+                        .filesystem
+                        .allocate_in_path_raw(
+                            &write_components[0..write_components.len() - 1],
+                            -((growth - grown) as i64),
+                        )
+                        .await;
+                }
+
+                if !copied {
+                    return Err(StatusCode::Failure);
+                }
+
+                Ok(russh_sftp::protocol::Packet::Status(Status {
+                    id,
+                    status_code: StatusCode::Ok,
+                    error_message: "Ok".to_string(),
+                    language_tag: "en-US".to_string(),
+                }))
+            }
             _ => Err(StatusCode::OpUnsupported),
         }
     }
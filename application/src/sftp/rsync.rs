@@ -0,0 +1,2248 @@
+use crate::{
+    routes::State,
+    server::{
+        activity::{Activity, ActivityEvent},
+        permissions::{Permission, Permissions},
+        throttle::Throttle,
+    },
+};
+use cap_std::fs::PermissionsExt;
+use md4::{Digest, Md4};
+use russh::{Channel, server::Msg};
+use serde_json::json;
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// the newest protocol that still sends whole files without compat flags or incremental recursion
+const PROTOCOL_VERSION: i32 = 29;
+/// the receiver goes through the transfer, redo and delayed update phases
+const MAX_PHASE: u32 = 2;
+const NDX_DONE: i32 = -1;
+
+const MPLEX_BASE: u32 = 7;
+const MSG_DATA: u8 = 0;
+const MSG_INFO: u8 = 2;
+const MSG_ERROR: u8 = 3;
+
+/// data is split into frames and literal tokens of this size, like rsync does
+const CHUNK_SIZE: usize = 32 * 1024;
+const MAX_PATH_LENGTH: usize = 4096;
+/// file lists longer than this are treated as a protocol error
+const MAX_FILES: usize = 1 << 20;
+
+const RERR_SYNTAX: u32 = 1;
+const RERR_PROTOCOL: u32 = 2;
+const RERR_FILESELECT: u32 = 3;
+const RERR_UNSUPPORTED: u32 = 4;
+const RERR_STREAMIO: u32 = 12;
+const RERR_PARTIAL: u32 = 23;
+
+const XMIT_TOP_DIR: u16 = 1 << 0;
+const XMIT_SAME_MODE: u16 = 1 << 1;
+const XMIT_EXTENDED_FLAGS: u16 = 1 << 2;
+const XMIT_SAME_UID: u16 = 1 << 3;
+const XMIT_SAME_GID: u16 = 1 << 4;
+const XMIT_SAME_NAME: u16 = 1 << 5;
+const XMIT_LONG_NAME: u16 = 1 << 6;
+const XMIT_SAME_TIME: u16 = 1 << 7;
+const XMIT_SAME_RDEV_MAJOR: u16 = 1 << 8;
+const XMIT_RDEV_MINOR_8: u16 = 1 << 11;
+
+const ITEM_REPORT_SIZE: u16 = 1 << 2;
+const ITEM_REPORT_TIME: u16 = 1 << 3;
+const ITEM_BASIS_TYPE_FOLLOWS: u16 = 1 << 11;
+const ITEM_XNAME_FOLLOWS: u16 = 1 << 12;
+const ITEM_IS_NEW: u16 = 1 << 13;
+const ITEM_TRANSFER: u16 = 1 << 15;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+#[derive(Default)]
+struct RsyncOptions {
+    sender: bool,
+    recursive: bool,
+    dirs: bool,
+    links: bool,
+    preserve_uid: bool,
+    preserve_gid: bool,
+    preserve_devices: bool,
+    preserve_specials: bool,
+    preserve_times: bool,
+    preserve_perms: bool,
+
+    ignore_times: bool,
+    size_only: bool,
+    update: bool,
+    ignore_existing: bool,
+    existing: bool,
+    numeric_ids: bool,
+    modify_window: i64,
+    checksum_seed: Option<i32>,
+
+    paths: Vec<String>,
+}
+
+impl RsyncOptions {
+    /// Parses the arguments of `rsync --server` as sent by a rsync client,
+    /// anything that changes the wire format beyond whole-file transfers is refused
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut server = false;
+        let mut positional = Vec::new();
+
+        for arg in args {
+            if !positional.is_empty() {
+                positional.push(arg.clone());
+            } else if let Some(arg) = arg.strip_prefix("--") {
+                let (name, value) = match arg.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (arg, None),
+                };
+
+                match (name, value) {
+                    ("server", None) => server = true,
+                    ("sender", None) => options.sender = true,
+                    ("numeric-ids", None) => options.numeric_ids = true,
+                    ("size-only", None) => options.size_only = true,
+                    ("ignore-existing", None) => options.ignore_existing = true,
+                    ("existing" | "ignore-non-existing", None) => options.existing = true,
+                    ("devices", None) => options.preserve_devices = true,
+                    ("specials", None) => options.preserve_specials = true,
+                    ("checksum-seed", Some(value)) => {
+                        options.checksum_seed = Some(
+                            value
+                                .parse()
+                                .map_err(|_| format!("invalid checksum seed '{value}'"))?,
+                        );
+                    }
+                    ("modify-window", Some(value)) => {
+                        options.modify_window = value
+                            .parse()
+                            .map_err(|_| format!("invalid modify window '{value}'"))?;
+                    }
+                    ("timeout" | "log-format" | "out-format", Some(_))
+                    | (
+                        "partial" | "inplace" | "no-i-r" | "no-inc-recursive" | "ignore-errors"
+                        | "force" | "safe-links",
+                        None,
+                    ) => {}
+                    _ => return Err(format!("option --{name} is not supported by this server")),
+                }
+            } else if let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) {
+                for flag in flags.chars() {
+                    match flag {
+                        // the rest of the cluster describes the client, not the transfer
+                        'e' => break,
+                        'r' => options.recursive = true,
+                        'd' => options.dirs = true,
+                        'l' => options.links = true,
+                        'o' => options.preserve_uid = true,
+                        'g' => options.preserve_gid = true,
+                        'D' => {
+                            options.preserve_devices = true;
+                            options.preserve_specials = true;
+                        }
+                        't' => options.preserve_times = true,
+                        'p' => options.preserve_perms = true,
+                        'I' => options.ignore_times = true,
+                        'u' => options.update = true,
+                        'v' | 'q' | 'i' | 'x' | 'S' | 'W' | 'E' | 'O' | 'J' | 'k' | 'K' => {}
+                        _ => return Err(format!("option -{flag} is not supported by this server")),
+                    }
+                }
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+
+        if !server {
+            return Err("only `rsync --server` is supported".to_string());
+        }
+
+        // the first argument is the directory rsync would change into, which is always the root here
+        if positional.len() < 2 && options.sender {
+            return Err("no source files given".to_string());
+        }
+        if positional.len() > 2 && !options.sender {
+            return Err("ambiguous target".to_string());
+        }
+        options.paths = positional.into_iter().skip(1).collect();
+
+        Ok(options)
+    }
+}
+
+struct RsyncError {
+    message: String,
+    exit_code: u32,
+}
+
+impl RsyncError {
+    fn new(message: impl Into<String>, exit_code: u32) -> Self {
+        Self {
+            message: message.into(),
+            exit_code,
+        }
+    }
+
+    #[inline]
+    fn protocol(message: &str) -> Self {
+        Self::new(format!("protocol error: {message}"), RERR_PROTOCOL)
+    }
+}
+
+impl From<std::io::Error> for RsyncError {
+    fn from(err: std::io::Error) -> Self {
+        Self::new(format!("connection error: {err}"), RERR_STREAMIO)
+    }
+}
+
+/// MD4 seeded with the checksum seed, which protocol 29 still uses for the whole-file checksum
+fn seeded_md4(seed: i32) -> Md4 {
+    let mut md4 = Md4::new();
+    md4.update(seed.to_le_bytes());
+
+    md4
+}
+
+#[derive(Clone, Default)]
+struct FileEntry {
+    name: String,
+    size: i64,
+    mtime: i32,
+    mode: u32,
+    uid: i32,
+    gid: i32,
+    link: Option<String>,
+    top_dir: bool,
+}
+
+impl FileEntry {
+    #[inline]
+    fn file_type(&self) -> u32 {
+        self.mode & S_IFMT
+    }
+
+    #[inline]
+    fn is_dir(&self) -> bool {
+        self.file_type() == S_IFDIR
+    }
+
+    /// The order rsync sorts its file list in, files come before directories on every level.
+    /// Both sides sort the list themselves, indices on the wire refer to this order
+    fn sort_key(&self) -> Vec<(bool, String)> {
+        if self.name == "." {
+            return Vec::new();
+        }
+
+        let components = self.name.split('/').collect::<Vec<_>>();
+        components
+            .iter()
+            .enumerate()
+            .map(|(index, component)| {
+                if index + 1 < components.len() || self.is_dir() {
+                    (true, format!("{component}/"))
+                } else {
+                    (false, component.to_string())
+                }
+            })
+            .collect()
+    }
+
+    /// Rejects names that could escape the directory being written to
+    fn is_safe_name(name: &str) -> bool {
+        name == "."
+            || name
+                .split('/')
+                .all(|component| !component.is_empty() && component != "." && component != "..")
+    }
+}
+
+/// A `- pattern` or `+ pattern` rule of the filter list sent by a receiving client
+struct FilterRule {
+    include: bool,
+    directory: bool,
+    basename: bool,
+    matcher: globset::GlobMatcher,
+}
+
+impl FilterRule {
+    fn parse(rule: &str) -> Option<Self> {
+        let (include, pattern) = match rule.split_at_checked(2)? {
+            ("- ", pattern) => (false, pattern),
+            ("+ ", pattern) => (true, pattern),
+            _ => return None,
+        };
+        let (directory, pattern) = match pattern.strip_suffix('/') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+
+        // like rsync, patterns without a slash only match the last component of a name
+        let (glob, basename) = match pattern.strip_prefix('/') {
+            Some(pattern) => (pattern.to_string(), false),
+            None if pattern.contains('/') || pattern.contains("**") => {
+                (format!("**/{pattern}"), false)
+            }
+            None => (pattern.to_string(), true),
+        };
+
+        Some(Self {
+            include,
+            directory,
+            basename,
+            matcher: globset::GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .backslash_escape(true)
+                .build()
+                .ok()?
+                .compile_matcher(),
+        })
+    }
+
+    fn matches(&self, name: &str, is_dir: bool) -> bool {
+        if self.directory && !is_dir {
+            return false;
+        }
+
+        if self.basename {
+            self.matcher
+                .is_match(name.rsplit('/').next().unwrap_or(name))
+        } else {
+            self.matcher.is_match(name)
+        }
+    }
+
+    #[inline]
+    fn is_excluded(rules: &[Self], name: &str, is_dir: bool) -> bool {
+        rules
+            .iter()
+            .find(|rule| rule.matches(name, is_dir))
+            .is_some_and(|rule| !rule.include)
+    }
+}
+
+type Reader<'a> = BufReader<Pin<Box<dyn AsyncRead + Send + 'a>>>;
+type Writer = Pin<Box<dyn AsyncWrite + Send>>;
+
+/// What the client sends, which is never multiplexed in protocol 29
+struct Input<'a> {
+    reader: Reader<'a>,
+    read: u64,
+}
+
+impl Input<'_> {
+    async fn bytes(&mut self, buffer: &mut [u8]) -> Result<(), RsyncError> {
+        self.reader.read_exact(buffer).await?;
+        self.read += buffer.len() as u64;
+
+        Ok(())
+    }
+
+    async fn byte(&mut self) -> Result<u8, RsyncError> {
+        let mut buffer = [0; 1];
+        self.bytes(&mut buffer).await?;
+
+        Ok(buffer[0])
+    }
+
+    async fn short(&mut self) -> Result<u16, RsyncError> {
+        let mut buffer = [0; 2];
+        self.bytes(&mut buffer).await?;
+
+        Ok(u16::from_le_bytes(buffer))
+    }
+
+    async fn int(&mut self) -> Result<i32, RsyncError> {
+        let mut buffer = [0; 4];
+        self.bytes(&mut buffer).await?;
+
+        Ok(i32::from_le_bytes(buffer))
+    }
+
+    async fn longint(&mut self) -> Result<i64, RsyncError> {
+        let value = self.int().await?;
+        if value != -1 {
+            return Ok(value as i64);
+        }
+
+        let mut buffer = [0; 8];
+        self.bytes(&mut buffer).await?;
+
+        Ok(i64::from_le_bytes(buffer))
+    }
+
+    async fn string(&mut self, length: usize) -> Result<Vec<u8>, RsyncError> {
+        if length > MAX_PATH_LENGTH {
+            return Err(RsyncError::protocol("name too long"));
+        }
+
+        let mut buffer = vec![0; length];
+        self.bytes(&mut buffer).await?;
+
+        Ok(buffer)
+    }
+
+    async fn vstring(&mut self) -> Result<Vec<u8>, RsyncError> {
+        let mut length = self.byte().await? as usize;
+        if length & 0x80 != 0 {
+            length = (length & 0x7f) * 256 + self.byte().await? as usize;
+        }
+
+        self.string(length).await
+    }
+
+    /// Reads the block checksum header of a transfer request
+    async fn sum_head(&mut self) -> Result<[i32; 4], RsyncError> {
+        let mut head = [0; 4];
+        for value in head.iter_mut() {
+            *value = self.int().await?;
+        }
+
+        let [count, block_length, checksum_length, remainder] = head;
+        if count < 0
+            || block_length < 0
+            || !(0..=16).contains(&checksum_length)
+            || !(0..=block_length).contains(&remainder)
+        {
+            return Err(RsyncError::protocol("invalid checksum header"));
+        }
+
+        Ok(head)
+    }
+
+    /// Reads the length of the next literal data token, 0 ends the file.
+    /// There is no basis file, so the client can not refer to blocks of it
+    async fn literal_length(&mut self) -> Result<usize, RsyncError> {
+        usize::try_from(self.int().await?)
+            .map_err(|_| RsyncError::protocol("unexpected block reference"))
+    }
+
+    async fn skip(&mut self, length: u64) -> Result<(), RsyncError> {
+        let skipped =
+            tokio::io::copy(&mut (&mut self.reader).take(length), &mut tokio::io::sink()).await?;
+        self.read += skipped;
+
+        if skipped < length {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(())
+    }
+}
+
+/// What is sent to the client, multiplexed once the protocol is set up
+struct Output {
+    writer: Writer,
+    stderr: Writer,
+    multiplexed: bool,
+
+    buffer: Vec<u8>,
+    written: u64,
+    errors: usize,
+}
+
+impl Output {
+    #[inline]
+    fn byte(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    #[inline]
+    fn short(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[inline]
+    fn int(&mut self, value: i32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn longint(&mut self, value: i64) {
+        match i32::try_from(value) {
+            Ok(value) if value >= 0 => self.int(value),
+            _ => {
+                self.int(-1);
+                self.buffer.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    #[inline]
+    fn bytes(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    fn vstring(&mut self, data: &[u8]) {
+        if data.len() > 0x7f {
+            self.byte((data.len() / 256) as u8 | 0x80);
+        }
+        self.byte(data.len() as u8);
+        self.bytes(data);
+    }
+
+    /// Writes data before multiplexing starts
+    async fn raw(&mut self, data: &[u8]) -> Result<(), RsyncError> {
+        self.writer.write_all(data).await?;
+        self.writer.flush().await?;
+        self.written += data.len() as u64;
+
+        Ok(())
+    }
+
+    async fn frame(&mut self, code: u8, data: &[u8]) -> Result<(), RsyncError> {
+        for chunk in data.chunks(CHUNK_SIZE) {
+            let header = ((MPLEX_BASE + code as u32) << 24) | chunk.len() as u32;
+
+            self.writer.write_all(&header.to_le_bytes()).await?;
+            self.writer.write_all(chunk).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), RsyncError> {
+        if !self.buffer.is_empty() {
+            let mut buffer = std::mem::take(&mut self.buffer);
+            self.frame(MSG_DATA, &buffer).await?;
+            self.written += buffer.len() as u64;
+
+            buffer.clear();
+            self.buffer = buffer;
+        }
+
+        self.writer.flush().await?;
+
+        Ok(())
+    }
+
+    #[inline]
+    async fn maybe_flush(&mut self) -> Result<(), RsyncError> {
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Shows a message on the client, on stderr if the protocol has not started yet
+    async fn message(&mut self, code: u8, message: &str) -> Result<(), RsyncError> {
+        if !self.multiplexed {
+            self.stderr.write_all(message.as_bytes()).await?;
+            self.stderr.flush().await?;
+
+            return Ok(());
+        }
+
+        self.flush().await?;
+        self.frame(code, message.as_bytes()).await?;
+        self.writer.flush().await?;
+
+        Ok(())
+    }
+
+    #[inline]
+    async fn info(&mut self, message: &str) -> Result<(), RsyncError> {
+        self.message(MSG_INFO, &format!("{message}\n")).await
+    }
+
+    /// Reports a file that could not be transferred, the transfer goes on
+    #[inline]
+    async fn error(&mut self, message: &str) -> Result<(), RsyncError> {
+        self.errors += 1;
+        self.message(MSG_ERROR, &format!("rsync: {message}\n"))
+            .await
+    }
+}
+
+/// A file on the server that is part of a download
+struct SourceFile {
+    entry: FileEntry,
+    path: PathBuf,
+}
+
+/// A restricted `rsync --server`, spoken over an `exec` request. It speaks protocol 29
+/// and always transfers whole files, options that need more of the protocol are refused
+pub struct RsyncSession {
+    pub state: State,
+    pub server: crate::server::Server,
+
+    pub user_ip: Option<IpAddr>,
+    pub user_uuid: Option<uuid::Uuid>,
+    pub user_permissions: Permissions,
+
+    pub throttle: Throttle,
+}
+
+impl RsyncSession {
+    pub async fn run(self, channel: Channel<Msg>, args: Vec<String>) {
+        let (mut read_half, write_half) = channel.split();
+        let mut input = Input {
+            reader: BufReader::new(Box::pin(read_half.make_reader())),
+            read: 0,
+        };
+        let mut output = Output {
+            writer: Box::pin(write_half.make_writer()),
+            stderr: Box::pin(write_half.make_writer_ext(Some(1))),
+            multiplexed: false,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            written: 0,
+            errors: 0,
+        };
+
+        let exit_status = match self.serve(&mut input, &mut output, &args).await {
+            Ok(()) if output.errors > 0 => RERR_PARTIAL,
+            Ok(()) => 0,
+            Err(err) => {
+                output.error(&err.message).await.ok();
+
+                err.exit_code
+            }
+        };
+
+        output.flush().await.ok();
+        drop(output);
+        drop(input);
+
+        write_half.exit_status(exit_status).await.ok();
+        write_half.eof().await.ok();
+        write_half.close().await.ok();
+    }
+
+    #[inline]
+    fn allow_action(&self) -> bool {
+        !self.server.is_locked_state()
+    }
+
+    async fn serve(
+        &self,
+        input: &mut Input<'_>,
+        output: &mut Output,
+        args: &[String],
+    ) -> Result<(), RsyncError> {
+        let options =
+            RsyncOptions::parse(args).map_err(|message| RsyncError::new(message, RERR_SYNTAX))?;
+
+        if !self.allow_action() || (!options.sender && self.state.config.system.sftp.read_only) {
+            return Err(RsyncError::new("permission denied", RERR_FILESELECT));
+        }
+
+        output.raw(&PROTOCOL_VERSION.to_le_bytes()).await?;
+        let remote_version = input.int().await?;
+        if remote_version < PROTOCOL_VERSION {
+            return Err(RsyncError::new(
+                format!(
+                    "protocol version {remote_version} is not supported, at least {PROTOCOL_VERSION} is required"
+                ),
+                RERR_PROTOCOL,
+            ));
+        }
+
+        let seed = options
+            .checksum_seed
+            .filter(|seed| *seed != 0)
+            .unwrap_or_else(|| chrono::Utc::now().timestamp() as i32);
+        output.raw(&seed.to_le_bytes()).await?;
+        output.multiplexed = true;
+
+        if options.sender {
+            self.send(input, output, &options, seed).await
+        } else {
+            self.receive(input, output, &options, seed).await
+        }
+    }
+
+    #[inline]
+    fn modified(metadata: &cap_std::fs::Metadata) -> i64 {
+        metadata
+            .modified()
+            .map(|t| {
+                t.into_std()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+            })
+            .unwrap_or_default()
+            .as_secs() as i64
+    }
+
+    async fn log_activity(&self, event: ActivityEvent, path: &Path, bytes: Option<u64>) {
+        let mut metadata = json!({
+            "files": [self.server.filesystem.relative_path(path)],
+        });
+        if let Some(bytes) = bytes {
+            metadata["bytes"] = json!(bytes);
+        }
+
+        self.server
+            .activity
+            .log_activity(Activity {
+                event,
+                user: self.user_uuid,
+                ip: self.user_ip,
+                metadata: Some(metadata),
+                timestamp: chrono::Utc::now(),
+            })
+            .await;
+    }
+
+    async fn receive(
+        &self,
+        input: &mut Input<'_>,
+        output: &mut Output,
+        options: &RsyncOptions,
+        seed: i32,
+    ) -> Result<(), RsyncError> {
+        let mut files = Self::receive_file_list(input, options).await?;
+        files.sort_by_cached_key(FileEntry::sort_key);
+
+        let targets = self.resolve_targets(&files, options).await?;
+        let requested = files
+            .iter()
+            .map(|_| AtomicBool::new(false))
+            .collect::<Vec<_>>();
+
+        let output = tokio::sync::Mutex::new(output);
+        tokio::try_join!(
+            self.generate(&files, &targets, &requested, &output, options),
+            self.receive_files(input, &files, &targets, &requested, &output, options, seed),
+        )?;
+
+        let output = output.into_inner();
+        output.int(NDX_DONE);
+        output.flush().await
+    }
+
+    async fn receive_file_list(
+        input: &mut Input<'_>,
+        options: &RsyncOptions,
+    ) -> Result<Vec<FileEntry>, RsyncError> {
+        let mut files = Vec::new();
+        let mut last_name = Vec::new();
+        let mut last = FileEntry::default();
+
+        loop {
+            let mut flags = input.byte().await? as u16;
+            if flags == 0 {
+                break;
+            }
+            if flags & XMIT_EXTENDED_FLAGS != 0 {
+                flags |= (input.byte().await? as u16) << 8;
+            }
+
+            if files.len() >= MAX_FILES {
+                return Err(RsyncError::protocol("file list too long"));
+            }
+
+            let prefix = if flags & XMIT_SAME_NAME != 0 {
+                input.byte().await? as usize
+            } else {
+                0
+            };
+            let suffix = if flags & XMIT_LONG_NAME != 0 {
+                usize::try_from(input.int().await?)
+                    .map_err(|_| RsyncError::protocol("invalid name length"))?
+            } else {
+                input.byte().await? as usize
+            };
+            if prefix > last_name.len() {
+                return Err(RsyncError::protocol("invalid name"));
+            }
+
+            last_name.truncate(prefix);
+            last_name.extend(input.string(suffix).await?);
+
+            let mut entry = FileEntry {
+                name: String::from_utf8(last_name.clone())
+                    .ok()
+                    .filter(|name| FileEntry::is_safe_name(name))
+                    .ok_or_else(|| {
+                        RsyncError::new(
+                            format!(
+                                "refusing invalid file name '{}'",
+                                String::from_utf8_lossy(&last_name)
+                            ),
+                            RERR_PROTOCOL,
+                        )
+                    })?,
+                size: input.longint().await?,
+                top_dir: flags & XMIT_TOP_DIR != 0,
+                ..last.clone()
+            };
+            if entry.size < 0 {
+                return Err(RsyncError::protocol("invalid file size"));
+            }
+
+            if flags & XMIT_SAME_TIME == 0 {
+                entry.mtime = input.int().await?;
+            }
+            if flags & XMIT_SAME_MODE == 0 {
+                entry.mode = input.int().await? as u32;
+            }
+            if options.preserve_uid && flags & XMIT_SAME_UID == 0 {
+                entry.uid = input.int().await?;
+            }
+            if options.preserve_gid && flags & XMIT_SAME_GID == 0 {
+                entry.gid = input.int().await?;
+            }
+
+            let file_type = entry.file_type();
+            if (options.preserve_devices && matches!(file_type, S_IFCHR | S_IFBLK))
+                || (options.preserve_specials && matches!(file_type, S_IFIFO | S_IFSOCK))
+            {
+                // devices are never created, their numbers are only read past
+                if flags & XMIT_SAME_RDEV_MAJOR == 0 {
+                    input.int().await?;
+                }
+                if flags & XMIT_RDEV_MINOR_8 != 0 {
+                    input.byte().await?;
+                } else {
+                    input.int().await?;
+                }
+            }
+
+            entry.link = None;
+            if options.links && file_type == S_IFLNK {
+                let length = usize::try_from(input.int().await?)
+                    .map_err(|_| RsyncError::protocol("invalid link length"))?;
+                entry.link =
+                    Some(String::from_utf8_lossy(&input.string(length).await?).to_string());
+            }
+
+            last = entry.clone();
+            files.push(entry);
+        }
+
+        if !options.numeric_ids {
+            for preserved in [options.preserve_uid, options.preserve_gid] {
+                if !preserved {
+                    continue;
+                }
+
+                // owners are not kept, the names they map to do not matter
+                while input.int().await? != 0 {
+                    let length = input.byte().await?;
+                    input.skip(length as u64).await?;
+                }
+            }
+        }
+
+        // the io error flag of the client, its own errors are reported by itself
+        input.int().await?;
+
+        Ok(files)
+    }
+
+    /// Works out where each entry goes, the same way rsync treats its destination argument
+    async fn resolve_targets(
+        &self,
+        files: &[FileEntry],
+        options: &RsyncOptions,
+    ) -> Result<Vec<PathBuf>, RsyncError> {
+        let destination = options.paths.first().map(|p| p.as_str()).unwrap_or(".");
+        let target = match self.server.filesystem.canonicalize(destination).await {
+            Ok(path) => path,
+            Err(_) => PathBuf::from(destination.trim_start_matches('/')),
+        };
+
+        // a single entry is written to the target itself, unless it is an existing directory
+        let base = match self.server.filesystem.metadata(&target).await {
+            Ok(metadata) if metadata.is_dir() => target,
+            Ok(_) if files.len() == 1 && !files[0].is_dir() => return Ok(vec![target]),
+            Ok(_) => {
+                return Err(RsyncError::new(
+                    format!("{}: destination must be a directory", target.display()),
+                    RERR_FILESELECT,
+                ));
+            }
+            Err(_) if files.len() <= 1 && !destination.ends_with('/') => {
+                return Ok(vec![target; files.len()]);
+            }
+            Err(_) => {
+                if self
+                    .server
+                    .filesystem
+                    .is_denied(
+                        &target,
+                        true,
+                        &self.user_permissions,
+                        Permission::FileCreate,
+                    )
+                    .await
+                    || self.server.filesystem.create_dir(&target).await.is_err()
+                {
+                    return Err(RsyncError::new(
+                        format!("mkdir \"{}\" failed", target.display()),
+                        RERR_FILESELECT,
+                    ));
+                }
+
+                self.server.filesystem.chown_path(&target).await;
+                self.log_activity(ActivityEvent::SftpCreateDirectory, &target, None)
+                    .await;
+
+                target
+            }
+        };
+
+        Ok(files
+            .iter()
+            .map(|entry| match entry.name.as_str() {
+                "." => base.clone(),
+                name => base.join(name),
+            })
+            .collect())
+    }
+
+    /// Asks the client for every file that is missing or out of date
+    async fn generate(
+        &self,
+        files: &[FileEntry],
+        targets: &[PathBuf],
+        requested: &[AtomicBool],
+        output: &tokio::sync::Mutex<&mut Output>,
+        options: &RsyncOptions,
+    ) -> Result<(), RsyncError> {
+        for (index, (entry, target)) in files.iter().zip(targets).enumerate() {
+            if index > 0 && files[index - 1].name == entry.name {
+                continue;
+            }
+
+            match entry.file_type() {
+                S_IFDIR => {
+                    if let Err(message) = self.create_directory(target, entry, options).await {
+                        output.lock().await.error(&message).await?;
+                    }
+                }
+                S_IFREG => match self.check_file(target, entry, options).await {
+                    Ok(Some(flags)) => {
+                        requested[index].store(true, Ordering::Relaxed);
+
+                        let mut output = output.lock().await;
+                        output.int(index as i32);
+                        output.short(flags);
+                        // no block checksums, the client sends the whole file
+                        for _ in 0..4 {
+                            output.int(0);
+                        }
+                        output.maybe_flush().await?;
+                    }
+                    Ok(None) => {}
+                    Err(message) => output.lock().await.error(&message).await?,
+                },
+                _ => {
+                    output
+                        .lock()
+                        .await
+                        .info(&format!("skipping non-regular file \"{}\"", entry.name))
+                        .await?
+                }
+            }
+        }
+
+        let mut output = output.lock().await;
+        output.int(NDX_DONE);
+        output.flush().await
+    }
+
+    async fn create_directory(
+        &self,
+        path: &Path,
+        entry: &FileEntry,
+        options: &RsyncOptions,
+    ) -> Result<(), String> {
+        if let Ok(metadata) = self.server.filesystem.symlink_metadata(path).await {
+            if metadata.is_dir() {
+                return Ok(());
+            }
+
+            return Err(format!("{}: not a directory", path.display()));
+        }
+
+        if self
+            .server
+            .filesystem
+            .is_denied(path, true, &self.user_permissions, Permission::FileCreate)
+            .await
+        {
+            return Err(format!("{}: permission denied", path.display()));
+        }
+
+        if self.server.filesystem.create_dir(path).await.is_err() {
+            return Err(format!("mkdir \"{}\" failed", path.display()));
+        }
+
+        if options.preserve_perms {
+            self.server
+                .filesystem
+                .set_permissions(
+                    path,
+                    cap_std::fs::Permissions::from_mode(entry.mode & 0o777),
+                )
+                .await
+                .ok();
+        }
+        self.server.filesystem.chown_path(path).await;
+
+        self.log_activity(ActivityEvent::SftpCreateDirectory, path, None)
+            .await;
+
+        Ok(())
+    }
+
+    /// The item flags to request a file with, or `None` if it is already up to date
+    async fn check_file(
+        &self,
+        path: &Path,
+        entry: &FileEntry,
+        options: &RsyncOptions,
+    ) -> Result<Option<u16>, String> {
+        let metadata = self.server.filesystem.symlink_metadata(path).await.ok();
+        if metadata.as_ref().is_some_and(|m| !m.is_file()) {
+            return Err(format!("{}: not a regular file", path.display()));
+        }
+
+        if (options.ignore_existing && metadata.is_some())
+            || (options.existing && metadata.is_none())
+        {
+            return Ok(None);
+        }
+
+        let permission = if metadata.is_some() {
+            Permission::FileUpdate
+        } else {
+            Permission::FileCreate
+        };
+        if self
+            .server
+            .filesystem
+            .is_denied(path, false, &self.user_permissions, permission)
+            .await
+        {
+            return Err(format!("{}: permission denied", path.display()));
+        }
+
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None => return Ok(Some(ITEM_TRANSFER | ITEM_IS_NEW)),
+        };
+
+        let difference = Self::modified(&metadata) - entry.mtime as i64;
+        if options.update && difference > options.modify_window {
+            return Ok(None);
+        }
+
+        let same_size = metadata.len() == entry.size as u64;
+        let same_time = difference.abs() <= options.modify_window;
+        if !options.ignore_times && same_size && (options.size_only || same_time) {
+            return Ok(None);
+        }
+
+        let mut flags = ITEM_TRANSFER;
+        if !same_size {
+            flags |= ITEM_REPORT_SIZE;
+        }
+        if !same_time && options.preserve_times {
+            flags |= ITEM_REPORT_TIME;
+        }
+
+        Ok(Some(flags))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn receive_files(
+        &self,
+        input: &mut Input<'_>,
+        files: &[FileEntry],
+        targets: &[PathBuf],
+        requested: &[AtomicBool],
+        output: &tokio::sync::Mutex<&mut Output>,
+        options: &RsyncOptions,
+        seed: i32,
+    ) -> Result<(), RsyncError> {
+        let mut phase = 0;
+
+        loop {
+            let index = input.int().await?;
+            if index == NDX_DONE {
+                phase += 1;
+                if phase > MAX_PHASE {
+                    break;
+                }
+
+                // nothing is ever redone or delayed, both phases end right away
+                if phase == 1 {
+                    let mut output = output.lock().await;
+                    output.int(NDX_DONE);
+                    output.int(NDX_DONE);
+                    output.flush().await?;
+                }
+
+                continue;
+            }
+
+            // every requested file is accepted once
+            let index = usize::try_from(index)
+                .ok()
+                .filter(|index| {
+                    requested
+                        .get(*index)
+                        .is_some_and(|requested| requested.swap(false, Ordering::Relaxed))
+                })
+                .ok_or_else(|| RsyncError::protocol("invalid file index"))?;
+
+            let flags = input.short().await?;
+            if flags & ITEM_BASIS_TYPE_FOLLOWS != 0 {
+                input.byte().await?;
+            }
+            if flags & ITEM_XNAME_FOLLOWS != 0 {
+                input.vstring().await?;
+            }
+            if flags & ITEM_TRANSFER == 0 {
+                continue;
+            }
+
+            input.sum_head().await?;
+            self.receive_file(input, &files[index], &targets[index], output, options, seed)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Receives a file next to its target and renames it into place once it is complete,
+    /// a failed transfer leaves the target as it was
+    async fn receive_file(
+        &self,
+        input: &mut Input<'_>,
+        entry: &FileEntry,
+        path: &Path,
+        output: &tokio::sync::Mutex<&mut Output>,
+        options: &RsyncOptions,
+        seed: i32,
+    ) -> Result<(), RsyncError> {
+        let parent = path.parent().unwrap_or(Path::new(""));
+        let temporary = parent.join(format!(
+            ".{}.{:08x}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            rand::random::<u32>()
+        ));
+
+        let mut failure = None;
+        let mut file = match self.server.filesystem.create(&temporary).await {
+            Ok(file) => Some(file),
+            Err(_) => {
+                failure = Some(format!("{}: failed to create file", path.display()));
+                None
+            }
+        };
+        let mut allocated = 0;
+
+        let received = self
+            .receive_data(input, parent, &mut file, &mut allocated, &mut failure, seed)
+            .await;
+
+        let mut leftover = file.is_some();
+        if received.is_ok() && failure.is_none() {
+            if let Some(file) = file.take() {
+                match self
+                    .finish_file(file, &temporary, path, entry, options)
+                    .await
+                {
+                    Ok(()) => leftover = false,
+                    Err(message) => failure = Some(message),
+                }
+            }
+        }
+        drop(file);
+
+        if leftover {
+            self.server
+                .filesystem
+                .allocate_in_path(parent, -allocated)
+                .await;
+            if let Ok(filesystem) = self.server.filesystem.base_dir().await {
+                let temporary = self.server.filesystem.relative_path(&temporary);
+                tokio::task::spawn_blocking(move || filesystem.remove_file(temporary))
+                    .await
+                    .ok();
+            }
+        }
+
+        received?;
+        match failure {
+            Some(message) => output.lock().await.error(&message).await,
+            None => {
+                self.log_activity(ActivityEvent::SftpCreate, path, None)
+                    .await;
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads the literal data and checksum of a file, writing it while nothing has failed.
+    /// Data is always read in full so the stream stays in sync
+    async fn receive_data(
+        &self,
+        input: &mut Input<'_>,
+        parent: &Path,
+        file: &mut Option<tokio::fs::File>,
+        allocated: &mut i64,
+        failure: &mut Option<String>,
+        seed: i32,
+    ) -> Result<(), RsyncError> {
+        let throttle = self.throttle.reversed();
+        let mut md4 = seeded_md4(seed);
+        let mut buffer = vec![0; CHUNK_SIZE];
+
+        loop {
+            let mut remaining = input.literal_length().await?;
+            if remaining == 0 {
+                break;
+            }
+
+            while remaining > 0 {
+                let chunk = &mut buffer[..remaining.min(CHUNK_SIZE)];
+                input.bytes(chunk).await?;
+                remaining -= chunk.len();
+
+                throttle.consume(chunk.len()).await;
+                md4.update(&chunk[..]);
+
+                if let (Some(writer), None) = (file.as_mut(), failure.as_ref()) {
+                    if !self
+                        .server
+                        .filesystem
+                        .allocate_in_path(parent, chunk.len() as i64)
+                        .await
+                    {
+                        *failure = Some("not enough disk space".to_string());
+                    } else {
+                        *allocated += chunk.len() as i64;
+
+                        if let Err(err) = writer.write_all(chunk).await {
+                            *failure = Some(err.to_string());
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut checksum = [0; 16];
+        input.bytes(&mut checksum).await?;
+
+        if failure.is_none() && checksum[..] != md4.finalize()[..] {
+            *failure = Some("checksum mismatch".to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn finish_file(
+        &self,
+        mut file: tokio::fs::File,
+        temporary: &Path,
+        path: &Path,
+        entry: &FileEntry,
+        options: &RsyncOptions,
+    ) -> Result<(), String> {
+        if let Err(err) = file.flush().await {
+            return Err(format!("{}: {err}", path.display()));
+        }
+
+        let file = file.into_std().await;
+        if let (true, Ok(mtime)) = (options.preserve_times, u64::try_from(entry.mtime)) {
+            file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime))
+                .ok();
+        }
+        drop(file);
+
+        let replaced = self
+            .server
+            .filesystem
+            .symlink_metadata(path)
+            .await
+            .ok()
+            .filter(|m| m.is_file());
+
+        let permissions = if options.preserve_perms {
+            Some(cap_std::fs::Permissions::from_mode(entry.mode & 0o777))
+        } else {
+            replaced.as_ref().map(|m| m.permissions())
+        };
+        if let Some(permissions) = permissions {
+            self.server
+                .filesystem
+                .set_permissions(temporary, permissions)
+                .await
+                .ok();
+        }
+
+        if self
+            .server
+            .filesystem
+            .rename_path(temporary, path)
+            .await
+            .is_err()
+        {
+            return Err(format!("{}: failed to replace file", path.display()));
+        }
+
+        if let Some(replaced) = replaced {
+            self.server
+                .filesystem
+                .allocate_in_path(
+                    path.parent().unwrap_or(Path::new("")),
+                    -(replaced.len() as i64),
+                )
+                .await;
+        }
+        self.server.filesystem.chown_path(path).await;
+
+        Ok(())
+    }
+
+    async fn send(
+        &self,
+        input: &mut Input<'_>,
+        output: &mut Output,
+        options: &RsyncOptions,
+        seed: i32,
+    ) -> Result<(), RsyncError> {
+        let mut rules = Vec::new();
+        loop {
+            let length = input.int().await?;
+            if length == 0 {
+                break;
+            }
+
+            let rule = input
+                .string(
+                    usize::try_from(length)
+                        .map_err(|_| RsyncError::protocol("invalid filter rule"))?,
+                )
+                .await?;
+            let rule = String::from_utf8_lossy(&rule);
+
+            if rule == "!" {
+                rules.clear();
+                continue;
+            }
+
+            rules.push(FilterRule::parse(&rule).ok_or_else(|| {
+                RsyncError::new(
+                    format!("filter rule '{rule}' is not supported by this server"),
+                    RERR_UNSUPPORTED,
+                )
+            })?);
+        }
+
+        let mut files = self.build_file_list(output, options, &rules).await?;
+        files.sort_by_cached_key(|file| file.entry.sort_key());
+
+        let total_size = Self::write_file_list(
+            output,
+            files.iter().map(|file| &file.entry),
+            options,
+            self.state.config.system.user.uid as i32,
+            self.state.config.system.user.gid as i32,
+        )
+        .await?;
+        output.int(if output.errors > 0 { 1 } else { 0 });
+        output.flush().await?;
+
+        let mut phase = 0;
+        loop {
+            let index = input.int().await?;
+            if index == NDX_DONE {
+                phase += 1;
+                if phase > MAX_PHASE {
+                    break;
+                }
+
+                output.int(NDX_DONE);
+                output.flush().await?;
+
+                continue;
+            }
+
+            let file = usize::try_from(index)
+                .ok()
+                .and_then(|index| files.get(index))
+                .ok_or_else(|| RsyncError::protocol("invalid file index"))?;
+
+            let flags = input.short().await?;
+            let basis_type = if flags & ITEM_BASIS_TYPE_FOLLOWS != 0 {
+                Some(input.byte().await?)
+            } else {
+                None
+            };
+            let xname = if flags & ITEM_XNAME_FOLLOWS != 0 {
+                Some(input.vstring().await?)
+            } else {
+                None
+            };
+
+            let mut sum_head = None;
+            let mut source = None;
+            if flags & ITEM_TRANSFER != 0 {
+                if file.entry.file_type() != S_IFREG {
+                    return Err(RsyncError::protocol("transfer of a non-regular file"));
+                }
+
+                // blocks of the old file on the client are never used, whole files are sent
+                let head = input.sum_head().await?;
+                input.skip(head[0] as u64 * (4 + head[2] as u64)).await?;
+                sum_head = Some(head);
+
+                match self.server.filesystem.open(&file.path).await {
+                    Ok(opened) => source = Some(opened),
+                    Err(_) => {
+                        output
+                            .error(&format!(
+                                "send_files failed to open \"{}\"",
+                                file.entry.name
+                            ))
+                            .await?;
+
+                        continue;
+                    }
+                }
+            }
+
+            // the client logs the items it asked for once they are echoed back
+            output.int(index);
+            output.short(flags);
+            if let Some(basis_type) = basis_type {
+                output.byte(basis_type);
+            }
+            if let Some(xname) = &xname {
+                output.vstring(xname);
+            }
+
+            if let (Some(head), Some(source)) = (sum_head, source) {
+                for value in head {
+                    output.int(value);
+                }
+
+                let sent = self.send_data(output, source, seed).await?;
+                self.log_activity(ActivityEvent::SftpRead, &file.path, Some(sent))
+                    .await;
+            }
+            output.maybe_flush().await?;
+        }
+
+        output.int(NDX_DONE);
+
+        // total read, total written, total size, file list build and transfer time
+        let total_read = input.read as i64;
+        let total_written = output.written as i64;
+        output.longint(total_read);
+        output.longint(total_written);
+        output.longint(total_size);
+        output.longint(0);
+        output.longint(0);
+        output.flush().await?;
+
+        if input.int().await? != NDX_DONE {
+            return Err(RsyncError::protocol("invalid packet at end of run"));
+        }
+
+        Ok(())
+    }
+
+    /// Writes the file list of a download, owners are always sent as `uid` and `gid`.
+    /// Returns the total size of the regular files in it
+    async fn write_file_list<'a>(
+        output: &mut Output,
+        files: impl Iterator<Item = &'a FileEntry>,
+        options: &RsyncOptions,
+        uid: i32,
+        gid: i32,
+    ) -> Result<i64, RsyncError> {
+        let mut total_size = 0;
+
+        for entry in files {
+            let name = entry.name.as_bytes();
+
+            let mut flags = 0;
+            if entry.is_dir() && entry.top_dir {
+                flags |= XMIT_TOP_DIR;
+            }
+            if name.len() > 255 {
+                flags |= XMIT_LONG_NAME;
+            }
+            // a zero byte ends the list, so files always carry at least one flag
+            if flags == 0 && !entry.is_dir() {
+                flags |= XMIT_TOP_DIR;
+            }
+            if flags == 0 || flags > 0xff {
+                output.short(flags | XMIT_EXTENDED_FLAGS);
+            } else {
+                output.byte(flags as u8);
+            }
+
+            if flags & XMIT_LONG_NAME != 0 {
+                output.int(name.len() as i32);
+            } else {
+                output.byte(name.len() as u8);
+            }
+            output.bytes(name);
+
+            output.longint(entry.size);
+            output.int(entry.mtime);
+            output.int(entry.mode as i32);
+            if options.preserve_uid {
+                output.int(uid);
+            }
+            if options.preserve_gid {
+                output.int(gid);
+            }
+            if let Some(link) = &entry.link {
+                output.int(link.len() as i32);
+                output.bytes(link.as_bytes());
+            }
+
+            if entry.file_type() == S_IFREG {
+                total_size += entry.size;
+            }
+            output.maybe_flush().await?;
+        }
+        output.byte(0);
+
+        // empty id lists, owners are sent as numbers
+        if !options.numeric_ids {
+            if options.preserve_uid {
+                output.int(0);
+            }
+            if options.preserve_gid {
+                output.int(0);
+            }
+        }
+
+        Ok(total_size)
+    }
+
+    /// Sends a file as literal data followed by its checksum
+    async fn send_data(
+        &self,
+        output: &mut Output,
+        mut source: tokio::fs::File,
+        seed: i32,
+    ) -> Result<u64, RsyncError> {
+        let mut md4 = seeded_md4(seed);
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut sent = 0;
+
+        loop {
+            // a file that can no longer be read is cut short, the checksum tells the client
+            let length = source.read(&mut buffer).await.unwrap_or(0);
+            if length == 0 {
+                break;
+            }
+
+            self.throttle.consume(length).await;
+            md4.update(&buffer[..length]);
+
+            output.int(length as i32);
+            output.bytes(&buffer[..length]);
+            output.maybe_flush().await?;
+
+            sent += length as u64;
+        }
+
+        output.int(0);
+        output.bytes(&md4.finalize());
+
+        Ok(sent)
+    }
+
+    async fn build_file_list(
+        &self,
+        output: &mut Output,
+        options: &RsyncOptions,
+        rules: &[FilterRule],
+    ) -> Result<Vec<SourceFile>, RsyncError> {
+        let mut files = Vec::new();
+        let mut directories: Vec<(PathBuf, String)> = Vec::new();
+
+        for source in &options.paths {
+            let trimmed = source.trim_end_matches('/');
+            // `dir/` and `dir/.` send the contents of the directory, `dir` sends the directory itself
+            let contents = trimmed.is_empty()
+                || trimmed == "."
+                || trimmed.ends_with("/.")
+                || source.ends_with('/');
+
+            let path = match self.server.filesystem.canonicalize(source).await {
+                Ok(path) => path,
+                Err(_) => {
+                    output
+                        .error(&format!(
+                            "link_stat \"{source}\" failed: no such file or directory"
+                        ))
+                        .await?;
+                    continue;
+                }
+            };
+            let metadata = match self.server.filesystem.metadata(&path).await {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    output
+                        .error(&format!(
+                            "link_stat \"{source}\" failed: no such file or directory"
+                        ))
+                        .await?;
+                    continue;
+                }
+            };
+
+            if self
+                .server
+                .filesystem
+                .is_hidden(&path, metadata.is_dir(), &self.user_permissions)
+                .await
+            {
+                output
+                    .error(&format!(
+                        "link_stat \"{source}\" failed: no such file or directory"
+                    ))
+                    .await?;
+                continue;
+            }
+
+            let name = if metadata.is_dir() && contents {
+                ".".to_string()
+            } else {
+                trimmed.rsplit('/').next().unwrap_or(trimmed).to_string()
+            };
+
+            if metadata.is_dir() && !options.recursive && !options.dirs {
+                output.info(&format!("skipping directory {name}")).await?;
+                continue;
+            }
+            if name != "." && FilterRule::is_excluded(rules, &name, metadata.is_dir()) {
+                continue;
+            }
+
+            if let Some(mut file) = self
+                .source_file(output, options, path, name, &metadata)
+                .await?
+            {
+                file.entry.top_dir = true;
+
+                if file.entry.is_dir() && (options.recursive || contents) {
+                    directories.push((
+                        file.path.clone(),
+                        match file.entry.name.as_str() {
+                            "." => String::new(),
+                            name => format!("{name}/"),
+                        },
+                    ));
+                }
+
+                files.push(file);
+            }
+
+            while let Some((directory, prefix)) = directories.pop() {
+                let mut entries = match self.server.filesystem.read_dir(&directory).await {
+                    Ok(entries) => entries,
+                    Err(_) => {
+                        output
+                            .error(&format!("opendir \"{}\" failed", directory.display()))
+                            .await?;
+                        continue;
+                    }
+                };
+
+                while let Some(Ok(entry)) = entries.next_entry().await {
+                    let path = directory.join(&entry);
+                    let name = format!("{prefix}{entry}");
+
+                    let metadata = match self.server.filesystem.symlink_metadata(&path).await {
+                        Ok(metadata) => metadata,
+                        Err(_) => continue,
+                    };
+
+                    if self
+                        .server
+                        .filesystem
+                        .is_hidden(&path, metadata.is_dir(), &self.user_permissions)
+                        .await
+                        || FilterRule::is_excluded(rules, &name, metadata.is_dir())
+                    {
+                        continue;
+                    }
+
+                    if files.len() >= MAX_FILES {
+                        return Err(RsyncError::new("file list too long", RERR_FILESELECT));
+                    }
+
+                    if let Some(file) = self
+                        .source_file(output, options, path, name, &metadata)
+                        .await?
+                    {
+                        if file.entry.is_dir() && options.recursive {
+                            directories.push((file.path.clone(), format!("{}/", file.entry.name)));
+                        }
+
+                        files.push(file);
+                    }
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    async fn source_file(
+        &self,
+        output: &mut Output,
+        options: &RsyncOptions,
+        path: PathBuf,
+        name: String,
+        metadata: &cap_std::fs::Metadata,
+    ) -> Result<Option<SourceFile>, RsyncError> {
+        let file_type = metadata.file_type();
+
+        let (kind, link) = if file_type.is_dir() {
+            (S_IFDIR, None)
+        } else if file_type.is_file() {
+            if self
+                .server
+                .filesystem
+                .is_denied(
+                    &path,
+                    false,
+                    &self.user_permissions,
+                    Permission::FileReadContent,
+                )
+                .await
+            {
+                output
+                    .error(&format!(
+                        "send_files failed to open \"{name}\": permission denied"
+                    ))
+                    .await?;
+                return Ok(None);
+            }
+
+            (S_IFREG, None)
+        } else if file_type.is_symlink() && options.links {
+            match self.server.filesystem.read_link(&path).await {
+                Ok(target) => (S_IFLNK, Some(target.to_string_lossy().to_string())),
+                Err(_) => {
+                    output.error(&format!("readlink \"{name}\" failed")).await?;
+                    return Ok(None);
+                }
+            }
+        } else {
+            output
+                .info(&format!("skipping non-regular file \"{name}\""))
+                .await?;
+            return Ok(None);
+        };
+
+        Ok(Some(SourceFile {
+            entry: FileEntry {
+                name,
+                size: metadata.len() as i64,
+                mtime: Self::modified(metadata) as i32,
+                mode: kind | (metadata.permissions().mode() & 0o7777),
+                uid: 0,
+                gid: 0,
+                link,
+                top_dir: false,
+            },
+            path,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(data: Vec<u8>) -> Input<'static> {
+        Input {
+            reader: BufReader::new(Box::pin(std::io::Cursor::new(data))),
+            read: 0,
+        }
+    }
+
+    fn output() -> (Output, tokio::io::DuplexStream) {
+        let (writer, reader) = tokio::io::duplex(1 << 20);
+
+        (
+            Output {
+                writer: Box::pin(writer),
+                stderr: Box::pin(tokio::io::sink()),
+                multiplexed: true,
+                buffer: Vec::new(),
+                written: 0,
+                errors: 0,
+            },
+            reader,
+        )
+    }
+
+    /// Strips the multiplexing headers off everything written, like the client does
+    async fn demultiplex(mut output: Output, mut reader: tokio::io::DuplexStream) -> Vec<u8> {
+        output.flush().await.ok().unwrap();
+        drop(output);
+
+        let mut raw = Vec::new();
+        reader.read_to_end(&mut raw).await.unwrap();
+
+        let mut data = Vec::new();
+        let mut raw = raw.as_slice();
+        while !raw.is_empty() {
+            let header = u32::from_le_bytes(raw[..4].try_into().unwrap());
+            let length = (header & 0xffffff) as usize;
+
+            assert_eq!(header >> 24, MPLEX_BASE + MSG_DATA as u32);
+            data.extend_from_slice(&raw[4..4 + length]);
+            raw = &raw[4 + length..];
+        }
+
+        data
+    }
+
+    fn options(args: &[&str]) -> RsyncOptions {
+        RsyncOptions::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    fn exit_code<T>(result: Result<T, RsyncError>) -> Option<u32> {
+        result.err().map(|err| err.exit_code)
+    }
+
+    fn summary(entry: &FileEntry) -> (&str, i64, i32, u32, Option<&str>) {
+        (
+            &entry.name,
+            entry.size,
+            entry.mtime,
+            entry.mode,
+            entry.link.as_deref(),
+        )
+    }
+
+    fn checksum(seed: i32, data: &[u8]) -> [u8; 16] {
+        let mut md4 = seeded_md4(seed);
+        md4.update(data);
+
+        md4.finalize().into()
+    }
+
+    /// Reads the literal tokens and checksum of a whole-file transfer
+    async fn read_file_data(input: &mut Input<'_>, seed: i32) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        loop {
+            let length = input.literal_length().await.ok().unwrap();
+            if length == 0 {
+                break;
+            }
+
+            let mut chunk = vec![0; length];
+            input.bytes(&mut chunk).await.ok().unwrap();
+            data.extend(chunk);
+        }
+
+        let mut sum = [0; 16];
+        input.bytes(&mut sum).await.ok().unwrap();
+        assert_eq!(sum, checksum(seed, &data));
+
+        data
+    }
+
+    #[test]
+    fn md4_matches_reference_vectors() {
+        // the seed is hashed first, so it stands in for the first four bytes of the input
+        let vectors: [(&[u8], &str); 3] = [
+            (b"message digest", "d9130a8164549fe818874806e1c7014b"),
+            (
+                b"abcdefghijklmnopqrstuvwxyz",
+                "d79e1c308aa5bbcdeea8ed63df412da9",
+            ),
+            (
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "e33b4ddc9c38f2199c3e7b164fcc0536",
+            ),
+        ];
+
+        for (data, expected) in vectors {
+            let mut md4 = seeded_md4(i32::from_le_bytes(data[..4].try_into().unwrap()));
+            for chunk in data[4..].chunks(7) {
+                md4.update(chunk);
+            }
+
+            let digest = md4
+                .finalize()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>();
+            assert_eq!(digest, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn integers_and_strings_round_trip() {
+        let long_name = "a".repeat(300);
+
+        let (mut out, reader) = output();
+        out.byte(7);
+        out.short(0x1234);
+        out.int(-5);
+        out.longint(5);
+        out.longint(1 << 40);
+        out.longint(-2);
+        out.vstring(b"abc");
+        out.vstring(long_name.as_bytes());
+
+        let mut input = input(demultiplex(out, reader).await);
+        assert_eq!(input.byte().await.ok(), Some(7));
+        assert_eq!(input.short().await.ok(), Some(0x1234));
+        assert_eq!(input.int().await.ok(), Some(-5));
+        assert_eq!(input.longint().await.ok(), Some(5));
+        assert_eq!(input.longint().await.ok(), Some(1 << 40));
+        assert_eq!(input.longint().await.ok(), Some(-2));
+        assert_eq!(input.vstring().await.ok(), Some(b"abc".to_vec()));
+        assert_eq!(input.vstring().await.ok(), Some(long_name.into_bytes()));
+        assert_eq!(exit_code(input.byte().await), Some(RERR_STREAMIO));
+    }
+
+    #[tokio::test]
+    async fn truncated_values_are_errors() {
+        let mut long = (-1i32).to_le_bytes().to_vec();
+        long.extend([1, 2, 3]);
+        assert_eq!(exit_code(input(long).longint().await), Some(RERR_STREAMIO));
+
+        let mut string = vec![0x81, 0x00];
+        string.extend([b'a'; 10]);
+        assert_eq!(
+            exit_code(input(string).vstring().await),
+            Some(RERR_STREAMIO)
+        );
+
+        assert_eq!(
+            exit_code(input(vec![0; 10]).skip(100).await),
+            Some(RERR_STREAMIO)
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_strings_are_refused() {
+        assert_eq!(
+            exit_code(input(Vec::new()).string(MAX_PATH_LENGTH + 1).await),
+            Some(RERR_PROTOCOL)
+        );
+        assert_eq!(
+            exit_code(input(vec![0xff, 0xff]).vstring().await),
+            Some(RERR_PROTOCOL)
+        );
+    }
+
+    #[tokio::test]
+    async fn checksum_headers_are_validated() {
+        let encode = |head: [i32; 4]| {
+            head.iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            input(encode([3, 700, 16, 100])).sum_head().await.ok(),
+            Some([3, 700, 16, 100])
+        );
+
+        for head in [
+            [-1, 700, 16, 0],
+            [3, -700, 16, 0],
+            [3, 700, 17, 0],
+            [3, 700, -1, 0],
+            [3, 700, 16, 701],
+        ] {
+            assert_eq!(
+                exit_code(input(encode(head)).sum_head().await),
+                Some(RERR_PROTOCOL)
+            );
+        }
+
+        let mut truncated = encode([3, 700, 16, 100]);
+        truncated.truncate(10);
+        assert_eq!(
+            exit_code(input(truncated).sum_head().await),
+            Some(RERR_STREAMIO)
+        );
+    }
+
+    #[tokio::test]
+    async fn block_references_are_refused() {
+        assert_eq!(
+            input(5i32.to_le_bytes().to_vec())
+                .literal_length()
+                .await
+                .ok(),
+            Some(5)
+        );
+        assert_eq!(
+            exit_code(input((-3i32).to_le_bytes().to_vec()).literal_length().await),
+            Some(RERR_PROTOCOL)
+        );
+    }
+
+    #[tokio::test]
+    async fn upload_round_trip() {
+        let options = options(&["--server", "-rt", ".", "destination"]);
+        let seed = 1234;
+        let mtime = 1_700_000_000i32;
+
+        // encoded the way a rsync client compresses its file list
+        let mut data = Vec::new();
+        data.push(XMIT_TOP_DIR as u8);
+        data.push(1);
+        data.extend(b".");
+        data.extend(4096i32.to_le_bytes());
+        data.extend(mtime.to_le_bytes());
+        data.extend(((S_IFDIR | 0o755) as i32).to_le_bytes());
+
+        data.push(XMIT_SAME_TIME as u8);
+        data.push(7);
+        data.extend(b"plugins");
+        data.extend(4096i32.to_le_bytes());
+        data.extend(((S_IFDIR | 0o755) as i32).to_le_bytes());
+
+        data.push((XMIT_SAME_NAME | XMIT_SAME_TIME) as u8);
+        data.push(7);
+        data.push(6);
+        data.extend(b"/a.jar");
+        data.extend(3i32.to_le_bytes());
+        data.extend(((S_IFREG | 0o644) as i32).to_le_bytes());
+
+        data.push((XMIT_SAME_NAME | XMIT_SAME_TIME | XMIT_SAME_MODE) as u8);
+        data.push(8);
+        data.push(5);
+        data.extend(b"b.jar");
+        data.extend((-1i32).to_le_bytes());
+        data.extend(5_000_000_000i64.to_le_bytes());
+
+        data.push(0);
+        // io error flag
+        data.extend(0i32.to_le_bytes());
+
+        // the contents of a.jar, as two literal tokens
+        data.extend(2i32.to_le_bytes());
+        data.extend(b"ab");
+        data.extend(1i32.to_le_bytes());
+        data.extend(b"c");
+        data.extend(0i32.to_le_bytes());
+        data.extend(checksum(seed, b"abc"));
+
+        let mut input = input(data);
+        let mut files = RsyncSession::receive_file_list(&mut input, &options)
+            .await
+            .ok()
+            .unwrap();
+        files.sort_by_cached_key(FileEntry::sort_key);
+
+        assert_eq!(
+            files.iter().map(summary).collect::<Vec<_>>(),
+            [
+                (".", 4096, mtime, S_IFDIR | 0o755, None),
+                ("plugins", 4096, mtime, S_IFDIR | 0o755, None),
+                ("plugins/a.jar", 3, mtime, S_IFREG | 0o644, None),
+                ("plugins/b.jar", 5_000_000_000, mtime, S_IFREG | 0o644, None),
+            ]
+        );
+        assert!(files[0].top_dir && !files[1].top_dir);
+
+        assert_eq!(read_file_data(&mut input, seed).await, b"abc");
+        assert_eq!(exit_code(input.byte().await), Some(RERR_STREAMIO));
+    }
+
+    #[tokio::test]
+    async fn download_round_trip() {
+        let options = options(&["--server", "--sender", "-rlogt", ".", "."]);
+        let seed = -42;
+        let long_name = format!("dir/{}", "b".repeat(300));
+        let contents = (0..CHUNK_SIZE * 2 + 100)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+
+        let entry = |name: &str, size: i64, mode: u32, link: Option<&str>| FileEntry {
+            name: name.to_string(),
+            size,
+            mtime: 1_600_000_000,
+            mode,
+            link: link.map(|link| link.to_string()),
+            top_dir: name == ".",
+            ..Default::default()
+        };
+        let mut files = [
+            entry(".", 4096, S_IFDIR | 0o755, None),
+            entry("dir", 4096, S_IFDIR | 0o700, None),
+            entry(&long_name, contents.len() as i64, S_IFREG | 0o600, None),
+            entry("link", 5, S_IFLNK | 0o777, Some("a.txt")),
+            entry("a.txt", 0, S_IFREG | 0o644, None),
+        ];
+        files.sort_by_cached_key(FileEntry::sort_key);
+
+        let (mut out, reader) = output();
+        let total_size =
+            RsyncSession::write_file_list(&mut out, files.iter(), &options, 1000, 1001)
+                .await
+                .ok()
+                .unwrap();
+        out.int(0);
+
+        // the same tokens `send_data` writes
+        for chunk in contents.chunks(CHUNK_SIZE) {
+            out.int(chunk.len() as i32);
+            out.bytes(chunk);
+        }
+        out.int(0);
+        out.bytes(&checksum(seed, &contents));
+
+        assert_eq!(total_size, contents.len() as i64);
+
+        let mut input = input(demultiplex(out, reader).await);
+        let mut received = RsyncSession::receive_file_list(&mut input, &options)
+            .await
+            .ok()
+            .unwrap();
+        received.sort_by_cached_key(FileEntry::sort_key);
+
+        assert_eq!(
+            received.iter().map(summary).collect::<Vec<_>>(),
+            files.iter().map(summary).collect::<Vec<_>>()
+        );
+        assert!(
+            received
+                .iter()
+                .all(|entry| entry.uid == 1000 && entry.gid == 1001)
+        );
+
+        assert_eq!(read_file_data(&mut input, seed).await, contents);
+    }
+
+    #[tokio::test]
+    async fn invalid_file_lists_are_refused() {
+        let options = options(&["--server", "-r", ".", "destination"]);
+        let file_list = |name: &[u8], size: i64| {
+            let mut data = vec![XMIT_TOP_DIR as u8, name.len() as u8];
+            data.extend(name);
+            data.extend((-1i32).to_le_bytes());
+            data.extend(size.to_le_bytes());
+            data.extend(0i32.to_le_bytes());
+            data.extend(((S_IFREG | 0o644) as i32).to_le_bytes());
+            data.push(0);
+            data.extend(0i32.to_le_bytes());
+
+            data
+        };
+
+        for name in [&b".."[..], b"a/../b", b"/etc/passwd", b"a//b", b"\xff"] {
+            assert_eq!(
+                exit_code(
+                    RsyncSession::receive_file_list(&mut input(file_list(name, 1)), &options).await
+                ),
+                Some(RERR_PROTOCOL)
+            );
+        }
+        assert_eq!(
+            exit_code(
+                RsyncSession::receive_file_list(&mut input(file_list(b"a", -1)), &options).await
+            ),
+            Some(RERR_PROTOCOL)
+        );
+
+        // a shared prefix longer than the previous name
+        let prefix = vec![(XMIT_TOP_DIR | XMIT_SAME_NAME) as u8, 4, 1, b'a'];
+        assert_eq!(
+            exit_code(RsyncSession::receive_file_list(&mut input(prefix), &options).await),
+            Some(RERR_PROTOCOL)
+        );
+
+        for length in [-1, MAX_PATH_LENGTH as i32 + 1] {
+            let mut long_name = vec![(XMIT_TOP_DIR | XMIT_LONG_NAME) as u8];
+            long_name.extend(length.to_le_bytes());
+            assert_eq!(
+                exit_code(RsyncSession::receive_file_list(&mut input(long_name), &options).await),
+                Some(RERR_PROTOCOL)
+            );
+        }
+
+        let mut truncated = file_list(b"a", 1);
+        truncated.truncate(10);
+        assert_eq!(
+            exit_code(RsyncSession::receive_file_list(&mut input(truncated), &options).await),
+            Some(RERR_STREAMIO)
+        );
+    }
+
+    #[test]
+    fn file_lists_sort_like_rsync() {
+        let mut files = [".", "b", "a/x", "a", "c.txt"]
+            .into_iter()
+            .map(|name| FileEntry {
+                name: name.to_string(),
+                mode: if matches!(name, "." | "a") {
+                    S_IFDIR
+                } else {
+                    S_IFREG
+                },
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        files.sort_by_cached_key(FileEntry::sort_key);
+
+        assert_eq!(
+            files
+                .iter()
+                .map(|file| file.name.as_str())
+                .collect::<Vec<_>>(),
+            [".", "b", "c.txt", "a", "a/x"]
+        );
+    }
+
+    #[test]
+    fn filter_rules() {
+        let rules = ["- *.log", "+ /cache/keep", "- /cache/", "- logs/**"]
+            .into_iter()
+            .map(|rule| FilterRule::parse(rule).unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(FilterRule::is_excluded(&rules, "world/latest.log", false));
+        assert!(FilterRule::is_excluded(&rules, "cache", true));
+        assert!(!FilterRule::is_excluded(&rules, "cache", false));
+        assert!(!FilterRule::is_excluded(&rules, "cache/keep", true));
+        assert!(FilterRule::is_excluded(&rules, "server/logs/a.txt", false));
+        assert!(!FilterRule::is_excluded(&rules, "server.properties", false));
+        assert!(FilterRule::parse("P protected").is_none());
+    }
+
+    #[test]
+    fn unsupported_options_are_refused() {
+        let parse = |args: &[&str]| {
+            RsyncOptions::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+        };
+
+        assert!(parse(&["--server", "-z", ".", "a"]).is_err());
+        assert!(parse(&["--server", "--delete", ".", "a"]).is_err());
+        assert!(parse(&["--sender", ".", "a"]).is_err());
+        assert!(parse(&["--server", ".", "a", "b"]).is_err());
+        assert!(parse(&["--server", "-e.LsfxC", ".", "a"]).is_ok());
+    }
+}
@@ -0,0 +1,706 @@
+use crate::{
+    routes::State,
+    server::{
+        activity::{Activity, ActivityEvent},
+        permissions::{Permission, Permissions},
+        throttle::Throttle,
+    },
+};
+use cap_std::fs::PermissionsExt;
+use russh::{Channel, server::Msg};
+use serde_json::json;
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// control lines longer than this are treated as a protocol error
+const MAX_LINE_LENGTH: u64 = 4096;
+
+#[derive(Default)]
+struct ScpOptions {
+    sink: bool,
+    source: bool,
+    recursive: bool,
+    preserve: bool,
+    directory: bool,
+
+    paths: Vec<String>,
+}
+
+impl ScpOptions {
+    /// Parses the arguments of `scp -t` or `scp -f` as sent by a scp client
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = Self::default();
+        let mut flags = true;
+
+        for arg in args {
+            match arg.strip_prefix('-') {
+                Some("-") if flags => flags = false,
+                Some(arg) if flags && !arg.is_empty() => {
+                    for flag in arg.chars() {
+                        match flag {
+                            't' => options.sink = true,
+                            'f' => options.source = true,
+                            'r' => options.recursive = true,
+                            'p' => options.preserve = true,
+                            'd' => options.directory = true,
+                            'v' | 'q' | 'E' => {}
+                            _ => return None,
+                        }
+                    }
+                }
+                _ => options.paths.push(arg.clone()),
+            }
+        }
+
+        if options.sink == options.source || options.paths.is_empty() {
+            return None;
+        }
+
+        Some(options)
+    }
+}
+
+enum ScpError {
+    /// reported to the client with `\x01`, the transfer goes on
+    Warning(String),
+    /// reported to the client with `\x02`, the transfer is aborted
+    Fatal(String),
+}
+
+impl From<std::io::Error> for ScpError {
+    fn from(err: std::io::Error) -> Self {
+        ScpError::Fatal(err.to_string())
+    }
+}
+
+type Reader<'a> = BufReader<Pin<Box<dyn AsyncRead + Send + 'a>>>;
+type Writer = Pin<Box<dyn AsyncWrite + Send>>;
+
+/// The legacy scp protocol (`scp -O`), spoken over an `exec` request
+pub struct ScpSession {
+    pub state: State,
+    pub server: crate::server::Server,
+
+    pub user_ip: Option<IpAddr>,
+    pub user_uuid: Option<uuid::Uuid>,
+    pub user_permissions: Permissions,
+
+    pub throttle: Throttle,
+}
+
+impl ScpSession {
+    pub async fn run(self, channel: Channel<Msg>, args: Vec<String>) {
+        let (mut read_half, write_half) = channel.split();
+        let mut reader: Reader = BufReader::new(Box::pin(read_half.make_reader()));
+        let mut writer: Writer = Box::pin(write_half.make_writer());
+
+        let result = match ScpOptions::parse(&args) {
+            Some(options) if options.sink => self.sink(&mut reader, &mut writer, &options).await,
+            Some(options) => self.source(&mut reader, &mut writer, &options).await,
+            None => Err(ScpError::Fatal("unsupported scp arguments".to_string())),
+        };
+
+        let exit_status = match result {
+            Ok(()) => 0,
+            // warnings have already been reported while transferring
+            Err(ScpError::Warning(_)) => 1,
+            Err(ScpError::Fatal(message)) => {
+                writer
+                    .write_all(format!("\x02scp: {message}\n").as_bytes())
+                    .await
+                    .ok();
+
+                1
+            }
+        };
+
+        writer.flush().await.ok();
+        drop(writer);
+        drop(reader);
+
+        write_half.exit_status(exit_status).await.ok();
+        write_half.eof().await.ok();
+        write_half.close().await.ok();
+    }
+
+    #[inline]
+    fn allow_action(&self) -> bool {
+        !self.server.is_locked_state()
+    }
+
+    async fn read_line(reader: &mut Reader<'_>) -> Result<Option<String>, ScpError> {
+        let mut line = Vec::new();
+        (&mut *reader)
+            .take(MAX_LINE_LENGTH)
+            .read_until(b'\n', &mut line)
+            .await?;
+
+        if line.is_empty() {
+            return Ok(None);
+        }
+        if line.pop() != Some(b'\n') {
+            return Err(ScpError::Fatal("protocol error: line too long".to_string()));
+        }
+
+        Ok(Some(String::from_utf8_lossy(&line).to_string()))
+    }
+
+    /// Reads the acknowledgement the other side sends after each message
+    async fn read_response(reader: &mut Reader<'_>) -> Result<(), ScpError> {
+        match reader.read_u8().await? {
+            0 => Ok(()),
+            code => {
+                let message = Self::read_line(reader).await?.unwrap_or_default();
+
+                if code == 1 {
+                    Err(ScpError::Warning(message))
+                } else {
+                    Err(ScpError::Fatal(message))
+                }
+            }
+        }
+    }
+
+    #[inline]
+    async fn ok(writer: &mut Writer) -> Result<(), ScpError> {
+        writer.write_all(b"\0").await?;
+        writer.flush().await?;
+
+        Ok(())
+    }
+
+    #[inline]
+    async fn warn(writer: &mut Writer, message: &str) -> Result<(), ScpError> {
+        writer
+            .write_all(format!("\x01scp: {message}\n").as_bytes())
+            .await?;
+        writer.flush().await?;
+
+        Ok(())
+    }
+
+    /// Rejects names that could escape the directory being written to
+    fn parse_name(name: &str) -> Result<&str, ScpError> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(ScpError::Fatal(format!("invalid file name '{name}'")));
+        }
+
+        Ok(name)
+    }
+
+    /// Parses the `<mode> <size> <name>` part of a `C` or `D` line
+    fn parse_entry(line: &str) -> Result<(u32, u64, &str), ScpError> {
+        let mut parts = line.splitn(3, ' ');
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(mode), Some(size), Some(name)) => Ok((
+                u32::from_str_radix(mode, 8)
+                    .map_err(|_| ScpError::Fatal("protocol error: invalid mode".to_string()))?,
+                size.parse()
+                    .map_err(|_| ScpError::Fatal("protocol error: invalid size".to_string()))?,
+                Self::parse_name(name)?,
+            )),
+            _ => Err(ScpError::Fatal("protocol error: invalid entry".to_string())),
+        }
+    }
+
+    async fn sink(
+        &self,
+        reader: &mut Reader<'_>,
+        writer: &mut Writer,
+        options: &ScpOptions,
+    ) -> Result<(), ScpError> {
+        if options.paths.len() != 1 {
+            return Err(ScpError::Fatal("ambiguous target".to_string()));
+        }
+
+        if self.state.config.system.sftp.read_only || !self.allow_action() {
+            return Err(ScpError::Fatal("permission denied".to_string()));
+        }
+
+        let target = &options.paths[0];
+        let target = match self.server.filesystem.canonicalize(target).await {
+            Ok(path) => path,
+            Err(_) => PathBuf::from(target.trim_start_matches('/')),
+        };
+        let target_is_dir = self
+            .server
+            .filesystem
+            .metadata(&target)
+            .await
+            .is_ok_and(|m| m.is_dir());
+
+        if options.directory && !target_is_dir {
+            return Err(ScpError::Fatal(format!(
+                "{}: not a directory",
+                target.display()
+            )));
+        }
+
+        let mut directories: Vec<PathBuf> = Vec::new();
+        let mut modified = None;
+
+        Self::ok(writer).await?;
+
+        while let Some(line) = Self::read_line(reader).await? {
+            let (kind, line) = match line.char_indices().nth(1) {
+                Some((index, _)) => line.split_at(index),
+                None => (line.as_str(), ""),
+            };
+
+            // the first entry is written to the target itself unless it is a directory
+            let resolve = |name: &str| match directories.last() {
+                Some(directory) => directory.join(name),
+                None if target_is_dir => target.join(name),
+                None => target.clone(),
+            };
+
+            match kind {
+                "T" => {
+                    modified = line
+                        .split(' ')
+                        .next()
+                        .and_then(|mtime| mtime.parse::<u64>().ok())
+                        .map(|mtime| std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime));
+
+                    Self::ok(writer).await?;
+                }
+                "D" => {
+                    if !options.recursive {
+                        return Err(ScpError::Fatal("received directory without -r".to_string()));
+                    }
+
+                    let (mode, _, name) = Self::parse_entry(line)?;
+                    let path = resolve(name);
+
+                    match self.create_directory(&path, mode).await {
+                        Ok(()) => {
+                            directories.push(path);
+                            Self::ok(writer).await?;
+                        }
+                        // the client skips the whole directory, including its closing `E` line
+                        Err(ScpError::Warning(message)) => Self::warn(writer, &message).await?,
+                        Err(err) => return Err(err),
+                    }
+
+                    modified = None;
+                }
+                "E" => {
+                    if directories.pop().is_none() {
+                        return Err(ScpError::Fatal("protocol error: unexpected E".to_string()));
+                    }
+
+                    Self::ok(writer).await?;
+                }
+                "C" => {
+                    let (mode, size, name) = Self::parse_entry(line)?;
+                    let path = resolve(name);
+
+                    let mut file = match self.create_file(&path, size).await {
+                        Ok(file) => file,
+                        Err(ScpError::Warning(message)) => {
+                            Self::warn(writer, &message).await?;
+                            continue;
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    Self::ok(writer).await?;
+
+                    let copied = self
+                        .throttle
+                        .reversed()
+                        .copy(&mut (&mut *reader).take(size), &mut file)
+                        .await;
+                    let flushed = file.flush().await;
+
+                    if let Err(err) = match (copied, flushed) {
+                        (Ok(copied), Ok(())) if copied == size => Ok(()),
+                        (Ok(_), Ok(())) => {
+                            Err(ScpError::Fatal("unexpected end of file".to_string()))
+                        }
+                        (Err(err), _) | (_, Err(err)) => Err(ScpError::from(err)),
+                    } {
+                        // only the part that made it to disk stays allocated
+                        let written = file.metadata().await.map(|m| m.len()).unwrap_or(0);
+                        self.server
+                            .filesystem
+                            .allocate_in_path(
+                                path.parent().unwrap_or(Path::new("")),
+                                -(size.saturating_sub(written) as i64),
+                            )
+                            .await;
+
+                        return Err(err);
+                    }
+
+                    let file = file.into_std().await;
+                    if let Some(modified) = modified.take() {
+                        file.set_modified(modified).ok();
+                    }
+                    drop(file);
+
+                    self.server
+                        .filesystem
+                        .set_permissions(&path, cap_std::fs::Permissions::from_mode(mode & 0o777))
+                        .await
+                        .ok();
+                    self.server.filesystem.chown_path(&path).await;
+
+                    Self::read_response(reader).await?;
+                    Self::ok(writer).await?;
+
+                    self.server
+                        .activity
+                        .log_activity(Activity {
+                            event: ActivityEvent::SftpCreate,
+                            user: self.user_uuid,
+                            ip: self.user_ip,
+                            metadata: Some(json!({
+                                "files": [self.server.filesystem.relative_path(&path)],
+                            })),
+                            timestamp: chrono::Utc::now(),
+                        })
+                        .await;
+                }
+                "\x01" => {
+                    tracing::debug!(
+                        server = %self.server.uuid,
+                        "scp client warning: {}",
+                        line
+                    );
+                }
+                "\x02" => return Err(ScpError::Fatal(line.to_string())),
+                _ => return Err(ScpError::Fatal("protocol error".to_string())),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn create_directory(&self, path: &Path, mode: u32) -> Result<(), ScpError> {
+        if let Ok(metadata) = self.server.filesystem.metadata(path).await {
+            if metadata.is_dir() {
+                return Ok(());
+            }
+
+            return Err(ScpError::Warning(format!(
+                "{}: not a directory",
+                path.display()
+            )));
+        }
+
+        if self
+            .server
+            .filesystem
+            .is_denied(path, true, &self.user_permissions, Permission::FileCreate)
+            .await
+        {
+            return Err(ScpError::Warning(format!(
+                "{}: permission denied",
+                path.display()
+            )));
+        }
+
+        if self.server.filesystem.create_dir(path).await.is_err() {
+            return Err(ScpError::Warning(format!(
+                "{}: failed to create directory",
+                path.display()
+            )));
+        }
+
+        self.server
+            .filesystem
+            .set_permissions(path, cap_std::fs::Permissions::from_mode(mode & 0o777))
+            .await
+            .ok();
+        self.server.filesystem.chown_path(path).await;
+
+        self.server
+            .activity
+            .log_activity(Activity {
+                event: ActivityEvent::SftpCreateDirectory,
+                user: self.user_uuid,
+                ip: self.user_ip,
+                metadata: Some(json!({
+                    "files": [self.server.filesystem.relative_path(path)],
+                })),
+                timestamp: chrono::Utc::now(),
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn create_file(&self, path: &Path, size: u64) -> Result<tokio::fs::File, ScpError> {
+        let metadata = self.server.filesystem.metadata(path).await;
+        if metadata.as_ref().is_ok_and(|m| !m.is_file()) {
+            return Err(ScpError::Warning(format!(
+                "{}: not a regular file",
+                path.display()
+            )));
+        }
+
+        let permission = if metadata.is_ok() {
+            Permission::FileUpdate
+        } else {
+            Permission::FileCreate
+        };
+        if self
+            .server
+            .filesystem
+            .is_denied(path, false, &self.user_permissions, permission)
+            .await
+        {
+            return Err(ScpError::Warning(format!(
+                "{}: permission denied",
+                path.display()
+            )));
+        }
+
+        let old_size = metadata.map(|m| m.len() as i64).unwrap_or(0);
+        let parent = path.parent().unwrap_or(Path::new(""));
+        if !self
+            .server
+            .filesystem
+            .allocate_in_path(parent, size as i64 - old_size)
+            .await
+        {
+            return Err(ScpError::Warning(format!(
+                "{}: not enough disk space",
+                path.display()
+            )));
+        }
+
+        match self.server.filesystem.create(path).await {
+            Ok(file) => Ok(file),
+            Err(_) => {
+                self.server
+                    .filesystem
+                    .allocate_in_path(parent, old_size - size as i64)
+                    .await;
+
+                Err(ScpError::Warning(format!(
+                    "{}: failed to create file",
+                    path.display()
+                )))
+            }
+        }
+    }
+
+    async fn source(
+        &self,
+        reader: &mut Reader<'_>,
+        writer: &mut Writer,
+        options: &ScpOptions,
+    ) -> Result<(), ScpError> {
+        if !self.allow_action() {
+            return Err(ScpError::Fatal("permission denied".to_string()));
+        }
+
+        Self::read_response(reader).await?;
+
+        let mut failed = false;
+        for path in &options.paths {
+            let result = match self.server.filesystem.canonicalize(path).await {
+                Ok(path) => self.send_entry(reader, writer, options, path).await,
+                Err(_) => Err(ScpError::Warning(format!(
+                    "{path}: no such file or directory"
+                ))),
+            };
+
+            match result {
+                Ok(()) => {}
+                Err(ScpError::Warning(message)) => {
+                    failed = true;
+                    Self::warn(writer, &message).await?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        if failed {
+            return Err(ScpError::Warning(
+                "some files could not be sent".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn send_entry<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        writer: &'a mut Writer,
+        options: &'a ScpOptions,
+        path: PathBuf,
+    ) -> Pin<Box<dyn Future<Output = Result<(), ScpError>> + Send + 'a>> {
+        Box::pin(async move {
+            let metadata = match self.server.filesystem.metadata(&path).await {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    return Err(ScpError::Warning(format!(
+                        "{}: no such file or directory",
+                        path.display()
+                    )));
+                }
+            };
+
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| ".".to_string());
+            let mode = metadata.permissions().mode() & 0o7777;
+
+            if options.preserve {
+                let modified = metadata
+                    .modified()
+                    .map(|t| {
+                        t.into_std()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap_or_default()
+                    })
+                    .unwrap_or_default()
+                    .as_secs();
+
+                writer
+                    .write_all(format!("T{modified} 0 {modified} 0\n").as_bytes())
+                    .await?;
+                writer.flush().await?;
+                Self::read_response(reader).await?;
+            }
+
+            if metadata.is_dir() {
+                if !options.recursive {
+                    return Err(ScpError::Warning(format!(
+                        "{}: not a regular file",
+                        path.display()
+                    )));
+                }
+
+                if self
+                    .server
+                    .filesystem
+                    .is_hidden(&path, true, &self.user_permissions)
+                    .await
+                {
+                    return Err(ScpError::Warning(format!(
+                        "{}: permission denied",
+                        path.display()
+                    )));
+                }
+
+                let mut entries = match self.server.filesystem.read_dir(&path).await {
+                    Ok(entries) => entries,
+                    Err(_) => {
+                        return Err(ScpError::Warning(format!(
+                            "{}: failed to read directory",
+                            path.display()
+                        )));
+                    }
+                };
+
+                writer
+                    .write_all(format!("D{mode:04o} 0 {name}\n").as_bytes())
+                    .await?;
+                writer.flush().await?;
+                Self::read_response(reader).await?;
+
+                while let Some(Ok(entry)) = entries.next_entry().await {
+                    let entry = path.join(entry);
+                    let is_dir = self
+                        .server
+                        .filesystem
+                        .symlink_metadata(&entry)
+                        .await
+                        .is_ok_and(|m| m.is_dir());
+
+                    if self
+                        .server
+                        .filesystem
+                        .is_hidden(&entry, is_dir, &self.user_permissions)
+                        .await
+                    {
+                        continue;
+                    }
+
+                    match self.send_entry(reader, writer, options, entry).await {
+                        Ok(()) => {}
+                        Err(ScpError::Warning(message)) => Self::warn(writer, &message).await?,
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                writer.write_all(b"E\n").await?;
+                writer.flush().await?;
+                Self::read_response(reader).await?;
+
+                return Ok(());
+            }
+
+            if !metadata.is_file()
+                || self
+                    .server
+                    .filesystem
+                    .is_denied(
+                        &path,
+                        false,
+                        &self.user_permissions,
+                        Permission::FileReadContent,
+                    )
+                    .await
+            {
+                return Err(ScpError::Warning(format!(
+                    "{}: permission denied",
+                    path.display()
+                )));
+            }
+
+            let file = match self.server.filesystem.open(&path).await {
+                Ok(file) => file,
+                Err(_) => {
+                    return Err(ScpError::Warning(format!(
+                        "{}: failed to open file",
+                        path.display()
+                    )));
+                }
+            };
+
+            let size = metadata.len();
+            writer
+                .write_all(format!("C{mode:04o} {size} {name}\n").as_bytes())
+                .await?;
+            writer.flush().await?;
+            Self::read_response(reader).await?;
+
+            // the size is already announced, a file that shrinks in the meantime is padded
+            let copied = self.throttle.copy(&mut file.take(size), writer).await?;
+            if copied < size {
+                tokio::io::copy(&mut tokio::io::repeat(0).take(size - copied), writer).await?;
+            }
+
+            writer.write_all(b"\0").await?;
+            writer.flush().await?;
+            Self::read_response(reader).await?;
+
+            self.server
+                .activity
+                .log_activity(Activity {
+                    event: ActivityEvent::SftpRead,
+                    user: self.user_uuid,
+                    ip: self.user_ip,
+                    metadata: Some(json!({
+                        "files": [self.server.filesystem.relative_path(&path)],
+                        "bytes": copied,
+                    })),
+                    timestamp: chrono::Utc::now(),
+                })
+                .await;
+
+            Ok(())
+        })
+    }
+}
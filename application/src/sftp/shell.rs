@@ -0,0 +1,351 @@
+use crate::{
+    models::ServerPowerAction,
+    routes::State,
+    server::{
+        activity::{Activity, ActivityEvent},
+        permissions::{Permission, Permissions},
+        websocket::WebsocketEvent,
+    },
+};
+use russh::{Channel, ChannelMsg, server::Msg};
+use serde_json::json;
+use std::net::IpAddr;
+use tokio::sync::broadcast::error::RecvError;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const BACKSPACE: u8 = 0x08;
+const ESCAPE: u8 = 0x1b;
+const DELETE: u8 = 0x7f;
+
+/// Console access over a `shell` or `exec` request on the same connection as sftp
+pub struct ShellSession {
+    pub state: State,
+    pub server: crate::server::Server,
+
+    pub user_ip: Option<IpAddr>,
+    pub user_uuid: Option<uuid::Uuid>,
+    pub user_permissions: Permissions,
+
+    /// whether the client requested a pty, in which case we have to echo
+    /// and edit the input line ourselves
+    pub pty: bool,
+}
+
+impl ShellSession {
+    #[inline]
+    fn line_break(&self) -> &'static str {
+        if self.pty { "\r\n" } else { "\n" }
+    }
+
+    async fn write(&self, channel: &Channel<Msg>, data: &str) {
+        channel.data(data.as_bytes()).await.ok();
+    }
+
+    async fn send_command(&self, command: &str) -> Result<(), &'static str> {
+        let stdin = match self.server.container_stdin().await {
+            Some(stdin) => stdin,
+            None => return Err("server is not running"),
+        };
+
+        if let Err(err) = stdin.send(format!("{command}\n")).await {
+            tracing::error!(
+                server = %self.server.uuid,
+                "failed to send command to server: {}",
+                err
+            );
+
+            return Err("failed to send command to server");
+        }
+
+        self.server
+            .activity
+            .log_activity(Activity {
+                event: ActivityEvent::ConsoleCommand,
+                user: self.user_uuid,
+                ip: self.user_ip,
+                metadata: Some(json!({
+                    "command": command,
+                })),
+                timestamp: chrono::Utc::now(),
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn power(&self, action: ServerPowerAction) -> Result<(), String> {
+        let (permission, event) = match action {
+            ServerPowerAction::Start => (Permission::ControlStart, ActivityEvent::PowerStart),
+            ServerPowerAction::Stop => (Permission::ControlStop, ActivityEvent::PowerStop),
+            ServerPowerAction::Restart => (Permission::ControlRestart, ActivityEvent::PowerRestart),
+            ServerPowerAction::Kill => (Permission::ControlStop, ActivityEvent::PowerKill),
+        };
+
+        if !self.user_permissions.has_permission(permission) {
+            return Err("permission denied".to_string());
+        }
+
+        let docker = &self.state.docker;
+        let result = match action {
+            ServerPowerAction::Start => self.server.start(docker, None).await,
+            ServerPowerAction::Stop => self.server.stop(docker, None).await,
+            ServerPowerAction::Restart => self.server.restart(docker, None).await,
+            ServerPowerAction::Kill => self.server.kill(docker).await.map_err(Into::into),
+        };
+
+        if let Err(err) = result {
+            tracing::debug!(
+                server = %self.server.uuid,
+                "failed to run power action over ssh: {:#?}",
+                err
+            );
+
+            return Err(err.to_string());
+        }
+
+        self.server
+            .activity
+            .log_activity(Activity {
+                event,
+                user: self.user_uuid,
+                ip: self.user_ip,
+                metadata: None,
+                timestamp: chrono::Utc::now(),
+            })
+            .await;
+
+        Ok(())
+    }
+
+    /// Runs a single command like `ssh user.server@node restart` and closes the channel
+    pub async fn exec(self, channel: Channel<Msg>, command: String) {
+        let line_break = self.line_break();
+
+        let result = match command.trim() {
+            // the same permission the websocket requires before it sends the server state
+            "status"
+                if !self
+                    .user_permissions
+                    .has_permission(Permission::WebsocketConnect) =>
+            {
+                Err("permission denied".to_string())
+            }
+            "status" => {
+                let state = serde_json::to_value(self.server.state.get_state()).unwrap();
+
+                Ok(state.as_str().unwrap_or_default().to_string())
+            }
+            "start" => self
+                .power(ServerPowerAction::Start)
+                .await
+                .map(|_| "server is starting".to_string()),
+            "stop" => self
+                .power(ServerPowerAction::Stop)
+                .await
+                .map(|_| "server is stopping".to_string()),
+            "restart" => self
+                .power(ServerPowerAction::Restart)
+                .await
+                .map(|_| "server is restarting".to_string()),
+            "kill" => self
+                .power(ServerPowerAction::Kill)
+                .await
+                .map(|_| "server has been killed".to_string()),
+            command => Err(format!(
+                "unknown command '{command}', expected one of start, stop, restart, kill or status"
+            )),
+        };
+
+        match result {
+            Ok(output) => {
+                self.write(&channel, &format!("{output}{line_break}")).await;
+                channel.exit_status(0).await.ok();
+            }
+            Err(err) => {
+                channel
+                    .extended_data(1, format!("{err}{line_break}").as_bytes())
+                    .await
+                    .ok();
+                channel.exit_status(1).await.ok();
+            }
+        }
+
+        channel.eof().await.ok();
+        channel.close().await.ok();
+    }
+
+    /// Attaches to the server console until the client disconnects or presses Ctrl-D
+    pub async fn run(self, mut channel: Channel<Msg>) {
+        let line_break = self.line_break();
+
+        if self.pty {
+            self.write(
+                &channel,
+                &format!("attached to the server console, press Ctrl-D to detach{line_break}"),
+            )
+            .await;
+        }
+
+        if self.server.state.get_state() != crate::server::state::ServerState::Offline
+            || self.state.config.api.send_offline_server_logs
+        {
+            if let Ok(logs) = self
+                .server
+                .read_log(
+                    &self.state.docker,
+                    self.state.config.system.websocket_log_count,
+                )
+                .await
+            {
+                for line in logs.lines() {
+                    self.write(&channel, &format!("{}{line_break}", line.trim()))
+                        .await;
+                }
+            }
+        }
+
+        let mut events = self.server.websocket.subscribe();
+        let mut stdout = None;
+
+        let mut input = Vec::new();
+        let mut escape = false;
+
+        loop {
+            if stdout.is_none() {
+                stdout = self.server.container_stdout().await;
+            }
+
+            let output = tokio::select! {
+                message = channel.wait() => match message {
+                    Some(ChannelMsg::Data { data }) => {
+                        let mut echo = Vec::new();
+                        let mut detach = false;
+
+                        for byte in data.iter().copied() {
+                            if !self.pty {
+                                match byte {
+                                    b'\n' => {
+                                        let command = String::from_utf8_lossy(&input).to_string();
+                                        input.clear();
+
+                                        if let Err(err) = self.send_command(command.trim_end_matches('\r')).await {
+                                            echo.extend_from_slice(format!("{err}{line_break}").as_bytes());
+                                        }
+                                    }
+                                    byte => input.push(byte),
+                                }
+
+                                continue;
+                            }
+
+                            // skip over terminal escape sequences like arrow keys
+                            if escape {
+                                if byte != b'[' && (0x40..=0x7e).contains(&byte) {
+                                    escape = false;
+                                }
+
+                                continue;
+                            }
+
+                            match byte {
+                                ESCAPE => escape = true,
+                                b'\r' | b'\n' => {
+                                    echo.extend_from_slice(b"\r\n");
+
+                                    let command = String::from_utf8_lossy(&input).to_string();
+                                    input.clear();
+
+                                    if command.trim().is_empty() {
+                                        continue;
+                                    }
+
+                                    if let Err(err) = self.send_command(&command).await {
+                                        echo.extend_from_slice(format!("{err}\r\n").as_bytes());
+                                    }
+                                }
+                                BACKSPACE | DELETE => {
+                                    // drop a whole utf-8 character, not just its last byte
+                                    while let Some(byte) = input.pop() {
+                                        if byte & 0xc0 != 0x80 {
+                                            break;
+                                        }
+                                    }
+
+                                    echo.extend_from_slice(b"\x08 \x08");
+                                }
+                                CTRL_C => {
+                                    input.clear();
+                                    echo.extend_from_slice(b"^C\r\n");
+                                }
+                                CTRL_D if input.is_empty() => {
+                                    detach = true;
+                                    break;
+                                }
+                                byte if byte >= 0x20 => {
+                                    input.push(byte);
+                                    echo.push(byte);
+                                }
+                                _ => {}
+                            }
+                        }
+
+                        if !echo.is_empty() {
+                            channel.data(echo.as_slice()).await.ok();
+                        }
+
+                        if detach {
+                            break;
+                        }
+
+                        None
+                    }
+                    Some(ChannelMsg::Eof | ChannelMsg::Close) | None => break,
+                    _ => None,
+                },
+                line = async {
+                    match stdout.as_mut() {
+                        Some(stdout) => stdout.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => match line {
+                    Ok(line) => Some(line),
+                    Err(RecvError::Closed) => {
+                        stdout = None;
+
+                        None
+                    }
+                    Err(RecvError::Lagged(_)) => None,
+                },
+                message = events.recv() => match message {
+                    Ok(message) => match message.event {
+                        WebsocketEvent::ServerConsoleOutput | WebsocketEvent::ServerDaemonMessage => {
+                            message.args.into_iter().next()
+                        }
+                        _ => None,
+                    },
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => None,
+                },
+                _ = tokio::time::sleep(std::time::Duration::from_secs(1)), if stdout.is_none() => None,
+            };
+
+            if let Some(output) = output {
+                let output = output.trim_end_matches(['\r', '\n']);
+
+                if self.pty {
+                    // clear the line that is being typed, print the output and restore it
+                    let mut data = format!("\r\x1b[K{output}\r\n").into_bytes();
+                    data.extend_from_slice(&input);
+
+                    channel.data(data.as_slice()).await.ok();
+                } else {
+                    self.write(&channel, &format!("{output}\n")).await;
+                }
+            }
+        }
+
+        channel.eof().await.ok();
+        channel.close().await.ok();
+    }
+}